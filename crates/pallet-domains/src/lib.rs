@@ -33,10 +33,12 @@ extern crate alloc;
 use crate::block_tree::{Error as BlockTreeError, verify_execution_receipt};
 use crate::bundle_storage_fund::{charge_bundle_storage_fee, storage_fund_account};
 use crate::domain_registry::{DomainConfig, Error as DomainRegistryError};
+#[cfg(debug_assertions)]
+pub use crate::nominator_position::PositionInvariantError;
 use crate::runtime_registry::into_complete_raw_genesis;
-use crate::staking::OperatorStatus;
 #[cfg(feature = "runtime-benchmarks")]
 pub use crate::staking::do_register_operator;
+use crate::staking::{OperatorStatus, SharePrice};
 use crate::staking_epoch::EpochTransitionResult;
 pub use crate::weights::WeightInfo;
 #[cfg(not(feature = "std"))]
@@ -67,7 +69,7 @@ use sp_domains::execution_receipt::{
 };
 use sp_domains::{
     BundleAndExecutionReceiptVersion, DOMAIN_EXTRINSICS_SHUFFLING_SEED_SUBJECT, DomainBundleLimit,
-    DomainId, DomainInstanceData, EMPTY_EXTRINSIC_ROOT, OperatorId, OperatorPublicKey,
+    DomainId, DomainInstanceData, EMPTY_EXTRINSIC_ROOT, EpochIndex, OperatorId, OperatorPublicKey,
     OperatorSignature, ProofOfElection, RuntimeId,
 };
 use sp_domains_fraud_proof::fraud_proof::{
@@ -232,9 +234,10 @@ mod pallet {
     use crate::staking::do_reward_operators;
     use crate::staking::{
         Deposit, DomainEpoch, Error as StakingError, Operator, OperatorConfig, SharePrice,
-        StakingSummary, Withdrawal, do_deregister_operator, do_mark_invalid_bundle_authors,
-        do_mark_operators_as_slashed, do_nominate_operator, do_register_operator, do_unlock_funds,
-        do_unlock_nominator, do_unmark_invalid_bundle_authors, do_withdraw_stake,
+        StakeHistory, StakingSummary, Withdrawal, do_deregister_operator,
+        do_mark_invalid_bundle_authors, do_mark_operators_as_slashed, do_nominate_operator,
+        do_register_operator, do_unlock_funds, do_unlock_nominator,
+        do_unmark_invalid_bundle_authors, do_withdraw_stake,
     };
     #[cfg(not(feature = "runtime-benchmarks"))]
     use crate::staking_epoch::do_slash_operator;
@@ -269,8 +272,8 @@ mod pallet {
         BundleAndExecutionReceiptVersion, DomainBundleSubmitted, DomainId, DomainOwner,
         DomainSudoCall, DomainsTransfersTracker, EpochIndex,
         EvmDomainContractCreationAllowedByCall, GenesisDomain, OnChainRewards,
-        OnDomainInstantiated, OperatorAllowList, OperatorId, OperatorRewardSource, RuntimeId,
-        RuntimeObject, RuntimeType,
+        OnDomainInstantiated, OperatorAllowList, OperatorId, OperatorRewardSource,
+        OperatorRewardSourceKind, RuntimeId, RuntimeObject, RuntimeType,
     };
     use sp_domains_fraud_proof::fraud_proof_runtime_interface::domain_runtime_call;
     use sp_domains_fraud_proof::storage_proof::{self, FraudProofStorageKeyProvider};
@@ -546,6 +549,18 @@ mod pallet {
     pub type OperatorEpochSharePrice<T: Config> =
         StorageDoubleMap<_, Identity, OperatorId, Identity, DomainEpoch, SharePrice, OptionQuery>;
 
+    /// Total rewards of an operator, by the kind of reward source.
+    #[pallet::storage]
+    pub(super) type OperatorRewardsBySource<T: Config> = StorageDoubleMap<
+        _,
+        Identity,
+        OperatorId,
+        Identity,
+        OperatorRewardSourceKind,
+        BalanceOf<T>,
+        ValueQuery,
+    >;
+
     /// List of all deposits for given Operator.
     #[pallet::storage]
     pub(crate) type Deposits<T: Config> = StorageDoubleMap<
@@ -570,6 +585,22 @@ mod pallet {
         OptionQuery,
     >;
 
+    /// Total stake deposited, withdrawn and unlocked by a nominator for a given operator, removed
+    /// along with the nominator's deposit.
+    ///
+    /// The history starts at the nominator's first deposit, so nominators with deposits from before
+    /// this storage was added don't have a history until they fully withdraw and deposit again.
+    #[pallet::storage]
+    pub(crate) type NominatorStakeHistory<T: Config> = StorageDoubleMap<
+        _,
+        Identity,
+        OperatorId,
+        Identity,
+        NominatorId<T>,
+        StakeHistory<BalanceOf<T>, T::Share>,
+        OptionQuery,
+    >;
+
     /// The amount of balance the nominator hold for a given operator
     #[pallet::storage]
     pub(super) type DepositOnHold<T: Config> =
@@ -3301,9 +3332,10 @@ impl<T: Config> Pallet<T> {
     ///
     /// This calculates the total position including:
     /// - Current stake value (converted from shares using instant share price including rewards)
-    /// - Total storage fee deposits (known + pending)
+    /// - Total storage fee deposits (known + pending), and their share of the operator's storage fund
     /// - Pending deposits (not yet converted to shares)
     /// - Pending withdrawals (with unlock timing)
+    /// - The block when funds can be unlocked, if the operator is deregistered
     ///
     /// Note: Operator accounts are also nominator accounts, so this call will return the position
     /// for the operator account.
@@ -3316,6 +3348,138 @@ impl<T: Config> Pallet<T> {
     {
         nominator_position::nominator_position::<T>(operator_id, nominator_account)
     }
+
+    /// Returns the nominator position, like `nominator_position`, after checking that it is
+    /// consistent with the operator's totals.
+    ///
+    /// This is intended for audits and testing, so it is only available in debug builds.
+    #[cfg(debug_assertions)]
+    pub fn nominator_position_checked(
+        operator_id: OperatorId,
+        nominator_account: T::AccountId,
+    ) -> Result<
+        sp_domains::NominatorPosition<BalanceOf<T>, DomainBlockNumberFor<T>, T::Share>,
+        PositionInvariantError,
+    > {
+        nominator_position::nominator_position_checked::<T>(operator_id, nominator_account)
+    }
+
+    /// Returns the number of nominators with a deposit for the given operator, including the
+    /// operator account.
+    ///
+    /// This iterates all the deposits of the operator, so it is intended for off-chain use.
+    pub fn nominator_count(operator_id: OperatorId) -> u32 {
+        nominator_position::nominator_count::<T>(operator_id)
+    }
+
+    /// Returns the IDs of the operators the given account has a deposit with, in ascending order.
+    /// At most `MAX_OPERATORS_FOR_ACCOUNT` operators are returned.
+    ///
+    /// This checks the deposits of every operator ID, so it is intended for off-chain use.
+    pub fn operators_for_account(account: &T::AccountId) -> Vec<OperatorId> {
+        nominator_position::operators_for_account::<T>(account)
+    }
+
+    /// Returns the maximum stake a given account can withdraw from the operator at the current
+    /// block, without failing the minimum stake checks.
+    ///
+    /// Returns None if no position exists for the given operator and account at the current block,
+    /// or the operator is not registered.
+    pub fn max_withdrawable_stake(
+        operator_id: OperatorId,
+        nominator_account: T::AccountId,
+    ) -> Option<BalanceOf<T>> {
+        nominator_position::max_withdrawable_stake::<T>(operator_id, nominator_account)
+    }
+
+    /// Returns the maximum shares a given account can withdraw from the operator at the current
+    /// block, without failing the minimum stake checks.
+    ///
+    /// Returns None if no position exists for the given operator and account at the current block,
+    /// or the operator is not registered.
+    pub fn max_withdrawable_shares(
+        operator_id: OperatorId,
+        nominator_account: T::AccountId,
+    ) -> Option<T::Share> {
+        nominator_position::max_withdrawable_shares::<T>(operator_id, nominator_account)
+    }
+
+    /// Returns the total rewards a given account earned from the operator, since its first
+    /// deposit.
+    ///
+    /// Returns None if no position exists for the given operator and account at the current block,
+    /// or the position's first deposit was made before stake history was tracked.
+    pub fn nominator_lifetime_rewards(
+        operator_id: OperatorId,
+        nominator_account: T::AccountId,
+    ) -> Option<BalanceOf<T>> {
+        nominator_position::nominator_lifetime_rewards::<T>(operator_id, nominator_account)
+    }
+
+    /// Returns the weighted average share price a given account paid for its shares, across all
+    /// its converted deposits to the operator.
+    ///
+    /// Returns None if no position exists for the given operator and account at the current block,
+    /// none of its deposits are converted to shares yet, or the position's first deposit was made
+    /// before stake history was tracked.
+    pub fn average_entry_price(
+        operator_id: OperatorId,
+        nominator_account: T::AccountId,
+    ) -> Option<SharePrice> {
+        nominator_position::average_entry_price::<T>(operator_id, nominator_account)
+    }
+
+    /// Returns the operator's share prices at the end of each epoch from `from` to `to`
+    /// (inclusive), with None for epochs without a recorded or retained share price.
+    ///
+    /// At most `MAX_EPOCH_SHARE_PRICES` epochs are returned, starting at `from`.
+    pub fn epoch_share_prices(
+        operator_id: OperatorId,
+        from: EpochIndex,
+        to: EpochIndex,
+    ) -> Vec<(EpochIndex, Option<SharePrice>)> {
+        nominator_position::epoch_share_prices::<T>(operator_id, from, to)
+    }
+
+    /// Returns the total rewards of the operator, by the kind of reward source.
+    pub fn operator_reward_breakdown(
+        operator_id: OperatorId,
+    ) -> BTreeMap<sp_domains::OperatorRewardSourceKind, BalanceOf<T>> {
+        nominator_position::operator_reward_breakdown::<T>(operator_id)
+    }
+
+    /// Returns the projected rewards for a given operator and account, assuming the operator earns
+    /// `operator_rewards` in gross rewards, after the operator's nomination tax is deducted.
+    ///
+    /// Returns None if no position exists for the given operator and account at the current block.
+    pub fn projected_nominator_rewards(
+        operator_id: OperatorId,
+        nominator_account: T::AccountId,
+        operator_rewards: BalanceOf<T>,
+    ) -> Option<BalanceOf<T>> {
+        nominator_position::projected_nominator_rewards::<T>(
+            operator_id,
+            nominator_account,
+            operator_rewards,
+        )
+    }
+
+    /// Returns the projected annual percentage rate for a given operator and account, assuming the
+    /// operator earns `operator_rewards_per_year` in gross rewards, after the operator's nomination
+    /// tax is deducted.
+    ///
+    /// Returns None if no position exists, or the position has no staked value yet.
+    pub fn projected_nominator_apr(
+        operator_id: OperatorId,
+        nominator_account: T::AccountId,
+        operator_rewards_per_year: BalanceOf<T>,
+    ) -> Option<sp_runtime::Perquintill> {
+        nominator_position::projected_nominator_apr::<T>(
+            operator_id,
+            nominator_account,
+            operator_rewards_per_year,
+        )
+    }
 }
 
 impl<T: Config> subspace_runtime_primitives::OnSetCode<BlockNumberFor<T>> for Pallet<T> {
@@ -1,12 +1,27 @@
 //! Nominator position calculation logic
 
-use crate::pallet::{Config, Deposits, DomainStakingSummary, Operators, Withdrawals};
-
-use crate::staking::{do_convert_previous_epoch_deposits, do_convert_previous_epoch_withdrawal};
-use crate::{BalanceOf, DomainBlockNumberFor, ReceiptHashFor};
+use crate::pallet::{
+    Config, Deposits, DomainStakingSummary, NextOperatorId, NominatorStakeHistory, OperatorIdOwner,
+    OperatorRewardsBySource, Operators, Withdrawals,
+};
+
+use crate::staking::{
+    DomainEpoch, OperatorStatus, SharePrice, do_convert_previous_epoch_deposits,
+    do_convert_previous_epoch_withdrawal,
+};
+use crate::{BalanceOf, DomainBlockNumberFor, OperatorEpochSharePrice, ReceiptHashFor};
+use alloc::collections::btree_map::BTreeMap;
 use alloc::vec::Vec;
-use sp_domains::{EpochIndex, OperatorId};
-use sp_runtime::traits::{Saturating, Zero};
+use sp_core::Get;
+use sp_domains::{DomainId, EpochIndex, OperatorId, OperatorRewardSourceKind, WithdrawalValuation};
+use sp_runtime::traits::{One, Saturating, Zero};
+use sp_runtime::{PerThing, Perquintill};
+
+/// The maximum number of epochs returned by a single `epoch_share_prices` query.
+pub const MAX_EPOCH_SHARE_PRICES: EpochIndex = 1024;
+
+/// The maximum number of operators returned by `operators_for_account`.
+pub const MAX_OPERATORS_FOR_ACCOUNT: usize = 256;
 
 /// Core data needed for nominator position calculation
 struct PositionData<T: Config> {
@@ -60,15 +75,11 @@ fn fetch_position_data<T: Config>(
     })
 }
 
-/// Processes deposit information to calculate total shares, storage fees, and pending deposit
-fn process_deposit<T: Config>(
+/// Returns the nominator's deposit with any previous-epoch pending deposit converted to shares
+fn converted_deposit<T: Config>(
     position_data: &PositionData<T>,
     operator_id: OperatorId,
-) -> (
-    T::Share,
-    BalanceOf<T>,
-    Option<sp_domains::PendingDeposit<BalanceOf<T>>>,
-) {
+) -> crate::staking::Deposit<T::Share, BalanceOf<T>> {
     // Clone deposit for read-only conversion
     let mut deposit = position_data.deposit.clone();
 
@@ -79,6 +90,20 @@ fn process_deposit<T: Config>(
         position_data.current_epoch_index,
     );
 
+    deposit
+}
+
+/// Processes deposit information to calculate total shares, storage fees, and pending deposit
+fn process_deposit<T: Config>(
+    position_data: &PositionData<T>,
+    operator_id: OperatorId,
+) -> (
+    T::Share,
+    BalanceOf<T>,
+    Option<sp_domains::PendingDeposit<BalanceOf<T>>>,
+) {
+    let deposit = converted_deposit::<T>(position_data, operator_id);
+
     // Extract results
     let total_shares = deposit.known.shares;
 
@@ -117,11 +142,31 @@ fn calculate_adjusted_storage_fee<T: Config>(
     storage_fund_redeem_price.redeem(nominator_storage_fee)
 }
 
+/// Finds the retained share price of the operator for the epoch nearest to `epoch_index`,
+/// preferring later epochs on ties
+fn nearest_epoch_share_price<T: Config>(
+    operator_id: OperatorId,
+    domain_id: DomainId,
+    epoch_index: EpochIndex,
+) -> Option<(EpochIndex, SharePrice)> {
+    OperatorEpochSharePrice::<T>::iter_prefix(operator_id)
+        .filter_map(|(domain_epoch, share_price)| {
+            let (share_price_domain_id, share_price_epoch_index) = domain_epoch.deconstruct();
+            (share_price_domain_id == domain_id).then_some((share_price_epoch_index, share_price))
+        })
+        .min_by_key(|(share_price_epoch_index, _)| {
+            (
+                share_price_epoch_index.abs_diff(epoch_index),
+                *share_price_epoch_index < epoch_index,
+            )
+        })
+}
+
 /// Processes pending withdrawals for the nominator
 fn process_withdrawals<T: Config>(
     operator_id: OperatorId,
     nominator_account: &T::AccountId,
-    current_share_price: &crate::staking::SharePrice,
+    current_share_price: &SharePrice,
     current_epoch_index: EpochIndex,
 ) -> Vec<sp_domains::PendingWithdrawal<BalanceOf<T>, DomainBlockNumberFor<T>>> {
     let Some(withdrawal) = Withdrawals::<T>::get(operator_id, nominator_account) else {
@@ -153,20 +198,42 @@ fn process_withdrawals<T: Config>(
             stake_withdrawal_amount: w.amount_to_unlock,
             storage_fee_refund: w.storage_fee_refund,
             unlock_at_block: w.unlock_at_confirmed_domain_block_number,
+            valuation: WithdrawalValuation::EpochSharePrice,
         }
     }));
 
     // Process any remaining withdrawal in shares (not yet converted)
     if let Some(withdrawal_in_shares) = withdrawal.withdrawal_in_shares {
-        // This should only happen if the withdrawal is from the current epoch
-        // and cannot be converted yet, so we use the passed current share price
-        let withdrawal_amount =
-            current_share_price.shares_to_stake::<T>(withdrawal_in_shares.shares);
+        let (domain_id, withdrawal_epoch_index) = withdrawal_in_shares.domain_epoch.deconstruct();
+
+        let (share_price, valuation) = if withdrawal_epoch_index >= current_epoch_index {
+            // The withdrawal is from the current epoch and cannot be converted yet, so we use the
+            // passed current share price
+            (
+                current_share_price.clone(),
+                WithdrawalValuation::CurrentSharePrice,
+            )
+        } else {
+            // The withdrawal epoch share price is missing, so the conversion above failed. Use
+            // the nearest retained epoch share price, which is closer to the actual withdrawal
+            // value than the current share price.
+            match nearest_epoch_share_price::<T>(operator_id, domain_id, withdrawal_epoch_index) {
+                Some((epoch_index, share_price)) => (
+                    share_price,
+                    WithdrawalValuation::NearestEpochSharePrice(epoch_index),
+                ),
+                None => (
+                    current_share_price.clone(),
+                    WithdrawalValuation::FallbackCurrentSharePrice,
+                ),
+            }
+        };
 
         pending_withdrawals.push(sp_domains::PendingWithdrawal {
-            stake_withdrawal_amount: withdrawal_amount,
+            stake_withdrawal_amount: share_price.shares_to_stake::<T>(withdrawal_in_shares.shares),
             storage_fee_refund: withdrawal_in_shares.storage_fee_refund,
             unlock_at_block: withdrawal_in_shares.unlock_at_confirmed_domain_block_number,
+            valuation,
         });
     }
 
@@ -177,9 +244,10 @@ fn process_withdrawals<T: Config>(
 ///
 /// This calculates the total position including:
 /// - Current stake value (converted from shares using instant share price including rewards)
-/// - Total storage fee deposits (known + pending)
+/// - Total storage fee deposits (known + pending), and their share of the operator's storage fund
 /// - Pending deposits (not yet converted to shares)
 /// - Pending withdrawals (with unlock timing)
+/// - The block when funds can be unlocked, if the operator is deregistered
 ///
 /// Note: Operator accounts are also nominator accounts, so this call will return the position
 /// for the operator account.
@@ -210,6 +278,16 @@ pub fn nominator_position<T: Config>(
         total_storage_fee_deposit,
     );
 
+    // Calculate the nominator's proportion of the operator's storage fund
+    let storage_fund_share = if position_data.operator.total_storage_fee_deposit.is_zero() {
+        Perquintill::zero()
+    } else {
+        Perquintill::from_rational(
+            total_storage_fee_deposit,
+            position_data.operator.total_storage_fee_deposit,
+        )
+    };
+
     // Process pending withdrawals
     let pending_withdrawals = process_withdrawals::<T>(
         operator_id,
@@ -218,18 +296,420 @@ pub fn nominator_position<T: Config>(
         position_data.current_epoch_index,
     );
 
+    // The deregistration unlock block already includes the withdrawal locking period
+    let deregistration_unlock_block = match position_data.operator.status::<T>(operator_id) {
+        OperatorStatus::Deregistered(operator_deregistered_info) => {
+            Some(operator_deregistered_info.unlock_at_confirmed_domain_block_number)
+        }
+        _ => None,
+    };
+
     Some(NominatorPosition {
         current_staked_value,
         total_shares,
         storage_fee_deposit: sp_domains::StorageFeeDeposit {
             total_deposited: total_storage_fee_deposit,
             current_value: adjusted_storage_fee_deposit,
+            storage_fund_share,
         },
         pending_deposit,
         pending_withdrawals,
+        deregistration_unlock_block,
     })
 }
 
+/// An invariant violated by a nominator position, see `nominator_position_checked`.
+#[cfg(debug_assertions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionInvariantError {
+    /// No position exists for the given operator and account at the current block
+    PositionNotFound,
+    /// The nominator's shares exceed the operator's total shares
+    SharesExceedOperatorTotal,
+    /// The nominator's storage fee deposit exceeds the operator's total storage fee deposit
+    StorageFeeDepositExceedsOperatorTotal,
+}
+
+/// Returns the nominator position, like `nominator_position`, after checking that it is
+/// consistent with the operator's totals.
+///
+/// This is intended for audits and testing, so it is only available in debug builds.
+#[cfg(debug_assertions)]
+pub fn nominator_position_checked<T: Config>(
+    operator_id: OperatorId,
+    nominator_account: T::AccountId,
+) -> Result<
+    sp_domains::NominatorPosition<BalanceOf<T>, DomainBlockNumberFor<T>, T::Share>,
+    PositionInvariantError,
+> {
+    let position_data = fetch_position_data::<T>(operator_id, &nominator_account)
+        .ok_or(PositionInvariantError::PositionNotFound)?;
+    let (total_shares, total_storage_fee_deposit, _) =
+        process_deposit::<T>(&position_data, operator_id);
+
+    // Previous epoch deposits are included in the operator's total shares when the epoch is
+    // finalized, so the converted shares can never exceed them
+    if total_shares > position_data.operator.current_total_shares {
+        return Err(PositionInvariantError::SharesExceedOperatorTotal);
+    }
+
+    // Storage fee deposits, including pending deposits, are added to the operator's total when
+    // the deposit is made
+    if total_storage_fee_deposit > position_data.operator.total_storage_fee_deposit {
+        return Err(PositionInvariantError::StorageFeeDepositExceedsOperatorTotal);
+    }
+
+    nominator_position::<T>(operator_id, nominator_account)
+        .ok_or(PositionInvariantError::PositionNotFound)
+}
+
+/// Returns the number of nominators with a deposit for the given operator.
+///
+/// Note: The operator account is also a nominator account, so it is included in the count.
+/// Nominators who fully withdrew are counted until their funds are unlocked and their deposit is
+/// cleaned up.
+///
+/// This iterates all the deposits of the operator, so it is intended for off-chain use.
+pub fn nominator_count<T: Config>(operator_id: OperatorId) -> u32 {
+    Deposits::<T>::iter_prefix(operator_id).count() as u32
+}
+
+/// Returns the IDs of the operators the given account has a deposit with, in ascending order.
+///
+/// This is cheaper than calculating the full position for each operator, so it can be used to
+/// select the operators to query. At most `MAX_OPERATORS_FOR_ACCOUNT` operators are returned.
+///
+/// This checks the deposits of every operator ID, so it is intended for off-chain use.
+pub fn operators_for_account<T: Config>(account: &T::AccountId) -> Vec<OperatorId> {
+    (0..NextOperatorId::<T>::get())
+        .filter(|operator_id| Deposits::<T>::contains_key(operator_id, account))
+        .take(MAX_OPERATORS_FOR_ACCOUNT)
+        .collect()
+}
+
+/// Calculates the stake value of the remaining shares after a withdrawal, in the same way as
+/// `do_withdraw_stake` does for its minimum stake checks
+fn remaining_stake_value<T: Config>(
+    share_price: &SharePrice,
+    remaining_shares: T::Share,
+    known_shares: T::Share,
+    known_storage_fee_deposit: BalanceOf<T>,
+) -> BalanceOf<T> {
+    let remaining_storage_fee =
+        Perquintill::from_rational(remaining_shares.into(), known_shares.into())
+            .mul_floor(known_storage_fee_deposit);
+
+    share_price
+        .shares_to_stake::<T>(remaining_shares)
+        .saturating_add(remaining_storage_fee)
+}
+
+/// Calculates the maximum shares the nominator can withdraw without failing the minimum stake
+/// checks of `do_withdraw_stake`
+///
+/// Returns None if the operator is not registered, as withdrawals are not allowed.
+fn calculate_max_withdrawable_shares<T: Config>(
+    position_data: &PositionData<T>,
+    operator_id: OperatorId,
+    nominator_account: &T::AccountId,
+) -> Option<T::Share> {
+    if *position_data.operator.status::<T>(operator_id) != OperatorStatus::Registered {
+        return None;
+    }
+
+    let deposit = converted_deposit::<T>(position_data, operator_id);
+    let known_shares = deposit.known.shares;
+    if known_shares.is_zero() {
+        return Some(Zero::zero());
+    }
+
+    let is_operator_owner =
+        OperatorIdOwner::<T>::get(operator_id).as_ref() == Some(nominator_account);
+
+    let minimum_stake = if is_operator_owner {
+        T::MinOperatorStake::get()
+    } else {
+        // A nominator can fully exit, unless the remaining pending deposit is below the minimum
+        // nominator stake
+        let can_fully_exit = deposit
+            .pending
+            .map(|pd| {
+                pd.amount.saturating_add(pd.storage_fee_deposit)
+                    >= position_data.operator.minimum_nominator_stake
+            })
+            .unwrap_or(true);
+        if can_fully_exit {
+            return Some(known_shares);
+        }
+        position_data.operator.minimum_nominator_stake
+    };
+
+    let remaining_value = |remaining_shares| {
+        remaining_stake_value::<T>(
+            &position_data.current_share_price,
+            remaining_shares,
+            known_shares,
+            deposit.known.storage_fee_deposit,
+        )
+    };
+    if remaining_value(known_shares) < minimum_stake {
+        return Some(Zero::zero());
+    }
+
+    // Search for the fewest remaining shares that still meet the minimum stake
+    let mut low = T::Share::zero();
+    let mut high = known_shares;
+    while low < high {
+        let mid = low + (high - low) / T::Share::from(2u32);
+        if remaining_value(mid) >= minimum_stake {
+            high = mid;
+        } else {
+            low = mid + T::Share::one();
+        }
+    }
+
+    Some(known_shares - high)
+}
+
+/// Returns the maximum stake a given account can withdraw from the operator at the current block.
+///
+/// Nominators can withdraw their whole stake, while the operator account must keep the minimum
+/// operator stake. If the pending deposit is too small to keep the position open, the nominator
+/// must keep the minimum nominator stake instead.
+///
+/// Returns None if no position exists for the given operator and account at the current block,
+/// or the operator is not registered.
+pub fn max_withdrawable_stake<T: Config>(
+    operator_id: OperatorId,
+    nominator_account: T::AccountId,
+) -> Option<BalanceOf<T>> {
+    let position_data = fetch_position_data::<T>(operator_id, &nominator_account)?;
+    let max_shares =
+        calculate_max_withdrawable_shares::<T>(&position_data, operator_id, &nominator_account)?;
+
+    Some(
+        position_data
+            .current_share_price
+            .shares_to_stake::<T>(max_shares),
+    )
+}
+
+/// Returns the maximum shares a given account can withdraw from the operator at the current block.
+///
+/// This is the share-denominated version of [`max_withdrawable_stake`], which can be passed to
+/// `withdraw_stake` directly, avoiding rounding errors from converting stake back to shares.
+///
+/// Returns None if no position exists for the given operator and account at the current block,
+/// or the operator is not registered.
+pub fn max_withdrawable_shares<T: Config>(
+    operator_id: OperatorId,
+    nominator_account: T::AccountId,
+) -> Option<T::Share> {
+    let position_data = fetch_position_data::<T>(operator_id, &nominator_account)?;
+
+    calculate_max_withdrawable_shares::<T>(&position_data, operator_id, &nominator_account)
+}
+
+/// Returns the total rewards a given account earned from the operator, since its first deposit.
+///
+/// This is the value of the position (staked, pending deposit and pending withdrawals) plus the
+/// stake already unlocked, minus the total stake deposited. Storage fees and their gains or losses
+/// are not included, and the operator's nomination tax counts as rewards for the operator account.
+/// Losses are reported as zero rewards.
+///
+/// Returns None if no position exists for the given operator and account at the current block,
+/// or the position has no stake history, because its first deposit was made before the history
+/// was tracked.
+pub fn nominator_lifetime_rewards<T: Config>(
+    operator_id: OperatorId,
+    nominator_account: T::AccountId,
+) -> Option<BalanceOf<T>> {
+    let stake_history = NominatorStakeHistory::<T>::get(operator_id, &nominator_account)?;
+    let position = nominator_position::<T>(operator_id, nominator_account)?;
+
+    let pending_deposit = position
+        .pending_deposit
+        .map(|pending_deposit| pending_deposit.amount)
+        .unwrap_or_default();
+    let pending_withdrawals = position.pending_withdrawals.iter().fold(
+        BalanceOf::<T>::zero(),
+        |total, pending_withdrawal| {
+            total.saturating_add(pending_withdrawal.stake_withdrawal_amount)
+        },
+    );
+
+    let total_value = position
+        .current_staked_value
+        .saturating_add(pending_deposit)
+        .saturating_add(pending_withdrawals)
+        .saturating_add(stake_history.total_unlocked);
+
+    Some(total_value.saturating_sub(stake_history.total_deposited))
+}
+
+/// Returns the weighted average share price a given account paid for its shares, across all its
+/// converted deposits to the operator.
+///
+/// Each deposit is converted to shares at the share price of its epoch, so the average is the
+/// total shares acquired over the total stake converted. Withdrawals don't change the average.
+/// Shares from the operator's nomination tax are acquired at no cost, so the average is only
+/// approximate for the operator account.
+///
+/// Returns None if no position exists for the given operator and account at the current block,
+/// none of its deposits are converted to shares yet, or the position has no stake history,
+/// because its first deposit was made before the history was tracked.
+pub fn average_entry_price<T: Config>(
+    operator_id: OperatorId,
+    nominator_account: T::AccountId,
+) -> Option<SharePrice> {
+    let position_data = fetch_position_data::<T>(operator_id, &nominator_account)?;
+    let deposit = converted_deposit::<T>(&position_data, operator_id);
+    let stake_history = NominatorStakeHistory::<T>::get(operator_id, &nominator_account)?;
+
+    let total_shares_acquired = deposit
+        .known
+        .shares
+        .saturating_add(stake_history.total_shares_withdrawn);
+    let pending_deposit = deposit
+        .pending
+        .map(|pending_deposit| pending_deposit.amount)
+        .unwrap_or_default();
+    let total_stake_converted = stake_history
+        .total_deposited
+        .saturating_sub(pending_deposit);
+
+    if total_shares_acquired.is_zero() || total_stake_converted.is_zero() {
+        return None;
+    }
+
+    Some(SharePrice(Perquintill::from_rational(
+        total_shares_acquired.into(),
+        total_stake_converted,
+    )))
+}
+
+/// Returns the operator's share prices at the end of each epoch from `from` to `to` (inclusive),
+/// in the operator's current domain.
+///
+/// Share prices are only recorded for epochs with deposits or withdrawals, and may be pruned, so
+/// missing share prices are returned as None.
+///
+/// At most `MAX_EPOCH_SHARE_PRICES` epochs are returned, starting at `from`. Later epochs can be
+/// queried by starting after the last returned epoch.
+///
+/// Returns an empty list if the operator doesn't exist or `from` is greater than `to`.
+pub fn epoch_share_prices<T: Config>(
+    operator_id: OperatorId,
+    from: EpochIndex,
+    to: EpochIndex,
+) -> Vec<(EpochIndex, Option<SharePrice>)> {
+    let Some(operator) = Operators::<T>::get(operator_id) else {
+        return Vec::new();
+    };
+    let domain_id = operator.current_domain_id;
+    let to = to.min(from.saturating_add(MAX_EPOCH_SHARE_PRICES - 1));
+
+    (from..=to)
+        .map(|epoch_index| {
+            let share_price = OperatorEpochSharePrice::<T>::get(
+                operator_id,
+                DomainEpoch::from((domain_id, epoch_index)),
+            );
+            (epoch_index, share_price)
+        })
+        .collect()
+}
+
+/// Returns the total rewards of the operator, by the kind of reward source.
+///
+/// The rewards are tracked at the operator level, before the nomination tax is deducted, and
+/// include the rewards of all the nominators of the operator.
+pub fn operator_reward_breakdown<T: Config>(
+    operator_id: OperatorId,
+) -> BTreeMap<OperatorRewardSourceKind, BalanceOf<T>> {
+    OperatorRewardsBySource::<T>::iter_prefix(operator_id).collect()
+}
+
+/// Calculates the nominator's portion of `operator_rewards`, after the operator's nomination tax
+/// is deducted
+fn calculate_projected_rewards<T: Config>(
+    operator: &crate::staking::Operator<
+        BalanceOf<T>,
+        T::Share,
+        DomainBlockNumberFor<T>,
+        ReceiptHashFor<T>,
+    >,
+    nominator_shares: T::Share,
+    operator_rewards: BalanceOf<T>,
+) -> BalanceOf<T> {
+    // The nomination tax is taken by the operator before the rewards reach the pool
+    let operator_tax = operator.nomination_tax.mul_floor(operator_rewards);
+    let nominator_rewards = operator_rewards.saturating_sub(operator_tax);
+
+    Perquintill::from_rational(
+        nominator_shares.into(),
+        operator.current_total_shares.into(),
+    )
+    .mul_floor(nominator_rewards)
+}
+
+/// Returns the projected rewards for a given operator and account, assuming the operator earns
+/// `operator_rewards` in gross rewards.
+///
+/// The operator's nomination tax is deducted from the gross rewards before they are split between
+/// the nominators. The value of already accrued shares is unaffected, as the tax is already
+/// reflected in the current share price.
+///
+/// Returns None if no position exists for the given operator and account at the current block.
+pub fn projected_nominator_rewards<T: Config>(
+    operator_id: OperatorId,
+    nominator_account: T::AccountId,
+    operator_rewards: BalanceOf<T>,
+) -> Option<BalanceOf<T>> {
+    let position_data = fetch_position_data::<T>(operator_id, &nominator_account)?;
+    let (total_shares, _, _) = process_deposit::<T>(&position_data, operator_id);
+
+    Some(calculate_projected_rewards::<T>(
+        &position_data.operator,
+        total_shares,
+        operator_rewards,
+    ))
+}
+
+/// Returns the projected annual percentage rate for a given operator and account, assuming the
+/// operator earns `operator_rewards_per_year` in gross rewards over a year.
+///
+/// The operator's nomination tax is deducted from the projected rewards, see
+/// [`projected_nominator_rewards`]. Rates above 100% are saturated.
+///
+/// Returns None if no position exists, or the position has no staked value yet.
+pub fn projected_nominator_apr<T: Config>(
+    operator_id: OperatorId,
+    nominator_account: T::AccountId,
+    operator_rewards_per_year: BalanceOf<T>,
+) -> Option<Perquintill> {
+    let position_data = fetch_position_data::<T>(operator_id, &nominator_account)?;
+    let (total_shares, _, _) = process_deposit::<T>(&position_data, operator_id);
+
+    let current_staked_value = position_data
+        .current_share_price
+        .shares_to_stake::<T>(total_shares);
+    if current_staked_value.is_zero() {
+        return None;
+    }
+
+    let projected_rewards = calculate_projected_rewards::<T>(
+        &position_data.operator,
+        total_shares,
+        operator_rewards_per_year,
+    );
+
+    Some(Perquintill::from_rational(
+        projected_rewards,
+        current_staked_value,
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -244,8 +724,8 @@ mod tests {
     use prop_test::proptest::prelude::*;
     use prop_test::proptest::test_runner::TestCaseResult;
     use sp_core::Pair;
-    use sp_domains::{DomainId, OperatorPair};
-    use std::collections::BTreeMap;
+    use sp_domains::{DomainId, OperatorPair, OperatorRewardSource};
+    use sp_runtime::Percent;
     use subspace_runtime_primitives::AI3;
 
     // Test constants for consistent values across tests
@@ -274,6 +754,7 @@ mod tests {
         pub nominator_free_balance: u128,
         pub nominator_stake: u128,
         pub min_nominator_stake: u128,
+        pub nomination_tax: Percent,
     }
 
     impl Default for TestSetup {
@@ -287,6 +768,7 @@ mod tests {
                 nominator_free_balance: DEFAULT_NOMINATOR_FREE_BALANCE,
                 nominator_stake: DEFAULT_NOMINATOR_STAKE,
                 min_nominator_stake: DEFAULT_MIN_NOMINATOR_STAKE,
+                nomination_tax: Percent::zero(),
             }
         }
     }
@@ -302,7 +784,7 @@ mod tests {
             setup.operator_stake,
             setup.min_nominator_stake,
             pair.public(),
-            Default::default(),
+            setup.nomination_tax,
             BTreeMap::from_iter(vec![(
                 setup.nominator_account,
                 (setup.nominator_free_balance, setup.nominator_stake),
@@ -737,6 +1219,50 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_nominator_position_deregistration_unlock_block() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+            advance_epoch(domain_id);
+
+            // Registered operators have no unlock block
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(position.deregistration_unlock_block, None);
+
+            let deregistered_at = 10;
+            crate::HeadDomainNumber::<Test>::set(domain_id, deregistered_at);
+            assert_ok!(crate::Pallet::<Test>::deregister_operator(
+                frame_system::RawOrigin::Signed(setup.operator_account).into(),
+                operator_id,
+            ));
+
+            let unlock_block =
+                deregistered_at + <Test as crate::Config>::StakeWithdrawalLockingPeriod::get();
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(position.deregistration_unlock_block, Some(unlock_block));
+
+            // The unlock block stays the same, so the remaining blocks count down as the
+            // confirmed domain head advances
+            let mut previous_remaining_blocks = unlock_block - deregistered_at;
+            for head_domain_number in deregistered_at + 1..=unlock_block {
+                crate::HeadDomainNumber::<Test>::set(domain_id, head_domain_number);
+                let position =
+                    nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+                let remaining_blocks = position
+                    .deregistration_unlock_block
+                    .unwrap()
+                    .saturating_sub(head_domain_number);
+                assert_eq!(remaining_blocks, previous_remaining_blocks - 1);
+                previous_remaining_blocks = remaining_blocks;
+            }
+            assert_eq!(previous_remaining_blocks, 0);
+        });
+    }
+
     #[test]
     fn test_nominator_position_storage_fee_scenarios() {
         let mut ext = new_test_ext_with_extensions();
@@ -1181,4 +1707,745 @@ mod tests {
             );
         });
     }
+
+    #[test]
+    fn test_projected_nominator_apr_with_nomination_tax() {
+        let operator_rewards_per_year = 1200 * AI3;
+        let projection = |nomination_tax| {
+            let mut ext = new_test_ext_with_extensions();
+            ext.execute_with(|| {
+                let setup = TestSetup {
+                    nomination_tax,
+                    ..TestSetup::default()
+                };
+                let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+
+                // Epoch transition to activate staking
+                advance_epoch(domain_id);
+
+                let rewards = projected_nominator_rewards::<Test>(
+                    operator_id,
+                    setup.nominator_account,
+                    operator_rewards_per_year,
+                )
+                .unwrap();
+                let apr = projected_nominator_apr::<Test>(
+                    operator_id,
+                    setup.nominator_account,
+                    operator_rewards_per_year,
+                )
+                .unwrap();
+                (rewards, apr)
+            })
+        };
+
+        let (rewards_without_tax, apr_without_tax) = projection(Percent::zero());
+        let (rewards_with_tax, apr_with_tax) = projection(Percent::from_percent(10));
+
+        // Nominator holds 400 of 1200 shares, so it gets a third of the rewards left after tax
+        let expected_rewards_without_tax = 400 * AI3;
+        let expected_rewards_with_tax = 360 * AI3;
+        for (rewards, expected_rewards) in [
+            (rewards_without_tax, expected_rewards_without_tax),
+            (rewards_with_tax, expected_rewards_with_tax),
+        ] {
+            let rewards_range =
+                (expected_rewards.saturating_sub(TOLERANCE))..=(expected_rewards + TOLERANCE);
+            assert!(
+                rewards_range.contains(&rewards),
+                "Projected rewards {rewards} should be close to expected {expected_rewards}"
+            );
+        }
+
+        // The projected APR is reduced by the nomination tax
+        assert!(apr_with_tax < apr_without_tax);
+        assert!(apr_without_tax > Perquintill::from_percent(99));
+        assert!(apr_with_tax > Perquintill::from_percent(89));
+        assert!(apr_with_tax <= Perquintill::from_percent(90));
+    }
+
+    #[test]
+    fn test_nominator_count() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let domain_id = DomainId::new(DEFAULT_DOMAIN_ID);
+            let nominators = [2, 3, 4];
+            let pair = OperatorPair::from_seed(&[0; 32]);
+            let (operator_id, _) = crate::staking::tests::register_operator(
+                domain_id,
+                DEFAULT_OPERATOR_ACCOUNT,
+                DEFAULT_OPERATOR_FREE_BALANCE,
+                DEFAULT_OPERATOR_STAKE,
+                DEFAULT_MIN_NOMINATOR_STAKE,
+                pair.public(),
+                Default::default(),
+                BTreeMap::from_iter(nominators.map(|nominator_account| {
+                    (
+                        nominator_account,
+                        (DEFAULT_NOMINATOR_FREE_BALANCE, DEFAULT_NOMINATOR_STAKE),
+                    )
+                })),
+            );
+
+            // The operator account is counted as a nominator
+            assert_eq!(nominator_count::<Test>(operator_id), 4);
+
+            // Epoch transition to activate staking
+            advance_epoch(domain_id);
+
+            // Fully withdraw one of the nominators
+            let nominator_account = nominators[0];
+            let position = nominator_position::<Test>(operator_id, nominator_account).unwrap();
+            assert_ok!(crate::Pallet::<Test>::withdraw_stake(
+                frame_system::RawOrigin::Signed(nominator_account).into(),
+                operator_id,
+                position.total_shares,
+            ));
+            advance_epoch(domain_id);
+
+            // The deposit is kept until the withdrawal is unlocked
+            assert_eq!(nominator_count::<Test>(operator_id), 4);
+
+            let head_domain_number = crate::pallet::HeadDomainNumber::<Test>::get(domain_id);
+            crate::pallet::HeadDomainNumber::<Test>::set(
+                domain_id,
+                head_domain_number + <Test as crate::Config>::StakeWithdrawalLockingPeriod::get(),
+            );
+            assert_ok!(crate::Pallet::<Test>::unlock_funds(
+                frame_system::RawOrigin::Signed(nominator_account).into(),
+                operator_id,
+            ));
+
+            assert_eq!(nominator_count::<Test>(operator_id), 3);
+            assert_eq!(
+                nominator_count::<Test>(operator_id + 1),
+                0,
+                "Unknown operator has no nominators"
+            );
+        });
+    }
+
+    #[test]
+    fn test_max_withdrawable_stake() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+
+            // Epoch transition to activate staking
+            advance_epoch(domain_id);
+
+            // Test 1: A nominator can withdraw their whole stake
+            let max_nominator_stake =
+                max_withdrawable_stake::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(
+                max_nominator_stake,
+                expected_staking_portion(setup.nominator_stake)
+            );
+            withdraw_stake(
+                setup.nominator_account,
+                operator_id,
+                domain_id,
+                max_nominator_stake,
+            );
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(position.total_shares, 0);
+            assert_eq!(
+                max_withdrawable_stake::<Test>(operator_id, setup.nominator_account),
+                Some(0)
+            );
+
+            // Test 2: The operator account must keep the minimum operator stake, including its
+            // storage fee deposit: 800 AI3 staked + 200 AI3 storage fee, with 100 AI3 minimum
+            let max_operator_stake =
+                max_withdrawable_stake::<Test>(operator_id, setup.operator_account).unwrap();
+            let expected_operator_stake = 720 * AI3;
+            let expected_range = (expected_operator_stake.saturating_sub(TOLERANCE))
+                ..=(expected_operator_stake + TOLERANCE);
+            assert!(
+                expected_range.contains(&max_operator_stake),
+                "Max withdrawable stake {max_operator_stake} should be close to expected {expected_operator_stake}"
+            );
+            withdraw_stake(
+                setup.operator_account,
+                operator_id,
+                domain_id,
+                max_operator_stake,
+            );
+
+            // Nothing more can be withdrawn by the operator account
+            let max_operator_stake =
+                max_withdrawable_stake::<Test>(operator_id, setup.operator_account).unwrap();
+            assert!(max_operator_stake <= TOLERANCE);
+
+            // Test 3: No position
+            assert_eq!(max_withdrawable_stake::<Test>(operator_id, 100), None);
+        });
+    }
+
+    #[test]
+    fn test_max_withdrawable_shares() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+
+            // Epoch transition to activate staking, then add rewards so shares and stake differ
+            advance_epoch(domain_id);
+            add_rewards(domain_id, operator_id, 100 * AI3);
+
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            let max_shares =
+                max_withdrawable_shares::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(max_shares, position.total_shares);
+
+            // Withdrawing exactly the returned shares succeeds and empties the position
+            assert_ok!(crate::Pallet::<Test>::withdraw_stake(
+                frame_system::RawOrigin::Signed(setup.nominator_account).into(),
+                operator_id,
+                max_shares,
+            ));
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(position.total_shares, 0);
+            assert_eq!(position.current_staked_value, 0);
+            assert_eq!(
+                max_withdrawable_shares::<Test>(operator_id, setup.nominator_account),
+                Some(0)
+            );
+
+            // The operator account can withdraw all the shares above the minimum operator stake
+            let max_operator_shares =
+                max_withdrawable_shares::<Test>(operator_id, setup.operator_account).unwrap();
+            assert_ok!(crate::Pallet::<Test>::withdraw_stake(
+                frame_system::RawOrigin::Signed(setup.operator_account).into(),
+                operator_id,
+                max_operator_shares,
+            ));
+            assert_eq!(
+                max_withdrawable_shares::<Test>(operator_id, setup.operator_account),
+                Some(0)
+            );
+        });
+    }
+
+    #[test]
+    fn test_operator_reward_breakdown() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+
+            // No rewards yet
+            assert!(operator_reward_breakdown::<Test>(operator_id).is_empty());
+
+            let reward = |source, rewards| {
+                crate::staking::do_reward_operators::<Test>(
+                    domain_id,
+                    source,
+                    vec![operator_id].into_iter(),
+                    rewards,
+                )
+                .unwrap();
+            };
+            reward(
+                OperatorRewardSource::Bundle { at_block_number: 1 },
+                10 * AI3,
+            );
+            reward(
+                OperatorRewardSource::Bundle { at_block_number: 2 },
+                15 * AI3,
+            );
+            reward(OperatorRewardSource::XDMProtocolFees, 5 * AI3);
+
+            // Rewards are accumulated by the kind of source, across epochs
+            advance_epoch(domain_id);
+            reward(OperatorRewardSource::XDMProtocolFees, 2 * AI3);
+
+            assert_eq!(
+                operator_reward_breakdown::<Test>(operator_id),
+                BTreeMap::from_iter([
+                    (OperatorRewardSourceKind::Bundle, 25 * AI3),
+                    (OperatorRewardSourceKind::XDMProtocolFees, 7 * AI3),
+                ])
+            );
+        });
+    }
+
+    #[test]
+    fn test_nominator_position_storage_fund_share() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let large_nominator_account = setup.nominator_account;
+            let small_nominator_account = 3;
+            let small_nominator_stake = setup.nominator_stake / 2;
+            let pair = OperatorPair::from_seed(&[0; 32]);
+            let (operator_id, _) = crate::staking::tests::register_operator(
+                setup.domain_id,
+                setup.operator_account,
+                setup.operator_free_balance,
+                setup.operator_stake,
+                setup.min_nominator_stake,
+                pair.public(),
+                Default::default(),
+                BTreeMap::from_iter([
+                    (
+                        large_nominator_account,
+                        (setup.nominator_free_balance, setup.nominator_stake),
+                    ),
+                    (
+                        small_nominator_account,
+                        (setup.nominator_free_balance, small_nominator_stake),
+                    ),
+                ]),
+            );
+
+            let large_position =
+                nominator_position::<Test>(operator_id, large_nominator_account).unwrap();
+            let small_position =
+                nominator_position::<Test>(operator_id, small_nominator_account).unwrap();
+            let operator_position =
+                nominator_position::<Test>(operator_id, setup.operator_account).unwrap();
+
+            let total_storage_fee = expected_storage_fee(setup.operator_stake)
+                + expected_storage_fee(setup.nominator_stake)
+                + expected_storage_fee(small_nominator_stake);
+            assert_eq!(
+                large_position.storage_fee_deposit.storage_fund_share,
+                Perquintill::from_rational(
+                    expected_storage_fee(setup.nominator_stake),
+                    total_storage_fee
+                )
+            );
+            assert_eq!(
+                small_position.storage_fee_deposit.storage_fund_share,
+                Perquintill::from_rational(
+                    expected_storage_fee(small_nominator_stake),
+                    total_storage_fee
+                )
+            );
+
+            // The larger nominator contributed twice as much as the smaller one
+            let large_share = large_position
+                .storage_fee_deposit
+                .storage_fund_share
+                .deconstruct();
+            let small_share = small_position
+                .storage_fee_deposit
+                .storage_fund_share
+                .deconstruct();
+            assert!(large_share.abs_diff(2 * small_share) <= 1);
+
+            // All the shares add up to the whole storage fund
+            let total_share = large_share
+                + small_share
+                + operator_position
+                    .storage_fee_deposit
+                    .storage_fund_share
+                    .deconstruct();
+            assert!(Perquintill::one().deconstruct() - total_share <= 2);
+        });
+    }
+
+    #[test]
+    fn test_nominator_position_missing_withdrawal_epoch_share_price() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+
+            // Epoch transition to activate staking
+            advance_epoch(domain_id);
+
+            // Withdraw in the current epoch, the share price is not available yet
+            let withdrawal_epoch_index =
+                crate::pallet::DomainStakingSummary::<Test>::get(domain_id)
+                    .unwrap()
+                    .current_epoch_index;
+            withdraw_stake(setup.nominator_account, operator_id, domain_id, 200 * AI3);
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(
+                position.pending_withdrawals[0].valuation,
+                WithdrawalValuation::CurrentSharePrice
+            );
+
+            // The share price changes at the end of the withdrawal epoch
+            add_rewards(domain_id, operator_id, 100 * AI3);
+            advance_epoch(domain_id);
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(
+                position.pending_withdrawals[0].valuation,
+                WithdrawalValuation::EpochSharePrice
+            );
+
+            // More rewards, so the current share price drifts away from the withdrawal epoch
+            add_rewards(domain_id, operator_id, 100 * AI3);
+
+            // Test 1: The withdrawal epoch share price is missing, so the nearest retained epoch
+            // share price is used, rather than the current share price
+            OperatorEpochSharePrice::<Test>::remove(
+                operator_id,
+                crate::staking::DomainEpoch::from((domain_id, withdrawal_epoch_index)),
+            );
+            let withdrawal_shares =
+                crate::pallet::Withdrawals::<Test>::get(operator_id, setup.nominator_account)
+                    .unwrap()
+                    .withdrawal_in_shares
+                    .unwrap()
+                    .shares;
+
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(position.pending_withdrawals.len(), 1);
+            let pending_withdrawal = &position.pending_withdrawals[0];
+            let WithdrawalValuation::NearestEpochSharePrice(nearest_epoch_index) =
+                pending_withdrawal.valuation
+            else {
+                panic!(
+                    "Expected nearest epoch share price valuation, got {:?}",
+                    pending_withdrawal.valuation
+                );
+            };
+            assert!(nearest_epoch_index < withdrawal_epoch_index);
+            let nearest_share_price = OperatorEpochSharePrice::<Test>::get(
+                operator_id,
+                crate::staking::DomainEpoch::from((domain_id, nearest_epoch_index)),
+            )
+            .unwrap();
+            assert_eq!(
+                pending_withdrawal.stake_withdrawal_amount,
+                nearest_share_price.shares_to_stake::<Test>(withdrawal_shares)
+            );
+
+            // Test 2: No epoch share price is retained, so the current share price is used
+            let _ = OperatorEpochSharePrice::<Test>::clear_prefix(operator_id, u32::MAX, None);
+            let current_share_price = crate::staking::current_share_price::<Test>(
+                operator_id,
+                &crate::pallet::Operators::<Test>::get(operator_id).unwrap(),
+                &crate::pallet::DomainStakingSummary::<Test>::get(domain_id).unwrap(),
+            )
+            .unwrap();
+
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            let pending_withdrawal = &position.pending_withdrawals[0];
+            assert_eq!(
+                pending_withdrawal.valuation,
+                WithdrawalValuation::FallbackCurrentSharePrice
+            );
+            assert_eq!(
+                pending_withdrawal.stake_withdrawal_amount,
+                current_share_price.shares_to_stake::<Test>(withdrawal_shares)
+            );
+        });
+    }
+
+    #[test]
+    fn test_nominator_lifetime_rewards() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+
+            // No rewards before the deposit is converted
+            assert_eq!(
+                nominator_lifetime_rewards::<Test>(operator_id, setup.nominator_account),
+                Some(0)
+            );
+
+            // Epoch transition to activate staking
+            advance_epoch(domain_id);
+            assert_eq!(
+                nominator_lifetime_rewards::<Test>(operator_id, setup.nominator_account),
+                Some(0)
+            );
+
+            // Nominator's proportional share: (400/1200) * 100 AI3 = 33.333... AI3
+            add_rewards(domain_id, operator_id, 100 * AI3);
+            let expected_rewards = 100 * AI3 / 3;
+            let rewards_range =
+                (expected_rewards.saturating_sub(TOLERANCE))..=(expected_rewards + TOLERANCE);
+            let lifetime_rewards =
+                nominator_lifetime_rewards::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert!(
+                rewards_range.contains(&lifetime_rewards),
+                "Lifetime rewards {lifetime_rewards} should be close to expected {expected_rewards}"
+            );
+
+            // Withdrawals don't change the lifetime rewards, whether pending or unlocked
+            withdraw_stake(setup.nominator_account, operator_id, domain_id, 200 * AI3);
+            advance_epoch(domain_id);
+            let lifetime_rewards =
+                nominator_lifetime_rewards::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert!(
+                rewards_range.contains(&lifetime_rewards),
+                "Lifetime rewards {lifetime_rewards} should be close to expected {expected_rewards}"
+            );
+
+            let head_domain_number = crate::pallet::HeadDomainNumber::<Test>::get(domain_id);
+            crate::pallet::HeadDomainNumber::<Test>::set(
+                domain_id,
+                head_domain_number + <Test as crate::Config>::StakeWithdrawalLockingPeriod::get(),
+            );
+            assert_ok!(crate::Pallet::<Test>::unlock_funds(
+                frame_system::RawOrigin::Signed(setup.nominator_account).into(),
+                operator_id,
+            ));
+            let position =
+                nominator_position::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert!(position.pending_withdrawals.is_empty());
+
+            let lifetime_rewards =
+                nominator_lifetime_rewards::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert!(
+                rewards_range.contains(&lifetime_rewards),
+                "Lifetime rewards {lifetime_rewards} should be close to expected {expected_rewards}"
+            );
+        });
+    }
+
+    #[test]
+    fn test_nominator_lifetime_rewards_without_history() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup {
+                nominator_free_balance: 1000 * AI3,
+                ..TestSetup::default()
+            };
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+            advance_epoch(domain_id);
+
+            // Deposits made before the stake history was tracked have no history
+            NominatorStakeHistory::<Test>::remove(operator_id, setup.nominator_account);
+            assert!(nominator_position::<Test>(operator_id, setup.nominator_account).is_some());
+            assert_eq!(
+                nominator_lifetime_rewards::<Test>(operator_id, setup.nominator_account),
+                None
+            );
+
+            // Later deposits don't start a partial history
+            make_additional_nomination(setup.nominator_account, operator_id, 100 * AI3);
+            assert!(
+                NominatorStakeHistory::<Test>::get(operator_id, setup.nominator_account).is_none()
+            );
+            assert_eq!(
+                nominator_lifetime_rewards::<Test>(operator_id, setup.nominator_account),
+                None
+            );
+
+            // The average entry price also needs the full history
+            advance_epoch(domain_id);
+            assert_eq!(
+                average_entry_price::<Test>(operator_id, setup.nominator_account),
+                None
+            );
+        });
+    }
+
+    #[test]
+    fn test_average_entry_price() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup {
+                nominator_free_balance: 1000 * AI3,
+                ..TestSetup::default()
+            };
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+
+            // No converted deposits yet
+            assert_eq!(
+                average_entry_price::<Test>(operator_id, setup.nominator_account),
+                None
+            );
+
+            // The first deposit is converted at the initial 1:1 share price
+            let first_epoch_index = crate::pallet::DomainStakingSummary::<Test>::get(domain_id)
+                .unwrap()
+                .current_epoch_index;
+            advance_epoch(domain_id);
+            let first_share_price = OperatorEpochSharePrice::<Test>::get(
+                operator_id,
+                crate::staking::DomainEpoch::from((domain_id, first_epoch_index)),
+            )
+            .unwrap();
+            assert_eq!(
+                average_entry_price::<Test>(operator_id, setup.nominator_account),
+                Some(first_share_price.clone())
+            );
+
+            // Rewards increase the stake value of shares, so the second deposit gets fewer shares
+            add_rewards(domain_id, operator_id, 300 * AI3);
+            let second_epoch_index = first_epoch_index + 1;
+            make_additional_nomination(setup.nominator_account, operator_id, 300 * AI3);
+            advance_epoch(domain_id);
+            let second_share_price = OperatorEpochSharePrice::<Test>::get(
+                operator_id,
+                crate::staking::DomainEpoch::from((domain_id, second_epoch_index)),
+            )
+            .unwrap();
+            assert!(second_share_price.0 < first_share_price.0);
+
+            // The average lies between the two deposit share prices
+            let average_price =
+                average_entry_price::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert!(average_price.0 < first_share_price.0);
+            assert!(average_price.0 > second_share_price.0);
+
+            // Withdrawals don't change the average entry price
+            withdraw_stake(setup.nominator_account, operator_id, domain_id, 100 * AI3);
+            let average_price_after_withdrawal =
+                average_entry_price::<Test>(operator_id, setup.nominator_account).unwrap();
+            assert_eq!(average_price_after_withdrawal, average_price);
+        });
+    }
+
+    #[test]
+    fn test_epoch_share_prices() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+            let first_epoch_index = crate::pallet::DomainStakingSummary::<Test>::get(domain_id)
+                .unwrap()
+                .current_epoch_index;
+
+            // The first epoch has a deposit, the second has no staking activity, and the third has
+            // a deposit after rewards
+            advance_epoch(domain_id);
+            advance_epoch(domain_id);
+            add_rewards(domain_id, operator_id, 100 * AI3);
+            make_additional_nomination(setup.nominator_account, operator_id, 100 * AI3);
+            advance_epoch(domain_id);
+
+            let share_prices =
+                epoch_share_prices::<Test>(operator_id, first_epoch_index, first_epoch_index + 2);
+            assert_eq!(share_prices.len(), 3);
+
+            let epoch_share_price = |epoch_index| {
+                OperatorEpochSharePrice::<Test>::get(
+                    operator_id,
+                    DomainEpoch::from((domain_id, epoch_index)),
+                )
+            };
+            assert_eq!(
+                share_prices,
+                vec![
+                    (
+                        first_epoch_index,
+                        Some(epoch_share_price(first_epoch_index).unwrap())
+                    ),
+                    (first_epoch_index + 1, None),
+                    (
+                        first_epoch_index + 2,
+                        Some(epoch_share_price(first_epoch_index + 2).unwrap())
+                    ),
+                ]
+            );
+            assert_ne!(share_prices[0].1, share_prices[2].1);
+
+            // Invalid ranges and unknown operators are empty
+            assert!(
+                epoch_share_prices::<Test>(operator_id, first_epoch_index + 1, first_epoch_index)
+                    .is_empty()
+            );
+            assert!(epoch_share_prices::<Test>(operator_id + 1, 0, 10).is_empty());
+
+            // Large ranges are truncated
+            let share_prices = epoch_share_prices::<Test>(operator_id, 0, EpochIndex::MAX);
+            assert_eq!(share_prices.len(), MAX_EPOCH_SHARE_PRICES as usize);
+            assert_eq!(
+                share_prices.last().map(|(epoch_index, _)| *epoch_index),
+                Some(MAX_EPOCH_SHARE_PRICES - 1)
+            );
+            let share_prices =
+                epoch_share_prices::<Test>(operator_id, EpochIndex::MAX, EpochIndex::MAX);
+            assert_eq!(share_prices.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_operators_for_account() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let mut operator_ids = Vec::new();
+            for (seed, operator_account) in [(0, 1), (1, 3), (2, 4)] {
+                let pair = OperatorPair::from_seed(&[seed; 32]);
+                let (operator_id, _) = crate::staking::tests::register_operator(
+                    setup.domain_id,
+                    operator_account,
+                    setup.operator_free_balance,
+                    setup.operator_stake,
+                    setup.min_nominator_stake,
+                    pair.public(),
+                    setup.nomination_tax,
+                    BTreeMap::from_iter(vec![(
+                        setup.nominator_account,
+                        (setup.nominator_free_balance, setup.nominator_stake),
+                    )]),
+                );
+                operator_ids.push(operator_id);
+            }
+
+            assert_eq!(
+                operators_for_account::<Test>(&setup.nominator_account),
+                operator_ids
+            );
+            // Operator accounts only see their own operator
+            assert_eq!(operators_for_account::<Test>(&3), vec![operator_ids[1]]);
+            assert!(operators_for_account::<Test>(&99).is_empty());
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_nominator_position_checked() {
+        let mut ext = new_test_ext_with_extensions();
+        ext.execute_with(|| {
+            let setup = TestSetup::default();
+            let (operator_id, domain_id) = setup_operator_with_nominator(setup);
+            advance_epoch(domain_id);
+
+            // A consistent state returns the same position as the unchecked call
+            assert_eq!(
+                nominator_position_checked::<Test>(operator_id, setup.nominator_account),
+                Ok(nominator_position::<Test>(operator_id, setup.nominator_account).unwrap())
+            );
+            assert_eq!(
+                nominator_position_checked::<Test>(operator_id, 99),
+                Err(PositionInvariantError::PositionNotFound)
+            );
+
+            // Operator total shares lower than the nominator's shares
+            let nominator_shares: u128 =
+                Deposits::<Test>::get(operator_id, setup.nominator_account)
+                    .unwrap()
+                    .known
+                    .shares;
+            Operators::<Test>::mutate(operator_id, |maybe_operator| {
+                maybe_operator.as_mut().unwrap().current_total_shares = nominator_shares - 1;
+            });
+            assert_eq!(
+                nominator_position_checked::<Test>(operator_id, setup.nominator_account),
+                Err(PositionInvariantError::SharesExceedOperatorTotal)
+            );
+
+            // Operator total storage fee deposit lower than the nominator's storage fee deposit
+            Operators::<Test>::mutate(operator_id, |maybe_operator| {
+                let operator = maybe_operator.as_mut().unwrap();
+                operator.current_total_shares = nominator_shares;
+                operator.total_storage_fee_deposit =
+                    expected_storage_fee(setup.nominator_stake) - 1;
+            });
+            assert_eq!(
+                nominator_position_checked::<Test>(operator_id, setup.nominator_account),
+                Err(PositionInvariantError::StorageFeeDepositExceedsOperatorTotal)
+            );
+        });
+    }
 }
@@ -7,7 +7,8 @@ use crate::block_tree::invalid_bundle_authors_for_receipt;
 use crate::bundle_storage_fund::{self, deposit_reserve_for_storage_fund};
 use crate::pallet::{
     Deposits, DomainRegistry, DomainStakingSummary, HeadDomainNumber, NextOperatorId,
-    OperatorIdOwner, Operators, PendingSlashes, PendingStakingOperationCount, Withdrawals,
+    NominatorStakeHistory, OperatorIdOwner, OperatorRewardsBySource, Operators, PendingSlashes,
+    PendingStakingOperationCount, Withdrawals,
 };
 use crate::staking_epoch::{mint_funds, mint_into_treasury};
 use crate::{
@@ -163,6 +164,18 @@ pub(crate) struct WithdrawalInShares<DomainBlockNumber, Share, Balance> {
     pub(crate) storage_fee_refund: Balance,
 }
 
+/// A nominator's total deposited, withdrawn and unlocked stake for a given operator pool.
+#[derive(TypeInfo, Debug, Encode, Decode, Copy, Clone, PartialEq, Eq, Default)]
+pub(crate) struct StakeHistory<Balance, Share> {
+    /// Total stake deposited by the nominator, excluding the storage fee deposit and the
+    /// operator's nomination tax
+    pub(crate) total_deposited: Balance,
+    /// Total shares withdrawn by the nominator
+    pub(crate) total_shares_withdrawn: Share,
+    /// Total stake unlocked by the nominator, excluding the storage fee refund
+    pub(crate) total_unlocked: Balance,
+}
+
 #[derive(TypeInfo, Debug, Encode, Decode, Clone, PartialEq, Eq)]
 pub struct OperatorDeregisteredInfo<DomainBlockNumber> {
     pub domain_epoch: DomainEpoch,
@@ -316,6 +329,35 @@ fn note_pending_staking_operation<T: Config>(domain_id: DomainId) -> Result<(),
     Ok(())
 }
 
+/// Adds a new deposit to the nominator's stake history.
+///
+/// Must be called before the deposit is added to `Deposits`. The history is started by the
+/// nominator's first deposit, so nominators whose deposits were made before the history was
+/// tracked don't have a history, and their deposits are not added to it.
+fn note_stake_deposited<T: Config>(
+    operator_id: OperatorId,
+    nominator_id: &NominatorId<T>,
+    amount: BalanceOf<T>,
+) {
+    if !Deposits::<T>::contains_key(operator_id, nominator_id) {
+        NominatorStakeHistory::<T>::insert(
+            operator_id,
+            nominator_id,
+            StakeHistory {
+                total_deposited: amount,
+                ..StakeHistory::default()
+            },
+        );
+        return;
+    }
+
+    NominatorStakeHistory::<T>::mutate(operator_id, nominator_id, |maybe_stake_history| {
+        if let Some(stake_history) = maybe_stake_history {
+            stake_history.total_deposited = stake_history.total_deposited.saturating_add(amount)
+        }
+    });
+}
+
 pub fn do_register_operator<T: Config>(
     operator_owner: T::AccountId,
     domain_id: DomainId,
@@ -361,6 +403,7 @@ pub fn do_register_operator<T: Config>(
                 .map_err(Error::BundleStorageFund)?;
 
         hold_deposit::<T>(&operator_owner, operator_id, new_deposit.staking)?;
+        note_stake_deposited::<T>(operator_id, &operator_owner, new_deposit.staking);
 
         let domain_stake_summary = maybe_domain_stake_summary
             .as_mut()
@@ -592,6 +635,7 @@ pub fn do_nominate_operator<T: Config>(
             .map_err(Error::BundleStorageFund)?;
 
         hold_deposit::<T>(&nominator_id, operator_id, new_deposit.staking)?;
+        note_stake_deposited::<T>(operator_id, &nominator_id, new_deposit.staking);
         Pallet::<T>::deposit_event(Event::OperatorNominated {
             operator_id,
             nominator_id: nominator_id.clone(),
@@ -871,6 +915,18 @@ pub fn do_withdraw_stake<T: Config>(
                 .checked_add(&shares_withdrew)
                 .ok_or(Error::ShareOverflow)?;
 
+            NominatorStakeHistory::<T>::mutate(
+                operator_id,
+                nominator_id.clone(),
+                |maybe_stake_history| {
+                    if let Some(stake_history) = maybe_stake_history {
+                        stake_history.total_shares_withdrawn = stake_history
+                            .total_shares_withdrawn
+                            .saturating_add(shares_withdrew)
+                    }
+                },
+            );
+
             deposit.known.shares = remaining_shares;
             if remaining_shares.is_zero()
                 && let Some(pending_deposit) = deposit.pending
@@ -1007,6 +1063,18 @@ pub fn do_unlock_funds<T: Config>(
             .checked_sub(&total_storage_fee_refund)
             .ok_or(Error::BalanceUnderflow)?;
 
+        NominatorStakeHistory::<T>::mutate(
+            operator_id,
+            nominator_id.clone(),
+            |maybe_stake_history| {
+                if let Some(stake_history) = maybe_stake_history {
+                    stake_history.total_unlocked = stake_history
+                        .total_unlocked
+                        .saturating_add(total_unlocked_amount)
+                }
+            },
+        );
+
         // If the amount to release is more than currently locked,
         // mint the diff and release the rest
         let (amount_to_mint, amount_to_release) = DepositOnHold::<T>::try_mutate(
@@ -1069,6 +1137,7 @@ pub fn do_unlock_funds<T: Config>(
                     && deposit.pending.is_none()
                 {
                     *maybe_deposit = None;
+                    NominatorStakeHistory::<T>::remove(operator_id, nominator_id.clone());
 
                     DepositOnHold::<T>::mutate_exists(
                         (operator_id, nominator_id),
@@ -1125,6 +1194,7 @@ pub fn do_unlock_nominator<T: Config>(
         );
         let mut deposit = Deposits::<T>::take(operator_id, nominator_id.clone())
             .ok_or(Error::UnknownNominator)?;
+        NominatorStakeHistory::<T>::remove(operator_id, nominator_id.clone());
 
         // convert any deposits from the previous epoch to shares.
         // share prices will always be present because
@@ -1291,6 +1361,12 @@ pub(crate) fn do_cleanup_operator<T: Config>(
     // remove operator epoch share prices
     let _ = OperatorEpochSharePrice::<T>::clear_prefix(operator_id, u32::MAX, None);
 
+    // remove operator rewards by source
+    let _ = OperatorRewardsBySource::<T>::clear_prefix(operator_id, u32::MAX, None);
+
+    // remove any remaining nominator stake history
+    let _ = NominatorStakeHistory::<T>::clear_prefix(operator_id, u32::MAX, None);
+
     Ok(())
 }
 
@@ -1334,6 +1410,10 @@ pub fn do_reward_operators<T: Config>(
                 .and_modify(|rewards| *rewards = rewards.saturating_add(operator_reward))
                 .or_insert(operator_reward);
 
+            OperatorRewardsBySource::<T>::mutate(operator_id, source.kind(), |total_rewards| {
+                *total_rewards = total_rewards.saturating_add(operator_reward)
+            });
+
             Pallet::<T>::deposit_event(Event::OperatorRewarded {
                 source: source.clone(),
                 operator_id,
@@ -2,7 +2,8 @@
 use crate::bundle_storage_fund::deposit_reserve_for_storage_fund;
 use crate::pallet::{
     AccumulatedTreasuryFunds, Deposits, DomainStakingSummary, LastEpochStakingDistribution,
-    OperatorIdOwner, Operators, PendingSlashes, PendingStakingOperationCount, Withdrawals,
+    NominatorStakeHistory, OperatorIdOwner, Operators, PendingSlashes,
+    PendingStakingOperationCount, Withdrawals,
 };
 use crate::staking::{
     DomainEpoch, Error as TransitionError, OperatorStatus, SharePrice, WithdrawalInShares,
@@ -466,6 +467,7 @@ pub fn do_slash_operator<T: Config>(
             // any gains will be minted to treasury account
             for (nominator_id, mut deposit) in Deposits::<T>::drain_prefix(operator_id) {
                 let locked_amount = DepositOnHold::<T>::take((operator_id, nominator_id.clone()));
+                NominatorStakeHistory::<T>::remove(operator_id, nominator_id.clone());
 
                 // convert any previous epoch deposits
                 match do_convert_previous_epoch_deposits::<T>(
@@ -147,6 +147,8 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 	/// Proof: `Domains::AccumulatedTreasuryFunds` (`max_values`: Some(1), `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::PendingSlashes` (r:1 w:1)
 	/// Proof: `Domains::PendingSlashes` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::OperatorRewardsBySource` (r:100 w:100)
+	/// Proof: `Domains::OperatorRewardsBySource` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// The range of component `n` is `[1, 100]`.
 	/// The range of component `s` is `[0, 100]`.
 	fn confirm_domain_block(n: u32, s: u32, ) -> Weight {
@@ -160,10 +162,10 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 			// Standard Error: 419_507
 			.saturating_add(Weight::from_parts(14_690_876, 0).saturating_mul(s.into()))
 			.saturating_add(T::DbWeight::get().reads(3_u64))
-			.saturating_add(T::DbWeight::get().reads((2_u64).saturating_mul(n.into())))
+			.saturating_add(T::DbWeight::get().reads((3_u64).saturating_mul(n.into())))
 			.saturating_add(T::DbWeight::get().reads((1_u64).saturating_mul(s.into())))
 			.saturating_add(T::DbWeight::get().writes(3_u64))
-			.saturating_add(T::DbWeight::get().writes((1_u64).saturating_mul(n.into())))
+			.saturating_add(T::DbWeight::get().writes((2_u64).saturating_mul(n.into())))
 			.saturating_add(T::DbWeight::get().writes((1_u64).saturating_mul(s.into())))
 			.saturating_add(Weight::from_parts(0, 2588).saturating_mul(n.into()))
 			.saturating_add(Weight::from_parts(0, 979).saturating_mul(s.into()))
@@ -209,6 +211,8 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 	/// Proof: `Domains::NominatorCount` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorSigningKey` (r:0 w:1)
 	/// Proof: `Domains::OperatorSigningKey` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:0 w:9)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// The range of component `n` is `[0, 9]`.
 	fn slash_operator(n: u32, ) -> Weight {
 		// Proof Size summary in bytes:
@@ -221,7 +225,7 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 			.saturating_add(T::DbWeight::get().reads(14_u64))
 			.saturating_add(T::DbWeight::get().reads((4_u64).saturating_mul(n.into())))
 			.saturating_add(T::DbWeight::get().writes(12_u64))
-			.saturating_add(T::DbWeight::get().writes((3_u64).saturating_mul(n.into())))
+			.saturating_add(T::DbWeight::get().writes((4_u64).saturating_mul(n.into())))
 			.saturating_add(Weight::from_parts(0, 8025).saturating_mul(n.into()))
 	}
 	/// Storage: `Domains::DomainStakingSummary` (r:1 w:1)
@@ -338,6 +342,8 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 	/// Proof: `Domains::OperatorIdOwner` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorEpochSharePrice` (r:0 w:1)
 	/// Proof: `Domains::OperatorEpochSharePrice` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:0 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn register_operator() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `651`
@@ -345,7 +351,7 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 		// Minimum execution time: 252_801_000 picoseconds.
 		Weight::from_parts(256_406_000, 9015)
 			.saturating_add(T::DbWeight::get().reads(10_u64))
-			.saturating_add(T::DbWeight::get().writes(12_u64))
+			.saturating_add(T::DbWeight::get().writes(13_u64))
 	}
 	/// Storage: `Domains::Operators` (r:1 w:1)
 	/// Proof: `Domains::Operators` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -363,14 +369,16 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 	/// Proof: `Domains::Deposits` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorEpochSharePrice` (r:1 w:0)
 	/// Proof: `Domains::OperatorEpochSharePrice` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:1 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn nominate_operator() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `1131`
 		//  Estimated: `9015`
 		// Minimum execution time: 168_898_000 picoseconds.
 		Weight::from_parts(174_307_000, 9015)
-			.saturating_add(T::DbWeight::get().reads(9_u64))
-			.saturating_add(T::DbWeight::get().writes(6_u64))
+			.saturating_add(T::DbWeight::get().reads(10_u64))
+			.saturating_add(T::DbWeight::get().writes(7_u64))
 	}
 	/// Storage: `Domains::OperatorIdOwner` (r:1 w:0)
 	/// Proof: `Domains::OperatorIdOwner` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -413,14 +421,16 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 	/// Proof: `Balances::Holds` (`max_values`: None, `max_size`: Some(5550), added: 8025, mode: `MaxEncodedLen`)
 	/// Storage: `Domains::LatestConfirmedDomainExecutionReceipt` (r:1 w:0)
 	/// Proof: `Domains::LatestConfirmedDomainExecutionReceipt` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:1 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn withdraw_stake() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `1674`
 		//  Estimated: `9015`
 		// Minimum execution time: 152_234_000 picoseconds.
 		Weight::from_parts(156_823_000, 9015)
-			.saturating_add(T::DbWeight::get().reads(12_u64))
-			.saturating_add(T::DbWeight::get().writes(7_u64))
+			.saturating_add(T::DbWeight::get().reads(13_u64))
+			.saturating_add(T::DbWeight::get().writes(8_u64))
 	}
 	/// Storage: `Domains::Operators` (r:1 w:0)
 	/// Proof: `Domains::Operators` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -440,6 +450,8 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 	/// Proof: `Balances::Holds` (`max_values`: None, `max_size`: Some(134), added: 2609, mode: `MaxEncodedLen`)
 	/// Storage: `Domains::Deposits` (r:1 w:1)
 	/// Proof: `Domains::Deposits` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:1 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// The range of component `w` is `[1, 32]`.
 	fn unlock_funds(w: u32, ) -> Weight {
 		// Proof Size summary in bytes:
@@ -450,8 +462,8 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 			.saturating_add(Weight::from_parts(0, 4622))
 			// Standard Error: 373_480
 			.saturating_add(Weight::from_parts(1_180_519, 0).saturating_mul(w.into()))
-			.saturating_add(T::DbWeight::get().reads(9))
-			.saturating_add(T::DbWeight::get().writes(5))
+			.saturating_add(T::DbWeight::get().reads(10))
+			.saturating_add(T::DbWeight::get().writes(6))
 			.saturating_add(Weight::from_parts(0, 36).saturating_mul(w.into()))
 	}
 	/// Storage: `Domains::Operators` (r:1 w:1)
@@ -478,6 +490,8 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 	/// Proof: `Domains::AccumulatedTreasuryFunds` (`max_values`: Some(1), `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorSigningKey` (r:0 w:1)
 	/// Proof: `Domains::OperatorSigningKey` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:0 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn unlock_nominator() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `1491`
@@ -485,7 +499,7 @@ impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
 		// Minimum execution time: 262_655_000 picoseconds.
 		Weight::from_parts(269_561_000, 9015)
 			.saturating_add(T::DbWeight::get().reads(13_u64))
-			.saturating_add(T::DbWeight::get().writes(9_u64))
+			.saturating_add(T::DbWeight::get().writes(10_u64))
 	}
 	/// Storage: `Domains::DomainRegistry` (r:1 w:1)
 	/// Proof: `Domains::DomainRegistry` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -746,6 +760,8 @@ impl WeightInfo for () {
 	/// Proof: `Domains::AccumulatedTreasuryFunds` (`max_values`: Some(1), `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::PendingSlashes` (r:1 w:1)
 	/// Proof: `Domains::PendingSlashes` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::OperatorRewardsBySource` (r:100 w:100)
+	/// Proof: `Domains::OperatorRewardsBySource` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// The range of component `n` is `[1, 100]`.
 	/// The range of component `s` is `[0, 100]`.
 	fn confirm_domain_block(n: u32, s: u32, ) -> Weight {
@@ -759,10 +775,10 @@ impl WeightInfo for () {
 			// Standard Error: 419_507
 			.saturating_add(Weight::from_parts(14_690_876, 0).saturating_mul(s.into()))
 			.saturating_add(ParityDbWeight::get().reads(3_u64))
-			.saturating_add(ParityDbWeight::get().reads((2_u64).saturating_mul(n.into())))
+			.saturating_add(ParityDbWeight::get().reads((3_u64).saturating_mul(n.into())))
 			.saturating_add(ParityDbWeight::get().reads((1_u64).saturating_mul(s.into())))
 			.saturating_add(ParityDbWeight::get().writes(3_u64))
-			.saturating_add(ParityDbWeight::get().writes((1_u64).saturating_mul(n.into())))
+			.saturating_add(ParityDbWeight::get().writes((2_u64).saturating_mul(n.into())))
 			.saturating_add(ParityDbWeight::get().writes((1_u64).saturating_mul(s.into())))
 			.saturating_add(Weight::from_parts(0, 2588).saturating_mul(n.into()))
 			.saturating_add(Weight::from_parts(0, 979).saturating_mul(s.into()))
@@ -808,6 +824,8 @@ impl WeightInfo for () {
 	/// Proof: `Domains::NominatorCount` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorSigningKey` (r:0 w:1)
 	/// Proof: `Domains::OperatorSigningKey` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:0 w:9)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// The range of component `n` is `[0, 9]`.
 	fn slash_operator(n: u32, ) -> Weight {
 		// Proof Size summary in bytes:
@@ -820,7 +838,7 @@ impl WeightInfo for () {
 			.saturating_add(ParityDbWeight::get().reads(14_u64))
 			.saturating_add(ParityDbWeight::get().reads((4_u64).saturating_mul(n.into())))
 			.saturating_add(ParityDbWeight::get().writes(12_u64))
-			.saturating_add(ParityDbWeight::get().writes((3_u64).saturating_mul(n.into())))
+			.saturating_add(ParityDbWeight::get().writes((4_u64).saturating_mul(n.into())))
 			.saturating_add(Weight::from_parts(0, 8025).saturating_mul(n.into()))
 	}
 	/// Storage: `Domains::DomainStakingSummary` (r:1 w:1)
@@ -937,6 +955,8 @@ impl WeightInfo for () {
 	/// Proof: `Domains::OperatorIdOwner` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorEpochSharePrice` (r:0 w:1)
 	/// Proof: `Domains::OperatorEpochSharePrice` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:0 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn register_operator() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `651`
@@ -944,7 +964,7 @@ impl WeightInfo for () {
 		// Minimum execution time: 252_801_000 picoseconds.
 		Weight::from_parts(256_406_000, 9015)
 			.saturating_add(ParityDbWeight::get().reads(10_u64))
-			.saturating_add(ParityDbWeight::get().writes(12_u64))
+			.saturating_add(ParityDbWeight::get().writes(13_u64))
 	}
 	/// Storage: `Domains::Operators` (r:1 w:1)
 	/// Proof: `Domains::Operators` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -962,14 +982,16 @@ impl WeightInfo for () {
 	/// Proof: `Domains::Deposits` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorEpochSharePrice` (r:1 w:0)
 	/// Proof: `Domains::OperatorEpochSharePrice` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:1 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn nominate_operator() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `1131`
 		//  Estimated: `9015`
 		// Minimum execution time: 168_898_000 picoseconds.
 		Weight::from_parts(174_307_000, 9015)
-			.saturating_add(ParityDbWeight::get().reads(9_u64))
-			.saturating_add(ParityDbWeight::get().writes(6_u64))
+			.saturating_add(ParityDbWeight::get().reads(10_u64))
+			.saturating_add(ParityDbWeight::get().writes(7_u64))
 	}
 	/// Storage: `Domains::OperatorIdOwner` (r:1 w:0)
 	/// Proof: `Domains::OperatorIdOwner` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -1012,14 +1034,16 @@ impl WeightInfo for () {
 	/// Proof: `Balances::Holds` (`max_values`: None, `max_size`: Some(5550), added: 8025, mode: `MaxEncodedLen`)
 	/// Storage: `Domains::LatestConfirmedDomainExecutionReceipt` (r:1 w:0)
 	/// Proof: `Domains::LatestConfirmedDomainExecutionReceipt` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:1 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn withdraw_stake() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `1674`
 		//  Estimated: `9015`
 		// Minimum execution time: 152_234_000 picoseconds.
 		Weight::from_parts(156_823_000, 9015)
-			.saturating_add(ParityDbWeight::get().reads(12_u64))
-			.saturating_add(ParityDbWeight::get().writes(7_u64))
+			.saturating_add(ParityDbWeight::get().reads(13_u64))
+			.saturating_add(ParityDbWeight::get().writes(8_u64))
 	}
 	/// Storage: `Domains::Operators` (r:1 w:0)
 	/// Proof: `Domains::Operators` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -1039,6 +1063,8 @@ impl WeightInfo for () {
 	/// Proof: `Balances::Holds` (`max_values`: None, `max_size`: Some(134), added: 2609, mode: `MaxEncodedLen`)
 	/// Storage: `Domains::Deposits` (r:1 w:1)
 	/// Proof: `Domains::Deposits` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:1 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// The range of component `w` is `[1, 32]`.
 	fn unlock_funds(w: u32, ) -> Weight {
 		// Proof Size summary in bytes:
@@ -1049,8 +1075,8 @@ impl WeightInfo for () {
 			.saturating_add(Weight::from_parts(0, 4622))
 			// Standard Error: 373_480
 			.saturating_add(Weight::from_parts(1_180_519, 0).saturating_mul(w.into()))
-			.saturating_add(ParityDbWeight::get().reads(9))
-			.saturating_add(ParityDbWeight::get().writes(5))
+			.saturating_add(ParityDbWeight::get().reads(10))
+			.saturating_add(ParityDbWeight::get().writes(6))
 			.saturating_add(Weight::from_parts(0, 36).saturating_mul(w.into()))
 	}
 	/// Storage: `Domains::Operators` (r:1 w:1)
@@ -1077,6 +1103,8 @@ impl WeightInfo for () {
 	/// Proof: `Domains::AccumulatedTreasuryFunds` (`max_values`: Some(1), `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorSigningKey` (r:0 w:1)
 	/// Proof: `Domains::OperatorSigningKey` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:0 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn unlock_nominator() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `1491`
@@ -1084,7 +1112,7 @@ impl WeightInfo for () {
 		// Minimum execution time: 262_655_000 picoseconds.
 		Weight::from_parts(269_561_000, 9015)
 			.saturating_add(ParityDbWeight::get().reads(13_u64))
-			.saturating_add(ParityDbWeight::get().writes(9_u64))
+			.saturating_add(ParityDbWeight::get().writes(10_u64))
 	}
 	/// Storage: `Domains::DomainRegistry` (r:1 w:1)
 	/// Proof: `Domains::DomainRegistry` (`max_values`: None, `max_size`: None, mode: `Measured`)
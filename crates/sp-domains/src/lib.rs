@@ -45,7 +45,7 @@ use sp_core::sr25519::vrf::VrfSignature;
 use sp_core::sr25519::vrf::{VrfPreOutput, VrfProof};
 use sp_runtime::generic::OpaqueDigestItemId;
 use sp_runtime::traits::{CheckedAdd, Hash as HashT, Header as HeaderT, NumberFor};
-use sp_runtime::{Digest, DigestItem, Percent};
+use sp_runtime::{Digest, DigestItem, Percent, Perquintill};
 use sp_runtime_interface::pass_by;
 use sp_runtime_interface::pass_by::PassBy;
 use sp_std::collections::btree_map::BTreeMap;
@@ -976,6 +976,27 @@ pub enum OperatorRewardSource<Number> {
     Dummy,
 }
 
+impl<Number> OperatorRewardSource<Number> {
+    /// Returns the kind of the reward source.
+    pub fn kind(&self) -> OperatorRewardSourceKind {
+        match self {
+            OperatorRewardSource::Bundle { .. } => OperatorRewardSourceKind::Bundle,
+            OperatorRewardSource::XDMProtocolFees => OperatorRewardSourceKind::XDMProtocolFees,
+            #[cfg(any(feature = "std", feature = "runtime-benchmarks"))]
+            OperatorRewardSource::Dummy => OperatorRewardSourceKind::Dummy,
+        }
+    }
+}
+
+/// The kind of an operator reward source, without the source details.
+#[derive(Debug, Decode, Encode, TypeInfo, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum OperatorRewardSourceKind {
+    Bundle,
+    XDMProtocolFees,
+    #[cfg(any(feature = "std", feature = "runtime-benchmarks"))]
+    Dummy,
+}
+
 /// Bundle and Execution Versions.
 #[derive(Debug, Decode, Encode, TypeInfo, PartialEq, Eq, Clone, Copy)]
 pub struct BundleAndExecutionReceiptVersion {
@@ -990,6 +1011,19 @@ pub struct StorageFeeDeposit<Balance> {
     pub total_deposited: Balance,
     /// Current value adjusted for fund performance (gains/losses)
     pub current_value: Balance,
+    /// Proportion of the operator's total storage fee deposit contributed by the nominator.
+    ///
+    /// Only present in `DomainsApi` version 7 and later.
+    pub storage_fund_share: Perquintill,
+}
+
+/// A nominator's storage fee deposit information, as returned by `DomainsApi` versions before 7.
+#[derive(Debug, Encode, Decode, TypeInfo, Clone, PartialEq, Eq)]
+pub struct StorageFeeDepositV6<Balance> {
+    /// Original amount contributed to storage fees
+    pub total_deposited: Balance,
+    /// Current value adjusted for fund performance (gains/losses)
+    pub current_value: Balance,
 }
 
 /// Represents a nominator's pending deposit that hasn't been converted to shares yet
@@ -1010,6 +1044,36 @@ pub struct PendingWithdrawal<Balance, DomainBlockNumber> {
     pub storage_fee_refund: Balance,
     /// The domain block number when this withdrawal can be unlocked
     pub unlock_at_block: DomainBlockNumber,
+    /// How the stake withdrawal amount was valued.
+    ///
+    /// Only present in `DomainsApi` version 7 and later.
+    pub valuation: WithdrawalValuation,
+}
+
+/// A nominator's pending withdrawal, as returned by `DomainsApi` versions before 7.
+#[derive(Debug, Encode, Decode, TypeInfo, Clone, PartialEq, Eq)]
+pub struct PendingWithdrawalV6<Balance, DomainBlockNumber> {
+    /// The amount of stake that will be withdrawn
+    pub stake_withdrawal_amount: Balance,
+    /// The amount of storage fee deposit that will be refunded
+    pub storage_fee_refund: Balance,
+    /// The domain block number when this withdrawal can be unlocked
+    pub unlock_at_block: DomainBlockNumber,
+}
+
+/// Represents how the stake amount of a nominator's pending withdrawal was valued
+#[derive(Debug, Encode, Decode, TypeInfo, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalValuation {
+    /// Converted using the share price at the end of the withdrawal epoch
+    EpochSharePrice,
+    /// The withdrawal epoch has not ended yet, so the current share price is used as an estimate
+    CurrentSharePrice,
+    /// The withdrawal epoch share price is missing, so the share price at the end of the nearest
+    /// retained epoch is used as an estimate
+    NearestEpochSharePrice(EpochIndex),
+    /// The withdrawal epoch share price is missing and no other epoch share price is retained, so
+    /// the current share price is used as an estimate
+    FallbackCurrentSharePrice,
 }
 
 /// Complete nominator position information for a specific operator
@@ -1025,12 +1089,32 @@ pub struct NominatorPosition<Balance, DomainBlockNumber, Share> {
     pub pending_deposit: Option<PendingDeposit<Balance>>,
     /// Pending withdrawals with unlock timing
     pub pending_withdrawals: Vec<PendingWithdrawal<Balance, DomainBlockNumber>>,
+    /// The domain block number when the nominator can unlock their funds, if the operator is
+    /// deregistered.
+    ///
+    /// Only present in `DomainsApi` version 7 and later.
+    pub deregistration_unlock_block: Option<DomainBlockNumber>,
+}
+
+/// A nominator's position for a specific operator, as returned by `DomainsApi` versions before 7.
+#[derive(Debug, Encode, Decode, TypeInfo, Clone, PartialEq, Eq)]
+pub struct NominatorPositionV6<Balance, DomainBlockNumber, Share> {
+    /// Current value of the nominator's position (shares converted to balance using current share price)
+    pub current_staked_value: Balance,
+    /// Total shares owned by nominator
+    pub total_shares: Share,
+    /// Storage fee deposit information (original and current adjusted values)
+    pub storage_fee_deposit: StorageFeeDepositV6<Balance>,
+    /// Pending deposit not yet converted to shares
+    pub pending_deposit: Option<PendingDeposit<Balance>>,
+    /// Pending withdrawals with unlock timing
+    pub pending_withdrawals: Vec<PendingWithdrawalV6<Balance, DomainBlockNumber>>,
 }
 
 sp_api::decl_runtime_apis! {
     /// APIs used to access the domains pallet.
     // When updating this version, document new APIs with "Only present in API versions" comments.
-    #[api_version(6)]
+    #[api_version(7)]
     pub trait DomainsApi<DomainHeader: HeaderT> {
         /// Submits the transaction bundle via an unsigned extrinsic.
         fn submit_bundle_unsigned(opaque_bundle: OpaqueBundle<NumberFor<Block>, Block::Hash, DomainHeader, Balance>);
@@ -1123,6 +1207,22 @@ sp_api::decl_runtime_apis! {
         /// Returns genesis execution receipt for domains.
         fn genesis_execution_receipt(domain_id: DomainId) -> Option<ExecutionReceiptFor<DomainHeader, Block, Balance>>;
 
+        /// Returns the complete nominator position for a given operator and account.
+        ///
+        /// This calculates the total position including:
+        /// - Current stake value (converted from shares using current share price)
+        /// - Total storage fee deposits (known + pending)
+        /// - Pending deposits (not yet converted to shares)
+        /// - Pending withdrawals (with unlock timing)
+        ///
+        /// Before API version 7, the position didn't include the storage fund share, the
+        /// valuation of pending withdrawals, or the deregistration unlock block.
+        #[changed_in(7)]
+        fn nominator_position(
+            operator_id: OperatorId,
+            nominator_account: sp_runtime::AccountId32,
+        ) -> Option<NominatorPositionV6<Balance, HeaderNumberFor<DomainHeader>, Balance>>;
+
         /// Returns the complete nominator position for a given operator and account.
         ///
         /// This calculates the total position including:
@@ -19,6 +19,7 @@ use bytes::{Bytes, BytesMut};
 use core::array::TryFromSliceError;
 use core::hash::{Hash, Hasher};
 use core::iter::Step;
+use core::ops::Range;
 use core::{fmt, mem, slice};
 use derive_more::{
     Add, AddAssign, AsMut, AsRef, Deref, DerefMut, Display, Div, DivAssign, From, Into, Mul,
@@ -106,6 +107,17 @@ impl PieceIndex {
         Self(n)
     }
 
+    /// Returns `count` consecutive piece indexes, starting at `start`.
+    ///
+    /// The range continues across segment boundaries, so it can be passed directly to piece
+    /// getters. Returns `None` if the end of the range overflows.
+    #[inline]
+    pub fn range(start: PieceIndex, count: u64) -> Option<Range<PieceIndex>> {
+        let end = start.0.checked_add(count)?;
+
+        Some(start..Self(end))
+    }
+
     /// Create piece index from bytes.
     #[inline]
     pub const fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
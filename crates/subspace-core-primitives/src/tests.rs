@@ -156,3 +156,31 @@ fn parity_piece_index_next_source_panic() {
         piece_index.next_source_index();
     }
 }
+
+#[test]
+fn piece_index_range() {
+    assert!(
+        PieceIndex::range(PieceIndex::from(254), 4)
+            .unwrap()
+            .eq([254, 255, 256, 257].map(PieceIndex::from))
+    );
+    assert_eq!(PieceIndex::range(PieceIndex::ONE, 0).unwrap().count(), 0);
+
+    // Ranges can cover a whole segment
+    let segment_index = SegmentIndex::ONE;
+    assert!(
+        PieceIndex::range(
+            segment_index.first_piece_index(),
+            ArchivedHistorySegment::NUM_PIECES as u64
+        )
+        .unwrap()
+        .eq(segment_index.segment_piece_indexes())
+    );
+
+    // Ranges which overflow are rejected
+    assert!(PieceIndex::range(PieceIndex::from(u64::MAX), 1).is_none());
+    assert_eq!(
+        PieceIndex::range(PieceIndex::from(u64::MAX), 0).map(Iterator::count),
+        Some(0)
+    );
+}
@@ -19,6 +19,9 @@ use subspace_networking::protocols::request_response::handlers::cached_piece_by_
     CachedPieceByIndexRequest, CachedPieceByIndexRequestHandler, CachedPieceByIndexResponse,
     PieceResult,
 };
+use subspace_networking::protocols::request_response::handlers::cached_pieces_by_index::{
+    CachedPiecesByIndexRequest, CachedPiecesByIndexRequestHandler, CachedPiecesByIndexResponse,
+};
 use subspace_networking::protocols::request_response::handlers::piece_by_index::{
     PieceByIndexRequest, PieceByIndexRequestHandler, PieceByIndexResponse,
 };
@@ -177,6 +180,32 @@ where
                     .in_current_span()
                 })
             },
+            {
+                let farmer_caches = farmer_caches.clone();
+
+                CachedPiecesByIndexRequestHandler::create(move |_, request| {
+                    let CachedPiecesByIndexRequest { mut piece_indices } = request;
+                    debug!(?piece_indices, "Cached pieces request received");
+
+                    let farmer_caches = farmer_caches.clone();
+
+                    async move {
+                        piece_indices.truncate(CachedPiecesByIndexRequest::RECOMMENDED_LIMIT);
+
+                        let mut pieces = Vec::with_capacity(piece_indices.len());
+                        for piece_index in piece_indices {
+                            if let Some(piece) =
+                                farmer_caches.get_piece(piece_index.to_multihash()).await
+                            {
+                                pieces.push((piece_index, piece));
+                            }
+                        }
+
+                        Some(CachedPiecesByIndexResponse { pieces })
+                    }
+                    .in_current_span()
+                })
+            },
             PieceByIndexRequestHandler::create(move |_, request| {
                 let PieceByIndexRequest {
                     piece_index,
@@ -1,14 +1,22 @@
 //! RPC API for the Subspace Gateway.
 
-use jsonrpsee::core::async_trait;
+#[cfg(test)]
+mod tests;
+
+use futures::{StreamExt, stream};
+use jsonrpsee::core::{SubscriptionResult, async_trait};
 use jsonrpsee::proc_macros::rpc;
 use jsonrpsee::types::{ErrorObject, ErrorObjectOwned};
+use jsonrpsee::{PendingSubscriptionSink, SubscriptionMessage, SubscriptionSink};
 use std::fmt;
 use std::ops::{Deref, DerefMut};
-use subspace_core_primitives::objects::GlobalObjectMapping;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use subspace_core_primitives::objects::{GlobalObject, GlobalObjectMapping};
+use subspace_core_primitives::pieces::PieceIndex;
 use subspace_data_retrieval::object_fetcher::{self, ObjectFetcher};
 use subspace_data_retrieval::piece_getter::PieceGetter;
-use tracing::{debug, error};
+use tracing::{debug, error, trace};
 
 const SUBSPACE_ERROR: i32 = 9000;
 
@@ -19,6 +27,19 @@ const SUBSPACE_ERROR: i32 = 9000;
 // TODO: turn this into a CLI option
 const MAX_OBJECTS_PER_REQUEST: usize = 100;
 
+/// The maximum number of objects fetched at the same time in a `subspace_fetchObjects` batch.
+const MAX_CONCURRENT_OBJECT_FETCHES: usize = 10;
+
+/// How often `subspace_subscribeObjectAvailable` subscriptions check if their object can be
+/// fetched.
+const OBJECT_AVAILABILITY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The maximum time a `subspace_subscribeObjectAvailable` subscription waits for its object.
+const OBJECT_AVAILABILITY_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// The maximum number of `subspace_subscribeObjectAvailable` subscriptions at the same time.
+const MAX_OBJECT_AVAILABILITY_SUBSCRIPTIONS: usize = 100;
+
 /// Top-level error type for the RPC handler.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -29,6 +50,16 @@ pub enum Error {
         count: usize,
     },
 
+    /// There are too many object availability subscriptions.
+    #[error(
+        "Object availability subscriptions exceeded limit {MAX_OBJECT_AVAILABILITY_SUBSCRIPTIONS}"
+    )]
+    TooManySubscriptions,
+
+    /// The object didn't become available before the subscription timed out.
+    #[error("Object was not available within {OBJECT_AVAILABILITY_TIMEOUT:?}")]
+    ObjectAvailabilityTimeout,
+
     /// The object fetcher failed.
     #[error(transparent)]
     ObjectFetcherError(#[from] object_fetcher::Error),
@@ -40,6 +71,54 @@ impl From<Error> for ErrorObjectOwned {
     }
 }
 
+/// An error fetching a single object in a `subspace_fetchObjects` batch.
+///
+/// The fields match the error object in a JSON-RPC error response.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ObjectError {
+    /// The error code.
+    pub code: i32,
+    /// The error message.
+    pub message: String,
+}
+
+impl From<Error> for ObjectError {
+    fn from(error: Error) -> Self {
+        Self {
+            code: SUBSPACE_ERROR + 1,
+            message: format!("{error:?}"),
+        }
+    }
+}
+
+/// The size and piece layout of an object, returned without fetching the object's data.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectMetadata {
+    /// The length of the object data, in bytes.
+    pub size: usize,
+    /// The source pieces which contain the object, in archived history order.
+    pub piece_indices: Vec<PieceIndex>,
+    /// The number of segments containing the object's pieces.
+    pub segment_count: usize,
+}
+
+impl From<object_fetcher::ObjectMetadata> for ObjectMetadata {
+    fn from(metadata: object_fetcher::ObjectMetadata) -> Self {
+        let object_fetcher::ObjectMetadata {
+            size,
+            piece_indices,
+            segment_count,
+        } = metadata;
+
+        Self {
+            size,
+            piece_indices,
+            segment_count,
+        }
+    }
+}
+
 /// Binary data, encoded as hex.
 #[derive(Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(transparent)]
@@ -94,6 +173,43 @@ pub trait SubspaceGatewayRpcApi {
     /// can't share any pieces, because a maximum-sized object only uses 6 pieces.
     #[method(name = "subspace_fetchObject")]
     async fn fetch_object(&self, mappings: GlobalObjectMapping) -> Result<Vec<HexData>, Error>;
+
+    /// Get object data for each mapping in a batch, fetching the objects concurrently.
+    /// Returns a result for each mapping, in the same order as `mappings`, so some objects can
+    /// be returned even if other object fetches were unsuccessful.
+    ///
+    /// Returns an error if there are too many mappings in the batch.
+    #[method(name = "subspace_fetchObjects")]
+    async fn fetch_objects(
+        &self,
+        mappings: Vec<GlobalObject>,
+    ) -> Result<Vec<Result<HexData, ObjectError>>, Error>;
+
+    /// Get the size and piece layout of an object, without fetching all of the object's data.
+    /// Usually only the object's first piece is downloaded.
+    ///
+    /// The object's hash isn't checked, because that needs the object's data. If the object is at
+    /// the end of a segment, `pieceIndices` can include a piece which doesn't contain any object
+    /// data.
+    #[method(name = "subspace_objectMetadata")]
+    async fn object_metadata(&self, mapping: GlobalObject) -> Result<ObjectMetadata, Error>;
+
+    /// Object availability subscription.
+    /// Sends the mapping once, when the object can be fetched from the DSN, then closes the
+    /// subscription.
+    ///
+    /// Availability is checked by fetching the object's metadata, which downloads the object's
+    /// first piece, and the first piece of the next segment if the object might continue there.
+    ///
+    /// Closes the subscription with an error if the mapping is invalid, the object can never be
+    /// fetched, or the object isn't available within an hour. Rejects the subscription if there
+    /// are too many subscriptions.
+    #[subscription(
+        name = "subspace_subscribeObjectAvailable" => "subspace_object_available",
+        unsubscribe = "subspace_unsubscribeObjectAvailable",
+        item = GlobalObject,
+    )]
+    async fn subscribe_object_available(&self, mapping: GlobalObject) -> SubscriptionResult;
 }
 
 /// Subspace Gateway RPC configuration
@@ -112,6 +228,8 @@ where
 {
     /// DSN object fetcher instance.
     object_fetcher: ObjectFetcher<PG>,
+    /// The number of active object availability subscriptions.
+    object_availability_subscriptions: AtomicUsize,
 }
 
 /// [`SubspaceGatewayRpc`] is used to fetch objects from the DSN.
@@ -123,8 +241,71 @@ where
     pub fn new(config: SubspaceGatewayRpcConfig<PG>) -> Self {
         Self {
             object_fetcher: config.object_fetcher,
+            object_availability_subscriptions: AtomicUsize::new(0),
         }
     }
+
+    /// Checks if the object in `mapping` can be fetched until it is available, then sends the
+    /// mapping to `sink`.
+    ///
+    /// Returns an error if the object can never become available.
+    async fn wait_for_object(
+        &self,
+        sink: &SubscriptionSink,
+        mapping: GlobalObject,
+    ) -> SubscriptionResult {
+        loop {
+            let error = match self.object_fetcher.fetch_object_metadata(mapping).await {
+                Ok(_metadata) => {
+                    debug!(?mapping, "Object is available");
+                    sink.send(SubscriptionMessage::from_json(&mapping)?).await?;
+                    return Ok(());
+                }
+                Err(error) => error,
+            };
+
+            // Pieces can become available later, but other errors are caused by the mapping or
+            // the object data, so they won't change
+            if !matches!(
+                error,
+                object_fetcher::Error::PieceGetterError { .. }
+                    | object_fetcher::Error::PieceNotFound { .. }
+            ) {
+                debug!(?mapping, %error, "Object can't become available");
+                return Err(Error::from(error).to_string().into());
+            }
+
+            trace!(?mapping, %error, "Object is not available yet");
+
+            tokio::time::sleep(OBJECT_AVAILABILITY_CHECK_INTERVAL).await;
+        }
+    }
+}
+
+/// An active object availability subscription, which is released when it is dropped.
+struct ObjectAvailabilitySubscription<'a> {
+    active_subscriptions: &'a AtomicUsize,
+}
+
+impl<'a> ObjectAvailabilitySubscription<'a> {
+    /// Registers a new subscription, or returns `None` if there are too many subscriptions.
+    fn try_new(active_subscriptions: &'a AtomicUsize) -> Option<Self> {
+        active_subscriptions
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |active| {
+                (active < MAX_OBJECT_AVAILABILITY_SUBSCRIPTIONS).then_some(active + 1)
+            })
+            .ok()?;
+
+        Some(Self {
+            active_subscriptions,
+        })
+    }
+}
+
+impl Drop for ObjectAvailabilitySubscription<'_> {
+    fn drop(&mut self) {
+        self.active_subscriptions.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 #[async_trait]
@@ -144,4 +325,76 @@ where
 
         Ok(objects)
     }
+
+    async fn fetch_objects(
+        &self,
+        mappings: Vec<GlobalObject>,
+    ) -> Result<Vec<Result<HexData, ObjectError>>, Error> {
+        let count = mappings.len();
+        if count > MAX_OBJECTS_PER_REQUEST {
+            debug!(%count, %MAX_OBJECTS_PER_REQUEST, "Too many mappings in request");
+            return Err(Error::TooManyMappings { count });
+        }
+
+        let objects = stream::iter(mappings)
+            .map(|mapping| async move {
+                let mut objects = self
+                    .object_fetcher
+                    .fetch_objects(GlobalObjectMapping::from_object(mapping))
+                    .await
+                    .map_err(|error| {
+                        debug!(?mapping, %error, "Object fetch in batch failed");
+                        ObjectError::from(Error::from(error))
+                    })?;
+
+                let object = objects
+                    .pop()
+                    .expect("One object is returned for each mapping; qed");
+
+                Ok(HexData::from(object))
+            })
+            .buffered(MAX_CONCURRENT_OBJECT_FETCHES)
+            .collect()
+            .await;
+
+        Ok(objects)
+    }
+
+    async fn object_metadata(&self, mapping: GlobalObject) -> Result<ObjectMetadata, Error> {
+        let metadata = self.object_fetcher.fetch_object_metadata(mapping).await?;
+
+        Ok(ObjectMetadata::from(metadata))
+    }
+
+    async fn subscribe_object_available(
+        &self,
+        pending: PendingSubscriptionSink,
+        mapping: GlobalObject,
+    ) -> SubscriptionResult {
+        let Some(_subscription) =
+            ObjectAvailabilitySubscription::try_new(&self.object_availability_subscriptions)
+        else {
+            debug!(?mapping, "Too many object availability subscriptions");
+            pending.reject(Error::TooManySubscriptions).await;
+            return Ok(());
+        };
+        let sink = pending.accept().await?;
+
+        // Stop checking if the client unsubscribes or disconnects, including during a check
+        tokio::select! {
+            () = sink.closed() => {
+                debug!(?mapping, "Object availability subscription closed");
+                Ok(())
+            }
+            result = tokio::time::timeout(
+                OBJECT_AVAILABILITY_TIMEOUT,
+                self.wait_for_object(&sink, mapping),
+            ) => {
+                result.unwrap_or_else(|_elapsed| {
+                    debug!(?mapping, "Object availability subscription timed out");
+                    Err(Error::ObjectAvailabilityTimeout.to_string().into())
+                })
+            }
+        }
+    }
 }
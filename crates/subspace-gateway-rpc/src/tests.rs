@@ -0,0 +1,308 @@
+//! Tests for the Subspace Gateway RPCs.
+
+use super::*;
+use futures::Stream;
+use parity_scale_codec::{Compact, Encode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use subspace_core_primitives::hashes::blake3_hash;
+use subspace_core_primitives::pieces::{Piece, PieceIndex, RawRecord};
+
+/// The maximum object size used in tests.
+const MAX_OBJECT_SIZE: usize = 5 * 1024 * 1024;
+
+/// A piece getter which returns a set of pieces, which can be added to.
+#[derive(Debug, Default)]
+struct MapPieceGetter(Mutex<HashMap<PieceIndex, Piece>>);
+
+impl MapPieceGetter {
+    fn new(pieces: HashMap<PieceIndex, Piece>) -> Self {
+        Self(Mutex::new(pieces))
+    }
+
+    fn insert(&self, piece_index: PieceIndex, piece: Piece) {
+        self.0.lock().unwrap().insert(piece_index, piece);
+    }
+}
+
+#[async_trait]
+impl PieceGetter for MapPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        Ok(self.0.lock().unwrap().get(&piece_index).cloned())
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        Ok(Box::new(stream::iter(piece_indices.into_iter().map(
+            |piece_index| {
+                (
+                    piece_index,
+                    Ok(self.0.lock().unwrap().get(&piece_index).cloned()),
+                )
+            },
+        ))))
+    }
+}
+
+/// Returns a piece containing `object_data` at the start of its raw record data, and the mapping
+/// for that object at `piece_index`.
+fn piece_with_object(piece_index: PieceIndex, object_data: &[u8]) -> (Piece, GlobalObject) {
+    let mut piece = Piece::default();
+    let encoded_object = Compact(object_data.len() as u32)
+        .encode()
+        .into_iter()
+        .chain(object_data.iter().copied());
+
+    piece
+        .record_mut()
+        .to_mut_raw_record_chunks()
+        .flatten()
+        .zip(encoded_object)
+        .for_each(|(raw_data_byte, object_byte)| *raw_data_byte = object_byte);
+
+    let mapping = GlobalObject {
+        hash: blake3_hash(object_data),
+        piece_index,
+        offset: 0,
+    };
+
+    (piece, mapping)
+}
+
+#[tokio::test]
+async fn fetch_objects_mixed_batch() {
+    let first_data = (0..1000).map(|byte| byte as u8).collect::<Vec<_>>();
+    let second_data = vec![7; 500];
+    let (first_piece, first_mapping) = piece_with_object(PieceIndex::from(60), &first_data);
+    let (second_piece, second_mapping) = piece_with_object(PieceIndex::from(64), &second_data);
+
+    let piece_getter = MapPieceGetter::new(HashMap::from([
+        (first_mapping.piece_index, first_piece),
+        (second_mapping.piece_index, second_piece),
+    ]));
+    let rpc = SubspaceGatewayRpc::new(SubspaceGatewayRpcConfig {
+        object_fetcher: ObjectFetcher::new(Arc::new(piece_getter), MAX_OBJECT_SIZE),
+    });
+
+    // The piece for this object is missing
+    let missing_mapping = GlobalObject {
+        piece_index: PieceIndex::from(62),
+        ..first_mapping
+    };
+    // Parity pieces can't contain objects
+    let parity_mapping = GlobalObject {
+        piece_index: PieceIndex::from(61),
+        ..first_mapping
+    };
+
+    let results = rpc
+        .fetch_objects(vec![
+            second_mapping,
+            missing_mapping,
+            first_mapping,
+            parity_mapping,
+            second_mapping,
+        ])
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0], Ok(HexData::from(second_data.clone())));
+    assert!(results[1].is_err(), "{:?}", results[1]);
+    assert_eq!(results[2], Ok(HexData::from(first_data)));
+    assert!(results[3].is_err(), "{:?}", results[3]);
+    assert_eq!(results[4], Ok(HexData::from(second_data)));
+}
+
+#[tokio::test]
+async fn object_metadata() {
+    // An object which continues into the next source piece
+    let object_data = (0..RawRecord::SIZE + 1000)
+        .map(|byte| byte as u8)
+        .collect::<Vec<_>>();
+    let mut encoded_object = Compact(object_data.len() as u32)
+        .encode()
+        .into_iter()
+        .chain(object_data.iter().copied());
+    let mut pieces = [Piece::default(), Piece::default()];
+    for piece in &mut pieces {
+        piece
+            .record_mut()
+            .to_mut_raw_record_chunks()
+            .flatten()
+            .zip(&mut encoded_object)
+            .for_each(|(raw_data_byte, object_byte)| *raw_data_byte = object_byte);
+    }
+    let [first_piece, second_piece] = pieces;
+    let mapping = GlobalObject {
+        hash: blake3_hash(&object_data),
+        piece_index: PieceIndex::from(60),
+        offset: 0,
+    };
+
+    // Only the first piece is needed for the metadata
+    let piece_getter = Arc::new(MapPieceGetter::new(HashMap::from([(
+        PieceIndex::from(60),
+        first_piece,
+    )])));
+    let rpc = SubspaceGatewayRpc::new(SubspaceGatewayRpcConfig {
+        object_fetcher: ObjectFetcher::new(Arc::clone(&piece_getter), MAX_OBJECT_SIZE),
+    });
+
+    let metadata = rpc.object_metadata(mapping).await.unwrap();
+    assert_eq!(
+        metadata,
+        ObjectMetadata {
+            size: object_data.len(),
+            piece_indices: vec![PieceIndex::from(60), PieceIndex::from(62)],
+            segment_count: 1,
+        }
+    );
+
+    // The reported size matches the fetched object
+    piece_getter.insert(PieceIndex::from(62), second_piece);
+    let objects = rpc
+        .fetch_object(GlobalObjectMapping::from_object(mapping))
+        .await
+        .unwrap();
+    assert_eq!(objects, vec![HexData::from(object_data)]);
+    assert_eq!(metadata.size, objects[0].len());
+}
+
+#[tokio::test]
+async fn fetch_objects_batch_limit() {
+    let rpc = SubspaceGatewayRpc::new(SubspaceGatewayRpcConfig {
+        object_fetcher: ObjectFetcher::new(Arc::new(MapPieceGetter::default()), MAX_OBJECT_SIZE),
+    });
+    let (_piece, mapping) = piece_with_object(PieceIndex::from(60), &[1, 2, 3]);
+
+    let result = rpc
+        .fetch_objects(vec![mapping; MAX_OBJECTS_PER_REQUEST + 1])
+        .await;
+    assert!(matches!(
+        result,
+        Err(Error::TooManyMappings { count }) if count == MAX_OBJECTS_PER_REQUEST + 1
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn subscribe_object_available() {
+    let data = vec![3; 1000];
+    let (piece, mapping) = piece_with_object(PieceIndex::from(60), &data);
+    let piece_getter = Arc::new(MapPieceGetter::default());
+    let rpc = SubspaceGatewayRpc::new(SubspaceGatewayRpcConfig {
+        object_fetcher: ObjectFetcher::new(Arc::clone(&piece_getter), MAX_OBJECT_SIZE),
+    });
+
+    let mut subscription = rpc
+        .into_rpc()
+        .subscribe_unbounded("subspace_subscribeObjectAvailable", [mapping])
+        .await
+        .unwrap();
+
+    // The object's piece isn't available yet
+    assert!(
+        tokio::time::timeout(
+            OBJECT_AVAILABILITY_CHECK_INTERVAL * 3,
+            subscription.next::<GlobalObject>()
+        )
+        .await
+        .is_err()
+    );
+
+    // The notification is sent at the next check after the piece becomes available
+    piece_getter.insert(mapping.piece_index, piece);
+    let (notified_mapping, _subscription_id) = tokio::time::timeout(
+        OBJECT_AVAILABILITY_CHECK_INTERVAL + Duration::from_secs(1),
+        subscription.next::<GlobalObject>(),
+    )
+    .await
+    .unwrap()
+    .unwrap()
+    .unwrap();
+    assert_eq!(notified_mapping, mapping);
+}
+
+#[tokio::test]
+async fn subscribe_object_available_invalid_mapping() {
+    let (piece, mapping) = piece_with_object(PieceIndex::from(60), &[1, 2, 3]);
+    let piece_getter = MapPieceGetter::new(HashMap::from([(mapping.piece_index, piece)]));
+    let rpc = SubspaceGatewayRpc::new(SubspaceGatewayRpcConfig {
+        object_fetcher: ObjectFetcher::new(Arc::new(piece_getter), MAX_OBJECT_SIZE),
+    });
+
+    // Parity pieces can't contain objects, so the subscription is closed without a notification
+    let parity_mapping = GlobalObject {
+        piece_index: PieceIndex::from(61),
+        ..mapping
+    };
+    let mut subscription = rpc
+        .into_rpc()
+        .subscribe_unbounded("subspace_subscribeObjectAvailable", [parity_mapping])
+        .await
+        .unwrap();
+    assert!(!matches!(
+        subscription.next::<GlobalObject>().await,
+        Some(Ok(_))
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn subscribe_object_available_timeout() {
+    let (_piece, mapping) = piece_with_object(PieceIndex::from(60), &[1, 2, 3]);
+    let rpc = SubspaceGatewayRpc::new(SubspaceGatewayRpcConfig {
+        object_fetcher: ObjectFetcher::new(Arc::new(MapPieceGetter::default()), MAX_OBJECT_SIZE),
+    });
+
+    // The object's piece never becomes available, so the subscription is closed without a
+    // notification
+    let mut subscription = rpc
+        .into_rpc()
+        .subscribe_unbounded("subspace_subscribeObjectAvailable", [mapping])
+        .await
+        .unwrap();
+    let result = tokio::time::timeout(
+        OBJECT_AVAILABILITY_TIMEOUT + OBJECT_AVAILABILITY_CHECK_INTERVAL,
+        subscription.next::<GlobalObject>(),
+    )
+    .await
+    .unwrap();
+    assert!(!matches!(result, Some(Ok(_))));
+}
+
+#[tokio::test(start_paused = true)]
+async fn subscribe_object_available_limit() {
+    let (_piece, mapping) = piece_with_object(PieceIndex::from(60), &[1, 2, 3]);
+    let rpc = SubspaceGatewayRpc::new(SubspaceGatewayRpcConfig {
+        object_fetcher: ObjectFetcher::new(Arc::new(MapPieceGetter::default()), MAX_OBJECT_SIZE),
+    })
+    .into_rpc();
+
+    let mut subscriptions = Vec::new();
+    for _ in 0..MAX_OBJECT_AVAILABILITY_SUBSCRIPTIONS {
+        subscriptions.push(
+            rpc.subscribe_unbounded("subspace_subscribeObjectAvailable", [mapping])
+                .await
+                .unwrap(),
+        );
+    }
+    assert!(
+        rpc.subscribe_unbounded("subspace_subscribeObjectAvailable", [mapping])
+            .await
+            .is_err()
+    );
+
+    // Closed subscriptions free up space for new subscriptions
+    drop(subscriptions.pop());
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(
+        rpc.subscribe_unbounded("subspace_subscribeObjectAvailable", [mapping])
+            .await
+            .is_ok()
+    );
+}
@@ -8,20 +8,49 @@ use crate::commands::http::HttpCommandOptions;
 use crate::commands::network::{NetworkArgs, configure_network};
 use crate::commands::rpc::RpcCommandOptions;
 use crate::node_client::RpcNodeClient;
-use crate::piece_getter::DsnPieceGetter;
-use crate::piece_validator::SegmentCommitmentPieceValidator;
+use crate::piece_getter::{
+    AdaptiveTimeout, BandwidthLimit, CacheRetryPolicy, DEFAULT_MAX_IN_FLIGHT_CACHE_FETCHES,
+    DsnPieceGetter, PieceOrder, RateLimit, RetrievalMode,
+};
+use crate::piece_validator::{CachingPieceValidator, SegmentCommitmentPieceValidator};
+use anyhow::anyhow;
 use async_lock::Semaphore;
 use clap::Parser;
+use prometheus_client::registry::Registry;
+use std::io;
+use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
 use std::sync::Arc;
+use std::time::Duration;
+use subspace_archiving::piece_reconstructor::PiecesReconstructor;
+use subspace_core_primitives::pieces::Record;
 use subspace_data_retrieval::object_fetcher::ObjectFetcher;
+use subspace_erasure_coding::ErasureCoding;
 use subspace_kzg::Kzg;
+use subspace_metrics::{RegistryAdapter, start_prometheus_metrics_server};
 use subspace_networking::NodeRunner;
 use subspace_networking::utils::piece_provider::PieceProvider;
+use subspace_process::AsyncJoinOnDrop;
+use tracing::{info, warn};
 
 /// The default size limit, based on the maximum consensus block size.
 pub const DEFAULT_MAX_SIZE: usize = 5 * 1024 * 1024;
 /// Multiplier on top of outgoing connections number for piece downloading purposes
 const PIECE_PROVIDER_MULTIPLIER: usize = 10;
+/// The default number of validated pieces to remember, which is a few segments worth of pieces.
+const DEFAULT_VALIDATED_PIECE_CACHE_SIZE: u32 = 1000;
+/// The default time to wait for DSN connections on startup, in seconds.
+const DEFAULT_DSN_WARMUP_TIMEOUT_SECS: u64 = 30;
+/// The default number of objects which can be fetched at the same time.
+const DEFAULT_MAX_CONCURRENT_OBJECTS: usize = 64;
+/// The default delay before the first retry of a piece which is missing from DSN caches, in
+/// seconds.
+const DEFAULT_DSN_CACHE_RETRY_DELAY_SECS: u64 = 1;
+
+/// The piece getter used by the gateway.
+pub(crate) type GatewayPieceGetter = DsnPieceGetter<
+    PieceProvider<CachingPieceValidator<SegmentCommitmentPieceValidator<RpcNodeClient>>>,
+>;
 
 /// Commands for working with a gateway.
 #[derive(Debug, Parser)]
@@ -45,24 +74,145 @@ pub(crate) struct GatewayOptions {
     dev: bool,
 
     /// The maximum object size to fetch.
-    /// Larger objects will return an error.
-    #[arg(long, default_value_t = DEFAULT_MAX_SIZE)]
-    max_size: usize,
+    /// Larger objects are rejected as soon as their length is known, before the rest of the
+    /// object is downloaded.
+    #[arg(long, alias = "max-size", default_value_t = DEFAULT_MAX_SIZE)]
+    max_object_size: usize,
+
+    /// Where to look for pieces.
+    /// Cache-only mode is faster, but some objects might not be found.
+    #[arg(long, value_enum, default_value_t = RetrievalMode::default())]
+    retrieval_mode: RetrievalMode,
+
+    /// The number of times to retry each piece which is missing from DSN caches, before trying
+    /// archival storage.
+    /// Zero disables retries.
+    #[arg(long, default_value_t = 0)]
+    dsn_cache_retries: u32,
+
+    /// The number of seconds to wait before the first retry of a piece which is missing from DSN
+    /// caches. The delay doubles after each retry.
+    #[arg(long, default_value_t = DEFAULT_DSN_CACHE_RETRY_DELAY_SECS)]
+    dsn_cache_retry_delay: u64,
+
+    /// The maximum number of DSN cache retries for each batch of pieces fetched for an object.
+    /// Once the budget is spent, pieces which are missing from DSN caches are not retried.
+    /// If not set, only `--dsn-cache-retries` limits retries.
+    #[arg(long)]
+    dsn_cache_retry_budget: Option<u32>,
+
+    /// The maximum number of DSN cache fetches which run at the same time, across all requests.
+    #[arg(long, default_value_t = DEFAULT_MAX_IN_FLIGHT_CACHE_FETCHES)]
+    max_in_flight_cache_fetches: NonZeroUsize,
+
+    /// If set, the pieces in each object are fetched in order, with at most this many pieces
+    /// fetched or buffered at the same time. This limits memory use, but a slow piece delays the
+    /// pieces after it.
+    /// If not set, pieces are used as soon as they are fetched.
+    #[arg(long)]
+    ordered_pieces_buffer: Option<NonZeroUsize>,
+
+    /// Reconstruct pieces which can't be found in the DSN from the other pieces in their segment.
+    /// This downloads half the pieces in the segment for each missing piece.
+    #[arg(long)]
+    reconstruct_missing_pieces: bool,
+
+    /// The maximum number of seconds spent getting each piece, including retries and archival
+    /// storage. Pieces which time out are reported as missing.
+    /// Zero disables the timeout.
+    #[arg(long, default_value_t = 0)]
+    piece_timeout: u64,
+
+    /// Base the piece timeout on the latency of recent successful piece fetches.
+    /// `--piece-timeout` is used until enough piece latencies have been recorded.
+    #[arg(long)]
+    adaptive_piece_timeout: bool,
+
+    /// The number of recently fetched pieces to keep in memory, so repeated requests for them
+    /// don't use the DSN.
+    /// Zero disables the piece cache.
+    #[arg(long, default_value_t = 0)]
+    piece_cache_size: u32,
+
+    /// The maximum sustained number of DSN piece requests per second, including retries.
+    /// If not set, DSN piece requests are not rate limited.
+    #[arg(long)]
+    dsn_requests_per_second: Option<NonZeroU32>,
+
+    /// The maximum number of DSN piece requests which can be made at once, after a quiet period.
+    /// Defaults to `--dsn-requests-per-second`.
+    #[arg(long, requires = "dsn_requests_per_second")]
+    dsn_request_burst: Option<NonZeroU32>,
+
+    /// The maximum sustained number of piece bytes downloaded from the DSN per second.
+    /// If not set, DSN piece downloads are not bandwidth limited.
+    #[arg(long)]
+    dsn_bandwidth_limit: Option<NonZeroU64>,
+
+    /// The maximum number of piece bytes which can be downloaded from the DSN at once, after a
+    /// quiet period. Defaults to `--dsn-bandwidth-limit`.
+    #[arg(long, requires = "dsn_bandwidth_limit")]
+    dsn_bandwidth_burst: Option<NonZeroU64>,
+
+    /// The number of validated pieces to remember, so re-fetched pieces skip validation.
+    /// Zero disables the validated piece cache.
+    #[arg(long, default_value_t = DEFAULT_VALIDATED_PIECE_CACHE_SIZE)]
+    validated_piece_cache_size: u32,
+
+    /// The number of seconds to wait for DSN connections on startup. Requests are served while
+    /// waiting, but they might be slow.
+    /// Zero disables waiting.
+    #[arg(long, default_value_t = DEFAULT_DSN_WARMUP_TIMEOUT_SECS)]
+    dsn_warmup_timeout: u64,
+
+    /// The maximum number of objects to fetch from the DSN at the same time, across all requests.
+    /// Other object fetches wait until one of these fetches finishes.
+    /// Zero disables the limit.
+    #[arg(long, default_value_t = DEFAULT_MAX_CONCURRENT_OBJECTS)]
+    max_concurrent_objects: usize,
+
+    /// Endpoints for the prometheus metrics server. It doesn't start without at least
+    /// one specified endpoint. Format: 127.0.0.1:8080
+    #[arg(long)]
+    prometheus_listen_on: Vec<SocketAddr>,
 
     #[clap(flatten)]
     dsn_options: NetworkArgs,
 }
 
-/// Configures and returns object fetcher and DSN node runner.
+/// Configures and returns object fetcher, DSN warmup, DSN node runner, and the prometheus metrics
+/// server worker, if it is enabled.
+///
+/// The node runner must be running before the DSN is warmed up.
 pub async fn initialize_object_fetcher(
     options: GatewayOptions,
 ) -> anyhow::Result<(
-    ObjectFetcher<DsnPieceGetter<SegmentCommitmentPieceValidator<RpcNodeClient>>>,
+    ObjectFetcher<GatewayPieceGetter>,
+    DsnWarmup,
     NodeRunner,
+    Option<AsyncJoinOnDrop<io::Result<()>>>,
 )> {
     let GatewayOptions {
         dev,
-        max_size,
+        max_object_size,
+        retrieval_mode,
+        dsn_cache_retries,
+        dsn_cache_retry_delay,
+        dsn_cache_retry_budget,
+        max_in_flight_cache_fetches,
+        ordered_pieces_buffer,
+        reconstruct_missing_pieces,
+        piece_timeout,
+        adaptive_piece_timeout,
+        piece_cache_size,
+        dsn_requests_per_second,
+        dsn_request_burst,
+        dsn_bandwidth_limit,
+        dsn_bandwidth_burst,
+        validated_piece_cache_size,
+        dsn_warmup_timeout,
+        max_concurrent_objects,
+        prometheus_listen_on,
         mut dsn_options,
     } = options;
     // Development mode handling is limited to this section
@@ -73,20 +223,132 @@ pub async fn initialize_object_fetcher(
     }
 
     let kzg = Kzg::new();
+    let mut registry = Registry::with_prefix("subspace_gateway");
+    let should_start_prometheus_server = !prometheus_listen_on.is_empty();
 
     let out_connections = dsn_options.out_connections;
     // TODO: move this service code into its own function, in a new library part of this crate
-    let (dsn_node, dsn_node_runner, node_client) = configure_network(dsn_options).await?;
+    let (dsn_node, dsn_node_runner, node_client) = configure_network(
+        dsn_options,
+        should_start_prometheus_server.then_some(&mut registry),
+    )
+    .await?;
 
     let piece_provider = PieceProvider::new(
         dsn_node.clone(),
-        SegmentCommitmentPieceValidator::new(dsn_node, node_client, kzg),
+        CachingPieceValidator::new(
+            SegmentCommitmentPieceValidator::new(dsn_node, node_client, kzg.clone()),
+            validated_piece_cache_size,
+        ),
         Arc::new(Semaphore::new(
             out_connections as usize * PIECE_PROVIDER_MULTIPLIER,
         )),
     );
-    let piece_getter = DsnPieceGetter::new(piece_provider);
-    let object_fetcher = ObjectFetcher::new(piece_getter.into(), max_size);
+    let mut piece_getter_builder = DsnPieceGetter::builder(piece_provider)
+        .retrieval_mode(retrieval_mode)
+        .cache_retry_policy(CacheRetryPolicy {
+            max_retries: dsn_cache_retries,
+            initial_delay: Duration::from_secs(dsn_cache_retry_delay),
+        })
+        .max_in_flight_cache_fetches(max_in_flight_cache_fetches);
+    if let Some(dsn_cache_retry_budget) = dsn_cache_retry_budget {
+        piece_getter_builder = piece_getter_builder.get_pieces_retry_budget(dsn_cache_retry_budget);
+    }
+    if let Some(max_buffered) = ordered_pieces_buffer {
+        piece_getter_builder = piece_getter_builder.piece_order(PieceOrder::Input { max_buffered });
+    }
+    if reconstruct_missing_pieces {
+        let erasure_coding = ErasureCoding::new(
+            NonZeroUsize::new(Record::NUM_S_BUCKETS.next_power_of_two().ilog2() as usize)
+                .expect("Not zero; qed"),
+        )
+        .map_err(|error| anyhow!("Failed to instantiate erasure coding: {error}"))?;
+        piece_getter_builder =
+            piece_getter_builder.piece_reconstructor(PiecesReconstructor::new(kzg, erasure_coding));
+    }
+    if piece_timeout > 0 {
+        piece_getter_builder =
+            piece_getter_builder.piece_timeout(Duration::from_secs(piece_timeout));
+    }
+    if adaptive_piece_timeout {
+        piece_getter_builder = piece_getter_builder.adaptive_timeout(AdaptiveTimeout::default());
+    }
+    if let Some(piece_cache_size) = NonZeroU32::new(piece_cache_size) {
+        piece_getter_builder = piece_getter_builder.piece_cache_capacity(piece_cache_size);
+    }
+    if let Some(requests_per_second) = dsn_requests_per_second {
+        piece_getter_builder = piece_getter_builder.rate_limit(RateLimit {
+            requests_per_second,
+            burst: dsn_request_burst.unwrap_or(requests_per_second),
+        });
+    }
+    if let Some(bytes_per_second) = dsn_bandwidth_limit {
+        piece_getter_builder = piece_getter_builder.bandwidth_limit(BandwidthLimit {
+            bytes_per_second,
+            burst_bytes: dsn_bandwidth_burst.unwrap_or(bytes_per_second),
+        });
+    }
+    if should_start_prometheus_server {
+        piece_getter_builder = piece_getter_builder.registry(&mut registry);
+    }
+    let piece_getter = Arc::new(piece_getter_builder.build());
+    let mut object_fetcher = ObjectFetcher::new(Arc::clone(&piece_getter), max_object_size);
+    if let Some(max_concurrent_objects) = NonZeroUsize::new(max_concurrent_objects) {
+        object_fetcher = object_fetcher.with_max_concurrent_objects(max_concurrent_objects);
+    }
+    let dsn_warmup = DsnWarmup {
+        piece_getter,
+        timeout: Duration::from_secs(dsn_warmup_timeout),
+    };
+
+    // The prometheus server is a non-essential service, so we don't exit if it stops.
+    // TODO: spawn this in a dedicated thread
+    let prometheus_worker = if should_start_prometheus_server {
+        let prometheus_task = start_prometheus_metrics_server(
+            prometheus_listen_on,
+            RegistryAdapter::PrometheusClient(registry),
+        )?;
 
-    Ok((object_fetcher, dsn_node_runner))
+        let join_handle = tokio::spawn(prometheus_task);
+        Some(AsyncJoinOnDrop::new(join_handle, true))
+    } else {
+        None
+    };
+
+    Ok((
+        object_fetcher,
+        dsn_warmup,
+        dsn_node_runner,
+        prometheus_worker,
+    ))
+}
+
+/// Waits for DSN connections on gateway startup.
+pub(crate) struct DsnWarmup {
+    piece_getter: Arc<GatewayPieceGetter>,
+    timeout: Duration,
+}
+
+impl DsnWarmup {
+    /// Returns the piece getter which is warmed up.
+    pub(crate) fn piece_getter(&self) -> Arc<GatewayPieceGetter> {
+        Arc::clone(&self.piece_getter)
+    }
+
+    /// Waits until the DSN is reachable, or the timeout elapses.
+    ///
+    /// This should run at the same time as the server, so the server can respond while the DSN is
+    /// connecting. Requests are still served if the DSN isn't reachable yet, because peers can
+    /// connect later.
+    pub(crate) async fn run(self) {
+        if self.timeout.is_zero() {
+            return;
+        }
+
+        info!(timeout = ?self.timeout, "Waiting for DSN connections...");
+        match self.piece_getter.warmup(self.timeout).await {
+            Ok(connected_peers) => info!(%connected_peers, "Connected to DSN"),
+            Err(error) => warn!(%error, "DSN warmup failed, serving requests anyway"),
+        }
+    }
 }
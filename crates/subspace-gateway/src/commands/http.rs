@@ -3,25 +3,107 @@
 
 pub(crate) mod server;
 
-use crate::commands::http::server::{ServerParameters, start_server};
+use crate::commands::http::server::auth::ApiKeys;
+use crate::commands::http::server::object_cache::ObjectCache;
+use crate::commands::http::server::rate_limit::PerIpRateLimiter;
+use crate::commands::http::server::{
+    ListenAddress, ServerParameters, load_tls_config, start_server,
+};
 use crate::commands::{GatewayOptions, initialize_object_fetcher};
 use clap::Parser;
 use futures::channel::oneshot;
 use futures::{FutureExt, select};
-use subspace_process::{run_future_in_dedicated_thread, shutdown_signal};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::Duration;
+use subspace_process::{AsyncJoinOnDrop, run_future_in_dedicated_thread, shutdown_signal};
 use tracing::info;
 
+/// The default time to wait for in-flight requests on shutdown, in seconds.
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 30;
+/// The default maximum time to spend on each object request, in seconds.
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 60;
+/// The default maximum total size of cached objects, in bytes.
+const DEFAULT_OBJECT_CACHE_SIZE: usize = 100 * 1024 * 1024;
+/// The default time objects are kept in the object cache, in seconds.
+const DEFAULT_OBJECT_CACHE_TTL_SECS: u64 = 600;
+/// The default minimum response size which is compressed, in bytes.
+const DEFAULT_COMPRESSION_MIN_SIZE: u64 = 1024;
+
 /// Options for HTTP server.
 #[derive(Debug, Parser)]
 pub(crate) struct HttpCommandOptions {
     #[clap(flatten)]
     gateway_options: GatewayOptions,
 
-    #[arg(long, default_value = "http://127.0.0.1:3000")]
-    indexer_endpoint: String,
+    /// The mapping indexer service endpoints. Can be a comma-separated list, or used multiple
+    /// times. If an indexer can't be reached, the next indexer is used.
+    #[arg(
+        long = "indexer-endpoint",
+        value_delimiter = ',',
+        default_value = "http://127.0.0.1:3000"
+    )]
+    indexer_endpoints: Vec<String>,
 
+    /// The address to listen for HTTP requests on: `host:port`, `ip:port`, `[ipv6]:port`, or
+    /// `unix:/path/to.sock` for a Unix domain socket.
     #[arg(long, default_value = "127.0.0.1:8080")]
-    http_listen_on: String,
+    http_listen_on: ListenAddress,
+
+    /// PEM-encoded TLS certificate chain. If set, the server only accepts HTTPS connections.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM-encoded TLS private key, for the certificate in `--tls-cert`.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// The maximum sustained number of requests per second from each client IP address.
+    /// If not set, client requests are not rate limited.
+    #[arg(long)]
+    per_ip_requests_per_second: Option<NonZeroU32>,
+
+    /// The maximum number of requests each client IP address can make at once, after a quiet
+    /// period. Defaults to `--per-ip-requests-per-second`.
+    #[arg(long, requires = "per_ip_requests_per_second")]
+    per_ip_request_burst: Option<NonZeroU32>,
+
+    /// An API key which can fetch objects, in an `X-Api-Key` or `Authorization: Bearer` header.
+    /// Can be used multiple times. If no API keys are set, anyone can fetch objects.
+    #[arg(long = "api-key")]
+    api_keys: Vec<String>,
+
+    /// A file containing API keys which can fetch objects, one per line.
+    #[arg(long)]
+    api_keys_file: Option<PathBuf>,
+
+    /// The number of seconds to wait for in-flight requests to finish on shutdown. New requests
+    /// are not accepted while waiting.
+    #[arg(long, default_value_t = DEFAULT_DRAIN_TIMEOUT_SECS)]
+    drain_timeout: u64,
+
+    /// The maximum number of seconds to spend on each object request. Slower requests return
+    /// `504 Gateway Timeout`, or end early if the response has already started.
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS)]
+    request_timeout: u64,
+
+    /// The maximum total size of recently fetched objects which are cached in memory, in bytes.
+    /// Zero disables the object cache.
+    #[arg(long, default_value_t = DEFAULT_OBJECT_CACHE_SIZE)]
+    object_cache_size: usize,
+
+    /// The number of seconds objects are kept in the object cache.
+    #[arg(long, default_value_t = DEFAULT_OBJECT_CACHE_TTL_SECS)]
+    object_cache_ttl: u64,
+
+    /// Never compress responses, even if the client accepts compressed responses.
+    #[arg(long)]
+    disable_compression: bool,
+
+    /// The minimum response size which is compressed, in bytes. Streamed responses containing
+    /// multiple objects are always compressed.
+    #[arg(long, default_value_t = DEFAULT_COMPRESSION_MIN_SIZE)]
+    compression_min_size: u64,
 }
 
 /// Runs an HTTP server which fetches DSN objects based on object hashes.
@@ -30,11 +112,36 @@ pub async fn run(run_options: HttpCommandOptions) -> anyhow::Result<()> {
 
     let HttpCommandOptions {
         gateway_options,
-        indexer_endpoint,
+        indexer_endpoints,
         http_listen_on,
+        tls_cert,
+        tls_key,
+        per_ip_requests_per_second,
+        per_ip_request_burst,
+        api_keys,
+        api_keys_file,
+        drain_timeout,
+        request_timeout,
+        object_cache_size,
+        object_cache_ttl,
+        disable_compression,
+        compression_min_size,
     } = run_options;
 
-    let (object_fetcher, mut dsn_node_runner) = initialize_object_fetcher(gateway_options).await?;
+    let api_keys = ApiKeys::load(api_keys, api_keys_file.as_deref())?;
+    if !api_keys.is_empty() {
+        info!(?api_keys, "Object requests require an API key");
+    }
+
+    // Load the TLS configuration before connecting to the DSN, so configuration errors are fast
+    let tls_config = match (tls_cert, tls_key) {
+        (Some(tls_cert), Some(tls_key)) => Some(load_tls_config(&tls_cert, &tls_key)?),
+        _ => None,
+    };
+
+    let (object_fetcher, dsn_warmup, mut dsn_node_runner, _prometheus_worker) =
+        initialize_object_fetcher(gateway_options).await?;
+    let piece_getter = dsn_warmup.piece_getter();
     let dsn_fut = run_future_in_dedicated_thread(
         move || async move { dsn_node_runner.run().await },
         "gateway-networking".to_string(),
@@ -43,10 +150,31 @@ pub async fn run(run_options: HttpCommandOptions) -> anyhow::Result<()> {
     // TODO: spawn this in a dedicated thread
     let server_params = ServerParameters {
         object_fetcher,
-        indexer_endpoint,
+        dsn_status: piece_getter.clone(),
+        piece_getter,
+        indexer_endpoints,
         http_endpoint: http_listen_on,
+        tls_config,
+        rate_limiter: per_ip_requests_per_second.map(|requests_per_second| {
+            PerIpRateLimiter::new(
+                requests_per_second,
+                per_ip_request_burst.unwrap_or(requests_per_second),
+            )
+        }),
+        api_keys,
+        drain_timeout: Duration::from_secs(drain_timeout),
+        request_timeout: Duration::from_secs(request_timeout),
+        object_cache: (object_cache_size > 0)
+            .then(|| ObjectCache::new(object_cache_size, Duration::from_secs(object_cache_ttl))),
+        compression_min_size: (!disable_compression).then_some(compression_min_size),
     };
-    let http_server_handle = actix_web::rt::spawn(start_server(server_params));
+    let http_server = start_server(server_params)?;
+    let http_server_stop_handle = http_server.handle();
+    let http_server_handle = actix_web::rt::spawn(http_server);
+
+    // Connect to the DSN while serving requests, so health checks work during warmup, and
+    // `/ready` fails until the DSN is connected
+    let _dsn_warmup_handle = AsyncJoinOnDrop::new(tokio::spawn(dsn_warmup.run()), true);
 
     // This defines order in which things are dropped
     let dsn_fut = dsn_fut;
@@ -55,7 +183,11 @@ pub async fn run(run_options: HttpCommandOptions) -> anyhow::Result<()> {
     select! {
         // Signal future
         // Match the return type, so we change the code if we add errors in future.
-        () = signal.fuse() => {},
+        () = signal.fuse() => {
+            // Stop accepting new requests, and let in-flight requests finish
+            info!(drain_timeout_secs = %drain_timeout, "Stopping HTTP server...");
+            http_server_stop_handle.stop(true).await;
+        },
 
         // Networking future
         Ok(()) | Err(oneshot::Canceled) = dsn_fut.fuse() => {
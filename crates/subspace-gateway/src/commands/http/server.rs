@@ -1,12 +1,122 @@
 //! HTTP server which fetches objects from the DSN based on a hash, using a mapping indexer service.
 
-use actix_web::{App, HttpResponse, HttpServer, Responder, web};
+pub(crate) mod auth;
+pub(crate) mod error;
+pub(crate) mod object_cache;
+pub(crate) mod rate_limit;
+#[cfg(test)]
+mod tests;
+
+use crate::commands::http::server::auth::ApiKeys;
+use crate::commands::http::server::error::{ErrorCode, HttpError, json_error_handler};
+use crate::commands::http::server::object_cache::ObjectCache;
+use crate::commands::http::server::rate_limit::PerIpRateLimiter;
+use crate::piece_getter::{DsnPieceGetter, DsnPieceSource};
+use actix_web::body::{BodySize, EitherBody, MessageBody};
+use actix_web::dev::{Server, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    self, ByteRangeSpec, ContentEncoding, ContentRangeSpec, HeaderName, HeaderValue,
+};
+use actix_web::middleware::{Compress, Condition, ErrorHandlers, Next, from_fn};
+use actix_web::{App, HttpResponse, HttpServer, Responder, ResponseError, web};
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use futures::{Stream, StreamExt, future, stream};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, fs, io, mem};
 use subspace_core_primitives::hashes::Blake3Hash;
-use subspace_data_retrieval::object_fetcher::ObjectFetcher;
+use subspace_core_primitives::objects::{GlobalObject, GlobalObjectMapping};
+use subspace_core_primitives::pieces::PieceIndex;
+use subspace_data_retrieval::object_fetcher::{self, ObjectFetcher};
 use subspace_data_retrieval::piece_getter::PieceGetter;
 use subspace_rpc_primitives::ObjectMappingResponse;
-use tracing::{debug, error, trace};
+use tokio::time::{Instant, timeout, timeout_at};
+use tracing::{Instrument, Span, debug, error, info_span, trace};
+
+/// The prefix for Unix domain socket listen addresses.
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// The header which correlates a request with the gateway's logs. Clients can set it, or the
+/// gateway generates one. It is always returned in the response.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The maximum length of a request ID supplied by a client.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// The maximum time to wait for the indexer service during a readiness check.
+const INDEXER_READINESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The DSN connection status used by readiness checks.
+#[async_trait]
+pub(crate) trait DsnStatus: Send + Sync {
+    /// Returns true if the DSN node has peers which can serve pieces.
+    async fn is_connected(&self) -> bool;
+}
+
+#[async_trait]
+impl<PS> DsnStatus for DsnPieceGetter<PS>
+where
+    PS: DsnPieceSource,
+{
+    async fn is_connected(&self) -> bool {
+        DsnPieceGetter::is_connected(self).await
+    }
+}
+
+/// An address the HTTP server listens on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ListenAddress {
+    /// A host name, IPv4 or IPv6 address, and TCP port, like `localhost:8080`, `127.0.0.1:8080` or
+    /// `[::1]:8080`. Host names are resolved when the server binds to the address.
+    Tcp(String),
+    /// A Unix domain socket path, like `unix:/run/gateway.sock`.
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix(UNIX_SOCKET_PREFIX) {
+            if path.is_empty() {
+                return Err(anyhow!("Unix socket path is empty in listen address {s}"));
+            }
+
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+
+        let invalid_address = || {
+            anyhow!(
+                "Invalid listen address {s}, expected `host:port`, `ip:port`, `[ipv6]:port`, or \
+                 `{UNIX_SOCKET_PREFIX}/path/to.sock`"
+            )
+        };
+
+        let (host, port) = s.rsplit_once(':').ok_or_else(invalid_address)?;
+        port.parse::<u16>().map_err(|_| invalid_address())?;
+        // IPv6 addresses need brackets, so the port can't be confused with part of the address
+        let is_bracketed = host.starts_with('[') && host.ends_with(']');
+        if host.is_empty() || (host.contains(':') && !is_bracketed) {
+            return Err(invalid_address());
+        }
+
+        Ok(Self::Tcp(s.to_string()))
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{address}"),
+            Self::Unix(path) => write!(f, "{UNIX_SOCKET_PREFIX}{}", path.display()),
+        }
+    }
+}
 
 /// Parameters for the DSN object HTTP server.
 pub(crate) struct ServerParameters<PG>
@@ -14,16 +124,103 @@ where
     PG: PieceGetter + Send + Sync + 'static,
 {
     pub(crate) object_fetcher: ObjectFetcher<PG>,
-    pub(crate) indexer_endpoint: String,
-    pub(crate) http_endpoint: String,
+    pub(crate) dsn_status: Arc<dyn DsnStatus>,
+    /// The piece getter used to serve raw pieces.
+    pub(crate) piece_getter: Arc<PG>,
+    /// The indexer service endpoints, in the order they are tried.
+    pub(crate) indexer_endpoints: Vec<String>,
+    pub(crate) http_endpoint: ListenAddress,
+    /// If set, the server only accepts HTTPS connections, using this TLS configuration.
+    pub(crate) tls_config: Option<rustls::ServerConfig>,
+    /// If set, limits the rate of requests from each client IP address.
+    pub(crate) rate_limiter: Option<PerIpRateLimiter>,
+    /// The API keys which can fetch objects. If empty, anyone can fetch objects.
+    pub(crate) api_keys: ApiKeys,
+    /// The maximum time to wait for in-flight requests to finish when the server is stopped.
+    pub(crate) drain_timeout: Duration,
+    /// The maximum time to spend on each object request, including fetching and sending objects.
+    pub(crate) request_timeout: Duration,
+    /// If set, recently fetched objects are cached, and served from the cache.
+    pub(crate) object_cache: Option<ObjectCache>,
+    /// If set, responses of at least this many bytes are compressed, if the client accepts a
+    /// supported encoding. If not set, responses are never compressed.
+    pub(crate) compression_min_size: Option<u64>,
 }
 
-/// Requests the object mappings for `hashes` from the indexer service.
-/// Multiple hashes are separated by `+`.
+/// Loads a TLS server configuration from a PEM-encoded certificate chain file, and a PEM-encoded
+/// private key file.
+pub(crate) fn load_tls_config(
+    cert_path: &Path,
+    key_path: &Path,
+) -> anyhow::Result<rustls::ServerConfig> {
+    let cert_file = File::open(cert_path)
+        .with_context(|| format!("Failed to open TLS certificate {}", cert_path.display()))?;
+    let cert_chain = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read TLS certificate {}", cert_path.display()))?;
+    if cert_chain.is_empty() {
+        return Err(anyhow!(
+            "No certificates found in TLS certificate {}",
+            cert_path.display()
+        ));
+    }
+
+    let key_file = File::open(key_path)
+        .with_context(|| format!("Failed to open TLS key {}", key_path.display()))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .with_context(|| format!("Failed to read TLS key {}", key_path.display()))?
+        .ok_or_else(|| anyhow!("No private key found in TLS key {}", key_path.display()))?;
+
+    // Use the same crypto provider as the other rustls users in the gateway
+    rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context("Unsupported TLS protocol versions")?
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)
+        .context("Invalid TLS certificate or key")
+}
+
+/// Requests the object mappings for `hashes` from the first reachable indexer service in
+/// `endpoints`.
+///
+/// If an indexer can't be reached, the next indexer is tried. Other errors are returned without
+/// trying the remaining indexers.
 async fn request_object_mapping(
-    endpoint: &str,
+    endpoints: &[String],
     hashes: &[Blake3Hash],
 ) -> anyhow::Result<ObjectMappingResponse> {
+    let mut last_error = None;
+
+    for endpoint in endpoints {
+        match request_object_mapping_from(endpoint, hashes).await {
+            Err(err) if err.is_connect() || err.is_timeout() => {
+                debug!(?hashes, ?err, ?endpoint, "Indexer is not reachable");
+                last_error = Some(err);
+            }
+            Err(err) => {
+                error!(?hashes, ?err, ?endpoint, "Request failed");
+                return Err(err.into());
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    error!(
+        ?hashes,
+        ?last_error,
+        ?endpoints,
+        "No indexers are reachable"
+    );
+
+    Err(last_error.map_or_else(|| anyhow!("No indexer endpoints configured"), Into::into))
+}
+
+/// Requests the object mappings for `hashes` from the indexer service at `endpoint`.
+/// Multiple hashes are separated by `+`.
+async fn request_object_mapping_from(
+    endpoint: &str,
+    hashes: &[Blake3Hash],
+) -> reqwest::Result<ObjectMappingResponse> {
     let client = reqwest::Client::new();
     let hash_list = hashes.iter().map(hex::encode).collect::<Vec<_>>();
     let object_mappings_url = format!("{}/objects/{}", endpoint, hash_list.join("+"));
@@ -34,104 +231,962 @@ async fn request_object_mapping(
         "Requesting object mappings..."
     );
 
-    let response = client.get(&object_mappings_url).send().await?.json().await;
+    let response = client
+        .get(&object_mappings_url)
+        .send()
+        .await?
+        .json()
+        .await?;
+    trace!(?hashes, json = ?response, "Received object mappings");
 
-    match &response {
-        Ok(json) => {
-            trace!(?hashes, ?json, "Received object mappings");
+    Ok(response)
+}
+
+/// Returns true if any of the indexer services respond to HTTP requests.
+async fn is_any_indexer_reachable(endpoints: &[String]) -> bool {
+    for endpoint in endpoints {
+        if is_indexer_reachable(endpoint).await {
+            return true;
         }
+    }
+
+    false
+}
+
+/// Returns true if the indexer service responds to HTTP requests.
+///
+/// Any response means the indexer is reachable, even if it is an error status.
+async fn is_indexer_reachable(endpoint: &str) -> bool {
+    let response = reqwest::Client::new()
+        .get(endpoint)
+        .timeout(INDEXER_READINESS_TIMEOUT)
+        .send()
+        .await;
+
+    match response {
+        Ok(_) => true,
         Err(err) => {
-            error!(?hashes, ?err, ?object_mappings_url, "Request failed");
+            debug!(?err, ?endpoint, "Indexer readiness check failed");
+            false
         }
     }
+}
+
+/// Liveness check, which succeeds while the HTTP server is running.
+async fn serve_health() -> impl Responder {
+    HttpResponse::Ok().finish()
+}
+
+/// Readiness check, which succeeds once the DSN node has peers, and at least one indexer service
+/// is reachable.
+async fn serve_ready<PG>(
+    additional_data: web::Data<Arc<ServerParameters<PG>>>,
+) -> Result<HttpResponse, HttpError>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    let server_params = additional_data.into_inner();
+
+    if !server_params.dsn_status.is_connected().await {
+        debug!("Not ready: no DSN peers are connected");
+        return Err(HttpError::new(
+            ErrorCode::Unavailable,
+            "DSN is not connected",
+        ));
+    }
 
-    response.map_err(|err| err.into())
+    if !is_any_indexer_reachable(&server_params.indexer_endpoints).await {
+        debug!("Not ready: indexer is not reachable");
+        return Err(HttpError::new(
+            ErrorCode::Unavailable,
+            "Indexer is not reachable",
+        ));
+    }
+
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Fetches the DSN objects with `hashes`, using the mapping indexer service.
 /// Multiple hashes are separated by `+`.
+///
+/// Single objects are streamed, so each piece of the object is sent as soon as it is fetched.
+/// Single object requests can have a `Range: bytes=...` header, which returns part of the object.
+/// Single object responses have the object hash as their `ETag`, so an `If-None-Match` request
+/// with that `ETag` returns `304 Not Modified`, without fetching the object.
+///
+/// Returns `404 Not Found` if the indexer doesn't have a mapping for any of the hashes.
+///
+/// If the request takes longer than the request timeout, returns `504 Gateway Timeout`, or ends
+/// the response body with an error if it has already started.
 async fn serve_object<PG>(
     hashes: web::Path<String>,
+    range: Option<web::Header<header::Range>>,
+    if_none_match: Option<web::Header<header::IfNoneMatch>>,
     additional_data: web::Data<Arc<ServerParameters<PG>>>,
-) -> impl Responder
+) -> Result<HttpResponse, HttpError>
 where
     PG: PieceGetter + Send + Sync + 'static,
 {
     let server_params = additional_data.into_inner();
-    let hashes = hashes.into_inner();
-    let hashes = hashes
+    let deadline = Instant::now() + server_params.request_timeout;
+    let hashes = parse_object_hashes(&hashes)?;
+
+    if let Some(response) = not_modified_response(&hashes, if_none_match) {
+        return Ok(response);
+    }
+
+    // Ranges are only supported for single objects. Other Range headers are ignored, and the
+    // full response is returned, as allowed by RFC 9110.
+    let range = range
+        .and_then(|range| single_byte_range(range.into_inner()))
+        .filter(|_range| hashes.len() == 1);
+
+    if let (Some(object_cache), [hash]) = (&server_params.object_cache, hashes.as_slice())
+        && let Some(object) = object_cache.get(hash)
+    {
+        trace!(?hashes, size = %object.len(), "Object found in cache");
+
+        return match range {
+            Some(range) => object_range_response(&hashes, object, range),
+            None => Ok(HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .insert_header(header::ETag(object_etag(hash)))
+                .body(object)),
+        };
+    }
+
+    let object_mappings = fetch_object_mappings(&server_params, &hashes, deadline).await?;
+
+    if let (Some(range), [object_mapping]) = (range, object_mappings.objects()) {
+        return match timeout_at(
+            deadline,
+            serve_object_range(&server_params, &hashes, *object_mapping, range),
+        )
+        .await
+        {
+            Ok(response) => response,
+            Err(_elapsed) => Err(request_timeout_error(&server_params, &hashes)),
+        };
+    }
+
+    if let [object_mapping] = object_mappings.objects() {
+        return serve_single_object(server_params, &hashes, *object_mapping, deadline).await;
+    }
+
+    let mut mapping_hashes = object_mappings
+        .objects()
+        .iter()
+        .map(|object_mapping| object_mapping.hash)
+        .collect::<Vec<_>>()
+        .into_iter();
+    let mut objects = Box::pin(
+        server_params
+            .object_fetcher
+            .fetch_objects_stream(object_mappings),
+    );
+
+    // Wait for the first object before sending the response headers, so the most common errors
+    // (missing pieces, or oversized objects) are returned as an HTTP status.
+    let first_object = match timeout_at(deadline, objects.next()).await {
+        Ok(Some(Ok(object))) => object,
+        Ok(Some(Err(err))) => return Err(object_error(&hashes, err)),
+        Ok(None) => {
+            return Ok(HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .finish());
+        }
+        Err(_elapsed) => return Err(request_timeout_error(&server_params, &hashes)),
+    };
+    trace!(?hashes, size = %first_object.len(), "First object fetched successfully");
+
+    // Each object is sent as soon as it has been fetched and verified. The stream is dropped
+    // with the response, so fetching stops if the client disconnects. If a later object fails,
+    // the error ends the response body early, and the client sees an incomplete chunked body.
+    let objects = stream::once(future::ready(Ok(first_object))).chain(objects);
+    let body = objects_before_deadline(
+        objects,
+        deadline,
+        server_params.request_timeout,
+        Span::current(),
+    )
+    .map(move |object| match object {
+        Ok(object) => {
+            trace!(size = %object.len(), "Object fetched successfully");
+            let object = web::Bytes::from(object);
+            // Objects are returned in mapping order
+            if let Some(hash) = mapping_hashes.next() {
+                cache_object(&server_params, hash, object.clone());
+            }
+            Ok(object)
+        }
+        Err(err) => {
+            error!(?err, "Failed to fetch object after response started");
+            Err(err)
+        }
+    });
+
+    // TODO:
+    // - return a multi-part response, with one part per object.
+    // - add the object hash to each part, so we can sort mappings by piece index and offset,
+    //   for more efficient piece re-use. See the `ObjectFetcher::fetch_objects` performance docs
+    //   for more details.
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .streaming(body))
+}
+
+/// Streams the single object in `object_mapping`, sending the data in each piece as soon as that
+/// piece is fetched.
+///
+/// The object is found before sending the response headers, so the most common errors are
+/// returned as an HTTP status. The object hash is checked before the last piece of data is sent,
+/// so an invalid object ends the response body early, and the client sees an incomplete body.
+async fn serve_single_object<PG>(
+    server_params: Arc<ServerParameters<PG>>,
+    hashes: &[Blake3Hash],
+    object_mapping: GlobalObject,
+    deadline: Instant,
+) -> Result<HttpResponse, HttpError>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    let object = match timeout_at(
+        deadline,
+        server_params.object_fetcher.open_object(object_mapping),
+    )
+    .await
+    {
+        Ok(Ok(object)) => object,
+        Ok(Err(err)) => return Err(object_error(hashes, err)),
+        Err(_elapsed) => return Err(request_timeout_error(&server_params, hashes)),
+    };
+    let size = object.size();
+    trace!(?hashes, %size, "Object found successfully");
+
+    let hash = object_mapping.hash;
+    // The data is only kept if the object will be cached
+    let mut cached_object = server_params
+        .object_cache
+        .is_some()
+        .then(|| Vec::with_capacity(size));
+    let data = objects_before_deadline(
+        Box::pin(object.into_stream()),
+        deadline,
+        server_params.request_timeout,
+        Span::current(),
+    )
+    .map(move |data| match data {
+        Ok(data) => {
+            if let Some(cached_object) = &mut cached_object {
+                cached_object.extend_from_slice(&data);
+                // The last data is only returned after the object hash has been checked
+                if cached_object.len() == size {
+                    let object = web::Bytes::from(mem::take(cached_object));
+                    cache_object(&server_params, hash, object);
+                }
+            }
+            Ok(web::Bytes::from(data))
+        }
+        Err(err) => {
+            error!(?err, "Failed to fetch object after response started");
+            Err(err)
+        }
+    });
+
+    // The object is sent with its length, which allows small objects to skip compression
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .insert_header(header::ETag(object_etag(&hash)))
+        .no_chunking(size as u64)
+        .streaming(data))
+}
+
+/// Returns the headers for the DSN objects with `hashes`, without the object data.
+///
+/// Object sizes are found using the object cache, or each object's metadata, so objects usually
+/// aren't fetched. Object hashes can't be checked without the object data, so the size of an
+/// object with an invalid mapping can be incorrect.
+async fn serve_object_head<PG>(
+    hashes: web::Path<String>,
+    if_none_match: Option<web::Header<header::IfNoneMatch>>,
+    additional_data: web::Data<Arc<ServerParameters<PG>>>,
+) -> Result<HttpResponse, HttpError>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    let server_params = additional_data.into_inner();
+    let deadline = Instant::now() + server_params.request_timeout;
+    let hashes = parse_object_hashes(&hashes)?;
+
+    if let Some(response) = not_modified_response(&hashes, if_none_match) {
+        return Ok(response);
+    }
+
+    let size = total_object_size(&server_params, &hashes, deadline).await?;
+    trace!(?hashes, %size, "Object size found successfully");
+
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("application/octet-stream")
+        // The length is the uncompressed object length
+        .insert_header(ContentEncoding::Identity)
+        .no_chunking(size as u64);
+    if let [hash] = hashes.as_slice() {
+        response.insert_header(header::ETag(object_etag(hash)));
+    }
+
+    // The body is empty, but the content length is the object length
+    Ok(response.streaming(stream::empty::<Result<web::Bytes, actix_web::Error>>()))
+}
+
+/// Returns the total size of the objects with `hashes`, without fetching the objects, if
+/// possible.
+async fn total_object_size<PG>(
+    server_params: &ServerParameters<PG>,
+    hashes: &[Blake3Hash],
+    deadline: Instant,
+) -> Result<usize, HttpError>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    if let (Some(object_cache), [hash]) = (&server_params.object_cache, hashes)
+        && let Some(object) = object_cache.get(hash)
+    {
+        return Ok(object.len());
+    }
+
+    let object_mappings = fetch_object_mappings(server_params, hashes, deadline).await?;
+
+    let mut total_size = 0;
+    for mapping in object_mappings.objects().iter().copied() {
+        let metadata = match timeout_at(
+            deadline,
+            server_params.object_fetcher.fetch_object_metadata(mapping),
+        )
+        .await
+        {
+            Ok(Ok(metadata)) => metadata,
+            Ok(Err(err)) => return Err(object_error(hashes, err)),
+            Err(_elapsed) => return Err(request_timeout_error(server_params, hashes)),
+        };
+        total_size += metadata.size;
+    }
+
+    Ok(total_size)
+}
+
+/// Parses `hashes`, which are hex-encoded object hashes separated by `+`.
+fn parse_object_hashes(hashes: &str) -> Result<Vec<Blake3Hash>, HttpError> {
+    hashes
         .split('+')
         .map(|s| {
             let mut hash = Blake3Hash::default();
             hex::decode_to_slice(s, hash.as_mut()).map(|()| hash)
         })
-        .try_collect::<Vec<_>>();
-
-    let Ok(hashes) = hashes else {
-        return HttpResponse::BadRequest().finish();
-    };
+        .try_collect::<Vec<_>>()
+        .map_err(|_| {
+            HttpError::new(
+                ErrorCode::BadRequest,
+                "Object hashes must be 32 byte hex strings, separated by `+`",
+            )
+        })
+}
 
-    let Ok(object_mappings) =
-        request_object_mapping(&server_params.indexer_endpoint, &hashes).await
-    else {
-        return HttpResponse::BadRequest().finish();
+/// Returns the object mappings for `hashes` from the indexer service.
+///
+/// Returns an error if the indexer fails, returns mappings which weren't requested, or doesn't
+/// have a mapping for every hash.
+async fn fetch_object_mappings<PG>(
+    server_params: &ServerParameters<PG>,
+    hashes: &[Blake3Hash],
+    deadline: Instant,
+) -> Result<GlobalObjectMapping, HttpError>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    let object_mappings = match timeout_at(
+        deadline,
+        request_object_mapping(&server_params.indexer_endpoints, hashes),
+    )
+    .await
+    {
+        Ok(Ok(object_mappings)) => object_mappings.objects,
+        Ok(Err(_)) => {
+            return Err(HttpError::new(
+                ErrorCode::Unavailable,
+                "Object mapping request to the indexer failed",
+            ));
+        }
+        Err(_elapsed) => return Err(request_timeout_error(server_params, hashes)),
     };
 
-    for object_mapping in object_mappings.objects.objects() {
+    for object_mapping in object_mappings.objects() {
         if !hashes.contains(&object_mapping.hash) {
             error!(
                 ?object_mapping,
                 ?hashes,
                 "Returned object mapping wasn't in requested hashes"
             );
-            return HttpResponse::ServiceUnavailable().finish();
+            return Err(HttpError::new(
+                ErrorCode::Unavailable,
+                "Indexer returned an object mapping which wasn't requested",
+            ));
         }
     }
 
-    let object_fetcher_result = server_params
-        .object_fetcher
-        .fetch_objects(object_mappings.objects)
-        .await;
+    let missing_hashes = hashes
+        .iter()
+        .filter(|hash| {
+            !object_mappings
+                .objects()
+                .iter()
+                .any(|object_mapping| object_mapping.hash == **hash)
+        })
+        .map(hex::encode)
+        .collect::<Vec<_>>();
+    if !missing_hashes.is_empty() {
+        debug!(?missing_hashes, "Indexer has no object mapping for hashes");
+        return Err(HttpError::new(
+            ErrorCode::NotFound,
+            format!("Objects not found: {}", missing_hashes.join("+")),
+        ));
+    }
 
-    let objects = match object_fetcher_result {
-        Ok(objects) => {
-            trace!(
-                ?hashes,
-                count = %objects.len(),
-                sizes = ?objects.iter().map(|object| object.len()),
-                "Objects fetched successfully"
+    Ok(object_mappings)
+}
+
+/// Returns the `ETag` for the object with `hash`. Objects are content-addressed, so the hash
+/// identifies the object data.
+fn object_etag(hash: &Blake3Hash) -> header::EntityTag {
+    header::EntityTag::new_strong(hex::encode(hash))
+}
+
+/// Returns a `304 Not Modified` response if `if_none_match` contains the `ETag` of a single
+/// requested object.
+///
+/// `If-None-Match: *` is ignored, because it depends on whether the object exists.
+fn not_modified_response(
+    hashes: &[Blake3Hash],
+    if_none_match: Option<web::Header<header::IfNoneMatch>>,
+) -> Option<HttpResponse> {
+    let [hash] = hashes else {
+        return None;
+    };
+    let header::IfNoneMatch::Items(etags) = if_none_match?.into_inner() else {
+        return None;
+    };
+
+    let etag = object_etag(hash);
+    if !etags.iter().any(|client_etag| client_etag.weak_eq(&etag)) {
+        return None;
+    }
+
+    trace!(?hashes, "Object not modified");
+
+    Some(
+        HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .finish(),
+    )
+}
+
+/// Fetches the raw piece at `piece_index` from the DSN, without using object mappings.
+///
+/// Returns `404 Not Found` if the piece can't be found.
+async fn serve_piece<PG>(
+    piece_index: web::Path<u64>,
+    additional_data: web::Data<Arc<ServerParameters<PG>>>,
+) -> Result<HttpResponse, HttpError>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    let server_params = additional_data.into_inner();
+    let piece_index = PieceIndex::from(piece_index.into_inner());
+
+    match timeout(
+        server_params.request_timeout,
+        server_params.piece_getter.get_piece(piece_index),
+    )
+    .await
+    {
+        Ok(Ok(Some(piece))) => {
+            trace!(%piece_index, "Piece fetched successfully");
+            Ok(HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .body(Vec::<u8>::from(piece)))
+        }
+        Ok(Ok(None)) => {
+            debug!(%piece_index, "Piece not found");
+            Err(HttpError::new(
+                ErrorCode::NotFound,
+                format!("Piece {piece_index} not found"),
+            ))
+        }
+        Ok(Err(err)) => {
+            error!(%piece_index, ?err, "Failed to fetch piece");
+            Err(HttpError::new(
+                ErrorCode::Unavailable,
+                format!("Failed to fetch piece {piece_index}"),
+            ))
+        }
+        Err(_elapsed) => {
+            debug!(
+                %piece_index,
+                request_timeout = ?server_params.request_timeout,
+                "Piece request timed out"
             );
-            objects
+            Err(HttpError::new(
+                ErrorCode::Timeout,
+                format!(
+                    "Request timed out after {:?}",
+                    server_params.request_timeout
+                ),
+            ))
         }
-        Err(err) => {
-            error!(?hashes, ?err, "Failed to fetch objects");
-            return HttpResponse::ServiceUnavailable().finish();
+    }
+}
+
+/// Returns `objects`, ending with an error if the next object isn't fetched before `deadline`.
+///
+/// Objects are fetched in `span`, because the response body is sent after the request span has
+/// been exited.
+fn objects_before_deadline<S>(
+    objects: S,
+    deadline: Instant,
+    request_timeout: Duration,
+    span: Span,
+) -> impl Stream<Item = anyhow::Result<Vec<u8>>>
+where
+    S: Stream<Item = Result<Vec<u8>, object_fetcher::Error>> + Unpin,
+{
+    stream::unfold(Some(objects), move |objects| {
+        let span = span.clone();
+        async move {
+            let mut objects = objects?;
+
+            match timeout_at(deadline, objects.next()).instrument(span).await {
+                Ok(Some(object)) => Some((object.map_err(anyhow::Error::from), Some(objects))),
+                Ok(None) => None,
+                // Dropping the object stream cancels the fetch, and ending with an error stops the
+                // client treating the partial response as complete
+                Err(_elapsed) => Some((
+                    Err(anyhow!("Request timed out after {request_timeout:?}")),
+                    None,
+                )),
+            }
         }
+    })
+}
+
+/// Returns the HTTP error for a request which took longer than the request timeout.
+fn request_timeout_error<PG>(
+    server_params: &ServerParameters<PG>,
+    hashes: &[Blake3Hash],
+) -> HttpError
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    debug!(
+        ?hashes,
+        request_timeout = ?server_params.request_timeout,
+        "Object request timed out"
+    );
+
+    HttpError::new(
+        ErrorCode::Timeout,
+        format!(
+            "Request timed out after {:?}",
+            server_params.request_timeout
+        ),
+    )
+}
+
+/// Returns the byte range in `range`, if it contains exactly one byte range.
+fn single_byte_range(range: header::Range) -> Option<ByteRangeSpec> {
+    match range {
+        header::Range::Bytes(mut ranges) if ranges.len() == 1 => ranges.pop(),
+        _ => None,
+    }
+}
+
+/// Adds a fetched object to the object cache, if the cache is enabled.
+fn cache_object<PG>(server_params: &ServerParameters<PG>, hash: Blake3Hash, object: web::Bytes)
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    if let Some(object_cache) = &server_params.object_cache {
+        object_cache.insert(hash, object);
+    }
+}
+
+/// Fetches the bytes of a single object in `range`, downloading only the pieces which contain
+/// those bytes.
+///
+/// Object hashes cover the whole object, so the bytes of most objects can't be verified. Objects
+/// which could contain segment padding are fetched and verified before the range is returned.
+async fn serve_object_range<PG>(
+    server_params: &ServerParameters<PG>,
+    hashes: &[Blake3Hash],
+    object_mapping: GlobalObject,
+    range: ByteRangeSpec,
+) -> Result<HttpResponse, HttpError>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    let object = match server_params
+        .object_fetcher
+        .open_object(object_mapping)
+        .await
+    {
+        Ok(object) => object,
+        Err(err) => return Err(object_error(hashes, err)),
     };
+    let object_length = object.size() as u64;
+    let (start, end) = satisfiable_range(hashes, range, object_length)?;
 
-    // TODO:
-    // - return a multi-part response, with one part per object.
-    // - add the object hash to each part, so we can sort mappings by piece index and offset,
-    //   for more efficient piece re-use. See the `ObjectFetcher::fetch_objects` performance docs
-    //   for more details.
-    HttpResponse::Ok()
+    // The range end is inclusive
+    let data = match object.read_range(start as usize..end as usize + 1).await {
+        Ok(data) => web::Bytes::from(data),
+        Err(err) => return Err(object_error(hashes, err)),
+    };
+
+    Ok(partial_content_response(
+        hashes,
+        (start, end),
+        object_length,
+        data,
+    ))
+}
+
+/// Returns the bytes of `object` in `range`, or an error if the range can't be satisfied.
+fn object_range_response(
+    hashes: &[Blake3Hash],
+    object: web::Bytes,
+    range: ByteRangeSpec,
+) -> Result<HttpResponse, HttpError> {
+    let object_length = object.len() as u64;
+    let (start, end) = satisfiable_range(hashes, range, object_length)?;
+
+    Ok(partial_content_response(
+        hashes,
+        (start, end),
+        object_length,
+        object.slice(start as usize..=end as usize),
+    ))
+}
+
+/// Returns the inclusive start and end of `range` in an object of `object_length` bytes, or an
+/// error if the range can't be satisfied.
+fn satisfiable_range(
+    hashes: &[Blake3Hash],
+    range: ByteRangeSpec,
+    object_length: u64,
+) -> Result<(u64, u64), HttpError> {
+    let Some((start, end)) = range.to_satisfiable_range(object_length) else {
+        debug!(?hashes, ?range, %object_length, "Requested range is not satisfiable");
+        return Err(HttpError::new(
+            ErrorCode::RangeNotSatisfiable,
+            format!("Requested range is outside the {object_length} byte object"),
+        )
+        .with_header(header::ContentRange(ContentRangeSpec::Bytes {
+            range: None,
+            instance_length: Some(object_length),
+        })));
+    };
+
+    Ok((start, end))
+}
+
+/// Returns a partial response containing `data`, which is the object bytes from `start` to `end`
+/// inclusive.
+fn partial_content_response(
+    hashes: &[Blake3Hash],
+    (start, end): (u64, u64),
+    object_length: u64,
+    data: web::Bytes,
+) -> HttpResponse {
+    trace!(?hashes, %start, %end, %object_length, "Object range fetched successfully");
+    let etag = hashes.first().map(object_etag);
+
+    // The range end is inclusive. Content-Range applies to the encoded response, so partial
+    // responses aren't compressed.
+    let mut response = HttpResponse::PartialContent();
+    response
         .content_type("application/octet-stream")
-        .body(objects.concat())
+        .insert_header(ContentEncoding::Identity)
+        .insert_header(header::ContentRange(ContentRangeSpec::Bytes {
+            range: Some((start, end)),
+            instance_length: Some(object_length),
+        }));
+    if let Some(etag) = etag {
+        response.insert_header(header::ETag(etag));
+    }
+
+    response.body(data)
+}
+
+/// Returns the HTTP error for an object fetch error.
+fn object_error(hashes: &[Blake3Hash], err: object_fetcher::Error) -> HttpError {
+    match err {
+        object_fetcher::Error::ObjectTooLarge { .. }
+        | object_fetcher::Error::LengthPrefixTooLarge { .. } => {
+            debug!(?hashes, ?err, "Object exceeds the maximum object size");
+            HttpError::new(
+                ErrorCode::TooLarge,
+                "Object is larger than the maximum object size",
+            )
+        }
+        err => {
+            error!(?hashes, ?err, "Failed to fetch objects");
+            HttpError::new(
+                ErrorCode::Unavailable,
+                "Failed to fetch objects from the DSN",
+            )
+        }
+    }
+}
+
+/// Middleware which handles each request in a tracing span with a request ID, and returns that ID
+/// in the `X-Request-Id` response header.
+///
+/// The client's `X-Request-Id` is used if it is valid, otherwise a random ID is generated.
+async fn trace_request_id<B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error>
+where
+    B: MessageBody,
+{
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .filter(|request_id| is_valid_request_id(request_id))
+        .cloned()
+        .unwrap_or_else(new_request_id);
+    let span = info_span!(
+        "http_request",
+        request_id = %String::from_utf8_lossy(request_id.as_bytes()),
+        method = %req.method(),
+        path = %req.path(),
+    );
+
+    let mut response = next.call(req).instrument(span).await?;
+    response
+        .headers_mut()
+        .insert(HeaderName::from_static(REQUEST_ID_HEADER), request_id);
+
+    Ok(response)
+}
+
+/// Returns true if `request_id` is short, and only contains visible ASCII characters, so it is
+/// safe to log.
+fn is_valid_request_id(request_id: &HeaderValue) -> bool {
+    let request_id = request_id.as_bytes();
+
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LENGTH
+        && request_id.iter().all(u8::is_ascii_graphic)
 }
 
-/// Starts the DSN object HTTP server.
-pub async fn start_server<PG>(server_params: ServerParameters<PG>) -> std::io::Result<()>
+/// Returns a new random request ID.
+fn new_request_id() -> HeaderValue {
+    HeaderValue::try_from(format!("{:032x}", rand::random::<u128>()))
+        .expect("hex strings are valid header values")
+}
+
+/// Middleware which rejects requests from clients which have exceeded their rate limit, with a
+/// `429 Too Many Requests` response.
+async fn limit_request_rate<PG, B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+    B: MessageBody,
+{
+    let retry_after = match (
+        req.app_data::<web::Data<Arc<ServerParameters<PG>>>>(),
+        req.peer_addr(),
+    ) {
+        (Some(server_params), Some(peer_addr)) => server_params
+            .rate_limiter
+            .as_ref()
+            .and_then(|rate_limiter| rate_limiter.try_acquire(peer_addr.ip()).err()),
+        _ => None,
+    };
+
+    if let Some(retry_after) = retry_after {
+        debug!(peer_addr = ?req.peer_addr(), ?retry_after, "Client exceeded rate limit");
+
+        // Retry-After is in whole seconds, so round up to avoid early retries
+        let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        let response = HttpError::new(ErrorCode::RateLimited, "Too many requests")
+            .with_header((header::RETRY_AFTER, retry_after_secs.max(1)))
+            .error_response();
+
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Middleware which rejects requests without an allowed API key, with a `401 Unauthorized`
+/// response.
+async fn require_api_key<PG, B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+    B: MessageBody,
+{
+    let authorized = req
+        .app_data::<web::Data<Arc<ServerParameters<PG>>>>()
+        .is_none_or(|server_params| server_params.api_keys.is_authorized(req.headers()));
+
+    if !authorized {
+        debug!(peer_addr = ?req.peer_addr(), "Request without an allowed API key");
+
+        let response = HttpError::new(ErrorCode::Unauthorized, "Missing or invalid API key")
+            .with_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .error_response();
+
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Middleware which stops small responses being compressed, because compressing them doesn't
+/// save much bandwidth. Responses with an unknown size, like streamed objects, can be compressed.
+async fn skip_small_response_compression<PG, B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error>
+where
+    PG: PieceGetter + Send + Sync + 'static,
+    B: MessageBody,
+{
+    let compression_min_size = req
+        .app_data::<web::Data<Arc<ServerParameters<PG>>>>()
+        .and_then(|server_params| server_params.compression_min_size);
+
+    let mut response = next.call(req).await?;
+
+    if let (Some(compression_min_size), BodySize::Sized(body_size)) =
+        (compression_min_size, response.response().body().size())
+        && body_size < compression_min_size
+        && !response.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        // The compression middleware doesn't change responses with an identity encoding
+        response.headers_mut().insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static("identity"),
+        );
+    }
+
+    Ok(response)
+}
+
+/// Adds the DSN object HTTP server routes to `config`.
+fn configure_routes<PG>(config: &mut web::ServiceConfig, server_params: Arc<ServerParameters<PG>>)
+where
+    PG: PieceGetter + Send + Sync + 'static,
+{
+    let compression_enabled = server_params.compression_min_size.is_some();
+
+    config.app_data(web::Data::new(server_params)).service(
+        web::scope("")
+            // Errors from actix, like unknown routes, are converted to JSON error responses
+            .wrap(ErrorHandlers::new().default_handler(json_error_handler))
+            .wrap(from_fn(skip_small_response_compression::<PG, _>))
+            .wrap(Condition::new(compression_enabled, Compress::default()))
+            .wrap(from_fn(limit_request_rate::<PG, _>))
+            // The request ID covers all other middleware, so rejected requests are also logged
+            // with their ID
+            .wrap(from_fn(trace_request_id))
+            // Health checks don't need an API key, so load balancers can use them
+            .service(
+                web::scope("/data")
+                    .wrap(from_fn(require_api_key::<PG, _>))
+                    .route("/{hashes}", web::get().to(serve_object::<PG>))
+                    .route("/{hashes}", web::head().to(serve_object_head::<PG>)),
+            )
+            .service(
+                web::scope("/piece")
+                    .wrap(from_fn(require_api_key::<PG, _>))
+                    .route("/{piece_index}", web::get().to(serve_piece::<PG>)),
+            )
+            .route("/health", web::get().to(serve_health))
+            .route("/ready", web::get().to(serve_ready::<PG>)),
+    );
+}
+
+/// Binds the DSN object HTTP server to its endpoint, and returns the server, which serves
+/// requests when it is awaited.
+///
+/// The server doesn't handle shutdown signals itself. Use [`Server::handle`] to stop it, which
+/// waits up to the drain timeout for in-flight requests to finish.
+pub fn start_server<PG>(server_params: ServerParameters<PG>) -> io::Result<Server>
 where
     PG: PieceGetter + Send + Sync + 'static,
 {
-    let server_params = Arc::new(server_params);
     let http_endpoint = server_params.http_endpoint.clone();
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(server_params.clone()))
-            .route("/data/{hashes}", web::get().to(serve_object::<PG>))
+    let tls_config = server_params.tls_config.clone();
+    let drain_timeout = server_params.drain_timeout;
+    let server_params = Arc::new(server_params);
+    let server = HttpServer::new(move || {
+        App::new().configure(|config| configure_routes(config, server_params.clone()))
     })
-    .bind(http_endpoint)?
-    .run()
-    .await
+    // The gateway handles shutdown signals, and stops the server using its handle
+    .disable_signals()
+    .shutdown_timeout(drain_timeout.as_secs());
+
+    let server = match (http_endpoint, tls_config) {
+        (ListenAddress::Tcp(address), Some(tls_config)) => {
+            server.bind_rustls_0_23(address, tls_config)?
+        }
+        (ListenAddress::Tcp(address), None) => server.bind(address)?,
+        (ListenAddress::Unix(_path), Some(_tls_config)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "TLS is not supported on Unix domain sockets",
+            ));
+        }
+        #[cfg(unix)]
+        (ListenAddress::Unix(path), None) => {
+            remove_stale_unix_socket(&path)?;
+            server.bind_uds(path)?
+        }
+        #[cfg(not(unix))]
+        (ListenAddress::Unix(_path), None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            ));
+        }
+    };
+
+    Ok(server.run())
+}
+
+/// Removes a Unix domain socket left behind by a previous gateway at `path`, so the server can
+/// bind to it. Other kinds of files are not removed, so binding to them fails.
+#[cfg(unix)]
+fn remove_stale_unix_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            debug!(path = %path.display(), "Removing existing Unix domain socket");
+            fs::remove_file(path)
+        }
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
 }
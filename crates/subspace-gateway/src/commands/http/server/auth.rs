@@ -0,0 +1,99 @@
+//! API key authentication for HTTP requests.
+
+use actix_web::http::header::{self, HeaderMap};
+use anyhow::Context;
+use std::path::Path;
+use std::{fmt, fs};
+
+/// The header which contains an API key, as an alternative to `Authorization: Bearer <key>`.
+const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// The API keys which are allowed to fetch objects.
+///
+/// If no keys are configured, all requests are allowed.
+#[derive(Clone, Default)]
+pub(crate) struct ApiKeys {
+    keys: Vec<String>,
+}
+
+impl fmt::Debug for ApiKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never log the keys themselves
+        f.debug_struct("ApiKeys")
+            .field("keys", &self.keys.len())
+            .finish()
+    }
+}
+
+impl ApiKeys {
+    /// Creates a new set of API keys. Empty keys are ignored.
+    pub(crate) fn new(keys: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            keys: keys
+                .into_iter()
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Loads `keys`, and the keys in `keys_file`, if it is provided.
+    ///
+    /// The file contains one key per line. Empty lines, and lines starting with `#`, are ignored.
+    pub(crate) fn load(keys: Vec<String>, keys_file: Option<&Path>) -> anyhow::Result<Self> {
+        let mut file_keys = Vec::new();
+        if let Some(keys_file) = keys_file {
+            let contents = fs::read_to_string(keys_file)
+                .with_context(|| format!("Failed to read API keys file {}", keys_file.display()))?;
+            file_keys.extend(
+                contents
+                    .lines()
+                    .filter(|line| !line.trim_start().starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+
+        Ok(Self::new(keys.into_iter().chain(file_keys)))
+    }
+
+    /// Returns true if no API keys are configured, so all requests are allowed.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns true if `headers` contain an allowed API key, or no API keys are configured.
+    pub(crate) fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        let Some(request_key) = request_api_key(headers) else {
+            return false;
+        };
+
+        // Check every key, so the response time doesn't reveal which keys are close matches
+        self.keys.iter().fold(false, |authorized, key| {
+            constant_time_eq(key.as_bytes(), request_key.as_bytes()) | authorized
+        })
+    }
+}
+
+/// Returns the API key in the `X-Api-Key` header, or the bearer token in the `Authorization`
+/// header.
+fn request_api_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(api_key) = headers.get(API_KEY_HEADER) {
+        return api_key.to_str().ok().map(str::trim);
+    }
+
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Compares `a` and `b` in a time which only depends on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
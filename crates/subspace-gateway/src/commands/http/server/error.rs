@@ -0,0 +1,175 @@
+//! JSON error responses for HTTP requests.
+//!
+//! Every error response has a JSON body like:
+//! `{ "error": { "code": "not_found", "message": "..." } }`
+
+use actix_web::body::MessageBody;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, HeaderMap, TryIntoHeaderPair};
+use actix_web::middleware::ErrorHandlerResponse;
+use actix_web::{HttpResponse, ResponseError};
+use serde_json::json;
+use std::fmt;
+
+/// The kind of error in an error response, which clients can use to handle errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ErrorCode {
+    /// The request was invalid, for example because an object hash couldn't be parsed
+    BadRequest,
+    /// The request didn't have an allowed API key
+    Unauthorized,
+    /// The requested object or piece wasn't found
+    NotFound,
+    /// The requested object is larger than the maximum object size
+    TooLarge,
+    /// The requested byte range is outside the object
+    RangeNotSatisfiable,
+    /// The client has exceeded its rate limit
+    RateLimited,
+    /// The DSN or indexer couldn't be reached, or returned invalid data
+    Unavailable,
+    /// The request took longer than the request timeout
+    Timeout,
+}
+
+impl ErrorCode {
+    /// Returns the code used in error response bodies.
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::BadRequest => "bad_request",
+            Self::Unauthorized => "unauthorized",
+            Self::NotFound => "not_found",
+            Self::TooLarge => "too_large",
+            Self::RangeNotSatisfiable => "range_not_satisfiable",
+            Self::RateLimited => "rate_limited",
+            Self::Unavailable => "unavailable",
+            Self::Timeout => "timeout",
+        }
+    }
+
+    /// Returns the HTTP status for this error code.
+    fn status_code(self) -> StatusCode {
+        match self {
+            Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::RangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            Self::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            Self::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Returns the closest error code for an error status, which wasn't created by the gateway.
+    fn from_status_code(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::PAYLOAD_TOO_LARGE => Self::TooLarge,
+            StatusCode::RANGE_NOT_SATISFIABLE => Self::RangeNotSatisfiable,
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+            StatusCode::GATEWAY_TIMEOUT | StatusCode::REQUEST_TIMEOUT => Self::Timeout,
+            status if status.is_client_error() => Self::BadRequest,
+            _ => Self::Unavailable,
+        }
+    }
+}
+
+/// An HTTP error, which is returned to the client as a JSON error response.
+#[derive(Debug)]
+pub(crate) struct HttpError {
+    code: ErrorCode,
+    message: String,
+    /// Extra headers for the error response
+    headers: HeaderMap,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+impl ResponseError for HttpError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code()).json(json!({
+            "error": {
+                "code": self.code.as_str(),
+                "message": self.message,
+            }
+        }));
+
+        for (name, value) in &self.headers {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+
+        response
+    }
+}
+
+impl HttpError {
+    pub(crate) fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Adds `header` to the error response.
+    ///
+    /// Panics if the header is invalid, which is a bug in the gateway.
+    pub(crate) fn with_header(mut self, header: impl TryIntoHeaderPair) -> Self {
+        let Ok((name, value)) = header.try_into_pair() else {
+            panic!("Invalid error response header");
+        };
+        self.headers.insert(name, value);
+        self
+    }
+}
+
+/// Error handler which replaces error responses created by actix, like unknown routes or invalid
+/// path parameters, with JSON error responses. Responses which are already JSON are unchanged.
+pub(crate) fn json_error_handler<B>(
+    response: ServiceResponse<B>,
+) -> actix_web::Result<ErrorHandlerResponse<B>>
+where
+    B: MessageBody,
+{
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if is_json {
+        return Ok(ErrorHandlerResponse::Response(
+            response.map_into_left_body(),
+        ));
+    }
+
+    let status = response.status();
+    let (request, response) = response.into_parts();
+    let mut error = HttpError::new(
+        ErrorCode::from_status_code(status),
+        status.canonical_reason().unwrap_or("Request failed"),
+    );
+    // Keep headers like `Allow`, but replace the body headers
+    for (name, value) in response.headers() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            error.headers.append(name.clone(), value.clone());
+        }
+    }
+
+    // Keep the original status, even if the error code has a different default status
+    let mut error_response = error.error_response();
+    *error_response.status_mut() = status;
+
+    Ok(ErrorHandlerResponse::Response(
+        ServiceResponse::new(request, error_response).map_into_right_body(),
+    ))
+}
@@ -0,0 +1,97 @@
+//! An in-memory cache of recently served objects.
+
+use actix_web::web::Bytes;
+use parking_lot::Mutex;
+use schnellru::{LruMap, Unlimited};
+use std::time::{Duration, Instant};
+use subspace_core_primitives::hashes::Blake3Hash;
+
+/// A cached object, and the time it was cached.
+#[derive(Debug)]
+struct CachedObject {
+    data: Bytes,
+    cached_at: Instant,
+}
+
+/// The cached objects, and their total size.
+#[derive(Debug)]
+struct CachedObjects {
+    objects: LruMap<Blake3Hash, CachedObject, Unlimited>,
+    /// The total length of the cached object data, in bytes
+    total_size: usize,
+}
+
+impl CachedObjects {
+    fn remove(&mut self, hash: &Blake3Hash) {
+        if let Some(object) = self.objects.remove(hash) {
+            self.total_size -= object.data.len();
+        }
+    }
+}
+
+/// Caches objects by hash, so repeated requests for popular objects don't fetch them from the
+/// DSN again.
+///
+/// Objects are removed after `ttl`, or when the total size of the cached objects exceeds
+/// `max_size`, starting with the least recently used objects.
+#[derive(Debug)]
+pub(crate) struct ObjectCache {
+    max_size: usize,
+    ttl: Duration,
+    cached_objects: Mutex<CachedObjects>,
+}
+
+impl ObjectCache {
+    pub(crate) fn new(max_size: usize, ttl: Duration) -> Self {
+        Self {
+            max_size,
+            ttl,
+            cached_objects: Mutex::new(CachedObjects {
+                objects: LruMap::new(Unlimited),
+                total_size: 0,
+            }),
+        }
+    }
+
+    /// Returns the object with `hash`, if it is cached and hasn't expired.
+    pub(crate) fn get(&self, hash: &Blake3Hash) -> Option<Bytes> {
+        let mut cached_objects = self.cached_objects.lock();
+        let object = cached_objects.objects.get(hash)?;
+
+        if object.cached_at.elapsed() < self.ttl {
+            return Some(object.data.clone());
+        }
+
+        cached_objects.remove(hash);
+        None
+    }
+
+    /// Caches `data` as the object with `hash`, removing the least recently used objects if the
+    /// cache is full. Objects larger than the cache are not cached.
+    ///
+    /// The data must have already been checked against `hash`.
+    pub(crate) fn insert(&self, hash: Blake3Hash, data: Bytes) {
+        if data.len() > self.max_size {
+            return;
+        }
+
+        let mut cached_objects = self.cached_objects.lock();
+        cached_objects.remove(&hash);
+
+        cached_objects.total_size += data.len();
+        cached_objects.objects.insert(
+            hash,
+            CachedObject {
+                data,
+                cached_at: Instant::now(),
+            },
+        );
+
+        while cached_objects.total_size > self.max_size {
+            let Some((_hash, object)) = cached_objects.objects.pop_oldest() else {
+                break;
+            };
+            cached_objects.total_size -= object.data.len();
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Per-client rate limits for HTTP requests.
+
+use parking_lot::Mutex;
+use schnellru::{ByLength, LruMap};
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+
+/// The maximum number of client IP addresses which are rate limited separately.
+///
+/// When there are more clients, the least recently seen clients are forgotten, which resets
+/// their limits.
+const MAX_RATE_LIMITED_CLIENTS: u32 = 10_000;
+
+/// A token bucket, which limits the rate of requests from a client.
+#[derive(Debug)]
+struct TokenBucket {
+    /// The number of requests which can be made immediately
+    tokens: f64,
+    /// The last time tokens were added to the bucket
+    last_refill: Instant,
+}
+
+/// Limits the rate of requests from each client IP address.
+///
+/// Requests are limited using a token bucket for each client, which allows short bursts of
+/// requests, and then limits requests to `requests_per_second`.
+#[derive(Debug)]
+pub(crate) struct PerIpRateLimiter {
+    requests_per_second: NonZeroU32,
+    burst: NonZeroU32,
+    clients: Mutex<LruMap<IpAddr, TokenBucket, ByLength>>,
+}
+
+impl PerIpRateLimiter {
+    pub(crate) fn new(requests_per_second: NonZeroU32, burst: NonZeroU32) -> Self {
+        Self {
+            requests_per_second,
+            burst,
+            clients: Mutex::new(LruMap::new(ByLength::new(MAX_RATE_LIMITED_CLIENTS))),
+        }
+    }
+
+    /// Takes a request from the limit for `client_ip`.
+    ///
+    /// Returns the time until the client can make another request if the limit is exceeded.
+    pub(crate) fn try_acquire(&self, client_ip: IpAddr) -> Result<(), Duration> {
+        let rate = f64::from(self.requests_per_second.get());
+        let burst = f64::from(self.burst.get());
+        let now = Instant::now();
+
+        let mut clients = self.clients.lock();
+        let Some(bucket) = clients.get_or_insert(client_ip, || TokenBucket {
+            tokens: burst,
+            last_refill: now,
+        }) else {
+            // The map always has space for new clients, so this is unreachable in practice
+            return Ok(());
+        };
+
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
@@ -3,9 +3,11 @@
 use crate::node_client::{NodeClient, RpcNodeClient};
 use anyhow::anyhow;
 use clap::{Parser, ValueHint};
+use prometheus_client::registry::Registry;
 use subspace_networking::libp2p::kad::Mode;
 use subspace_networking::libp2p::{Multiaddr, identity};
 use subspace_networking::protocols::request_response::handlers::cached_piece_by_index::CachedPieceByIndexRequestHandler;
+use subspace_networking::protocols::request_response::handlers::cached_pieces_by_index::CachedPiecesByIndexRequestHandler;
 use subspace_networking::protocols::request_response::handlers::piece_by_index::PieceByIndexRequestHandler;
 use subspace_networking::{Config, KademliaMode, Node, NodeRunner, construct};
 use tracing::{debug, info};
@@ -64,6 +66,7 @@ pub async fn configure_network(
         pending_out_connections,
         listen_on,
     }: NetworkArgs,
+    prometheus_registry: Option<&mut Registry>,
 ) -> anyhow::Result<(Node, NodeRunner, RpcNodeClient)> {
     info!(url = %node_rpc_url, "Connecting to node RPC");
     let node_client = RpcNodeClient::new(&node_rpc_url)
@@ -91,10 +94,9 @@ pub async fn configure_network(
     // TODO:
     // - use a fixed identity keypair
     // - cache known peers on disk
-    // - prometheus telemetry
     let keypair = identity::ed25519::Keypair::generate();
     let keypair = identity::Keypair::from(keypair);
-    let default_config = Config::new(dsn_protocol_version, keypair, None);
+    let default_config = Config::new(dsn_protocol_version, keypair, prometheus_registry);
 
     let config = Config {
         bootstrap_addresses: bootstrap_nodes,
@@ -104,6 +106,8 @@ pub async fn configure_network(
             // We need to enable protocol to request pieces
             CachedPieceByIndexRequestHandler::create(|_, _| async { None }),
             // We need to enable protocol to request pieces
+            CachedPiecesByIndexRequestHandler::create(|_, _| async { None }),
+            // We need to enable protocol to request pieces
             PieceByIndexRequestHandler::create(|_, _| async { None }),
         ],
         max_established_outgoing_connections: out_connections,
@@ -9,7 +9,7 @@ use futures::channel::oneshot;
 use futures::{FutureExt, select};
 use std::pin::pin;
 use subspace_gateway_rpc::{SubspaceGatewayRpc, SubspaceGatewayRpcConfig};
-use subspace_process::{run_future_in_dedicated_thread, shutdown_signal};
+use subspace_process::{AsyncJoinOnDrop, run_future_in_dedicated_thread, shutdown_signal};
 use tracing::info;
 
 /// Options for RPC server.
@@ -31,7 +31,8 @@ pub async fn run(run_options: RpcCommandOptions) -> anyhow::Result<()> {
         gateway_options,
         rpc_options,
     } = run_options;
-    let (object_fetcher, mut dsn_node_runner) = initialize_object_fetcher(gateway_options).await?;
+    let (object_fetcher, dsn_warmup, mut dsn_node_runner, _prometheus_worker) =
+        initialize_object_fetcher(gateway_options).await?;
     let dsn_fut = run_future_in_dedicated_thread(
         move || async move { dsn_node_runner.run().await },
         "gateway-networking".to_string(),
@@ -42,6 +43,9 @@ pub async fn run(run_options: RpcCommandOptions) -> anyhow::Result<()> {
     let rpc_handle = launch_rpc_server(rpc_api, rpc_options).await?;
     let rpc_fut = rpc_handle.stopped();
 
+    // Connect to the DSN while serving requests, so the RPC server can respond during warmup
+    let _dsn_warmup_handle = AsyncJoinOnDrop::new(tokio::spawn(dsn_warmup.run()), true);
+
     // This defines order in which things are dropped
     let dsn_fut = dsn_fut;
     let rpc_fut = rpc_fut;
@@ -1,53 +1,378 @@
 //! An object piece getter which uses the DSN to fetch pieces.
 
+mod adaptive_timeout;
+mod error;
+mod metrics;
+mod rate_limit;
+#[cfg(test)]
+mod tests;
+
+use crate::piece_getter::adaptive_timeout::LatencyTracker;
+use crate::piece_getter::metrics::DsnPieceGetterMetrics;
+use crate::piece_getter::rate_limit::{BandwidthLimiter, RateLimiter};
+use async_lock::{OnceCell, Semaphore};
 use async_trait::async_trait;
 use futures::stream::StreamExt;
-use futures::{FutureExt, Stream};
+use futures::{Stream, stream};
+use parking_lot::Mutex;
+use prometheus_client::registry::Registry;
+use schnellru::{ByLength, LruMap};
+use std::collections::HashMap;
 use std::fmt;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use subspace_archiving::piece_reconstructor::PiecesReconstructor;
 use subspace_core_primitives::pieces::{Piece, PieceIndex};
-use subspace_data_retrieval::piece_getter::PieceGetter;
+use subspace_data_retrieval::piece_getter::{PieceGetter, get_pieces_with_concurrency};
+use subspace_data_retrieval::segment_downloading::download_segment_pieces;
+use subspace_networking::libp2p::PeerId;
 use subspace_networking::utils::piece_provider::{PieceProvider, PieceValidator};
+use tracing::{debug, warn};
+
+pub use crate::piece_getter::adaptive_timeout::AdaptiveTimeout;
+pub use crate::piece_getter::error::PieceFetchError;
+pub use crate::piece_getter::rate_limit::{BandwidthLimit, RateLimit};
 
 /// The maximum number of peer-to-peer walking rounds for L1 archival storage.
 const MAX_RANDOM_WALK_ROUNDS: usize = 15;
 
-/// Wrapper type for [`PieceProvider`], so it can implement [`PieceGetter`]
-pub struct DsnPieceGetter<PV: PieceValidator>(PieceProvider<PV>);
+/// How often connected peers are checked while warming up DSN connections.
+const WARMUP_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
-impl<PV> fmt::Debug for DsnPieceGetter<PV>
-where
-    PV: PieceValidator,
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_tuple("DsnPieceGetter")
-            .field(&format!("{:?}", self.0))
-            .finish()
+/// The default maximum number of DSN cache fetches which run at the same time.
+pub const DEFAULT_MAX_IN_FLIGHT_CACHE_FETCHES: NonZeroUsize =
+    NonZeroUsize::new(100).expect("Not zero; qed");
+
+/// A source of pieces from the DSN.
+///
+/// This is implemented by [`PieceProvider`], and by mock sources in tests.
+#[async_trait]
+pub trait DsnPieceSource: fmt::Debug + Send + Sync {
+    /// Get pieces with provided indices from DSN caches (L2).
+    ///
+    /// The number of elements in the returned stream is the same as the number of unique
+    /// `piece_indices`. Pieces which are not in any cache are returned as `None`.
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a>;
+
+    /// Get a piece from archival storage (L1).
+    ///
+    /// Returns `Ok(None)` if peers responded, but none of them had the piece, and an error if no
+    /// peers responded.
+    async fn get_from_archival_storage(
+        &self,
+        piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>>;
+
+    /// Returns the connected DSN peers which can serve pieces.
+    ///
+    /// Sources which aren't connected to the DSN have no peers.
+    async fn connected_peers(&self) -> anyhow::Result<Vec<PeerId>> {
+        Ok(Vec::new())
+    }
+
+    /// Returns true if no DSN peers are connected, so pieces can't be retrieved.
+    ///
+    /// Sources which don't get pieces from DSN peers are never disconnected.
+    async fn is_disconnected(&self) -> bool {
+        false
+    }
+
+    /// Starts connecting to bootstrap nodes and other known DSN peers.
+    ///
+    /// Sources which aren't connected to the DSN don't need to connect to anything.
+    async fn warmup(&self) -> anyhow::Result<()> {
+        Ok(())
     }
 }
 
-// TODO:
-// - reconstruct segment if piece is missing
-// - move this piece getter impl into a new library part of this crate
 #[async_trait]
-impl<PV> PieceGetter for DsnPieceGetter<PV>
+impl<PV> DsnPieceSource for PieceProvider<PV>
 where
     PV: PieceValidator,
 {
-    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
-        if let Some((got_piece_index, maybe_piece)) =
-            self.0.get_from_cache([piece_index]).await.next().await
-        {
-            assert_eq!(piece_index, got_piece_index);
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        Box::new(PieceProvider::get_from_cache(self, piece_indices).await)
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        Ok(self
+            .try_get_piece_from_archival_storage(piece_index, MAX_RANDOM_WALK_ROUNDS)
+            .await?)
+    }
 
-            if let Some(piece) = maybe_piece {
-                return Ok(Some(piece));
+    async fn connected_peers(&self) -> anyhow::Result<Vec<PeerId>> {
+        Ok(self.node().connected_servers().await?)
+    }
+
+    async fn is_disconnected(&self) -> bool {
+        match self.node().connected_servers().await {
+            Ok(connected_servers) => connected_servers.is_empty(),
+            Err(error) => {
+                debug!(%error, "Failed to get connected DSN peers");
+                true
             }
         }
+    }
 
-        Ok(self
-            .0
-            .get_piece_from_archival_storage(piece_index, MAX_RANDOM_WALK_ROUNDS)
-            .await)
+    async fn warmup(&self) -> anyhow::Result<()> {
+        Ok(self.node().bootstrap().await?)
+    }
+}
+
+/// Where a [`DsnPieceGetter`] looks for pieces.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum RetrievalMode {
+    /// Only get pieces from DSN caches, which is faster, but some pieces might not be found.
+    CacheOnly,
+    /// Get pieces from DSN caches, then get missing pieces from archival storage (L1).
+    #[default]
+    CacheThenArchival,
+}
+
+/// How pieces which are missing from DSN caches are retried.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CacheRetryPolicy {
+    /// The maximum number of retries for each missing piece.
+    pub max_retries: u32,
+    /// The delay before the first retry, which doubles after each retry.
+    pub initial_delay: Duration,
+}
+
+impl Default for CacheRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The order of the pieces returned by [`DsnPieceGetter::get_pieces`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum PieceOrder {
+    /// Pieces are returned as soon as they are retrieved.
+    #[default]
+    Completion,
+    /// Pieces are returned in the order of the requested piece indexes.
+    ///
+    /// At most `max_buffered` pieces are retrieved or buffered at the same time, so a slow piece
+    /// delays the pieces after it, rather than using unbounded memory.
+    Input {
+        /// The maximum number of pieces which are retrieved or buffered at the same time.
+        max_buffered: NonZeroUsize,
+    },
+}
+
+/// Options for a [`DsnPieceGetter`].
+#[derive(Clone, Debug)]
+pub struct DsnPieceGetterOptions {
+    /// Where to look for pieces.
+    pub retrieval_mode: RetrievalMode,
+    /// How pieces which are missing from DSN caches are retried, before trying archival storage.
+    pub cache_retry_policy: CacheRetryPolicy,
+    /// The maximum number of DSN cache fetches which run at the same time, across all requests.
+    pub max_in_flight_cache_fetches: NonZeroUsize,
+    /// The maximum time spent getting each piece, including retries and archival storage.
+    /// Pieces which time out are reported as missing, or as [`PieceFetchError::TimedOut`].
+    ///
+    /// If `adaptive_timeout` is also set, this timeout is only used until enough piece latencies
+    /// have been recorded.
+    pub piece_timeout: Option<Duration>,
+    /// If set, the piece timeout is based on the latency of recent successful piece fetches.
+    pub adaptive_timeout: Option<AdaptiveTimeout>,
+    /// The order of the pieces returned by `get_pieces`.
+    pub piece_order: PieceOrder,
+    /// If set, pieces which can't be found in the DSN are reconstructed from the other pieces in
+    /// their segment.
+    ///
+    /// This downloads half the pieces in the segment for each missing piece, so it is off by
+    /// default.
+    pub piece_reconstructor: Option<PiecesReconstructor>,
+    /// If set, up to this many recently retrieved pieces are kept in memory, and repeated
+    /// requests for them are served without using the DSN.
+    pub piece_cache_capacity: Option<NonZeroU32>,
+    /// If set, limits the rate of DSN cache and archival storage requests.
+    ///
+    /// Retries are also limited, but pieces served from the local piece cache are not.
+    pub rate_limit: Option<RateLimit>,
+    /// If set, limits the bandwidth of DSN cache and archival storage piece downloads.
+    ///
+    /// This applies alongside `rate_limit`, and pieces served from the local piece cache are not
+    /// limited.
+    pub bandwidth_limit: Option<BandwidthLimit>,
+    /// If set, the maximum number of cache retries for all the pieces in a `get_pieces` call.
+    ///
+    /// Once the budget is spent, pieces which are missing from DSN caches are not retried.
+    pub get_pieces_retry_budget: Option<u32>,
+}
+
+impl Default for DsnPieceGetterOptions {
+    fn default() -> Self {
+        Self {
+            retrieval_mode: RetrievalMode::default(),
+            cache_retry_policy: CacheRetryPolicy::default(),
+            max_in_flight_cache_fetches: DEFAULT_MAX_IN_FLIGHT_CACHE_FETCHES,
+            piece_timeout: None,
+            adaptive_timeout: None,
+            piece_order: PieceOrder::default(),
+            piece_reconstructor: None,
+            piece_cache_capacity: None,
+            rate_limit: None,
+            bandwidth_limit: None,
+            get_pieces_retry_budget: None,
+        }
+    }
+}
+
+/// Builds a [`DsnPieceGetter`], starting with the default [`DsnPieceGetterOptions`].
+#[derive(Debug)]
+pub struct DsnPieceGetterBuilder<'a, PS>
+where
+    PS: DsnPieceSource,
+{
+    piece_source: PS,
+    options: DsnPieceGetterOptions,
+    registry: Option<&'a mut Registry>,
+}
+
+impl<'a, PS> DsnPieceGetterBuilder<'a, PS>
+where
+    PS: DsnPieceSource,
+{
+    /// Creates a new builder, which gets pieces from `piece_source`.
+    pub fn new(piece_source: PS) -> Self {
+        Self {
+            piece_source,
+            options: DsnPieceGetterOptions::default(),
+            registry: None,
+        }
+    }
+
+    /// Sets where to look for pieces.
+    pub fn retrieval_mode(mut self, retrieval_mode: RetrievalMode) -> Self {
+        self.options.retrieval_mode = retrieval_mode;
+        self
+    }
+
+    /// Sets how pieces which are missing from DSN caches are retried.
+    pub fn cache_retry_policy(mut self, cache_retry_policy: CacheRetryPolicy) -> Self {
+        self.options.cache_retry_policy = cache_retry_policy;
+        self
+    }
+
+    /// Sets the maximum number of DSN cache fetches which run at the same time.
+    pub fn max_in_flight_cache_fetches(
+        mut self,
+        max_in_flight_cache_fetches: NonZeroUsize,
+    ) -> Self {
+        self.options.max_in_flight_cache_fetches = max_in_flight_cache_fetches;
+        self
+    }
+
+    /// Sets the maximum time spent getting each piece.
+    pub fn piece_timeout(mut self, piece_timeout: Duration) -> Self {
+        self.options.piece_timeout = Some(piece_timeout);
+        self
+    }
+
+    /// Bases the piece timeout on the latency of recent successful piece fetches.
+    pub fn adaptive_timeout(mut self, adaptive_timeout: AdaptiveTimeout) -> Self {
+        self.options.adaptive_timeout = Some(adaptive_timeout);
+        self
+    }
+
+    /// Sets the order of the pieces returned by `get_pieces`.
+    pub fn piece_order(mut self, piece_order: PieceOrder) -> Self {
+        self.options.piece_order = piece_order;
+        self
+    }
+
+    /// Reconstructs pieces which can't be found in the DSN using `piece_reconstructor`.
+    pub fn piece_reconstructor(mut self, piece_reconstructor: PiecesReconstructor) -> Self {
+        self.options.piece_reconstructor = Some(piece_reconstructor);
+        self
+    }
+
+    /// Keeps up to `piece_cache_capacity` recently retrieved pieces in memory.
+    pub fn piece_cache_capacity(mut self, piece_cache_capacity: NonZeroU32) -> Self {
+        self.options.piece_cache_capacity = Some(piece_cache_capacity);
+        self
+    }
+
+    /// Limits the rate of DSN cache and archival storage requests.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.options.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Limits the bandwidth of DSN cache and archival storage piece downloads.
+    pub fn bandwidth_limit(mut self, bandwidth_limit: BandwidthLimit) -> Self {
+        self.options.bandwidth_limit = Some(bandwidth_limit);
+        self
+    }
+
+    /// Sets the maximum number of cache retries for all the pieces in a `get_pieces` call.
+    pub fn get_pieces_retry_budget(mut self, get_pieces_retry_budget: u32) -> Self {
+        self.options.get_pieces_retry_budget = Some(get_pieces_retry_budget);
+        self
+    }
+
+    /// Registers piece request metrics in `registry`.
+    pub fn registry(mut self, registry: &'a mut Registry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Builds the configured DSN piece getter.
+    pub fn build(self) -> DsnPieceGetter<PS> {
+        DsnPieceGetter::new_with_options(self.piece_source, self.options, self.registry)
+    }
+}
+
+/// Wrapper type for a [`DsnPieceSource`] like [`PieceProvider`], so it can implement
+/// [`PieceGetter`]
+#[derive(Debug)]
+pub struct DsnPieceGetter<PS>
+where
+    PS: DsnPieceSource,
+{
+    piece_source: PS,
+    options: DsnPieceGetterOptions,
+    /// Limits the number of DSN cache fetches which run at the same time
+    cache_fetch_semaphore: Semaphore,
+    /// Recent piece latencies, if the adaptive timeout is enabled
+    latency_tracker: Option<LatencyTracker>,
+    /// Pieces which are currently being retrieved, shared by concurrent requests for the same
+    /// piece
+    in_flight_pieces: InFlightPieces,
+    /// Recently retrieved pieces, if the local piece cache is enabled
+    piece_cache: Option<Mutex<LruMap<PieceIndex, Piece, ByLength>>>,
+    /// Limits the rate of DSN requests, if a rate limit is configured
+    rate_limiter: Option<RateLimiter>,
+    /// Limits the bandwidth of DSN piece downloads, if a bandwidth limit is configured
+    bandwidth_limiter: Option<BandwidthLimiter>,
+    metrics: Option<DsnPieceGetterMetrics>,
+}
+
+// TODO: move this piece getter impl into a new library part of this crate
+#[async_trait]
+impl<PS> PieceGetter for DsnPieceGetter<PS>
+where
+    PS: DsnPieceSource,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        PieceFetchError::piece_getter_result(self.fetch_piece(piece_index).await)
     }
 
     async fn get_pieces<'a>(
@@ -56,34 +381,570 @@ where
     ) -> anyhow::Result<
         Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
     > {
-        let stream =
-            self.0
-                .get_from_cache(piece_indices)
-                .await
-                .then(move |(piece_index, maybe_piece)| {
-                    let fut = async move {
-                        if let Some(piece) = maybe_piece {
-                            return (piece_index, Ok(Some(piece)));
-                        }
+        Ok(Box::new(self.fetch_pieces(piece_indices).map(
+            |(piece_index, piece_result)| {
+                (
+                    piece_index,
+                    PieceFetchError::piece_getter_result(piece_result),
+                )
+            },
+        )))
+    }
+}
+
+impl<PS> DsnPieceGetter<PS>
+where
+    PS: DsnPieceSource,
+{
+    /// Returns a builder for a DSN piece getter, which gets pieces from `piece_source`.
+    pub fn builder<'a>(piece_source: PS) -> DsnPieceGetterBuilder<'a, PS> {
+        DsnPieceGetterBuilder::new(piece_source)
+    }
 
-                        self.0
-                            .get_piece_from_archival_storage(piece_index, MAX_RANDOM_WALK_ROUNDS)
-                            .map(|piece| (piece_index, Ok(piece)))
-                            .await
-                    };
-                    Box::pin(fut)
-                });
+    /// Creates new DSN piece getter, which gets pieces from `piece_source` using `options`.
+    ///
+    /// If `registry` is provided, piece request metrics are registered in it.
+    pub fn new_with_options(
+        piece_source: PS,
+        options: DsnPieceGetterOptions,
+        registry: Option<&mut Registry>,
+    ) -> Self {
+        let cache_fetch_semaphore = Semaphore::new(options.max_in_flight_cache_fetches.get());
+        let latency_tracker = options.adaptive_timeout.map(LatencyTracker::new);
+        let piece_cache = options
+            .piece_cache_capacity
+            .map(|capacity| Mutex::new(LruMap::new(ByLength::new(capacity.get()))));
+        let rate_limiter = options.rate_limit.map(RateLimiter::new);
+        let bandwidth_limiter = options.bandwidth_limit.map(BandwidthLimiter::new);
 
-        Ok(Box::new(stream))
+        Self {
+            piece_source,
+            options,
+            cache_fetch_semaphore,
+            latency_tracker,
+            in_flight_pieces: Mutex::default(),
+            piece_cache,
+            rate_limiter,
+            bandwidth_limiter,
+            metrics: registry.map(DsnPieceGetterMetrics::new),
+        }
+    }
+
+    /// Returns the connected DSN peers which can serve pieces.
+    pub async fn connected_peers(&self) -> anyhow::Result<Vec<PeerId>> {
+        self.piece_source.connected_peers().await
+    }
+
+    /// Returns true if the DSN is reachable, because at least one peer which can serve pieces is
+    /// connected.
+    pub async fn is_connected(&self) -> bool {
+        match self.connected_peers().await {
+            Ok(connected_peers) => !connected_peers.is_empty(),
+            Err(error) => {
+                debug!(%error, "Failed to get connected DSN peers");
+                false
+            }
+        }
+    }
+
+    /// Connects to DSN bootstrap nodes and other known peers, and waits until a peer which can
+    /// serve pieces is connected, so the first piece request is fast.
+    ///
+    /// Returns the number of connected peers, or an error if no peers are connected within
+    /// `timeout`.
+    pub async fn warmup(&self, timeout: Duration) -> anyhow::Result<usize> {
+        let warmup = async {
+            self.piece_source.warmup().await?;
+
+            loop {
+                let connected_peers = self.connected_peers().await?;
+                if !connected_peers.is_empty() {
+                    return anyhow::Ok(connected_peers.len());
+                }
+
+                tokio::time::sleep(WARMUP_POLL_INTERVAL).await;
+            }
+        };
+
+        tokio::time::timeout(timeout, warmup)
+            .await
+            .map_err(|_elapsed| anyhow::anyhow!("No DSN peers connected after {timeout:?}"))?
+    }
+
+    /// Returns the timeout currently used for each piece, or `None` if pieces don't time out.
+    ///
+    /// With an adaptive timeout, this changes as piece latencies are recorded.
+    pub fn effective_piece_timeout(&self) -> Option<Duration> {
+        self.latency_tracker
+            .as_ref()
+            .and_then(LatencyTracker::timeout)
+            .or(self.options.piece_timeout)
+    }
+
+    /// Gets a single piece from the DSN.
+    ///
+    /// Unlike [`PieceGetter::get_piece`], which reports pieces that can't be found as missing,
+    /// this returns the reason the piece couldn't be retrieved.
+    pub async fn fetch_piece(&self, piece_index: PieceIndex) -> Result<Piece, PieceFetchError> {
+        self.get_piece_with_retry_budget(piece_index, None).await
+    }
+
+    /// Gets pieces with the provided indices from the DSN, in the order set by the options.
+    ///
+    /// Unlike [`PieceGetter::get_pieces`], which reports pieces that can't be found as missing,
+    /// each piece result contains the reason the piece couldn't be retrieved.
+    pub fn fetch_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Result<Piece, PieceFetchError>)> + Send + Unpin + 'a>
+    {
+        // Each piece is fetched separately, so cache fetches can be limited by the semaphore,
+        // without serializing retries and archival storage lookups for other pieces.
+        //
+        // The piece futures are owned by the returned stream and are not spawned, so dropping the
+        // stream cancels any outstanding DSN requests.
+        let concurrency = piece_indices.len().max(1);
+        let retry_budget = self
+            .options
+            .get_pieces_retry_budget
+            .map(|retries| Arc::new(RetryBudget::new(retries)));
+        let piece_futures = stream::iter(piece_indices).map(move |piece_index| {
+            let retry_budget = retry_budget.clone();
+
+            async move {
+                let piece_result = self
+                    .get_piece_with_retry_budget(piece_index, retry_budget.as_deref())
+                    .await;
+
+                (piece_index, piece_result)
+            }
+        });
+
+        match self.options.piece_order {
+            PieceOrder::Completion => Box::new(piece_futures.buffer_unordered(concurrency)),
+            PieceOrder::Input { max_buffered } => {
+                Box::new(piece_futures.buffered(max_buffered.get()))
+            }
+        }
+    }
+
+    /// Gets a single piece, recording metrics if they are enabled.
+    ///
+    /// If `retry_budget` is provided, cache retries are limited by it.
+    async fn get_piece_with_retry_budget(
+        &self,
+        piece_index: PieceIndex,
+        retry_budget: Option<&RetryBudget>,
+    ) -> Result<Piece, PieceFetchError> {
+        let Some(metrics) = &self.metrics else {
+            return self.get_piece_cached(piece_index, retry_budget).await;
+        };
+
+        metrics.piece_requested.inc();
+        let start = Instant::now();
+        let piece_result = self.get_piece_cached(piece_index, retry_budget).await;
+        metrics
+            .piece_get_time
+            .observe(start.elapsed().as_secs_f64());
+        if piece_result.is_err() {
+            metrics.piece_failure.inc();
+        }
+
+        piece_result
+    }
+
+    /// Gets a single piece from the local piece cache, or retrieves it and adds it to the cache,
+    /// if the cache is enabled.
+    async fn get_piece_cached(
+        &self,
+        piece_index: PieceIndex,
+        retry_budget: Option<&RetryBudget>,
+    ) -> Result<Piece, PieceFetchError> {
+        let Some(piece_cache) = &self.piece_cache else {
+            return self.get_piece_coalesced(piece_index, retry_budget).await;
+        };
+
+        if let Some(piece) = piece_cache.lock().get(&piece_index) {
+            return Ok(piece.clone());
+        }
+
+        let piece = self.get_piece_coalesced(piece_index, retry_budget).await?;
+        piece_cache.lock().insert(piece_index, piece.clone());
+
+        Ok(piece)
+    }
+
+    /// Gets a single piece, sharing the retrieval with any concurrent requests for the same piece.
+    ///
+    /// Results are only shared while the retrieval is in flight, later requests retrieve the
+    /// piece again. Network errors are not shared, so each waiting request retries the retrieval.
+    async fn get_piece_coalesced(
+        &self,
+        piece_index: PieceIndex,
+        retry_budget: Option<&RetryBudget>,
+    ) -> Result<Piece, PieceFetchError> {
+        let in_flight_piece = InFlightPiece::new(&self.in_flight_pieces, piece_index);
+
+        let piece_result = in_flight_piece
+            .cell()
+            .get_or_try_init(|| async {
+                match self
+                    .get_piece_or_reconstruct(piece_index, retry_budget)
+                    .await
+                {
+                    Err(error @ PieceFetchError::Network { .. }) => Err(error),
+                    piece_result => Ok(piece_result),
+                }
+            })
+            .await?;
+
+        match piece_result {
+            Ok(piece) => Ok(piece.clone()),
+            Err(error) => Err(error
+                .try_clone()
+                .expect("Network errors are not stored in the cell; qed")),
+        }
+    }
+
+    /// Gets a single piece from the DSN, then reconstructs it from the other pieces in its segment
+    /// if it is missing, and reconstruction is enabled.
+    async fn get_piece_or_reconstruct(
+        &self,
+        piece_index: PieceIndex,
+        retry_budget: Option<&RetryBudget>,
+    ) -> Result<Piece, PieceFetchError> {
+        match self.get_piece_with_timeout(piece_index, retry_budget).await {
+            Ok(piece) => Ok(piece),
+            Err(error) => self.reconstruct_piece(piece_index, error).await,
+        }
+    }
+
+    /// Reconstructs a piece which couldn't be retrieved with `error` from the other pieces in its
+    /// segment, if reconstruction is enabled.
+    ///
+    /// Returns `error` if the piece can't be reconstructed.
+    async fn reconstruct_piece(
+        &self,
+        piece_index: PieceIndex,
+        error: PieceFetchError,
+    ) -> Result<Piece, PieceFetchError> {
+        // The DSN is unreachable, so the other pieces in the segment can't be downloaded
+        if matches!(
+            error,
+            PieceFetchError::Network { .. } | PieceFetchError::NotConnected { .. }
+        ) {
+            return Err(error);
+        }
+        let Some(piece_reconstructor) = &self.options.piece_reconstructor else {
+            return Err(error);
+        };
+
+        debug!(%piece_index, "Piece is missing from the DSN, reconstructing it from its segment");
+
+        // Sibling pieces are not reconstructed, because that would download the same segment
+        // again for each missing sibling
+        let segment_pieces = match download_segment_pieces(
+            piece_index.segment_index(),
+            &WithoutReconstruction(self),
+            0,
+            None,
+        )
+        .await
+        {
+            Ok(segment_pieces) => segment_pieces,
+            Err(reconstruction_error) => {
+                debug!(%piece_index, %reconstruction_error, "Not enough pieces to reconstruct piece");
+                return Err(error);
+            }
+        };
+
+        let piece_reconstructor = piece_reconstructor.clone();
+        let piece = tokio::task::spawn_blocking(move || {
+            piece_reconstructor.reconstruct_piece(&segment_pieces, piece_index.position() as usize)
+        })
+        .await
+        .expect("Panic if blocking task panicked");
+
+        piece.map_err(|reconstruction_error| {
+            warn!(%piece_index, %reconstruction_error, "Failed to reconstruct piece");
+            error
+        })
+    }
+
+    /// Gets a single piece from DSN caches, then from archival storage if needed, returning an
+    /// error if it takes longer than the piece timeout.
+    ///
+    /// Successful fetch latencies are recorded for the adaptive timeout.
+    async fn get_piece_with_timeout(
+        &self,
+        piece_index: PieceIndex,
+        retry_budget: Option<&RetryBudget>,
+    ) -> Result<Piece, PieceFetchError> {
+        let start = tokio::time::Instant::now();
+        let piece_future = async {
+            let piece_result = self
+                .get_piece_without_timeout(piece_index, retry_budget)
+                .await;
+            if piece_result.is_ok()
+                && let Some(latency_tracker) = &self.latency_tracker
+            {
+                latency_tracker.record(start.elapsed());
+            }
+
+            piece_result
+        };
+
+        let Some(piece_timeout) = self.effective_piece_timeout() else {
+            return piece_future.await;
+        };
+
+        match tokio::time::timeout(piece_timeout, piece_future).await {
+            Ok(piece_result) => piece_result,
+            Err(_elapsed) => {
+                debug!(%piece_index, ?piece_timeout, "Timed out getting piece");
+                Err(PieceFetchError::TimedOut {
+                    piece_index,
+                    timeout: piece_timeout,
+                })
+            }
+        }
+    }
+
+    /// Gets a single piece from DSN caches, then from archival storage if needed, without a
+    /// timeout.
+    async fn get_piece_without_timeout(
+        &self,
+        piece_index: PieceIndex,
+        retry_budget: Option<&RetryBudget>,
+    ) -> Result<Piece, PieceFetchError> {
+        let maybe_piece = self.get_piece_from_cache(piece_index).await;
+
+        self.get_missing_piece(piece_index, maybe_piece, retry_budget)
+            .await
+    }
+
+    /// Waits until a DSN request is allowed by the rate limit and bandwidth limit, if there are
+    /// any.
+    ///
+    /// Bandwidth for a whole piece is reserved, so concurrent requests can't exceed the bandwidth
+    /// limit. If the request doesn't return a piece, call [`Self::release_bandwidth`].
+    async fn acquire_rate_limit(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        if let Some(bandwidth_limiter) = &self.bandwidth_limiter {
+            bandwidth_limiter.acquire(Piece::SIZE as u64).await;
+        }
+    }
+
+    /// Returns the bandwidth reserved by [`Self::acquire_rate_limit`], after a request which
+    /// didn't return a piece.
+    fn release_bandwidth(&self) {
+        if let Some(bandwidth_limiter) = &self.bandwidth_limiter {
+            bandwidth_limiter.release(Piece::SIZE as u64);
+        }
+    }
+
+    /// Gets a single piece from DSN caches, waiting if too many cache fetches are in flight.
+    async fn get_piece_from_cache(&self, piece_index: PieceIndex) -> Option<Piece> {
+        // Wait for the rate limit first, so waiting requests don't hold cache fetch permits
+        self.acquire_rate_limit().await;
+        let _permit = self.cache_fetch_semaphore.acquire().await;
+
+        let Some((got_piece_index, maybe_piece)) = self
+            .piece_source
+            .get_from_cache(vec![piece_index])
+            .await
+            .next()
+            .await
+        else {
+            self.release_bandwidth();
+            return None;
+        };
+        assert_eq!(piece_index, got_piece_index);
+
+        if maybe_piece.is_none() {
+            self.release_bandwidth();
+        } else if let Some(metrics) = &self.metrics {
+            metrics.cache_hit.inc();
+        }
+
+        maybe_piece
+    }
+
+    /// If a piece wasn't found in DSN caches, retries the caches, then gets it from archival
+    /// storage, depending on the options.
+    ///
+    /// If `retry_budget` is provided, each retry is taken from it, and the piece isn't retried
+    /// once it is spent.
+    async fn get_missing_piece(
+        &self,
+        piece_index: PieceIndex,
+        maybe_piece: Option<Piece>,
+        retry_budget: Option<&RetryBudget>,
+    ) -> Result<Piece, PieceFetchError> {
+        if let Some(piece) = maybe_piece {
+            return Ok(piece);
+        }
+
+        let CacheRetryPolicy {
+            max_retries,
+            initial_delay,
+        } = self.options.cache_retry_policy;
+        let mut delay = initial_delay;
+        for retry in 1..=max_retries {
+            if let Some(retry_budget) = retry_budget
+                && !retry_budget.try_spend()
+            {
+                debug!(%piece_index, retry, "Retry budget is spent, not retrying piece");
+                break;
+            }
+
+            tokio::time::sleep(delay).await;
+            delay = delay.saturating_mul(2);
+
+            if let Some(piece) = self.get_piece_from_cache(piece_index).await {
+                debug!(%piece_index, retry, "Found piece in DSN cache after retrying");
+                return Ok(piece);
+            }
+        }
+
+        // Without peers, the piece is missing because the DSN can't be reached, and archival
+        // storage lookups would only time out
+        if self.piece_source.is_disconnected().await {
+            debug!(%piece_index, "No DSN peers are connected, piece can't be retrieved");
+            return Err(PieceFetchError::NotConnected { piece_index });
+        }
+
+        match self.options.retrieval_mode {
+            RetrievalMode::CacheOnly => Err(PieceFetchError::NotFound { piece_index }),
+            RetrievalMode::CacheThenArchival => {
+                self.get_piece_from_archival_storage(piece_index).await
+            }
+        }
+    }
+
+    /// Gets a single piece from archival storage, waiting for the rate limit if there is one.
+    async fn get_piece_from_archival_storage(
+        &self,
+        piece_index: PieceIndex,
+    ) -> Result<Piece, PieceFetchError> {
+        if let Some(metrics) = &self.metrics {
+            metrics.archival_fallback.inc();
+        }
+
+        self.acquire_rate_limit().await;
+        let piece_result = match self
+            .piece_source
+            .get_from_archival_storage(piece_index)
+            .await
+        {
+            Ok(Some(piece)) => Ok(piece),
+            Ok(None) => Err(PieceFetchError::NotFound { piece_index }),
+            Err(error) => Err(PieceFetchError::from_archival_error(piece_index, error)),
+        };
+        if piece_result.is_err() {
+            self.release_bandwidth();
+        }
+
+        piece_result
+    }
+}
+
+/// The cache retries left for all the pieces in a [`DsnPieceGetter::get_pieces`] call.
+#[derive(Debug)]
+struct RetryBudget {
+    remaining: AtomicU32,
+}
+
+impl RetryBudget {
+    fn new(retries: u32) -> Self {
+        Self {
+            remaining: AtomicU32::new(retries),
+        }
+    }
+
+    /// Takes a retry from the budget, returning false if the budget is spent.
+    fn try_spend(&self) -> bool {
+        self.remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+                remaining.checked_sub(1)
+            })
+            .is_ok()
     }
 }
 
-impl<PV> DsnPieceGetter<PV>
+/// Pieces which are being retrieved by a [`DsnPieceGetter`], by piece index.
+type InFlightPieces = Mutex<HashMap<PieceIndex, Arc<OnceCell<Result<Piece, PieceFetchError>>>>>;
+
+/// A request for a piece which is being retrieved by a [`DsnPieceGetter`].
+///
+/// The piece is removed from the in-flight pieces when the last request for it finishes or is
+/// cancelled.
+struct InFlightPiece<'a> {
+    in_flight_pieces: &'a InFlightPieces,
+    piece_index: PieceIndex,
+    /// Always `Some` until dropped
+    cell: Option<Arc<OnceCell<Result<Piece, PieceFetchError>>>>,
+}
+
+impl<'a> InFlightPiece<'a> {
+    fn new(in_flight_pieces: &'a InFlightPieces, piece_index: PieceIndex) -> Self {
+        let cell = Arc::clone(in_flight_pieces.lock().entry(piece_index).or_default());
+
+        Self {
+            in_flight_pieces,
+            piece_index,
+            cell: Some(cell),
+        }
+    }
+
+    fn cell(&self) -> &OnceCell<Result<Piece, PieceFetchError>> {
+        self.cell.as_ref().expect("Only taken in drop; qed")
+    }
+}
+
+impl Drop for InFlightPiece<'_> {
+    fn drop(&mut self) {
+        // Reference counts only change while the lock is held, so exactly one request sees the
+        // last reference outside the map
+        let mut in_flight_pieces = self.in_flight_pieces.lock();
+        let cell = self.cell.take().expect("Only taken in drop; qed");
+
+        if Arc::strong_count(&cell) == 2 {
+            in_flight_pieces.remove(&self.piece_index);
+        }
+        drop(cell);
+    }
+}
+
+/// Gets pieces from a [`DsnPieceGetter`] without reconstructing missing pieces.
+#[derive(Debug)]
+struct WithoutReconstruction<'a, PS>(&'a DsnPieceGetter<PS>)
 where
-    PV: PieceValidator,
+    PS: DsnPieceSource;
+
+#[async_trait]
+impl<PS> PieceGetter for WithoutReconstruction<'_, PS>
+where
+    PS: DsnPieceSource,
 {
-    /// Creates new DSN piece getter.
-    pub fn new(piece_provider: PieceProvider<PV>) -> Self {
-        Self(piece_provider)
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        PieceFetchError::piece_getter_result(self.0.get_piece_with_timeout(piece_index, None).await)
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let concurrency = NonZeroUsize::new(piece_indices.len()).unwrap_or(NonZeroUsize::MIN);
+
+        get_pieces_with_concurrency(
+            |piece_index| Box::pin(self.get_piece(piece_index)),
+            piece_indices,
+            concurrency,
+        )
     }
 }
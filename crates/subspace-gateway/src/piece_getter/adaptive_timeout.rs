@@ -0,0 +1,79 @@
+//! Piece timeouts which adapt to the latency of recent piece fetches.
+
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+/// Configuration for a piece timeout based on the latency of recent successful piece fetches.
+///
+/// The timeout is the `percentile` latency of the last `max_samples` fetches, multiplied by
+/// `factor`, and at least `min_timeout`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdaptiveTimeout {
+    /// The latency percentile the timeout is based on, between 0.0 and 1.0.
+    pub percentile: f64,
+    /// The multiplier applied to the percentile latency.
+    pub factor: f64,
+    /// The minimum timeout.
+    pub min_timeout: Duration,
+    /// The number of recent latencies which are used to calculate the timeout.
+    pub max_samples: NonZeroUsize,
+    /// The number of latencies needed before the adaptive timeout is used.
+    pub min_samples: usize,
+}
+
+impl Default for AdaptiveTimeout {
+    fn default() -> Self {
+        Self {
+            percentile: 0.95,
+            factor: 2.0,
+            min_timeout: Duration::from_secs(1),
+            max_samples: NonZeroUsize::new(1000).expect("Not zero; qed"),
+            min_samples: 20,
+        }
+    }
+}
+
+/// Tracks recent piece fetch latencies, and calculates an adaptive timeout from them.
+#[derive(Debug)]
+pub(super) struct LatencyTracker {
+    config: AdaptiveTimeout,
+    latencies: Mutex<VecDeque<Duration>>,
+}
+
+impl LatencyTracker {
+    pub(super) fn new(config: AdaptiveTimeout) -> Self {
+        Self {
+            config,
+            latencies: Mutex::new(VecDeque::with_capacity(config.max_samples.get())),
+        }
+    }
+
+    /// Records the latency of a successful piece fetch, replacing the oldest latency if the
+    /// window is full.
+    pub(super) fn record(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock();
+        if latencies.len() >= self.config.max_samples.get() {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    /// Returns the adaptive timeout, or `None` if there aren't enough latencies yet.
+    pub(super) fn timeout(&self) -> Option<Duration> {
+        let mut latencies = self.latencies.lock().iter().copied().collect::<Vec<_>>();
+        if latencies.is_empty() || latencies.len() < self.config.min_samples {
+            return None;
+        }
+        latencies.sort_unstable();
+
+        let percentile = self.config.percentile.clamp(0.0, 1.0);
+        let index = ((latencies.len() as f64 * percentile).ceil() as usize)
+            .saturating_sub(1)
+            .min(latencies.len() - 1);
+        let timeout = latencies[index].mul_f64(self.config.factor.max(0.0));
+
+        Some(timeout.max(self.config.min_timeout))
+    }
+}
@@ -0,0 +1,97 @@
+//! Errors which happen while getting pieces from the DSN.
+
+use std::time::Duration;
+use subspace_core_primitives::pieces::{Piece, PieceIndex};
+use subspace_networking::utils::piece_provider::PieceProviderError;
+use thiserror::Error;
+
+/// The reason a [`DsnPieceGetter`](super::DsnPieceGetter) couldn't get a piece.
+#[derive(Debug, Error)]
+pub enum PieceFetchError {
+    /// Peers responded, but none of them had the piece, and it couldn't be reconstructed
+    #[error("Piece {piece_index} was not found in the DSN")]
+    NotFound {
+        /// Requested piece index
+        piece_index: PieceIndex,
+    },
+
+    /// Getting the piece took longer than the piece timeout
+    #[error("Timed out getting piece {piece_index} after {timeout:?}")]
+    TimedOut {
+        /// Requested piece index
+        piece_index: PieceIndex,
+        /// The piece timeout which was exceeded
+        timeout: Duration,
+    },
+
+    /// Peers returned the piece, but every copy failed validation
+    #[error("Peers only returned invalid copies of piece {piece_index}")]
+    ValidationFailed {
+        /// Requested piece index
+        piece_index: PieceIndex,
+    },
+
+    /// No DSN peers are connected, so the piece couldn't be requested
+    #[error("No DSN peers are connected, can't get piece {piece_index}")]
+    NotConnected {
+        /// Requested piece index
+        piece_index: PieceIndex,
+    },
+
+    /// The DSN couldn't be reached, for example because no peers responded
+    #[error("Network error getting piece {piece_index}: {error}")]
+    Network {
+        /// Requested piece index
+        piece_index: PieceIndex,
+        /// The underlying error
+        error: anyhow::Error,
+    },
+}
+
+impl PieceFetchError {
+    /// Classifies an archival storage error from a [`DsnPieceSource`](super::DsnPieceSource).
+    pub(super) fn from_archival_error(piece_index: PieceIndex, error: anyhow::Error) -> Self {
+        match error.downcast_ref::<PieceProviderError>() {
+            Some(PieceProviderError::InvalidPiece { .. }) => Self::ValidationFailed { piece_index },
+            _ => Self::Network { piece_index, error },
+        }
+    }
+
+    /// Returns a copy of this error, or `None` if it is a network error, which can't be copied.
+    pub(super) fn try_clone(&self) -> Option<Self> {
+        match self {
+            Self::NotFound { piece_index } => Some(Self::NotFound {
+                piece_index: *piece_index,
+            }),
+            Self::TimedOut {
+                piece_index,
+                timeout,
+            } => Some(Self::TimedOut {
+                piece_index: *piece_index,
+                timeout: *timeout,
+            }),
+            Self::ValidationFailed { piece_index } => Some(Self::ValidationFailed {
+                piece_index: *piece_index,
+            }),
+            Self::NotConnected { piece_index } => Some(Self::NotConnected {
+                piece_index: *piece_index,
+            }),
+            Self::Network { .. } => None,
+        }
+    }
+
+    /// Converts a piece result into a `PieceGetter` result, where pieces which couldn't be found
+    /// are `Ok(None)`, and connectivity and network errors are errors.
+    pub(super) fn piece_getter_result(
+        piece_result: Result<Piece, Self>,
+    ) -> anyhow::Result<Option<Piece>> {
+        match piece_result {
+            Ok(piece) => Ok(Some(piece)),
+            Err(Self::NotFound { .. } | Self::TimedOut { .. } | Self::ValidationFailed { .. }) => {
+                Ok(None)
+            }
+            Err(error @ Self::NotConnected { .. }) => Err(error.into()),
+            Err(Self::Network { error, .. }) => Err(error),
+        }
+    }
+}
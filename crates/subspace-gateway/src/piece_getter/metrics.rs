@@ -0,0 +1,71 @@
+//! Metrics for the DSN piece getter
+
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::histogram::{Histogram, exponential_buckets};
+use prometheus_client::registry::{Registry, Unit};
+use std::sync::atomic::AtomicU64;
+
+/// Metrics for the DSN piece getter
+#[derive(Debug)]
+pub(super) struct DsnPieceGetterMetrics {
+    pub(super) piece_requested: Counter<u64, AtomicU64>,
+    pub(super) cache_hit: Counter<u64, AtomicU64>,
+    pub(super) archival_fallback: Counter<u64, AtomicU64>,
+    pub(super) piece_failure: Counter<u64, AtomicU64>,
+    pub(super) piece_get_time: Histogram,
+}
+
+impl DsnPieceGetterMetrics {
+    /// Create new instance
+    pub(super) fn new(registry: &mut Registry) -> Self {
+        let registry = registry.sub_registry_with_prefix("dsn_piece_getter");
+
+        let piece_requested = Counter::default();
+        registry.register_with_unit(
+            "piece_requested",
+            "Pieces requested",
+            Unit::Other("Pieces".to_string()),
+            piece_requested.clone(),
+        );
+
+        let cache_hit = Counter::default();
+        registry.register_with_unit(
+            "cache_hit",
+            "Pieces found in DSN caches",
+            Unit::Other("Pieces".to_string()),
+            cache_hit.clone(),
+        );
+
+        let archival_fallback = Counter::default();
+        registry.register_with_unit(
+            "archival_fallback",
+            "Pieces requested from archival storage",
+            Unit::Other("Pieces".to_string()),
+            archival_fallback.clone(),
+        );
+
+        let piece_failure = Counter::default();
+        registry.register_with_unit(
+            "piece_failure",
+            "Pieces which were not found, timed out, or caused an error",
+            Unit::Other("Pieces".to_string()),
+            piece_failure.clone(),
+        );
+
+        let piece_get_time = Histogram::new(exponential_buckets(0.001, 2.0, 15));
+        registry.register_with_unit(
+            "piece_get_time",
+            "Piece get time",
+            Unit::Seconds,
+            piece_get_time.clone(),
+        );
+
+        Self {
+            piece_requested,
+            cache_hit,
+            archival_fallback,
+            piece_failure,
+            piece_get_time,
+        }
+    }
+}
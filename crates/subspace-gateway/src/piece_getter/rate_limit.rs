@@ -0,0 +1,147 @@
+//! Outbound rate and bandwidth limits for DSN piece requests.
+
+use parking_lot::Mutex;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::time::Duration;
+
+/// A limit on the rate of DSN piece requests.
+///
+/// Requests are limited using a token bucket, which allows short bursts of requests, and then
+/// limits requests to `requests_per_second`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    /// The maximum sustained number of requests per second.
+    pub requests_per_second: NonZeroU32,
+    /// The maximum number of requests which can be made at once, after a quiet period.
+    pub burst: NonZeroU32,
+}
+
+/// A limit on the bandwidth used by DSN piece requests.
+///
+/// Bandwidth is limited using a token bucket, which allows short bursts of piece downloads, and
+/// then limits downloads to `bytes_per_second`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BandwidthLimit {
+    /// The maximum sustained number of piece bytes downloaded per second.
+    pub bytes_per_second: NonZeroU64,
+    /// The maximum number of piece bytes which can be downloaded at once, after a quiet period.
+    pub burst_bytes: NonZeroU64,
+}
+
+/// A token bucket, which limits the rate of requests or bytes.
+#[derive(Debug)]
+struct TokenBucket {
+    /// The number of requests or bytes which can be used immediately
+    tokens: f64,
+    /// The last time tokens were added to the bucket
+    last_refill: tokio::time::Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64) -> Self {
+        Self {
+            tokens: capacity,
+            last_refill: tokio::time::Instant::now(),
+        }
+    }
+
+    /// Adds the tokens for the time since the last refill, up to `capacity`.
+    fn refill(&mut self, rate: f64, capacity: f64) {
+        let now = tokio::time::Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * rate;
+        self.tokens = (self.tokens + refill).min(capacity);
+        self.last_refill = now;
+    }
+}
+
+/// Limits the rate of requests to a [`RateLimit`].
+#[derive(Debug)]
+pub(super) struct RateLimiter {
+    rate_limit: RateLimit,
+    bucket: Mutex<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub(super) fn new(rate_limit: RateLimit) -> Self {
+        Self {
+            rate_limit,
+            bucket: Mutex::new(TokenBucket::new(f64::from(rate_limit.burst.get()))),
+        }
+    }
+
+    /// Waits until a request is allowed by the rate limit.
+    ///
+    /// Tokens are refilled over time, so waiting requests are never blocked by other requests.
+    pub(super) async fn acquire(&self) {
+        let rate = f64::from(self.rate_limit.requests_per_second.get());
+        let capacity = f64::from(self.rate_limit.burst.get());
+
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock();
+                bucket.refill(rate, capacity);
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Limits the bandwidth of piece downloads to a [`BandwidthLimit`].
+#[derive(Debug)]
+pub(super) struct BandwidthLimiter {
+    bandwidth_limit: BandwidthLimit,
+    bucket: Mutex<TokenBucket>,
+}
+
+impl BandwidthLimiter {
+    pub(super) fn new(bandwidth_limit: BandwidthLimit) -> Self {
+        Self {
+            bandwidth_limit,
+            bucket: Mutex::new(TokenBucket::new(bandwidth_limit.burst_bytes.get() as f64)),
+        }
+    }
+
+    /// Waits until `bytes` can be downloaded within the bandwidth limit, then reserves them.
+    ///
+    /// Downloads larger than the burst wait for a full bucket, then reserve bandwidth in advance,
+    /// delaying later downloads.
+    pub(super) async fn acquire(&self, bytes: u64) {
+        let rate = self.bandwidth_limit.bytes_per_second.get() as f64;
+        let capacity = self.bandwidth_limit.burst_bytes.get() as f64;
+        let required = (bytes as f64).min(capacity);
+
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock();
+                bucket.refill(rate, capacity);
+
+                if bucket.tokens >= required {
+                    bucket.tokens -= bytes as f64;
+                    return;
+                }
+
+                Duration::from_secs_f64((required - bucket.tokens) / rate)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Returns reserved bandwidth which wasn't used, because nothing was downloaded.
+    pub(super) fn release(&self, bytes: u64) {
+        let rate = self.bandwidth_limit.bytes_per_second.get() as f64;
+        let capacity = self.bandwidth_limit.burst_bytes.get() as f64;
+
+        let mut bucket = self.bucket.lock();
+        bucket.refill(rate, capacity);
+        bucket.tokens = (bucket.tokens + bytes as f64).min(capacity);
+    }
+}
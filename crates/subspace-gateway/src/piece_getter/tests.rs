@@ -0,0 +1,1293 @@
+use crate::piece_getter::{
+    AdaptiveTimeout, BandwidthLimit, CacheRetryPolicy, DsnPieceGetter, DsnPieceGetterOptions,
+    DsnPieceSource, PieceFetchError, PieceOrder, RateLimit, RetrievalMode,
+};
+use async_lock::Semaphore;
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use futures::{Stream, StreamExt, stream};
+use prometheus_client::registry::Registry;
+use std::collections::HashMap;
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use subspace_archiving::archiver::Archiver;
+use subspace_archiving::piece_reconstructor::PiecesReconstructor;
+use subspace_core_primitives::objects::BlockObjectMapping;
+use subspace_core_primitives::pieces::{Piece, PieceIndex, Record};
+use subspace_core_primitives::segments::RecordedHistorySegment;
+use subspace_data_retrieval::piece_getter::PieceGetter;
+use subspace_erasure_coding::ErasureCoding;
+use subspace_kzg::Kzg;
+use subspace_networking::libp2p::multiaddr::Protocol;
+use subspace_networking::libp2p::{Multiaddr, PeerId};
+use subspace_networking::protocols::request_response::handlers::piece_by_index::{
+    PieceByIndexRequestHandler, PieceByIndexResponse,
+};
+use subspace_networking::utils::piece_provider::{
+    NoPieceValidator, PieceProvider, PieceProviderError,
+};
+use subspace_networking::{Config, Node, construct};
+
+/// A piece source which has `Piece::default()` for every piece index in its cache, but misses
+/// the first `failures` cache requests for each piece index. Its archival storage is empty.
+#[derive(Debug)]
+struct FlakyPieceSource {
+    failures: usize,
+    attempts: Mutex<HashMap<PieceIndex, usize>>,
+}
+
+impl FlakyPieceSource {
+    fn new(failures: usize) -> Self {
+        Self {
+            failures,
+            attempts: Mutex::default(),
+        }
+    }
+
+    /// Returns the number of cache requests for `piece_index`.
+    fn attempts(&self, piece_index: PieceIndex) -> usize {
+        self.attempts
+            .lock()
+            .unwrap()
+            .get(&piece_index)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl DsnPieceSource for FlakyPieceSource {
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        let pieces = piece_indices
+            .into_iter()
+            .map(|piece_index| {
+                let mut attempts = self.attempts.lock().unwrap();
+                let attempts = attempts.entry(piece_index).or_default();
+                *attempts += 1;
+
+                (
+                    piece_index,
+                    (*attempts > self.failures).then(Piece::default),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Box::new(stream::iter(pieces))
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        _piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        Ok(None)
+    }
+}
+
+/// A piece source which has `Piece::default()` for every piece index in its cache, and takes
+/// `delay` to respond to each cache request. Records the number and order of cache requests, and
+/// the maximum number of concurrent cache requests.
+#[derive(Debug)]
+struct SlowPieceSource {
+    delay: Duration,
+    requests: AtomicUsize,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+impl SlowPieceSource {
+    fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            requests: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl DsnPieceSource for SlowPieceSource {
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+        tokio::time::sleep(self.delay).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        Box::new(stream::iter(
+            piece_indices
+                .into_iter()
+                .map(|piece_index| (piece_index, Some(Piece::default()))),
+        ))
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        _piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        Ok(None)
+    }
+}
+
+/// A piece source which has `Piece::default()` for every piece index in its cache, except for
+/// `hanging_index`, where cache requests never complete. Its archival storage is empty.
+///
+/// Records the number of hanging cache requests which have not been cancelled.
+#[derive(Debug)]
+struct HangingPieceSource {
+    hanging_index: PieceIndex,
+    pending_requests: AtomicUsize,
+}
+
+impl HangingPieceSource {
+    fn new(hanging_index: PieceIndex) -> Self {
+        Self {
+            hanging_index,
+            pending_requests: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Decrements the number of pending requests when a request is cancelled.
+struct PendingRequest<'a>(&'a AtomicUsize);
+
+impl Drop for PendingRequest<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl DsnPieceSource for HangingPieceSource {
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        if piece_indices.contains(&self.hanging_index) {
+            self.pending_requests.fetch_add(1, Ordering::SeqCst);
+            let _pending_request = PendingRequest(&self.pending_requests);
+
+            std::future::pending::<()>().await;
+        }
+
+        Box::new(stream::iter(
+            piece_indices
+                .into_iter()
+                .map(|piece_index| (piece_index, Some(Piece::default()))),
+        ))
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        _piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        Ok(None)
+    }
+}
+
+/// A piece source which has `Piece::default()` for the `cached` piece indexes in its cache, and
+/// for the `archived` piece indexes in its archival storage.
+#[derive(Debug)]
+struct TieredPieceSource {
+    cached: Vec<PieceIndex>,
+    archived: Vec<PieceIndex>,
+}
+
+#[async_trait]
+impl DsnPieceSource for TieredPieceSource {
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        Box::new(stream::iter(piece_indices.into_iter().map(|piece_index| {
+            (
+                piece_index,
+                self.cached.contains(&piece_index).then(Piece::default),
+            )
+        })))
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        Ok(self.archived.contains(&piece_index).then(Piece::default))
+    }
+}
+
+/// A piece source with an empty cache and archival storage, which is connected to `peers`.
+#[derive(Debug, Default)]
+struct ConnectivityPieceSource {
+    peers: Mutex<Vec<PeerId>>,
+}
+
+#[async_trait]
+impl DsnPieceSource for ConnectivityPieceSource {
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        Box::new(stream::iter(
+            piece_indices
+                .into_iter()
+                .map(|piece_index| (piece_index, None)),
+        ))
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        _piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        Ok(None)
+    }
+
+    async fn connected_peers(&self) -> anyhow::Result<Vec<PeerId>> {
+        Ok(self.peers.lock().unwrap().clone())
+    }
+}
+
+/// A piece source which has `Piece::default()` for every piece index in its cache, and responds
+/// to cache requests for later piece indexes faster, so pieces are retrieved out of order.
+#[derive(Debug)]
+struct ReversePieceSource;
+
+#[async_trait]
+impl DsnPieceSource for ReversePieceSource {
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        let max_piece_index = piece_indices.iter().copied().max().unwrap_or_default();
+        tokio::time::sleep(Duration::from_secs(100 - u64::from(max_piece_index))).await;
+
+        Box::new(stream::iter(
+            piece_indices
+                .into_iter()
+                .map(|piece_index| (piece_index, Some(Piece::default()))),
+        ))
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        _piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        Ok(None)
+    }
+}
+
+/// A piece source which has the pieces of the first segment in its cache, except for
+/// `missing_index`. Its archival storage is empty.
+#[derive(Debug)]
+struct SegmentPieceSource {
+    pieces: Vec<Piece>,
+    missing_index: PieceIndex,
+}
+
+#[async_trait]
+impl DsnPieceSource for SegmentPieceSource {
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        Box::new(stream::iter(piece_indices.into_iter().map(|piece_index| {
+            let maybe_piece = self
+                .pieces
+                .get(u64::from(piece_index) as usize)
+                .filter(|_| piece_index != self.missing_index)
+                .cloned();
+
+            (piece_index, maybe_piece)
+        })))
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        _piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        Ok(None)
+    }
+}
+
+/// A piece source with an empty cache, where no archival storage peers respond.
+#[derive(Debug)]
+struct UnreachablePieceSource;
+
+#[async_trait]
+impl DsnPieceSource for UnreachablePieceSource {
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        Box::new(stream::iter(
+            piece_indices
+                .into_iter()
+                .map(|piece_index| (piece_index, None)),
+        ))
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        Err(PieceProviderError::NoPeerResponses { piece_index }.into())
+    }
+}
+
+/// A piece source where each piece index fails in a different way. Piece index zero is in its
+/// cache, one is missing, two only has invalid copies in archival storage, three can't be
+/// reached, and cache requests for four never complete.
+#[derive(Debug)]
+struct FailingPieceSource;
+
+#[async_trait]
+impl DsnPieceSource for FailingPieceSource {
+    async fn get_from_cache<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> Box<dyn Stream<Item = (PieceIndex, Option<Piece>)> + Send + Unpin + 'a> {
+        if piece_indices.contains(&PieceIndex::from(4)) {
+            std::future::pending::<()>().await;
+        }
+
+        Box::new(stream::iter(piece_indices.into_iter().map(|piece_index| {
+            (
+                piece_index,
+                (piece_index == PieceIndex::ZERO).then(Piece::default),
+            )
+        })))
+    }
+
+    async fn get_from_archival_storage(
+        &self,
+        piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        match u64::from(piece_index) {
+            2 => Err(PieceProviderError::InvalidPiece { piece_index }.into()),
+            3 => Err(PieceProviderError::NoPeerResponses { piece_index }.into()),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Starts a node which only has `Piece::default()` at piece index zero in its archival storage,
+/// and nothing in its cache. Returns its address.
+async fn archival_node() -> Multiaddr {
+    let config_1 = Config {
+        listen_on: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
+        allow_non_global_addresses_in_dht: true,
+        request_response_protocols: vec![PieceByIndexRequestHandler::create(
+            |_, request| async move {
+                Some(PieceByIndexResponse {
+                    piece: (request.piece_index == PieceIndex::ZERO).then(Piece::default),
+                    cached_pieces: Vec::new(),
+                })
+            },
+        )],
+        ..Config::default()
+    };
+    let (node_1, mut node_runner_1) = construct(config_1).unwrap();
+
+    let (node_1_address_sender, node_1_address_receiver) = oneshot::channel();
+    let on_new_listener_handler = node_1.on_new_listener(Arc::new({
+        let node_1_address_sender = Mutex::new(Some(node_1_address_sender));
+
+        move |address| {
+            if matches!(address.iter().next(), Some(Protocol::Ip4(_)))
+                && let Some(node_1_address_sender) = node_1_address_sender.lock().unwrap().take()
+            {
+                node_1_address_sender.send(address.clone()).unwrap();
+            }
+        }
+    }));
+
+    tokio::spawn(async move {
+        node_runner_1.run().await;
+    });
+
+    // Wait for first node to know its address
+    let node_1_addr = node_1_address_receiver.await.unwrap();
+    drop(on_new_listener_handler);
+
+    node_1_addr.with(Protocol::P2p(node_1.id()))
+}
+
+/// Starts a node which bootstraps from `bootstrap_address`, without waiting for it to connect.
+fn bootstrapping_node(bootstrap_address: Multiaddr) -> Node {
+    let config = Config {
+        listen_on: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
+        allow_non_global_addresses_in_dht: true,
+        bootstrap_addresses: vec![bootstrap_address],
+        ..Config::default()
+    };
+    let (node, mut node_runner) = construct(config).unwrap();
+
+    tokio::spawn(async move {
+        node_runner.run().await;
+    });
+
+    node
+}
+
+/// Starts a node which only has `Piece::default()` at piece index zero in its archival storage,
+/// and nothing in its cache. Returns another node which is connected to it.
+async fn connected_to_archival_node() -> Node {
+    let node_2 = bootstrapping_node(archival_node().await);
+
+    // Wait until the first node is available for archival storage requests
+    node_2.bootstrap().await.unwrap();
+    while node_2.connected_servers().await.unwrap().is_empty() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    node_2
+}
+
+/// Returns a piece provider which uses `node`, without validating pieces.
+fn piece_provider(node: &Node) -> PieceProvider<NoPieceValidator> {
+    PieceProvider::new(node.clone(), NoPieceValidator, Arc::new(Semaphore::new(10)))
+}
+
+#[tokio::test]
+async fn cache_only_mode() {
+    let node = connected_to_archival_node().await;
+    let piece_getter = DsnPieceGetter::builder(piece_provider(&node))
+        .retrieval_mode(RetrievalMode::CacheOnly)
+        .build();
+
+    // The piece is only in archival storage
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        None
+    );
+
+    let pieces = piece_getter
+        .get_pieces(vec![PieceIndex::ZERO])
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 1);
+    assert_eq!(pieces[0].0, PieceIndex::ZERO);
+    assert_eq!(pieces[0].1.as_ref().unwrap(), &None);
+}
+
+#[tokio::test]
+async fn cache_then_archival_mode() {
+    let node = connected_to_archival_node().await;
+    // This is the default mode
+    let piece_getter = DsnPieceGetter::builder(piece_provider(&node)).build();
+
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(Piece::default())
+    );
+
+    let pieces = piece_getter
+        .get_pieces(vec![PieceIndex::ZERO])
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 1);
+    assert_eq!(pieces[0].0, PieceIndex::ZERO);
+    assert_eq!(pieces[0].1.as_ref().unwrap(), &Some(Piece::default()));
+}
+
+#[tokio::test(start_paused = true)]
+async fn cache_retries() {
+    let piece_indices = vec![PieceIndex::ZERO, PieceIndex::ONE];
+    let piece_getter = DsnPieceGetter::new_with_options(
+        FlakyPieceSource::new(1),
+        DsnPieceGetterOptions {
+            cache_retry_policy: CacheRetryPolicy {
+                max_retries: 2,
+                initial_delay: Duration::from_secs(1),
+            },
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+
+    let start = tokio::time::Instant::now();
+    let pieces = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+
+    assert_eq!(pieces.len(), piece_indices.len());
+    for (piece_index, piece_result) in pieces {
+        assert_eq!(piece_result.unwrap(), Some(Piece::default()));
+        // The piece is missing on the first attempt, then found on the first retry
+        assert_eq!(piece_getter.piece_source.attempts(piece_index), 2);
+    }
+    // Retries for different pieces happen at the same time
+    assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+    // Without retries, the piece is reported as missing
+    let piece_getter = DsnPieceGetter::new_with_options(
+        FlakyPieceSource::new(1),
+        DsnPieceGetterOptions {
+            retrieval_mode: RetrievalMode::CacheOnly,
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        None
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn retry_budget() {
+    let piece_indices = (0..10).map(PieceIndex::from).collect::<Vec<_>>();
+    let piece_getter = DsnPieceGetter::new_with_options(
+        FlakyPieceSource::new(usize::MAX),
+        DsnPieceGetterOptions {
+            retrieval_mode: RetrievalMode::CacheOnly,
+            cache_retry_policy: CacheRetryPolicy {
+                max_retries: 5,
+                initial_delay: Duration::from_secs(1),
+            },
+            get_pieces_retry_budget: Some(12),
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+
+    let pieces = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), piece_indices.len());
+    assert!(
+        pieces
+            .into_iter()
+            .all(|(_piece_index, piece_result)| piece_result.unwrap().is_none())
+    );
+
+    // Each piece is requested once, then retried until the budget is spent
+    let retries = piece_indices
+        .iter()
+        .map(|piece_index| piece_getter.piece_source.attempts(*piece_index) - 1)
+        .sum::<usize>();
+    assert_eq!(retries, 12);
+
+    // Each call has its own budget, which isn't spent by this call
+    let attempts_before = piece_getter.piece_source.attempts(PieceIndex::ZERO);
+    piece_getter
+        .get_pieces(vec![PieceIndex::ZERO])
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(
+        piece_getter.piece_source.attempts(PieceIndex::ZERO) - attempts_before,
+        6
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn cache_fetch_concurrency_limit() {
+    let piece_indices = (0..10).map(PieceIndex::from).collect::<Vec<_>>();
+    let piece_getter = DsnPieceGetter::new_with_options(
+        SlowPieceSource::new(Duration::from_secs(1)),
+        DsnPieceGetterOptions {
+            max_in_flight_cache_fetches: NonZeroUsize::new(3).unwrap(),
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+
+    let pieces = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+
+    assert_eq!(pieces.len(), piece_indices.len());
+    assert!(
+        pieces
+            .into_iter()
+            .all(|(_piece_index, piece_result)| piece_result.unwrap().is_some())
+    );
+    assert_eq!(
+        piece_getter
+            .piece_source
+            .max_in_flight
+            .load(Ordering::SeqCst),
+        3
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn coalesce_in_flight_requests() {
+    let piece_getter = DsnPieceGetter::new_with_options(
+        SlowPieceSource::new(Duration::from_secs(1)),
+        DsnPieceGetterOptions::default(),
+        None,
+    );
+
+    // Simultaneous requests for the same piece share a single retrieval
+    let (first_piece, second_piece) = futures::join!(
+        piece_getter.get_piece(PieceIndex::ZERO),
+        piece_getter.get_piece(PieceIndex::ZERO),
+    );
+    assert_eq!(first_piece.unwrap(), Some(Piece::default()));
+    assert_eq!(second_piece.unwrap(), Some(Piece::default()));
+    assert_eq!(piece_getter.piece_source.requests.load(Ordering::SeqCst), 1);
+    assert!(piece_getter.in_flight_pieces.lock().is_empty());
+
+    // Finished retrievals are not reused
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(Piece::default())
+    );
+    assert_eq!(piece_getter.piece_source.requests.load(Ordering::SeqCst), 2);
+
+    // Requests for different pieces are not coalesced
+    let (first_piece, second_piece) = futures::join!(
+        piece_getter.get_piece(PieceIndex::ZERO),
+        piece_getter.get_piece(PieceIndex::ONE),
+    );
+    assert!(first_piece.unwrap().is_some());
+    assert!(second_piece.unwrap().is_some());
+    assert_eq!(piece_getter.piece_source.requests.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test(start_paused = true)]
+async fn local_piece_cache() {
+    let piece_getter = DsnPieceGetter::new_with_options(
+        SlowPieceSource::new(Duration::from_secs(1)),
+        DsnPieceGetterOptions {
+            piece_cache_capacity: Some(NonZeroU32::new(2).unwrap()),
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+
+    for piece_index in [0, 1].map(PieceIndex::from) {
+        assert!(piece_getter.get_piece(piece_index).await.unwrap().is_some());
+    }
+    assert_eq!(piece_getter.piece_source.requests.load(Ordering::SeqCst), 2);
+
+    // Recently retrieved pieces are served from memory
+    let start = tokio::time::Instant::now();
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(Piece::default())
+    );
+    assert_eq!(start.elapsed(), Duration::ZERO);
+    assert_eq!(piece_getter.piece_source.requests.load(Ordering::SeqCst), 2);
+
+    // The least recently used piece is evicted when the cache is full
+    assert!(
+        piece_getter
+            .get_piece(PieceIndex::from(2))
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        piece_getter
+            .get_piece(PieceIndex::ONE)
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert_eq!(piece_getter.piece_source.requests.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limit() {
+    let piece_indices = (0..10).map(PieceIndex::from).collect::<Vec<_>>();
+    let piece_getter = DsnPieceGetter::new_with_options(
+        SlowPieceSource::new(Duration::ZERO),
+        DsnPieceGetterOptions {
+            rate_limit: Some(RateLimit {
+                requests_per_second: NonZeroU32::new(2).unwrap(),
+                burst: NonZeroU32::new(4).unwrap(),
+            }),
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+
+    let start = tokio::time::Instant::now();
+    let mut request_times = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .map(|(_piece_index, piece_result)| {
+            assert!(piece_result.unwrap().is_some());
+            start.elapsed()
+        })
+        .collect::<Vec<_>>()
+        .await;
+    request_times.sort();
+
+    // The burst is sent immediately, then the rest are paced at the configured rate
+    assert_eq!(request_times.len(), piece_indices.len());
+    assert!(request_times[..4].iter().all(|time| time.is_zero()));
+    for (request, time) in request_times[4..].iter().enumerate() {
+        let expected_time = Duration::from_millis(500) * (request as u32 + 1);
+        assert!(
+            *time >= expected_time && *time < expected_time + Duration::from_millis(10),
+            "{request_times:?}"
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn bandwidth_limit() {
+    let bytes_per_second = 4 * Piece::SIZE as u64;
+    let burst_bytes = Piece::SIZE as u64;
+    let piece_indices = (0..20).map(PieceIndex::from).collect::<Vec<_>>();
+    let piece_getter = DsnPieceGetter::new_with_options(
+        SlowPieceSource::new(Duration::ZERO),
+        DsnPieceGetterOptions {
+            // The request rate limit is higher than the bandwidth limit, so it doesn't slow
+            // downloads
+            rate_limit: Some(RateLimit {
+                requests_per_second: NonZeroU32::new(100).unwrap(),
+                burst: NonZeroU32::new(100).unwrap(),
+            }),
+            bandwidth_limit: Some(BandwidthLimit {
+                bytes_per_second: NonZeroU64::new(bytes_per_second).unwrap(),
+                burst_bytes: NonZeroU64::new(burst_bytes).unwrap(),
+            }),
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+
+    let start = tokio::time::Instant::now();
+    let mut downloads = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .map(|(_piece_index, piece_result)| {
+            let piece = piece_result.unwrap().unwrap();
+            (start.elapsed(), piece.len() as u64)
+        })
+        .collect::<Vec<_>>()
+        .await;
+    downloads.sort();
+    assert_eq!(downloads.len(), piece_indices.len());
+
+    // After the initial burst, the measured throughput never exceeds the configured cap
+    let mut downloaded_bytes = 0;
+    for (time, bytes) in &downloads {
+        downloaded_bytes += bytes;
+        let allowed_bytes = burst_bytes as f64 + bytes_per_second as f64 * time.as_secs_f64();
+        assert!(downloaded_bytes as f64 <= allowed_bytes, "{downloads:?}");
+    }
+
+    // Downloads are smoothed to the cap, rather than being sent in bursts
+    let (last_time, _bytes) = downloads.last().unwrap();
+    let expected_time = Duration::from_millis(250) * (piece_indices.len() as u32 - 1);
+    assert!(
+        *last_time >= expected_time && *last_time < expected_time + Duration::from_millis(10),
+        "{downloads:?}"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn piece_timeout() {
+    let piece_indices = (0..3).map(PieceIndex::from).collect::<Vec<_>>();
+    let piece_getter = DsnPieceGetter::new_with_options(
+        HangingPieceSource::new(PieceIndex::ONE),
+        DsnPieceGetterOptions {
+            piece_timeout: Some(Duration::from_secs(5)),
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+
+    let start = tokio::time::Instant::now();
+    let pieces = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .collect::<HashMap<_, _>>()
+        .await;
+
+    // The stream completes, and only the hanging piece is missing
+    assert_eq!(pieces.len(), piece_indices.len());
+    for (piece_index, piece_result) in pieces {
+        let expected_piece = (piece_index != PieceIndex::ONE).then(Piece::default);
+        assert_eq!(piece_result.unwrap(), expected_piece);
+    }
+    assert_eq!(start.elapsed(), Duration::from_secs(5));
+}
+
+#[tokio::test(start_paused = true)]
+async fn adaptive_timeout() {
+    let piece_getter = DsnPieceGetter::new_with_options(
+        ReversePieceSource,
+        DsnPieceGetterOptions {
+            piece_timeout: Some(Duration::from_secs(1000)),
+            adaptive_timeout: Some(AdaptiveTimeout {
+                percentile: 0.9,
+                factor: 2.0,
+                min_timeout: Duration::ZERO,
+                max_samples: NonZeroUsize::new(10).unwrap(),
+                min_samples: 10,
+            }),
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+
+    // The fixed timeout is used until enough latencies are recorded
+    assert_eq!(
+        piece_getter.effective_piece_timeout(),
+        Some(Duration::from_secs(1000))
+    );
+
+    // Latencies from 1 to 10 seconds
+    let pieces = piece_getter
+        .get_pieces((90..100).map(PieceIndex::from).collect())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert!(pieces.iter().all(|(_, piece)| piece.is_ok()));
+    assert_eq!(
+        piece_getter.effective_piece_timeout(),
+        Some(Duration::from_secs(9 * 2))
+    );
+
+    // Faster pieces replace the oldest latencies, and the timeout follows them
+    for piece_index in (95..100).chain(95..100).map(PieceIndex::from) {
+        assert!(piece_getter.get_piece(piece_index).await.unwrap().is_some());
+    }
+    assert_eq!(
+        piece_getter.effective_piece_timeout(),
+        Some(Duration::from_secs(5 * 2))
+    );
+
+    // Pieces slower than the adaptive timeout time out, and don't change it
+    let start = tokio::time::Instant::now();
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::from(80)).await.unwrap(),
+        None
+    );
+    assert_eq!(start.elapsed(), Duration::from_secs(5 * 2));
+    assert_eq!(
+        piece_getter.effective_piece_timeout(),
+        Some(Duration::from_secs(5 * 2))
+    );
+}
+
+#[tokio::test]
+async fn dropping_stream_cancels_requests() {
+    let piece_getter = DsnPieceGetter::new_with_options(
+        HangingPieceSource::new(PieceIndex::ONE),
+        DsnPieceGetterOptions::default(),
+        None,
+    );
+
+    let mut pieces = piece_getter
+        .get_pieces((0..3).map(PieceIndex::from).collect())
+        .await
+        .unwrap();
+
+    // Only the hanging piece is left
+    for _ in 0..2 {
+        let (piece_index, piece_result) = pieces.next().await.unwrap();
+        assert_ne!(piece_index, PieceIndex::ONE);
+        assert_eq!(piece_result.unwrap(), Some(Piece::default()));
+    }
+    assert_eq!(
+        piece_getter
+            .piece_source
+            .pending_requests
+            .load(Ordering::SeqCst),
+        1
+    );
+
+    // Dropping the stream cancels the hanging request
+    drop(pieces);
+    assert_eq!(
+        piece_getter
+            .piece_source
+            .pending_requests
+            .load(Ordering::SeqCst),
+        0
+    );
+    assert!(piece_getter.in_flight_pieces.lock().is_empty());
+}
+
+#[tokio::test]
+async fn get_pieces_errors_without_peer_responses() {
+    let piece_getter = DsnPieceGetter::new_with_options(
+        UnreachablePieceSource,
+        DsnPieceGetterOptions::default(),
+        None,
+    );
+    let piece_indices = (0..3).map(PieceIndex::from).collect::<Vec<_>>();
+
+    // Unreachable pieces are errors, not missing pieces
+    let pieces = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), piece_indices.len());
+    for (piece_index, piece_result) in pieces {
+        assert!(piece_indices.contains(&piece_index));
+        assert!(piece_result.is_err());
+    }
+
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn piece_fetch_errors() {
+    let piece_timeout = Duration::from_secs(5);
+    let piece_getter = DsnPieceGetter::new_with_options(
+        FailingPieceSource,
+        DsnPieceGetterOptions {
+            piece_timeout: Some(piece_timeout),
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+    let piece_indices = (0..5).map(PieceIndex::from).collect::<Vec<_>>();
+
+    let pieces = piece_getter
+        .fetch_pieces(piece_indices.clone())
+        .collect::<HashMap<_, _>>()
+        .await;
+    assert_eq!(pieces.len(), piece_indices.len());
+
+    // Each failure mode is classified
+    assert_eq!(
+        pieces[&PieceIndex::ZERO].as_ref().unwrap(),
+        &Piece::default()
+    );
+    assert!(matches!(
+        pieces[&PieceIndex::ONE],
+        Err(PieceFetchError::NotFound { piece_index }) if piece_index == PieceIndex::ONE
+    ));
+    assert!(matches!(
+        pieces[&PieceIndex::from(2)],
+        Err(PieceFetchError::ValidationFailed { piece_index }) if piece_index == PieceIndex::from(2)
+    ));
+    assert!(matches!(
+        pieces[&PieceIndex::from(3)],
+        Err(PieceFetchError::Network { piece_index, .. }) if piece_index == PieceIndex::from(3)
+    ));
+    assert!(matches!(
+        pieces[&PieceIndex::from(4)],
+        Err(PieceFetchError::TimedOut { piece_index, timeout })
+            if piece_index == PieceIndex::from(4) && timeout == piece_timeout
+    ));
+    assert!(matches!(
+        piece_getter.fetch_piece(PieceIndex::ONE).await,
+        Err(PieceFetchError::NotFound { .. })
+    ));
+
+    // The piece getter only reports network errors as errors, other failures are missing pieces
+    let pieces = piece_getter
+        .get_pieces(piece_indices)
+        .await
+        .unwrap()
+        .collect::<HashMap<_, _>>()
+        .await;
+    for (piece_index, piece_result) in pieces {
+        match u64::from(piece_index) {
+            0 => assert_eq!(piece_result.unwrap(), Some(Piece::default())),
+            3 => assert!(piece_result.is_err()),
+            _ => assert_eq!(piece_result.unwrap(), None),
+        }
+    }
+}
+
+#[tokio::test]
+async fn not_connected() {
+    let (node, mut node_runner) = construct(Config {
+        listen_on: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
+        allow_non_global_addresses_in_dht: true,
+        ..Config::default()
+    })
+    .unwrap();
+    tokio::spawn(async move {
+        node_runner.run().await;
+    });
+    let piece_getter = DsnPieceGetter::builder(piece_provider(&node)).build();
+
+    // A disconnected node can't tell whether the piece exists, so it isn't reported as missing
+    assert!(matches!(
+        piece_getter.fetch_piece(PieceIndex::ZERO).await,
+        Err(PieceFetchError::NotConnected { piece_index }) if piece_index == PieceIndex::ZERO
+    ));
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+}
+
+#[tokio::test]
+async fn metrics() {
+    let mut registry = Registry::default();
+    let piece_getter = DsnPieceGetter::new_with_options(
+        TieredPieceSource {
+            cached: vec![PieceIndex::ZERO],
+            archived: vec![PieceIndex::ONE],
+        },
+        DsnPieceGetterOptions::default(),
+        Some(&mut registry),
+    );
+
+    // A cache hit, an archival storage hit, and a missing piece
+    let pieces = piece_getter
+        .get_pieces((0..3).map(PieceIndex::from).collect())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 3);
+
+    let metrics = piece_getter.metrics.as_ref().unwrap();
+    assert_eq!(metrics.piece_requested.get(), 3);
+    assert_eq!(metrics.cache_hit.get(), 1);
+    assert_eq!(metrics.archival_fallback.get(), 2);
+    assert_eq!(metrics.piece_failure.get(), 1);
+}
+
+#[tokio::test]
+async fn builder() {
+    let mut registry = Registry::default();
+    let piece_getter = DsnPieceGetter::builder(TieredPieceSource {
+        cached: vec![PieceIndex::ZERO],
+        archived: vec![PieceIndex::ONE],
+    })
+    .retrieval_mode(RetrievalMode::CacheOnly)
+    .piece_cache_capacity(NonZeroU32::new(10).unwrap())
+    .piece_timeout(Duration::from_secs(5))
+    .get_pieces_retry_budget(3)
+    .registry(&mut registry)
+    .build();
+
+    assert_eq!(
+        piece_getter.options.retrieval_mode,
+        RetrievalMode::CacheOnly
+    );
+    assert_eq!(
+        piece_getter.options.piece_timeout,
+        Some(Duration::from_secs(5))
+    );
+    assert_eq!(piece_getter.options.get_pieces_retry_budget, Some(3));
+    assert!(piece_getter.options.rate_limit.is_none());
+
+    // Cache only mode doesn't use archival storage
+    assert!(
+        piece_getter
+            .get_piece(PieceIndex::ZERO)
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        piece_getter
+            .get_piece(PieceIndex::ONE)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Retrieved pieces are kept in the local piece cache
+    assert!(
+        piece_getter
+            .piece_cache
+            .as_ref()
+            .unwrap()
+            .lock()
+            .get(&PieceIndex::ZERO)
+            .is_some()
+    );
+
+    // Metrics are registered
+    assert_eq!(
+        piece_getter.metrics.as_ref().unwrap().piece_requested.get(),
+        2
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn piece_order() {
+    let piece_indices = (0..10).map(PieceIndex::from).collect::<Vec<_>>();
+
+    let piece_getter = DsnPieceGetter::new_with_options(
+        ReversePieceSource,
+        DsnPieceGetterOptions::default(),
+        None,
+    );
+    let pieces = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .map(|(piece_index, _piece_result)| piece_index)
+        .collect::<Vec<_>>()
+        .await;
+    // By default, pieces are returned as soon as they are retrieved
+    assert_eq!(
+        pieces,
+        piece_indices.iter().copied().rev().collect::<Vec<_>>()
+    );
+
+    // Both a buffer which holds all the pieces, and a smaller buffer, preserve the input order
+    for max_buffered in [10, 3] {
+        let piece_getter = DsnPieceGetter::new_with_options(
+            ReversePieceSource,
+            DsnPieceGetterOptions {
+                piece_order: PieceOrder::Input {
+                    max_buffered: NonZeroUsize::new(max_buffered).unwrap(),
+                },
+                ..DsnPieceGetterOptions::default()
+            },
+            None,
+        );
+        let pieces = piece_getter
+            .get_pieces(piece_indices.clone())
+            .await
+            .unwrap()
+            .map(|(piece_index, piece_result)| {
+                assert_eq!(piece_result.unwrap(), Some(Piece::default()));
+                piece_index
+            })
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(pieces, piece_indices);
+    }
+}
+
+#[tokio::test]
+async fn piece_reconstruction() {
+    let kzg = Kzg::new();
+    let erasure_coding = ErasureCoding::new(
+        NonZeroUsize::new(Record::NUM_S_BUCKETS.next_power_of_two().ilog2() as usize)
+            .expect("Not zero; qed"),
+    )
+    .unwrap();
+
+    // A block which fills the first segment
+    let block = (0..RecordedHistorySegment::SIZE)
+        .map(|byte| byte as u8)
+        .collect();
+    let pieces = Archiver::new(kzg.clone(), erasure_coding.clone())
+        .add_block(block, BlockObjectMapping::default(), true)
+        .archived_segments
+        .into_iter()
+        .next()
+        .unwrap()
+        .pieces
+        .pieces()
+        .collect::<Vec<_>>();
+    let missing_index = PieceIndex::from(5);
+
+    let piece_source = SegmentPieceSource {
+        pieces: pieces.clone(),
+        missing_index,
+    };
+    let piece_getter = DsnPieceGetter::new_with_options(
+        piece_source,
+        DsnPieceGetterOptions {
+            retrieval_mode: RetrievalMode::CacheOnly,
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+    // Reconstruction is off by default
+    assert_eq!(piece_getter.get_piece(missing_index).await.unwrap(), None);
+
+    let piece_getter = DsnPieceGetter::new_with_options(
+        piece_getter.piece_source,
+        DsnPieceGetterOptions {
+            retrieval_mode: RetrievalMode::CacheOnly,
+            piece_reconstructor: Some(PiecesReconstructor::new(kzg, erasure_coding)),
+            ..DsnPieceGetterOptions::default()
+        },
+        None,
+    );
+    assert_eq!(
+        piece_getter.get_piece(missing_index).await.unwrap(),
+        Some(pieces[u64::from(missing_index) as usize].clone())
+    );
+}
+
+#[tokio::test]
+async fn connectivity() {
+    let piece_getter = DsnPieceGetter::new_with_options(
+        ConnectivityPieceSource::default(),
+        DsnPieceGetterOptions::default(),
+        None,
+    );
+
+    assert!(!piece_getter.is_connected().await);
+    assert!(piece_getter.connected_peers().await.unwrap().is_empty());
+
+    let peer_id = PeerId::random();
+    piece_getter
+        .piece_source
+        .peers
+        .lock()
+        .unwrap()
+        .push(peer_id);
+    assert!(piece_getter.is_connected().await);
+    assert_eq!(piece_getter.connected_peers().await.unwrap(), vec![peer_id]);
+
+    piece_getter.piece_source.peers.lock().unwrap().clear();
+    assert!(!piece_getter.is_connected().await);
+}
+
+#[tokio::test]
+async fn connected_to_dsn() {
+    let node = connected_to_archival_node().await;
+    let piece_getter = DsnPieceGetter::builder(piece_provider(&node)).build();
+
+    assert!(piece_getter.is_connected().await);
+}
+
+#[tokio::test]
+async fn warmup_connects_before_first_fetch() {
+    let node = bootstrapping_node(archival_node().await);
+    let piece_getter = DsnPieceGetter::builder(piece_provider(&node)).build();
+
+    let connected_peers = piece_getter.warmup(Duration::from_secs(30)).await.unwrap();
+    assert_eq!(connected_peers, 1);
+    assert!(piece_getter.is_connected().await);
+
+    // The first fetch uses the warmed up connection
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(Piece::default())
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn warmup_timeout() {
+    let piece_getter = DsnPieceGetter::new_with_options(
+        ConnectivityPieceSource::default(),
+        DsnPieceGetterOptions::default(),
+        None,
+    );
+
+    let start = tokio::time::Instant::now();
+    assert!(piece_getter.warmup(Duration::from_secs(5)).await.is_err());
+    assert_eq!(start.elapsed(), Duration::from_secs(5));
+}
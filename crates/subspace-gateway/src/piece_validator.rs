@@ -1,8 +1,14 @@
 //! Gateway-specific validator for pieces retrieved from the network.
 
+#[cfg(test)]
+mod tests;
+
 use crate::node_client::NodeClient;
 use async_trait::async_trait;
 use jsonrpsee::core::client::Error as JsonRpseeError;
+use parking_lot::Mutex;
+use schnellru::{ByLength, LruMap};
+use subspace_core_primitives::hashes::{Blake3Hash, blake3_hash};
 use subspace_core_primitives::pieces::{Piece, PieceIndex};
 use subspace_kzg::Kzg;
 use subspace_networking::Node;
@@ -105,3 +111,62 @@ where
         }
     }
 }
+
+/// A validator which remembers the pieces that were successfully validated by the inner
+/// validator, so re-fetched pieces with identical bytes skip re-validation.
+///
+/// Validated pieces are identified by their index and a hash of their bytes, so the cache stays
+/// small. If a piece's bytes don't match the cached hash, the cache entry is removed, and the
+/// piece is validated again.
+///
+/// Implements [`PieceValidator`].
+#[derive(Debug)]
+pub struct CachingPieceValidator<PV> {
+    inner: PV,
+    validated_pieces: Mutex<LruMap<PieceIndex, Blake3Hash>>,
+}
+
+impl<PV> CachingPieceValidator<PV> {
+    /// Create new instance, which caches up to `capacity` validated pieces.
+    /// A zero `capacity` disables the cache.
+    pub fn new(inner: PV, capacity: u32) -> Self {
+        Self {
+            inner,
+            validated_pieces: Mutex::new(LruMap::new(ByLength::new(capacity))),
+        }
+    }
+}
+
+#[async_trait]
+impl<PV> PieceValidator for CachingPieceValidator<PV>
+where
+    PV: PieceValidator,
+{
+    async fn validate_piece(
+        &self,
+        source_peer_id: PeerId,
+        piece_index: PieceIndex,
+        piece: Piece,
+    ) -> Option<Piece> {
+        let piece_hash = blake3_hash(piece.as_ref());
+
+        {
+            let mut validated_pieces = self.validated_pieces.lock();
+            match validated_pieces.get(&piece_index) {
+                Some(validated_hash) if *validated_hash == piece_hash => return Some(piece),
+                Some(_) => {
+                    validated_pieces.remove(&piece_index);
+                }
+                None => {}
+            }
+        }
+
+        let piece = self
+            .inner
+            .validate_piece(source_peer_id, piece_index, piece)
+            .await?;
+        self.validated_pieces.lock().insert(piece_index, piece_hash);
+
+        Some(piece)
+    }
+}
@@ -0,0 +1,56 @@
+use crate::piece_validator::CachingPieceValidator;
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use subspace_core_primitives::pieces::{Piece, PieceIndex};
+use subspace_networking::libp2p::PeerId;
+use subspace_networking::utils::piece_provider::PieceValidator;
+
+/// A validator which accepts all pieces, and counts how many times it was called.
+#[derive(Debug, Default)]
+struct CountingPieceValidator {
+    calls: AtomicUsize,
+}
+
+#[async_trait]
+impl PieceValidator for CountingPieceValidator {
+    async fn validate_piece(&self, _: PeerId, _: PieceIndex, piece: Piece) -> Option<Piece> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Some(piece)
+    }
+}
+
+#[tokio::test]
+async fn caching_piece_validator() {
+    let validator = CachingPieceValidator::new(CountingPieceValidator::default(), 10);
+    let peer_id = PeerId::random();
+    let piece = Piece::default();
+
+    for _ in 0..2 {
+        assert_eq!(
+            validator
+                .validate_piece(peer_id, PieceIndex::ZERO, piece.clone())
+                .await,
+            Some(piece.clone())
+        );
+    }
+    // The second validation is skipped
+    assert_eq!(validator.inner.calls.load(Ordering::SeqCst), 1);
+
+    // A piece with different bytes is validated again
+    let mut other_piece = Piece::default();
+    other_piece.as_mut()[0] = 1;
+    assert_eq!(
+        validator
+            .validate_piece(peer_id, PieceIndex::ZERO, other_piece.clone())
+            .await,
+        Some(other_piece)
+    );
+    assert_eq!(validator.inner.calls.load(Ordering::SeqCst), 2);
+
+    // Other piece indexes are validated separately
+    validator
+        .validate_piece(peer_id, PieceIndex::ONE, piece)
+        .await
+        .unwrap();
+    assert_eq!(validator.inner.calls.load(Ordering::SeqCst), 3);
+}
@@ -181,6 +181,7 @@ fn main() -> Result<(), Error> {
                     max_pending_in_connections: 100,
                     max_pending_out_connections: 150,
                     external_addresses: vec![],
+                    piece_retrieval_mode: Default::default(),
                 }
             };
 
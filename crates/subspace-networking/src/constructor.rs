@@ -50,7 +50,7 @@ const GOSSIPSUB_PROTOCOL_PREFIX: &str = "subspace/gossipsub";
 /// Defines max_negotiating_inbound_streams constant for the swarm.
 /// It must be set for large plots.
 const SWARM_MAX_NEGOTIATING_INBOUND_STREAMS: usize = 100000;
-/// How long will connection be allowed to be open without any usage
+/// The default time a connection is allowed to be open without any usage
 const IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(3);
 /// The default maximum established incoming connection number for the swarm.
 const SWARM_MAX_ESTABLISHED_INCOMING_CONNECTIONS: u32 = 100;
@@ -61,6 +61,7 @@ const SWARM_MAX_PENDING_INCOMING_CONNECTIONS: u32 = 80;
 /// The default maximum pending incoming connection number for the swarm.
 const SWARM_MAX_PENDING_OUTGOING_CONNECTIONS: u32 = 80;
 const KADEMLIA_QUERY_TIMEOUT: Duration = Duration::from_secs(40);
+/// The default maximum established connection number for each peer.
 const SWARM_MAX_ESTABLISHED_CONNECTIONS_PER_PEER: u32 = 3;
 const MAX_CONCURRENT_STREAMS_PER_CONNECTION: usize = 10;
 // TODO: Consider moving this constant to configuration or removing `Toggle` wrapper when we find a
@@ -206,6 +207,17 @@ pub struct Config {
     pub max_pending_incoming_connections: u32,
     /// Pending outgoing swarm connection limit.
     pub max_pending_outgoing_connections: u32,
+    /// Established swarm connection limit for each peer, across incoming and outgoing
+    /// connections.
+    ///
+    /// Requests to a peer share its established connections, so this limits the connection pool
+    /// for each peer.
+    pub max_established_connections_per_peer: u32,
+    /// How long a connection is kept open without any usage.
+    ///
+    /// Requests to a peer reuse its existing connection, so a longer timeout avoids reconnecting
+    /// to peers which are sent requests less frequently than this timeout.
+    pub idle_connection_timeout: Duration,
     /// How many temporarily banned unreachable peers to keep in memory.
     pub temporary_bans_cache_size: u32,
     /// Backoff policy for temporary banning of unreachable peers.
@@ -328,6 +340,8 @@ impl Config {
             max_established_outgoing_connections: SWARM_MAX_ESTABLISHED_OUTGOING_CONNECTIONS,
             max_pending_incoming_connections: SWARM_MAX_PENDING_INCOMING_CONNECTIONS,
             max_pending_outgoing_connections: SWARM_MAX_PENDING_OUTGOING_CONNECTIONS,
+            max_established_connections_per_peer: SWARM_MAX_ESTABLISHED_CONNECTIONS_PER_PEER,
+            idle_connection_timeout: IDLE_CONNECTION_TIMEOUT,
             temporary_bans_cache_size: TEMPORARY_BANS_CACHE_SIZE,
             temporary_ban_backoff,
             libp2p_metrics,
@@ -385,6 +399,8 @@ pub fn construct(config: Config) -> Result<(Node, NodeRunner), CreationError> {
         max_established_outgoing_connections,
         max_pending_incoming_connections,
         max_pending_outgoing_connections,
+        max_established_connections_per_peer,
+        idle_connection_timeout,
         temporary_bans_cache_size,
         temporary_ban_backoff,
         libp2p_metrics,
@@ -404,7 +420,7 @@ pub fn construct(config: Config) -> Result<(Node, NodeRunner), CreationError> {
     );
 
     let connection_limits = ConnectionLimits::default()
-        .with_max_established_per_peer(Some(SWARM_MAX_ESTABLISHED_CONNECTIONS_PER_PEER))
+        .with_max_established_per_peer(Some(max_established_connections_per_peer))
         .with_max_pending_incoming(Some(max_pending_incoming_connections))
         .with_max_pending_outgoing(Some(max_pending_outgoing_connections))
         .with_max_established_incoming(Some(max_established_incoming_connections))
@@ -488,7 +504,7 @@ pub fn construct(config: Config) -> Result<(Node, NodeRunner), CreationError> {
         .with_swarm_config(|config| {
             config
                 .with_max_negotiating_inbound_streams(SWARM_MAX_NEGOTIATING_INBOUND_STREAMS)
-                .with_idle_connection_timeout(IDLE_CONNECTION_TIMEOUT)
+                .with_idle_connection_timeout(idle_connection_timeout)
         })
         .build();
 
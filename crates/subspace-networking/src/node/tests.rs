@@ -7,6 +7,8 @@ use libp2p::multiaddr::Protocol;
 use parity_scale_codec::{Decode, Encode};
 use parking_lot::Mutex;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use subspace_process::init_logger;
 
 #[derive(Encode, Decode)]
@@ -84,3 +86,66 @@ async fn request_with_addresses() {
         .await
         .unwrap();
 }
+
+#[tokio::test]
+async fn connections_per_peer_are_limited() {
+    init_logger();
+
+    let config_1 = Config {
+        listen_on: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
+        allow_non_global_addresses_in_dht: true,
+        ..Config::default()
+    };
+    let (node_1, mut node_runner_1) = construct(config_1).unwrap();
+
+    let (node_1_address_sender, node_1_address_receiver) = oneshot::channel();
+    let on_new_listener_handler = node_1.on_new_listener(Arc::new({
+        let node_1_address_sender = Mutex::new(Some(node_1_address_sender));
+
+        move |address| {
+            if matches!(address.iter().next(), Some(Protocol::Ip4(_)))
+                && let Some(node_1_address_sender) = node_1_address_sender.lock().take()
+            {
+                node_1_address_sender.send(address.clone()).unwrap();
+            }
+        }
+    }));
+
+    tokio::spawn(async move {
+        node_runner_1.run().await;
+    });
+
+    // Wait for first node to know its address
+    let node_1_addr = node_1_address_receiver.await.unwrap();
+    drop(on_new_listener_handler);
+
+    let config_2 = Config {
+        allow_non_global_addresses_in_dht: true,
+        max_established_connections_per_peer: 1,
+        ..Config::default()
+    };
+    let (node_2, mut node_runner_2) = construct(config_2).unwrap();
+
+    let num_established_peer_connections = Arc::new(AtomicUsize::new(0));
+    let _num_established_peer_connections_handler = node_2
+        .on_num_established_peer_connections_change(Arc::new({
+            let num_established_peer_connections = Arc::clone(&num_established_peer_connections);
+
+            move |num| {
+                num_established_peer_connections.store(*num, Ordering::SeqCst);
+            }
+        }));
+
+    tokio::spawn(async move {
+        node_runner_2.run().await;
+    });
+
+    // Each dial opens a new connection, unless the peer's connection limit is reached
+    for _ in 0..3 {
+        node_2.dial(node_1_addr.clone()).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    assert_eq!(node_2.connected_peers().await.unwrap(), [node_1.id()]);
+    assert_eq!(num_established_peer_connections.load(Ordering::SeqCst), 1);
+}
@@ -1,6 +1,7 @@
 //! Handlers for different request-response protocols
 
 pub mod cached_piece_by_index;
+pub mod cached_pieces_by_index;
 pub mod generic_request_handler;
 pub mod piece_by_index;
 pub mod segment_header;
@@ -0,0 +1,41 @@
+//! Helper for incoming batched cached piece requests.
+//!
+//! Request handler can be created with [`CachedPiecesByIndexRequestHandler`].
+
+use crate::protocols::request_response::handlers::generic_request_handler::{
+    GenericRequest, GenericRequestHandler,
+};
+use parity_scale_codec::{Decode, Encode};
+use subspace_core_primitives::pieces::{Piece, PieceIndex};
+
+/// Cached-pieces-by-index request.
+///
+/// This is similar to `CachedPieceByIndexRequest`, but requests several pieces at once, which
+/// saves round-trips when the peer is known to have them cached.
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub struct CachedPiecesByIndexRequest {
+    /// Request keys - piece indices
+    pub piece_indices: Vec<PieceIndex>,
+}
+
+impl GenericRequest for CachedPiecesByIndexRequest {
+    const PROTOCOL_NAME: &'static str = "/subspace/cached-pieces-by-index/0.1.0";
+    const LOG_TARGET: &'static str = "cached-pieces-by-index-request-response-handler";
+    type Response = CachedPiecesByIndexResponse;
+}
+
+impl CachedPiecesByIndexRequest {
+    /// Max number of piece indexes to accept per request, so the response fits into the maximum
+    /// response size
+    pub const RECOMMENDED_LIMIT: usize = 8;
+}
+
+/// Cached-pieces-by-index response, contains the requested pieces which are cached locally
+#[derive(Debug, PartialEq, Eq, Clone, Encode, Decode)]
+pub struct CachedPiecesByIndexResponse {
+    /// Requested pieces which are cached locally, order from request is not preserved
+    pub pieces: Vec<(PieceIndex, Piece)>,
+}
+
+/// Cached-pieces-by-index request handler
+pub type CachedPiecesByIndexRequestHandler = GenericRequestHandler<CachedPiecesByIndexRequest>;
@@ -1,12 +1,29 @@
 //! Provides methods to retrieve pieces from DSN.
 
+mod peer_backoff;
+mod peer_blacklist;
+mod peer_scores;
+#[cfg(test)]
+mod tests;
+
+pub use crate::utils::piece_provider::peer_backoff::{PeerBackoff, PeerBackoffConfig};
+pub use crate::utils::piece_provider::peer_blacklist::{PeerBlacklist, PeerBlacklistConfig};
+pub use crate::utils::piece_provider::peer_scores::PeerScores;
+
 use crate::constructor::DummyRecordStore;
+use crate::node::SendRequestError;
 use crate::protocols::request_response::handlers::cached_piece_by_index::{
-    CachedPieceByIndexRequest, CachedPieceByIndexResponse, PieceResult,
+    CachedPieceByIndexRequest, CachedPieceByIndexResponse, ClosestPeers, PieceResult,
+};
+use crate::protocols::request_response::handlers::cached_pieces_by_index::{
+    CachedPiecesByIndexRequest, CachedPiecesByIndexResponse,
 };
 use crate::protocols::request_response::handlers::piece_by_index::{
     PieceByIndexRequest, PieceByIndexResponse,
 };
+use crate::protocols::request_response::request_response_factory::{
+    OutboundFailure, RequestFailure,
+};
 use crate::utils::multihash::ToMultihash;
 use crate::{Multihash, Node};
 use async_lock::{Semaphore, SemaphoreGuard};
@@ -23,13 +40,60 @@ use rand::prelude::*;
 use std::any::type_name;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::num::NonZeroUsize;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Instant;
 use subspace_core_primitives::pieces::{Piece, PieceIndex};
+use thiserror::Error;
 use tokio_stream::StreamMap;
 use tracing::{Instrument, debug, trace, warn};
 
+/// The maximum number of peers requested by a quorum lookup, as a multiple of the quorum.
+const QUORUM_MAX_PEERS_FACTOR: usize = 3;
+
+/// Errors that happen while retrieving pieces from DSN
+#[derive(Debug, Error)]
+pub enum PieceProviderError {
+    /// None of the peers responded to piece requests
+    #[error("None of the peers responded to requests for piece {piece_index}")]
+    NoPeerResponses {
+        /// Requested piece index
+        piece_index: PieceIndex,
+    },
+
+    /// Peers returned different pieces, and not enough of them agreed
+    #[error(
+        "Only {max_agreeing} peers agreed on the contents of piece {piece_index}, quorum is \
+         {quorum}"
+    )]
+    NoQuorum {
+        /// Requested piece index
+        piece_index: PieceIndex,
+        /// The number of peers which had to return identical pieces
+        quorum: NonZeroUsize,
+        /// The largest number of peers which returned identical pieces
+        max_agreeing: usize,
+    },
+
+    /// Peers returned the piece, but it failed validation
+    #[error("Peers only returned invalid copies of piece {piece_index}")]
+    InvalidPiece {
+        /// Requested piece index
+        piece_index: PieceIndex,
+    },
+}
+
+/// How peers responded to requests for a piece.
+#[derive(Debug, Default, Copy, Clone)]
+struct PieceResponses {
+    /// At least one peer responded to a request (regardless of whether it had the piece or not)
+    responded: bool,
+    /// At least one peer returned a piece which failed validation
+    invalid_piece: bool,
+}
+
 /// Validates piece against using its commitment.
 #[async_trait]
 pub trait PieceValidator: Sync + Send {
@@ -55,11 +119,17 @@ impl PieceValidator for NoPieceValidator {
 
 /// Piece provider with cancellation and piece validator.
 /// Use `NoPieceValidator` to disable validation.
+///
+/// Requests to a peer reuse the node's existing connection to that peer. Connections are closed
+/// after `Config::idle_connection_timeout`, and limited by the connection limits in `Config`.
 #[derive(Clone)]
 pub struct PieceProvider<PV> {
     node: Node,
     piece_validator: PV,
     piece_downloading_semaphore: Arc<Semaphore>,
+    peer_scores: Arc<PeerScores>,
+    peer_blacklist: Arc<PeerBlacklist>,
+    peer_backoff: Arc<PeerBackoff>,
 }
 
 impl<PV> fmt::Debug for PieceProvider<PV> {
@@ -84,9 +154,52 @@ where
             node,
             piece_validator,
             piece_downloading_semaphore,
+            peer_scores: Arc::default(),
+            peer_blacklist: Arc::default(),
+            peer_backoff: Arc::default(),
         }
     }
 
+    /// Returns the node used to retrieve pieces.
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// Replaces the default peer blacklist with one using `config`.
+    pub fn with_peer_blacklist_config(mut self, config: PeerBlacklistConfig) -> Self {
+        self.peer_blacklist = Arc::new(PeerBlacklist::new(config));
+        self
+    }
+
+    /// Replaces the default peer backoff with one using `config`.
+    pub fn with_peer_backoff_config(mut self, config: PeerBackoffConfig) -> Self {
+        self.peer_backoff = Arc::new(PeerBackoff::new(config));
+        self
+    }
+
+    /// Returns the scores used to choose between peers, which can be used to pin or seed
+    /// preferred peers.
+    pub fn peer_scores(&self) -> &PeerScores {
+        &self.peer_scores
+    }
+
+    /// Returns the peers which are not asked for pieces, because they returned too many invalid
+    /// pieces.
+    pub fn peer_blacklist(&self) -> &PeerBlacklist {
+        &self.peer_blacklist
+    }
+
+    /// Returns the peers which are not asked for pieces until their backoff expires, because
+    /// their recent requests failed.
+    pub fn peer_backoff(&self) -> &PeerBackoff {
+        &self.peer_backoff
+    }
+
+    /// Returns true if `peer_id` shouldn't be asked for pieces right now.
+    fn is_peer_excluded(&self, peer_id: &PeerId) -> bool {
+        self.peer_blacklist.is_blacklisted(peer_id) || self.peer_backoff.is_backed_off(peer_id)
+    }
+
     /// Get pieces with provided indices from cache.
     ///
     /// Number of elements in returned stream is the same as number of unique `piece_indices`.
@@ -106,6 +219,9 @@ where
                 &self.piece_validator,
                 &tx,
                 &self.piece_downloading_semaphore,
+                &self.peer_scores,
+                &self.peer_blacklist,
+                &self.peer_backoff,
             )
             .await;
 
@@ -155,6 +271,11 @@ where
         while let Some(provider_id) = get_providers_stream.next().await {
             trace!(%piece_index, key, %provider_id, "get_providers returned an item");
 
+            if self.is_peer_excluded(&provider_id) {
+                trace!(%piece_index, key, %provider_id, "Skipping blacklisted or backed off provider");
+                continue;
+            }
+
             let Ok(PieceByIndexResponse {
                 piece,
                 cached_pieces: _,
@@ -168,20 +289,27 @@ where
                     },
                 )
                 .await
-                .inspect_err(
-                    |error| debug!(%piece_index, key, %provider_id, ?error, "Piece request failed"),
-                )
+                .inspect_err(|error| {
+                    debug!(%piece_index, key, %provider_id, ?error, "Piece request failed");
+                    self.peer_backoff.record_failure(provider_id, error);
+                })
             else {
                 continue;
             };
+            self.peer_backoff.record_success(provider_id);
 
             if let Some(piece) = piece {
                 trace!(%piece_index, key, %provider_id, "Piece request succeeded");
 
-                return self
+                let maybe_piece = self
                     .piece_validator
                     .validate_piece(provider_id, piece_index, piece)
                     .await;
+                if maybe_piece.is_none() {
+                    self.peer_blacklist.record_invalid_piece(provider_id);
+                }
+
+                return maybe_piece;
             } else {
                 debug!(%piece_index, key, %provider_id, "Piece request returned empty piece");
             }
@@ -191,16 +319,35 @@ where
     }
 
     /// Get piece from a particular peer.
+    ///
+    /// Returns `None` without sending a request if the peer is blacklisted or backed off.
     pub async fn get_piece_from_peer(
         &self,
         peer_id: PeerId,
         piece_index: PieceIndex,
     ) -> Option<Piece> {
+        self.request_piece_from_peer(peer_id, piece_index, &mut PieceResponses::default())
+            .await
+    }
+
+    /// Get piece from a particular peer, recording how the peer responded in `responses`.
+    async fn request_piece_from_peer(
+        &self,
+        peer_id: PeerId,
+        piece_index: PieceIndex,
+        responses: &mut PieceResponses,
+    ) -> Option<Piece> {
+        if self.is_peer_excluded(&peer_id) {
+            trace!(%peer_id, %piece_index, "Not requesting piece from blacklisted or backed off peer");
+            return None;
+        }
+
         // TODO: Take advantage of `cached_pieces`
-        let PieceByIndexResponse {
+        let start = Instant::now();
+        let Ok(PieceByIndexResponse {
             piece,
             cached_pieces: _,
-        } = self
+        }) = self
             .node
             .send_generic_request(
                 peer_id,
@@ -211,33 +358,79 @@ where
                 },
             )
             .await
-            .inspect_err(|error| debug!(%peer_id, %piece_index, ?error, "Piece request failed"))
-            .ok()?;
+            .inspect_err(|error| {
+                debug!(%peer_id, %piece_index, ?error, "Piece request failed");
+                self.peer_backoff.record_failure(peer_id, error);
+            })
+        else {
+            self.peer_scores.record_failure(peer_id);
+            return None;
+        };
+        let latency = start.elapsed();
+        self.peer_backoff.record_success(peer_id);
+
+        responses.responded = true;
 
-        if let Some(piece) = piece {
+        let maybe_piece = if let Some(piece) = piece {
             trace!(%peer_id, %piece_index, "Piece request succeeded");
 
-            return self
+            let maybe_piece = self
                 .piece_validator
                 .validate_piece(peer_id, piece_index, piece)
                 .await;
+            if maybe_piece.is_none() {
+                responses.invalid_piece = true;
+                self.peer_blacklist.record_invalid_piece(peer_id);
+            }
+
+            maybe_piece
         } else {
             debug!(%peer_id, %piece_index, "Piece request returned empty piece");
+
+            None
+        };
+
+        if maybe_piece.is_some() {
+            self.peer_scores.record_success(peer_id, latency);
+        } else {
+            self.peer_scores.record_failure(peer_id);
         }
 
-        None
+        maybe_piece
     }
 
     /// Get piece from archival storage (L1). The algorithm tries to get a piece from currently
     /// connected peers and falls back to random walking.
+    ///
+    /// See [`Self::try_get_piece_from_archival_storage`] for a version that distinguishes missing
+    /// pieces from retrieval errors.
     pub async fn get_piece_from_archival_storage(
         &self,
         piece_index: PieceIndex,
         max_random_walking_rounds: usize,
     ) -> Option<Piece> {
+        self.try_get_piece_from_archival_storage(piece_index, max_random_walking_rounds)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Get piece from archival storage (L1). The algorithm tries to get a piece from currently
+    /// connected peers and falls back to random walking.
+    ///
+    /// Returns `Ok(None)` if peers responded, but none of them had the piece, and an error if no
+    /// peer responded at all (no connected peers, failed lookups or all requests failed), or if
+    /// peers only returned pieces which failed validation.
+    pub async fn try_get_piece_from_archival_storage(
+        &self,
+        piece_index: PieceIndex,
+        max_random_walking_rounds: usize,
+    ) -> Result<Option<Piece>, PieceProviderError> {
         // TODO: consider using retry policy for L1 lookups as well.
         trace!(%piece_index, "Getting piece from archival storage..");
 
+        let mut responses = PieceResponses::default();
+
         let connected_servers = {
             let connected_servers = match self.node.connected_servers().await {
                 Ok(connected_servers) => connected_servers,
@@ -248,32 +441,39 @@ where
                 }
             };
 
-            HashSet::<PeerId>::from_iter(connected_servers)
+            let mut connected_servers = HashSet::<PeerId>::from_iter(connected_servers)
+                .into_iter()
+                .collect::<Vec<_>>();
+            self.peer_scores.sort_peers(&mut connected_servers);
+
+            connected_servers
         };
 
         if connected_servers.is_empty() {
             debug!(%piece_index, "Cannot acquire piece from no connected peers (DSN L1 lookup)");
         } else {
             for peer_id in connected_servers.iter() {
-                let maybe_piece = self.get_piece_from_peer(*peer_id, piece_index).await;
+                let maybe_piece = self
+                    .request_piece_from_peer(*peer_id, piece_index, &mut responses)
+                    .await;
 
                 if maybe_piece.is_some() {
                     trace!(%piece_index, %peer_id, "DSN L1 lookup from connected peers succeeded");
 
-                    return maybe_piece;
+                    return Ok(maybe_piece);
                 }
             }
         }
 
         trace!(%piece_index, "Getting piece from DSN L1 using random walk.");
         let random_walk_result = self
-            .get_piece_by_random_walking(piece_index, max_random_walking_rounds)
+            .get_piece_by_random_walking(piece_index, max_random_walking_rounds, &mut responses)
             .await;
 
         if random_walk_result.is_some() {
             trace!(%piece_index, "DSN L1 lookup via random walk succeeded");
 
-            return random_walk_result;
+            return Ok(random_walk_result);
         } else {
             debug!(
                 %piece_index,
@@ -282,7 +482,118 @@ where
             );
         }
 
-        None
+        if responses.invalid_piece {
+            Err(PieceProviderError::InvalidPiece { piece_index })
+        } else if responses.responded {
+            Ok(None)
+        } else {
+            Err(PieceProviderError::NoPeerResponses { piece_index })
+        }
+    }
+
+    /// Get piece from currently connected peers, and only accept it once `quorum` peers return
+    /// identical pieces. Pieces from other peers are rejected as outliers.
+    ///
+    /// This protects against peers which return pieces that pass validation, but are not the
+    /// expected piece, for example if the piece validator has a gap.
+    ///
+    /// The best scored peers are requested first. Only `quorum` peers are requested at the same
+    /// time, and other peers are only requested if the outstanding requests can't reach a quorum,
+    /// for example because peers disagreed or failed. At most `QUORUM_MAX_PEERS_FACTOR` times
+    /// `quorum` peers are requested, and each request waits for a piece downloading permit.
+    ///
+    /// Returns `Ok(None)` if peers responded, but none of them had the piece, and an error if no
+    /// peer responded, or not enough peers agreed on the piece.
+    pub async fn get_piece_with_quorum(
+        &self,
+        piece_index: PieceIndex,
+        quorum: NonZeroUsize,
+    ) -> Result<Option<Piece>, PieceProviderError> {
+        let mut connected_servers = match self.node.connected_servers().await {
+            Ok(connected_servers) => connected_servers,
+            Err(err) => {
+                debug!(%piece_index, ?err, "Cannot get connected peers (quorum lookup)");
+
+                Vec::new()
+            }
+        };
+        self.peer_scores.sort_peers(&mut connected_servers);
+        connected_servers.truncate(quorum.get().saturating_mul(QUORUM_MAX_PEERS_FACTOR));
+        let mut remaining_servers = connected_servers.into_iter();
+
+        let request_piece = |peer_id: PeerId| async move {
+            // Full pieces are large, so respect the limit on concurrent piece downloads
+            let _permit = self.piece_downloading_semaphore.acquire().await;
+            let mut responses = PieceResponses::default();
+            let maybe_piece = self
+                .request_piece_from_peer(peer_id, piece_index, &mut responses)
+                .await;
+
+            (peer_id, responses.responded, maybe_piece)
+        };
+
+        let mut piece_requests = FuturesUnordered::new();
+        let mut responded = false;
+        let mut peers_by_piece = HashMap::<Piece, Vec<PeerId>>::new();
+        loop {
+            // Only widen the request set if the outstanding requests can't reach a quorum
+            let max_agreeing = peers_by_piece
+                .values()
+                .map(Vec::len)
+                .max()
+                .unwrap_or_default();
+            while max_agreeing + piece_requests.len() < quorum.get() {
+                let Some(peer_id) = remaining_servers.next() else {
+                    break;
+                };
+                piece_requests.push(request_piece(peer_id));
+            }
+
+            let Some((peer_id, peer_responded, maybe_piece)) = piece_requests.next().await else {
+                break;
+            };
+            responded |= peer_responded;
+            let Some(piece) = maybe_piece else {
+                continue;
+            };
+
+            let agreeing_peers = peers_by_piece.entry(piece.clone()).or_default();
+            agreeing_peers.push(peer_id);
+            if agreeing_peers.len() < quorum.get() {
+                continue;
+            }
+
+            for (_outlier_piece, outlier_peers) in peers_by_piece
+                .iter()
+                .filter(|(other_piece, _peers)| **other_piece != piece)
+            {
+                for outlier_peer_id in outlier_peers {
+                    warn!(
+                        %piece_index,
+                        %outlier_peer_id,
+                        "Peer returned a different piece to the quorum, rejecting it"
+                    );
+                    self.peer_scores.record_failure(*outlier_peer_id);
+                }
+            }
+            trace!(%piece_index, %quorum, "Quorum of peers agreed on piece");
+
+            return Ok(Some(piece));
+        }
+
+        if let Some(max_agreeing) = peers_by_piece.values().map(Vec::len).max() {
+            debug!(%piece_index, %quorum, %max_agreeing, "Not enough peers agreed on piece");
+
+            Err(PieceProviderError::NoQuorum {
+                piece_index,
+                quorum,
+                max_agreeing,
+            })
+        } else if responded {
+            Ok(None)
+        } else {
+            Err(PieceProviderError::NoPeerResponses { piece_index })
+        }
     }
 
     /// Get piece from L1 by random walking
@@ -290,12 +601,13 @@ where
         &self,
         piece_index: PieceIndex,
         walking_rounds: usize,
+        responses: &mut PieceResponses,
     ) -> Option<Piece> {
         for round in 0..walking_rounds {
             debug!(%piece_index, round, "Random walk round");
 
             let result = self
-                .get_piece_by_random_walking_from_single_round(piece_index, round)
+                .get_piece_by_random_walking_from_single_round(piece_index, round, responses)
                 .await;
 
             if result.is_some() {
@@ -313,6 +625,7 @@ where
         &self,
         piece_index: PieceIndex,
         round: usize,
+        responses: &mut PieceResponses,
     ) -> Option<Piece> {
         // TODO: Take advantage of `cached_pieces`
         trace!(%piece_index, "get_piece_by_random_walking round");
@@ -330,6 +643,11 @@ where
         while let Some(peer_id) = get_closest_peers_stream.next().await {
             trace!(%piece_index, %peer_id, %round, "get_closest_peers returned an item");
 
+            if self.is_peer_excluded(&peer_id) {
+                trace!(%piece_index, %peer_id, %round, "Skipping blacklisted or backed off peer");
+                continue;
+            }
+
             let Ok(PieceByIndexResponse {
                 piece,
                 cached_pieces: _,
@@ -343,20 +661,30 @@ where
                     },
                 )
                 .await
-                .inspect_err(
-                    |error| debug!(%peer_id, %piece_index, ?key, %round, ?error, "Piece request failed."),
-                )
+                .inspect_err(|error| {
+                    debug!(%peer_id, %piece_index, ?key, %round, ?error, "Piece request failed.");
+                    self.peer_backoff.record_failure(peer_id, error);
+                })
             else {
                 continue;
             };
 
+            responses.responded = true;
+            self.peer_backoff.record_success(peer_id);
+
             if let Some(piece) = piece {
                 trace!(%peer_id, %piece_index, ?key, %round,  "Piece request succeeded.");
 
-                return self
+                let maybe_piece = self
                     .piece_validator
                     .validate_piece(peer_id, piece_index, piece)
                     .await;
+                if maybe_piece.is_none() {
+                    responses.invalid_piece = true;
+                    self.peer_blacklist.record_invalid_piece(peer_id);
+                }
+
+                return maybe_piece;
             } else {
                 debug!(%peer_id, %piece_index, ?key, %round, "Piece request returned empty piece.");
             }
@@ -419,12 +747,16 @@ impl KademliaWrapper {
 
 /// Takes pieces to download as an input, sends results with pieces that were downloaded
 /// successfully and returns those that were not downloaded
+#[allow(clippy::too_many_arguments)]
 async fn download_cached_pieces<PV, PieceIndices>(
     piece_indices: PieceIndices,
     node: &Node,
     piece_validator: &PV,
     results: &mpsc::UnboundedSender<(PieceIndex, Option<Piece>)>,
     semaphore: &Semaphore,
+    peer_scores: &PeerScores,
+    peer_blacklist: &PeerBlacklist,
+    peer_backoff: &PeerBackoff,
 ) -> impl ExactSizeIterator<Item = PieceIndex>
 where
     PV: PieceValidator,
@@ -462,10 +794,14 @@ where
 
     let mut checked_peers = HashSet::new();
 
-    let Ok(connected_servers) = node.connected_servers().await else {
+    let Ok(mut connected_servers) = node.connected_servers().await else {
         trace!("Connected servers error");
         return pieces_to_download.into_keys();
     };
+    // Try preferred peers first, and skip blacklisted or backed off peers
+    peer_blacklist.retain_allowed(&mut connected_servers);
+    peer_backoff.retain_allowed(&mut connected_servers);
+    peer_scores.sort_peers(&mut connected_servers);
 
     let num_connected_servers = connected_servers.len();
     debug!(
@@ -511,6 +847,9 @@ where
                 download_cached_piece_from_peer(
                     node,
                     piece_validator,
+                    peer_scores,
+                    peer_blacklist,
+                    peer_backoff,
                     peer_id,
                     Vec::new(),
                     Arc::new(check_cached_pieces),
@@ -543,7 +882,11 @@ where
                 .await
                 .unwrap_or_default()
                 .into_iter()
-                .filter(|peer_id| checked_peers.insert(*peer_id))
+                .filter(|peer_id| {
+                    !peer_blacklist.is_blacklisted(peer_id)
+                        && !peer_backoff.is_backed_off(peer_id)
+                        && checked_peers.insert(*peer_id)
+                })
                 .take(additional_pieces_to_download)
             {
                 let permit = if downloading_stream.is_empty() {
@@ -570,6 +913,9 @@ where
                     let fut = download_cached_piece_from_peer(
                         node,
                         piece_validator,
+                        peer_scores,
+                        peer_blacklist,
+                        peer_backoff,
                         peer_id,
                         Vec::new(),
                         Arc::new(check_cached_pieces),
@@ -613,7 +959,10 @@ where
                     .expect("Entries are not removed here; qed")
                     .closest_peers(&kbucket_key);
                 for (peer_id, addresses) in closest_peers_to_check {
-                    if !checked_peers.insert(peer_id) {
+                    if peer_blacklist.is_blacklisted(&peer_id)
+                        || peer_backoff.is_backed_off(&peer_id)
+                        || !checked_peers.insert(peer_id)
+                    {
                         continue;
                     }
 
@@ -628,6 +977,9 @@ where
                     let fut = download_cached_piece_from_peer(
                         node,
                         piece_validator,
+                        peer_scores,
+                        peer_blacklist,
+                        peer_backoff,
                         peer_id,
                         addresses,
                         Arc::new(check_cached_pieces),
@@ -668,6 +1020,9 @@ where
             &mut downloading_stream,
             node,
             piece_validator,
+            peer_scores,
+            peer_blacklist,
+            peer_backoff,
             results,
         );
 
@@ -679,6 +1034,7 @@ where
     pieces_to_download.into_keys()
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_downloading_result<'a, 'b, PV>(
     piece_index: PieceIndex,
     result: DownloadedPieceFromPeer<'a>,
@@ -689,6 +1045,9 @@ fn process_downloading_result<'a, 'b, PV>(
     >,
     node: &'a Node,
     piece_validator: &'a PV,
+    peer_scores: &'a PeerScores,
+    peer_blacklist: &'a PeerBlacklist,
+    peer_backoff: &'a PeerBackoff,
     results: &'a mpsc::UnboundedSender<(PieceIndex, Option<Piece>)>,
 ) where
     PV: PieceValidator,
@@ -696,6 +1055,7 @@ fn process_downloading_result<'a, 'b, PV>(
     let DownloadedPieceFromPeer {
         peer_id,
         result,
+        batched_pieces,
         mut cached_pieces,
         not_cached_pieces,
         permit,
@@ -707,16 +1067,29 @@ fn process_downloading_result<'a, 'b, PV>(
         return;
     };
 
+    // Batched pieces can also be downloaded from other peers at the same time, so pieces are only
+    // sent to results the first time they are downloaded
+    for (batched_piece_index, piece) in batched_pieces {
+        trace!(piece_index = %batched_piece_index, %peer_id, "Got batched piece");
+
+        cached_pieces.remove(&batched_piece_index);
+        if pieces_to_download.remove(&batched_piece_index).is_some() {
+            results
+                .unbounded_send((batched_piece_index, Some(piece)))
+                .expect("This future isn't polled after receiver is dropped; qed");
+        }
+    }
+
     match result {
         PieceResult::Piece(piece) => {
             trace!(%piece_index, %peer_id, "Got piece");
 
-            // Downloaded successfully
-            pieces_to_download.remove(&piece_index);
-
-            results
-                .unbounded_send((piece_index, Some(piece)))
-                .expect("This future isn't polled after receiver is dropped; qed");
+            // Downloaded successfully, unless it was already downloaded in a batch
+            if pieces_to_download.remove(&piece_index).is_some() {
+                results
+                    .unbounded_send((piece_index, Some(piece)))
+                    .expect("This future isn't polled after receiver is dropped; qed");
+            }
 
             if pieces_to_download.is_empty() {
                 return;
@@ -772,25 +1145,66 @@ fn process_downloading_result<'a, 'b, PV>(
         return;
     };
 
-    let fut = download_cached_piece_from_peer(
-        node,
-        piece_validator,
-        peer_id,
-        Vec::new(),
-        // Sample more random cached piece indices for connected peer, algorithm can be
-        // improved, but has to be something simple and this should do it for now
-        Arc::new(sample_cached_piece_indices(
-            pieces_to_download.keys(),
-            &cached_pieces,
-            &not_cached_pieces,
-            piece_index_to_download_next,
-        )),
+    // Other pieces the peer claims to have are downloaded in the same request
+    let batched_piece_indices = cached_pieces
+        .iter()
+        .filter(|piece_index| {
+            **piece_index != piece_index_to_download_next
+                && !downloading_stream.contains_key(piece_index)
+        })
+        .take(CachedPiecesByIndexRequest::RECOMMENDED_LIMIT - 1)
+        .copied()
+        .collect::<Vec<_>>();
+
+    // Sample more random cached piece indices for connected peer, algorithm can be
+    // improved, but has to be something simple and this should do it for now
+    let check_cached_pieces = Arc::new(sample_cached_piece_indices(
+        pieces_to_download.keys(),
+        &cached_pieces,
+        &not_cached_pieces,
         piece_index_to_download_next,
-        cached_pieces,
-        not_cached_pieces,
-        permit,
-    );
-    downloading_stream.insert(piece_index_to_download_next, Box::pin(fut.into_stream()));
+    ));
+
+    if batched_piece_indices.is_empty() {
+        let fut = download_cached_piece_from_peer(
+            node,
+            piece_validator,
+            peer_scores,
+            peer_blacklist,
+            peer_backoff,
+            peer_id,
+            Vec::new(),
+            check_cached_pieces,
+            piece_index_to_download_next,
+            cached_pieces,
+            not_cached_pieces,
+            permit,
+        );
+        downloading_stream.insert(piece_index_to_download_next, Box::pin(fut.into_stream()));
+    } else {
+        trace!(
+            piece_index = %piece_index_to_download_next,
+            %peer_id,
+            batched_pieces = %batched_piece_indices.len(),
+            "Batching pieces to download from peer"
+        );
+
+        let fut = download_cached_pieces_from_peer(
+            node,
+            piece_validator,
+            peer_scores,
+            peer_blacklist,
+            peer_backoff,
+            peer_id,
+            check_cached_pieces,
+            piece_index_to_download_next,
+            batched_piece_indices,
+            cached_pieces,
+            not_cached_pieces,
+            permit,
+        );
+        downloading_stream.insert(piece_index_to_download_next, Box::pin(fut.into_stream()));
+    }
 }
 
 fn sample_cached_piece_indices<'a, I>(
@@ -824,6 +1238,8 @@ where
 struct DownloadedPieceFromPeer<'a> {
     peer_id: PeerId,
     result: Option<PieceResult>,
+    /// Other pieces which were downloaded in the same request
+    batched_pieces: Vec<(PieceIndex, Piece)>,
     cached_pieces: HashSet<PieceIndex>,
     not_cached_pieces: HashSet<PieceIndex>,
     permit: SemaphoreGuard<'a>,
@@ -836,6 +1252,9 @@ struct DownloadedPieceFromPeer<'a> {
 async fn download_cached_piece_from_peer<'a, PV>(
     node: &'a Node,
     piece_validator: &'a PV,
+    peer_scores: &'a PeerScores,
+    peer_blacklist: &'a PeerBlacklist,
+    peer_backoff: &'a PeerBackoff,
     peer_id: PeerId,
     addresses: Vec<Multiaddr>,
     check_cached_pieces: Arc<Vec<PieceIndex>>,
@@ -847,6 +1266,7 @@ async fn download_cached_piece_from_peer<'a, PV>(
 where
     PV: PieceValidator,
 {
+    let start = Instant::now();
     let result = match node
         .send_generic_request(
             peer_id,
@@ -859,19 +1279,32 @@ where
         .await
     {
         Ok(response) => {
+            peer_backoff.record_success(peer_id);
             let CachedPieceByIndexResponse {
                 result,
                 cached_pieces,
             } = response;
 
             match result {
-                PieceResult::Piece(piece) => piece_validator
-                    .validate_piece(peer_id, piece_index, piece)
-                    .await
-                    .map(|piece| CachedPieceByIndexResponse {
+                PieceResult::Piece(piece) => {
+                    let latency = start.elapsed();
+                    let maybe_piece = piece_validator
+                        .validate_piece(peer_id, piece_index, piece)
+                        .await;
+
+                    if maybe_piece.is_some() {
+                        peer_scores.record_success(peer_id, latency);
+                    } else {
+                        peer_scores.record_failure(peer_id);
+                        peer_blacklist.record_invalid_piece(peer_id);
+                    }
+
+                    maybe_piece.map(|piece| CachedPieceByIndexResponse {
                         result: PieceResult::Piece(piece),
                         cached_pieces,
-                    }),
+                    })
+                }
+                // The peer doesn't have the piece, which isn't a failure for a cache
                 PieceResult::ClosestPeers(closest_peers) => Some(CachedPieceByIndexResponse {
                     result: PieceResult::ClosestPeers(closest_peers),
                     cached_pieces,
@@ -880,6 +1313,8 @@ where
         }
         Err(error) => {
             debug!(%error, %peer_id, %piece_index, "Failed to download cached piece from peer");
+            peer_scores.record_failure(peer_id);
+            peer_backoff.record_failure(peer_id, &error);
 
             None
         }
@@ -898,6 +1333,7 @@ where
             DownloadedPieceFromPeer {
                 peer_id,
                 result: Some(result.result),
+                batched_pieces: Vec::new(),
                 cached_pieces: { cached_pieces },
                 not_cached_pieces,
                 permit,
@@ -906,9 +1342,146 @@ where
         None => DownloadedPieceFromPeer {
             peer_id,
             result: None,
+            batched_pieces: Vec::new(),
             cached_pieces,
             not_cached_pieces,
             permit,
         },
     }
 }
+
+/// Returns true if a request failed because the local node or the peer doesn't support its
+/// protocol.
+fn is_unsupported_protocol(error: &SendRequestError) -> bool {
+    matches!(
+        error,
+        SendRequestError::ProtocolFailure(
+            RequestFailure::UnknownProtocol
+                | RequestFailure::Network(OutboundFailure::UnsupportedProtocols)
+        )
+    )
+}
+
+/// Downloads `piece_index` and `batched_piece_indices`, which the peer claims to have cached,
+/// from a peer in a single request.
+///
+/// Falls back to downloading only `piece_index` if the local node or the peer doesn't support
+/// batched requests, see [`download_cached_piece_from_peer`] for the other arguments.
+#[allow(clippy::too_many_arguments)]
+async fn download_cached_pieces_from_peer<'a, PV>(
+    node: &'a Node,
+    piece_validator: &'a PV,
+    peer_scores: &'a PeerScores,
+    peer_blacklist: &'a PeerBlacklist,
+    peer_backoff: &'a PeerBackoff,
+    peer_id: PeerId,
+    check_cached_pieces: Arc<Vec<PieceIndex>>,
+    piece_index: PieceIndex,
+    batched_piece_indices: Vec<PieceIndex>,
+    mut cached_pieces: HashSet<PieceIndex>,
+    mut not_cached_pieces: HashSet<PieceIndex>,
+    permit: SemaphoreGuard<'a>,
+) -> DownloadedPieceFromPeer<'a>
+where
+    PV: PieceValidator,
+{
+    let mut piece_indices = batched_piece_indices;
+    piece_indices.insert(0, piece_index);
+
+    let start = Instant::now();
+    let pieces = match node
+        .send_generic_request(
+            peer_id,
+            Vec::new(),
+            CachedPiecesByIndexRequest {
+                piece_indices: piece_indices.clone(),
+            },
+        )
+        .await
+    {
+        Ok(CachedPiecesByIndexResponse { pieces }) => pieces,
+        Err(error) if is_unsupported_protocol(&error) => {
+            trace!(%peer_id, %piece_index, "Batched requests are not supported, downloading single piece");
+
+            return download_cached_piece_from_peer(
+                node,
+                piece_validator,
+                peer_scores,
+                peer_blacklist,
+                peer_backoff,
+                peer_id,
+                Vec::new(),
+                check_cached_pieces,
+                piece_index,
+                cached_pieces,
+                not_cached_pieces,
+                permit,
+            )
+            .await;
+        }
+        Err(error) => {
+            debug!(%error, %peer_id, %piece_index, "Failed to download cached pieces from peer");
+            peer_scores.record_failure(peer_id);
+            peer_backoff.record_failure(peer_id, &error);
+
+            return DownloadedPieceFromPeer {
+                peer_id,
+                result: None,
+                batched_pieces: Vec::new(),
+                cached_pieces,
+                not_cached_pieces,
+                permit,
+            };
+        }
+    };
+    let latency = start.elapsed();
+    peer_backoff.record_success(peer_id);
+
+    let mut maybe_piece = None;
+    let mut batched_pieces = Vec::with_capacity(piece_indices.len() - 1);
+    let mut downloaded_piece_indices = HashSet::with_capacity(piece_indices.len());
+    for (downloaded_piece_index, piece) in pieces {
+        if !piece_indices.contains(&downloaded_piece_index) {
+            debug!(%peer_id, piece_index = %downloaded_piece_index, "Peer returned a piece which wasn't requested");
+            continue;
+        }
+
+        let Some(piece) = piece_validator
+            .validate_piece(peer_id, downloaded_piece_index, piece)
+            .await
+        else {
+            peer_scores.record_failure(peer_id);
+            peer_blacklist.record_invalid_piece(peer_id);
+            continue;
+        };
+        peer_scores.record_success(peer_id, latency);
+        downloaded_piece_indices.insert(downloaded_piece_index);
+
+        if downloaded_piece_index == piece_index {
+            maybe_piece.replace(piece);
+        } else {
+            batched_pieces.push((downloaded_piece_index, piece));
+        }
+    }
+
+    // The peer doesn't have the requested pieces which it didn't return, or they were invalid
+    for piece_index in piece_indices {
+        if !downloaded_piece_indices.contains(&piece_index) {
+            cached_pieces.remove(&piece_index);
+            not_cached_pieces.insert(piece_index);
+        }
+    }
+
+    DownloadedPieceFromPeer {
+        peer_id,
+        // The peer doesn't return closest peers for batched requests
+        result: Some(match maybe_piece {
+            Some(piece) => PieceResult::Piece(piece),
+            None => PieceResult::ClosestPeers(ClosestPeers::default()),
+        }),
+        batched_pieces,
+        cached_pieces,
+        not_cached_pieces,
+        permit,
+    }
+}
@@ -0,0 +1,164 @@
+//! Backs off from peers which can't be reached for piece requests.
+
+use crate::node::SendRequestError;
+use crate::protocols::request_response::request_response_factory::{
+    OutboundFailure, RequestFailure,
+};
+use libp2p::PeerId;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Configuration of a [`PeerBackoff`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PeerBackoffConfig {
+    /// How long a peer is skipped after its first failed request.
+    pub initial_backoff: Duration,
+    /// The maximum time a peer is skipped, however many times its requests have failed.
+    pub max_backoff: Duration,
+}
+
+impl Default for PeerBackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PeerBackoffState {
+    /// The number of failed requests since the last successful request
+    consecutive_failures: u32,
+    /// The time the peer can be retried
+    retry_at: Instant,
+}
+
+/// Returns true if a request failed because the peer couldn't be reached.
+///
+/// Peers which respond with errors, or don't support a protocol, are reachable.
+fn is_connection_failure(error: &SendRequestError) -> bool {
+    matches!(
+        error,
+        SendRequestError::ProtocolFailure(
+            RequestFailure::NotConnected
+                | RequestFailure::Network(
+                    OutboundFailure::DialFailure
+                        | OutboundFailure::Timeout
+                        | OutboundFailure::ConnectionClosed
+                )
+        )
+    )
+}
+
+/// Peers which are not asked for pieces until their backoff expires, because their recent
+/// requests failed to connect, shared by all clones of a `PieceProvider`.
+///
+/// The backoff starts at `initial_backoff`, and doubles after each consecutive failure, up to
+/// `max_backoff`. A successful request resets the backoff. Peers which haven't failed for
+/// `max_backoff` after their backoff expires are forgotten, so their next backoff is reset too.
+#[derive(Debug, Default)]
+pub struct PeerBackoff {
+    config: PeerBackoffConfig,
+    peers: Mutex<HashMap<PeerId, PeerBackoffState>>,
+}
+
+impl PeerBackoff {
+    /// Creates a new peer backoff with `config`.
+    pub fn new(config: PeerBackoffConfig) -> Self {
+        Self {
+            config,
+            peers: Mutex::default(),
+        }
+    }
+
+    /// Returns the time `peer_id` can be retried, or `None` if it isn't backed off.
+    pub fn retry_at(&self, peer_id: &PeerId) -> Option<Instant> {
+        self.peers
+            .lock()
+            .get(peer_id)
+            .map(|state| state.retry_at)
+            .filter(|retry_at| *retry_at > Instant::now())
+    }
+
+    /// Returns the peers which are currently backed off, and the time they can be retried.
+    pub fn backed_off_peers(&self) -> Vec<(PeerId, Instant)> {
+        let now = Instant::now();
+
+        self.peers
+            .lock()
+            .iter()
+            .filter(|(_peer_id, state)| state.retry_at > now)
+            .map(|(peer_id, state)| (*peer_id, state.retry_at))
+            .collect()
+    }
+
+    /// Returns true if `peer_id` is currently backed off.
+    pub(crate) fn is_backed_off(&self, peer_id: &PeerId) -> bool {
+        self.retry_at(peer_id).is_some()
+    }
+
+    /// Records a successful request to `peer_id`, resetting its backoff.
+    pub(crate) fn record_success(&self, peer_id: PeerId) {
+        self.peers.lock().remove(&peer_id);
+    }
+
+    /// Records a failed request to `peer_id`, and backs off from it if the failure was a
+    /// connection failure.
+    pub(crate) fn record_failure(&self, peer_id: PeerId, error: &SendRequestError) {
+        if !is_connection_failure(error) {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut peers = self.peers.lock();
+        self.remove_expired(&mut peers, now);
+        let state = peers.entry(peer_id).or_insert(PeerBackoffState {
+            consecutive_failures: 0,
+            retry_at: now,
+        });
+
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        let backoff = self
+            .config
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(state.consecutive_failures - 1))
+            .min(self.config.max_backoff);
+        state.retry_at = now + backoff;
+
+        debug!(
+            %peer_id,
+            consecutive_failures = %state.consecutive_failures,
+            ?backoff,
+            "Backing off from peer after failed request"
+        );
+    }
+
+    /// Removes backed off peers from `peers`.
+    pub(crate) fn retain_allowed(&self, peers: &mut Vec<PeerId>) {
+        let now = Instant::now();
+        let mut backed_off_peers = self.peers.lock();
+        self.remove_expired(&mut backed_off_peers, now);
+
+        peers.retain(|peer_id| {
+            !backed_off_peers
+                .get(peer_id)
+                .is_some_and(|state| state.retry_at > now)
+        });
+    }
+
+    /// Removes peers whose backoff expired more than `max_backoff` ago.
+    fn remove_expired(&self, peers: &mut HashMap<PeerId, PeerBackoffState>, now: Instant) {
+        peers.retain(|_peer_id, state| {
+            now.saturating_duration_since(state.retry_at) <= self.config.max_backoff
+        });
+    }
+
+    /// Returns the number of peers with recent failed requests.
+    #[cfg(test)]
+    pub(crate) fn num_tracked_peers(&self) -> usize {
+        self.peers.lock().len()
+    }
+}
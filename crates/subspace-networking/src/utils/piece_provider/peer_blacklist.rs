@@ -0,0 +1,138 @@
+//! Temporarily excludes peers which repeatedly return invalid pieces.
+
+use libp2p::PeerId;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Configuration of a [`PeerBlacklist`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PeerBlacklistConfig {
+    /// The number of invalid pieces within `window` which gets a peer blacklisted.
+    pub max_invalid_pieces: NonZeroUsize,
+    /// The period invalid pieces are counted over.
+    pub window: Duration,
+    /// How long a peer stays blacklisted.
+    pub duration: Duration,
+}
+
+impl Default for PeerBlacklistConfig {
+    fn default() -> Self {
+        Self {
+            max_invalid_pieces: NonZeroUsize::new(3).expect("Not zero; qed"),
+            window: Duration::from_secs(5 * 60),
+            duration: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct PeerBlacklistInner {
+    /// The times of recent invalid pieces from each peer
+    invalid_pieces: HashMap<PeerId, VecDeque<Instant>>,
+    /// Blacklisted peers, and the time their blacklisting expires
+    blacklisted: HashMap<PeerId, Instant>,
+}
+
+impl PeerBlacklistInner {
+    /// Removes expired blacklistings, and invalid pieces which are older than `window`.
+    ///
+    /// Peers without any recent invalid pieces are removed, so they don't use memory.
+    fn remove_expired(&mut self, now: Instant, window: Duration) {
+        self.blacklisted
+            .retain(|_peer_id, expires_at| *expires_at > now);
+        self.invalid_pieces.retain(|_peer_id, invalid_pieces| {
+            while let Some(oldest) = invalid_pieces.front()
+                && now.duration_since(*oldest) > window
+            {
+                invalid_pieces.pop_front();
+            }
+
+            !invalid_pieces.is_empty()
+        });
+    }
+}
+
+/// Peers which are not asked for pieces, because they repeatedly returned pieces which failed
+/// validation, shared by all clones of a `PieceProvider`.
+///
+/// Peers are blacklisted after `max_invalid_pieces` invalid pieces within `window`, and are
+/// asked for pieces again after `duration`.
+#[derive(Debug, Default)]
+pub struct PeerBlacklist {
+    config: PeerBlacklistConfig,
+    inner: Mutex<PeerBlacklistInner>,
+}
+
+impl PeerBlacklist {
+    /// Creates a new blacklist with `config`.
+    pub fn new(config: PeerBlacklistConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Returns true if `peer_id` is currently blacklisted.
+    pub fn is_blacklisted(&self, peer_id: &PeerId) -> bool {
+        self.inner
+            .lock()
+            .blacklisted
+            .get(peer_id)
+            .is_some_and(|expires_at| *expires_at > Instant::now())
+    }
+
+    /// Returns the currently blacklisted peers, and the time their blacklisting expires.
+    pub fn blacklisted_peers(&self) -> Vec<(PeerId, Instant)> {
+        let mut inner = self.inner.lock();
+        inner.remove_expired(Instant::now(), self.config.window);
+
+        inner
+            .blacklisted
+            .iter()
+            .map(|(peer_id, expires_at)| (*peer_id, *expires_at))
+            .collect()
+    }
+
+    /// Records an invalid piece from `peer_id`, blacklisting it if it has returned too many
+    /// invalid pieces recently.
+    pub(crate) fn record_invalid_piece(&self, peer_id: PeerId) {
+        let now = Instant::now();
+        let mut inner = self.inner.lock();
+        inner.remove_expired(now, self.config.window);
+
+        let invalid_pieces = inner.invalid_pieces.entry(peer_id).or_default();
+        invalid_pieces.push_back(now);
+
+        if invalid_pieces.len() < self.config.max_invalid_pieces.get() {
+            return;
+        }
+
+        warn!(
+            %peer_id,
+            invalid_pieces = %invalid_pieces.len(),
+            duration = ?self.config.duration,
+            "Blacklisting peer which returned too many invalid pieces"
+        );
+        inner.invalid_pieces.remove(&peer_id);
+        inner
+            .blacklisted
+            .insert(peer_id, now + self.config.duration);
+    }
+
+    /// Removes blacklisted peers from `peers`.
+    pub(crate) fn retain_allowed(&self, peers: &mut Vec<PeerId>) {
+        let mut inner = self.inner.lock();
+        inner.remove_expired(Instant::now(), self.config.window);
+
+        peers.retain(|peer_id| !inner.blacklisted.contains_key(peer_id));
+    }
+
+    /// Returns the number of peers with recent invalid pieces.
+    #[cfg(test)]
+    pub(crate) fn num_tracked_peers(&self) -> usize {
+        self.inner.lock().invalid_pieces.len()
+    }
+}
@@ -0,0 +1,163 @@
+//! Scores peers by how reliably and quickly they serve pieces.
+
+use libp2p::PeerId;
+use parking_lot::Mutex;
+use schnellru::{ByLength, LruMap};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// The weight of each new outcome in a peer's score, between 0 and 1.
+const OUTCOME_WEIGHT: f64 = 0.2;
+/// The success rate of peers without any recorded outcomes.
+///
+/// This is lower than the success rate of reliable peers, but higher than unreliable peers, so
+/// new peers are still tried.
+const DEFAULT_SUCCESS_RATE: f64 = 0.5;
+/// The default maximum number of peers with scores.
+const DEFAULT_MAX_SCORED_PEERS: u32 = 10_000;
+
+/// The observed reliability and latency of a peer.
+#[derive(Debug, Copy, Clone)]
+struct PeerScore {
+    /// Moving average of request outcomes, 1.0 is always successful, 0.0 always fails
+    success_rate: f64,
+    /// Moving average of successful request latencies
+    latency: Option<Duration>,
+}
+
+impl Default for PeerScore {
+    fn default() -> Self {
+        Self {
+            success_rate: DEFAULT_SUCCESS_RATE,
+            latency: None,
+        }
+    }
+}
+
+impl PeerScore {
+    /// Returns the score value, higher is better.
+    ///
+    /// Peers which succeed more often score higher, and slow peers score lower.
+    fn value(&self) -> f64 {
+        let latency = self.latency.unwrap_or_default().as_secs_f64();
+
+        self.success_rate / (1.0 + latency)
+    }
+}
+
+#[derive(Debug)]
+struct PeerScoresInner {
+    /// Scores of recently scored peers, the least recently scored peers are forgotten first
+    scores: LruMap<PeerId, PeerScore>,
+    pinned: HashSet<PeerId>,
+}
+
+impl PeerScoresInner {
+    fn score(&self, peer_id: &PeerId) -> f64 {
+        self.scores
+            .peek(peer_id)
+            .copied()
+            .unwrap_or_default()
+            .value()
+    }
+
+    fn score_mut(&mut self, peer_id: PeerId) -> &mut PeerScore {
+        self.scores
+            .get_or_insert(peer_id, PeerScore::default)
+            .expect("Not limited by length, because capacity is not zero; qed")
+    }
+}
+
+/// Scores of peers which serve pieces, shared by all clones of a `PieceProvider`.
+///
+/// When several peers can serve a piece, pinned peers are tried first, then other peers in order
+/// of their score. Scores are updated from the outcome and latency of each piece request.
+///
+/// Scores are kept for a limited number of peers. When that limit is reached, the least recently
+/// scored peers are forgotten, and are scored like new peers.
+#[derive(Debug)]
+pub struct PeerScores {
+    inner: Mutex<PeerScoresInner>,
+}
+
+impl Default for PeerScores {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SCORED_PEERS)
+    }
+}
+
+impl PeerScores {
+    /// Creates new peer scores, which keeps scores for up to `max_scored_peers` peers.
+    ///
+    /// Zero is treated as one.
+    pub fn new(max_scored_peers: u32) -> Self {
+        Self {
+            inner: Mutex::new(PeerScoresInner {
+                scores: LruMap::new(ByLength::new(max_scored_peers.max(1))),
+                pinned: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Pins a preferred peer, so it is always tried before unpinned peers.
+    pub fn pin_peer(&self, peer_id: PeerId) {
+        self.inner.lock().pinned.insert(peer_id);
+    }
+
+    /// Unpins a peer, so it is tried in order of its score.
+    pub fn unpin_peer(&self, peer_id: PeerId) {
+        self.inner.lock().pinned.remove(&peer_id);
+    }
+
+    /// Seeds the score of a peer with a known success rate between 0.0 and 1.0, replacing any
+    /// previously observed outcomes.
+    pub fn seed_peer(&self, peer_id: PeerId, success_rate: f64) {
+        *self.inner.lock().score_mut(peer_id) = PeerScore {
+            success_rate: success_rate.clamp(0.0, 1.0),
+            latency: None,
+        };
+    }
+
+    /// Returns the current score of a peer, higher is better.
+    pub fn score(&self, peer_id: &PeerId) -> f64 {
+        self.inner.lock().score(peer_id)
+    }
+
+    /// Records a successful piece request to `peer_id`, which took `latency`.
+    pub(crate) fn record_success(&self, peer_id: PeerId, latency: Duration) {
+        let mut inner = self.inner.lock();
+        let score = inner.score_mut(peer_id);
+
+        score.success_rate += (1.0 - score.success_rate) * OUTCOME_WEIGHT;
+        score.latency = Some(match score.latency {
+            Some(average) => {
+                average.mul_f64(1.0 - OUTCOME_WEIGHT) + latency.mul_f64(OUTCOME_WEIGHT)
+            }
+            None => latency,
+        });
+    }
+
+    /// Records a failed piece request to `peer_id`.
+    pub(crate) fn record_failure(&self, peer_id: PeerId) {
+        let mut inner = self.inner.lock();
+        let score = inner.score_mut(peer_id);
+
+        score.success_rate -= score.success_rate * OUTCOME_WEIGHT;
+    }
+
+    /// Sorts `peers` so pinned peers are first, then the rest in descending score order.
+    ///
+    /// The sort is stable, so peers with equal scores keep their original order.
+    pub(crate) fn sort_peers(&self, peers: &mut [PeerId]) {
+        let inner = self.inner.lock();
+
+        peers.sort_by(|a, b| {
+            let a_pinned = inner.pinned.contains(a);
+            let b_pinned = inner.pinned.contains(b);
+
+            b_pinned
+                .cmp(&a_pinned)
+                .then_with(|| inner.score(b).total_cmp(&inner.score(a)))
+        });
+    }
+}
@@ -0,0 +1,584 @@
+use crate::protocols::request_response::handlers::cached_piece_by_index::{
+    CachedPieceByIndexRequest, CachedPieceByIndexRequestHandler, CachedPieceByIndexResponse,
+    PieceResult,
+};
+use crate::protocols::request_response::handlers::cached_pieces_by_index::{
+    CachedPiecesByIndexRequest, CachedPiecesByIndexRequestHandler, CachedPiecesByIndexResponse,
+};
+use crate::protocols::request_response::handlers::piece_by_index::{
+    PieceByIndexRequestHandler, PieceByIndexResponse,
+};
+use crate::protocols::request_response::request_response_factory::{
+    RequestFailure, RequestHandler,
+};
+use crate::utils::piece_provider::{
+    NoPieceValidator, PeerBackoff, PeerBackoffConfig, PeerBlacklist, PeerBlacklistConfig,
+    PeerScores, PieceProvider, PieceProviderError, PieceValidator,
+};
+use crate::{Config, Node, SendRequestError, construct};
+use async_lock::Semaphore;
+use async_trait::async_trait;
+use futures::StreamExt;
+use futures::channel::oneshot;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use parking_lot::Mutex;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use subspace_core_primitives::pieces::{Piece, PieceIndex};
+use subspace_process::init_logger;
+
+/// A piece validator which only accepts `Piece::default()`.
+#[derive(Debug)]
+struct DefaultPieceValidator;
+
+#[async_trait]
+impl PieceValidator for DefaultPieceValidator {
+    async fn validate_piece(&self, _: PeerId, _: PieceIndex, piece: Piece) -> Option<Piece> {
+        (piece == Piece::default()).then_some(piece)
+    }
+}
+
+/// Starts a node which returns `piece` for every piece request, and returns its address.
+async fn piece_server(piece: Piece) -> Multiaddr {
+    counting_piece_server(piece).await.0
+}
+
+/// Starts a node which returns `piece` for every piece request, and returns its address, and the
+/// number of piece requests it has received.
+async fn counting_piece_server(piece: Piece) -> (Multiaddr, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let address = server(vec![PieceByIndexRequestHandler::create({
+        let requests = Arc::clone(&requests);
+
+        move |_, _| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            let piece = piece.clone();
+
+            async move {
+                Some(PieceByIndexResponse {
+                    piece: Some(piece),
+                    cached_pieces: Vec::new(),
+                })
+            }
+        }
+    })])
+    .await;
+
+    (address, requests)
+}
+
+/// Starts a node which has `Piece::default()` for every piece index in its cache, and supports
+/// batched cached piece requests. Returns its address, and the number of single and batched cached
+/// piece requests it has received.
+async fn batching_piece_server() -> (Multiaddr, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let single_requests = Arc::new(AtomicUsize::new(0));
+    let batched_requests = Arc::new(AtomicUsize::new(0));
+    let address = server(vec![
+        CachedPieceByIndexRequestHandler::create({
+            let single_requests = Arc::clone(&single_requests);
+
+            move |_, request: CachedPieceByIndexRequest| {
+                single_requests.fetch_add(1, Ordering::SeqCst);
+
+                async move {
+                    Some(CachedPieceByIndexResponse {
+                        result: PieceResult::Piece(Piece::default()),
+                        cached_pieces: Arc::unwrap_or_clone(request.cached_pieces),
+                    })
+                }
+            }
+        }),
+        CachedPiecesByIndexRequestHandler::create({
+            let batched_requests = Arc::clone(&batched_requests);
+
+            move |_, request: CachedPiecesByIndexRequest| {
+                batched_requests.fetch_add(1, Ordering::SeqCst);
+
+                async move {
+                    Some(CachedPiecesByIndexResponse {
+                        pieces: request
+                            .piece_indices
+                            .into_iter()
+                            .map(|piece_index| (piece_index, Piece::default()))
+                            .collect(),
+                    })
+                }
+            }
+        }),
+    ])
+    .await;
+
+    (address, single_requests, batched_requests)
+}
+
+/// Starts a node which serves `request_response_protocols`, and returns its address.
+async fn server(request_response_protocols: Vec<Box<dyn RequestHandler>>) -> Multiaddr {
+    let config = Config {
+        listen_on: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
+        allow_non_global_addresses_in_dht: true,
+        request_response_protocols,
+        ..Config::default()
+    };
+    let (node, mut node_runner) = construct(config).unwrap();
+
+    let (address_sender, address_receiver) = oneshot::channel();
+    let on_new_listener_handler = node.on_new_listener(Arc::new({
+        let address_sender = Mutex::new(Some(address_sender));
+
+        move |address| {
+            if matches!(address.iter().next(), Some(Protocol::Ip4(_)))
+                && let Some(address_sender) = address_sender.lock().take()
+            {
+                address_sender.send(address.clone()).unwrap();
+            }
+        }
+    }));
+
+    tokio::spawn(async move {
+        node_runner.run().await;
+    });
+
+    // Wait for the node to know its address
+    let address = address_receiver.await.unwrap();
+    drop(on_new_listener_handler);
+
+    address.with(Protocol::P2p(node.id()))
+}
+
+/// Starts a node which can request pieces, and is connected to the nodes at `addresses`.
+async fn connected_client(addresses: Vec<Multiaddr>) -> Node {
+    let num_servers = addresses.len();
+    let config = Config {
+        listen_on: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
+        allow_non_global_addresses_in_dht: true,
+        bootstrap_addresses: addresses,
+        request_response_protocols: vec![
+            CachedPieceByIndexRequestHandler::create(|_, _| async { None }),
+            CachedPiecesByIndexRequestHandler::create(|_, _| async { None }),
+            PieceByIndexRequestHandler::create(|_, _| async { None }),
+        ],
+        ..Config::default()
+    };
+    let (node, mut node_runner) = construct(config).unwrap();
+
+    tokio::spawn(async move {
+        node_runner.run().await;
+    });
+
+    node.bootstrap().await.unwrap();
+    while node.connected_servers().await.unwrap().len() < num_servers {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    node
+}
+
+#[tokio::test]
+async fn archival_storage_without_peers_is_an_error() {
+    init_logger();
+
+    let (node, mut node_runner) = construct(Config::default()).unwrap();
+
+    tokio::spawn(async move {
+        node_runner.run().await;
+    });
+
+    let piece_provider = PieceProvider::new(node, NoPieceValidator, Arc::new(Semaphore::new(1)));
+
+    let result = piece_provider
+        .try_get_piece_from_archival_storage(PieceIndex::ZERO, 1)
+        .await;
+    assert!(
+        matches!(
+            result,
+            Err(PieceProviderError::NoPeerResponses { piece_index }) if piece_index == PieceIndex::ZERO
+        ),
+        "{result:?}"
+    );
+
+    // Infallible version still reports a missing piece
+    assert!(
+        piece_provider
+            .get_piece_from_archival_storage(PieceIndex::ZERO, 1)
+            .await
+            .is_none()
+    );
+}
+
+#[test]
+fn peer_scores_prefer_reliable_peers() {
+    let peer_scores = PeerScores::default();
+    let reliable_peer = PeerId::random();
+    let unreliable_peer = PeerId::random();
+    let slow_peer = PeerId::random();
+    let new_peer = PeerId::random();
+
+    // Simulate requests to peers with different reliability and latency
+    for request in 0..20 {
+        peer_scores.record_success(reliable_peer, Duration::from_millis(10));
+        if request % 2 == 0 {
+            peer_scores.record_success(unreliable_peer, Duration::from_millis(10));
+        } else {
+            peer_scores.record_failure(unreliable_peer);
+        }
+        peer_scores.record_success(slow_peer, Duration::from_secs(2));
+    }
+
+    let mut peers = vec![new_peer, slow_peer, unreliable_peer, reliable_peer];
+    peer_scores.sort_peers(&mut peers);
+    assert_eq!(peers[0], reliable_peer, "{peers:?}");
+    assert_eq!(peers.last(), Some(&slow_peer), "{peers:?}");
+
+    // Failures move a peer down the order
+    for _ in 0..20 {
+        peer_scores.record_failure(reliable_peer);
+    }
+    peer_scores.sort_peers(&mut peers);
+    assert_eq!(peers.last(), Some(&reliable_peer), "{peers:?}");
+
+    // Pinned peers are always tried first
+    peer_scores.pin_peer(slow_peer);
+    peer_scores.sort_peers(&mut peers);
+    assert_eq!(peers[0], slow_peer, "{peers:?}");
+
+    // Seeded scores replace observed outcomes
+    peer_scores.unpin_peer(slow_peer);
+    peer_scores.seed_peer(reliable_peer, 1.0);
+    peer_scores.sort_peers(&mut peers);
+    assert_eq!(peers[0], reliable_peer, "{peers:?}");
+}
+
+#[test]
+fn peer_scores_are_bounded() {
+    let peer_scores = PeerScores::new(2);
+    let new_score = peer_scores.score(&PeerId::random());
+    let peers = [PeerId::random(), PeerId::random(), PeerId::random()];
+
+    for peer_id in peers {
+        peer_scores.record_failure(peer_id);
+        assert!(peer_scores.score(&peer_id) < new_score);
+    }
+
+    // The least recently scored peer is forgotten
+    assert_eq!(peer_scores.score(&peers[0]), new_score);
+    assert!(peer_scores.score(&peers[1]) < new_score);
+    assert!(peer_scores.score(&peers[2]) < new_score);
+}
+
+#[tokio::test]
+async fn quorum_rejects_tampered_pieces() {
+    init_logger();
+
+    let piece = Piece::default();
+    let mut tampered_piece = Piece::default();
+    tampered_piece.as_mut()[0] = 1;
+
+    let addresses = vec![
+        piece_server(piece.clone()).await,
+        piece_server(piece.clone()).await,
+        piece_server(tampered_piece).await,
+    ];
+    let node = connected_client(addresses).await;
+    let piece_provider = PieceProvider::new(node, NoPieceValidator, Arc::new(Semaphore::new(10)));
+
+    // The honest peers agree
+    let result = piece_provider
+        .get_piece_with_quorum(PieceIndex::ZERO, NonZeroUsize::new(2).unwrap())
+        .await;
+    assert_eq!(result.unwrap(), Some(piece));
+
+    // The tampered piece prevents all the peers from agreeing
+    let result = piece_provider
+        .get_piece_with_quorum(PieceIndex::ZERO, NonZeroUsize::new(3).unwrap())
+        .await;
+    assert!(
+        matches!(
+            result,
+            Err(PieceProviderError::NoQuorum {
+                max_agreeing: 2,
+                ..
+            })
+        ),
+        "{result:?}"
+    );
+}
+
+#[tokio::test]
+async fn quorum_only_requests_needed_peers() {
+    init_logger();
+
+    let mut addresses = Vec::new();
+    let mut request_counts = Vec::new();
+    for _ in 0..4 {
+        let (address, requests) = counting_piece_server(Piece::default()).await;
+        addresses.push(address);
+        request_counts.push(requests);
+    }
+    let node = connected_client(addresses).await;
+    // A single permit forces the requests to run one at a time
+    let piece_provider = PieceProvider::new(node, NoPieceValidator, Arc::new(Semaphore::new(1)));
+
+    // When peers agree, only the quorum is requested
+    let result = piece_provider
+        .get_piece_with_quorum(PieceIndex::ZERO, NonZeroUsize::new(2).unwrap())
+        .await;
+    assert_eq!(result.unwrap(), Some(Piece::default()));
+    assert_eq!(
+        request_counts
+            .iter()
+            .map(|requests| requests.load(Ordering::SeqCst))
+            .sum::<usize>(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn piece_requests_reuse_connections() {
+    init_logger();
+
+    let piece = Piece::default();
+    let address = piece_server(piece.clone()).await;
+    let Some(Protocol::P2p(server_peer_id)) = address.iter().last() else {
+        panic!("Server address ends with its peer ID; qed");
+    };
+    let node = connected_client(vec![address]).await;
+
+    let new_connections = Arc::new(AtomicUsize::new(0));
+    let _handler_id = node.on_connected_peer(Arc::new({
+        let new_connections = Arc::clone(&new_connections);
+
+        move |_peer_id| {
+            new_connections.fetch_add(1, Ordering::SeqCst);
+        }
+    }));
+
+    let piece_provider = PieceProvider::new(node, NoPieceValidator, Arc::new(Semaphore::new(10)));
+    let num_requests = 20;
+    for _ in 0..num_requests {
+        assert_eq!(
+            piece_provider
+                .get_piece_from_peer(server_peer_id, PieceIndex::ZERO)
+                .await,
+            Some(piece.clone())
+        );
+    }
+
+    // Every request used the connection which was established during bootstrap
+    assert_eq!(new_connections.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn peers_returning_invalid_pieces_are_blacklisted() {
+    init_logger();
+
+    let mut invalid_piece = Piece::default();
+    invalid_piece.as_mut()[0] = 1;
+
+    let (address, requests) = counting_piece_server(invalid_piece).await;
+    let Some(Protocol::P2p(server_peer_id)) = address.iter().last() else {
+        panic!("Server address ends with its peer ID; qed");
+    };
+    let node = connected_client(vec![address]).await;
+    let blacklist_duration = Duration::from_secs(1);
+    let piece_provider =
+        PieceProvider::new(node, DefaultPieceValidator, Arc::new(Semaphore::new(10)))
+            .with_peer_blacklist_config(PeerBlacklistConfig {
+                max_invalid_pieces: NonZeroUsize::new(3).unwrap(),
+                window: Duration::from_secs(60),
+                duration: blacklist_duration,
+            });
+
+    // The peer is asked for pieces until it reaches the threshold
+    for _ in 0..3 {
+        assert!(
+            piece_provider
+                .get_piece_from_peer(server_peer_id, PieceIndex::ZERO)
+                .await
+                .is_none()
+        );
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    let blacklisted_peers = piece_provider.peer_blacklist().blacklisted_peers();
+    assert_eq!(blacklisted_peers.len(), 1);
+    assert_eq!(blacklisted_peers[0].0, server_peer_id);
+
+    // Then it is excluded
+    assert!(
+        piece_provider
+            .get_piece_from_peer(server_peer_id, PieceIndex::ZERO)
+            .await
+            .is_none()
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+    // And asked again once the blacklisting expires
+    tokio::time::sleep(blacklist_duration).await;
+    assert!(
+        piece_provider
+            .peer_blacklist()
+            .blacklisted_peers()
+            .is_empty()
+    );
+    assert!(
+        piece_provider
+            .get_piece_from_peer(server_peer_id, PieceIndex::ZERO)
+            .await
+            .is_none()
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 4);
+    assert!(
+        !piece_provider
+            .peer_blacklist()
+            .is_blacklisted(&server_peer_id)
+    );
+}
+
+#[test]
+fn expired_invalid_pieces_are_removed() {
+    let window = Duration::from_millis(50);
+    let peer_blacklist = PeerBlacklist::new(PeerBlacklistConfig {
+        max_invalid_pieces: NonZeroUsize::new(2).unwrap(),
+        window,
+        duration: Duration::from_secs(60),
+    });
+    let peer_id = PeerId::random();
+
+    peer_blacklist.record_invalid_piece(peer_id);
+    assert_eq!(peer_blacklist.num_tracked_peers(), 1);
+
+    // Once its invalid pieces are outside the window, the peer is forgotten
+    std::thread::sleep(window * 2);
+    let mut peers = vec![peer_id];
+    peer_blacklist.retain_allowed(&mut peers);
+    assert_eq!(peers, [peer_id]);
+    assert_eq!(peer_blacklist.num_tracked_peers(), 0);
+
+    // And its old invalid pieces don't count towards the threshold
+    peer_blacklist.record_invalid_piece(peer_id);
+    assert!(!peer_blacklist.is_blacklisted(&peer_id));
+    peer_blacklist.record_invalid_piece(peer_id);
+    assert!(peer_blacklist.is_blacklisted(&peer_id));
+    assert_eq!(peer_blacklist.num_tracked_peers(), 0);
+}
+
+#[test]
+fn expired_backoffs_are_removed() {
+    let initial_backoff = Duration::from_millis(10);
+    let max_backoff = Duration::from_millis(50);
+    let peer_backoff = PeerBackoff::new(PeerBackoffConfig {
+        initial_backoff,
+        max_backoff,
+    });
+    let peer_id = PeerId::random();
+    let error = SendRequestError::ProtocolFailure(RequestFailure::NotConnected);
+
+    peer_backoff.record_failure(peer_id, &error);
+    peer_backoff.record_failure(peer_id, &error);
+    assert!(peer_backoff.is_backed_off(&peer_id));
+    assert_eq!(peer_backoff.num_tracked_peers(), 1);
+
+    // Once the peer hasn't failed for `max_backoff` after its backoff expires, it is forgotten
+    std::thread::sleep(initial_backoff * 2 + max_backoff * 2);
+    let mut peers = vec![peer_id];
+    peer_backoff.retain_allowed(&mut peers);
+    assert_eq!(peers, [peer_id]);
+    assert_eq!(peer_backoff.num_tracked_peers(), 0);
+
+    // So its next backoff starts at `initial_backoff` again
+    let before_failure = Instant::now();
+    peer_backoff.record_failure(peer_id, &error);
+    let retry_at = peer_backoff.retry_at(&peer_id).unwrap();
+    assert!(retry_at <= Instant::now() + initial_backoff);
+    assert!(retry_at >= before_failure + initial_backoff);
+}
+
+#[tokio::test]
+async fn unreachable_peers_are_backed_off() {
+    init_logger();
+
+    let piece = Piece::default();
+    let healthy_address = piece_server(piece.clone()).await;
+    let Some(Protocol::P2p(healthy_peer_id)) = healthy_address.iter().last() else {
+        panic!("Server address ends with its peer ID; qed");
+    };
+    let node = connected_client(vec![healthy_address]).await;
+    let initial_backoff = Duration::from_millis(200);
+    let piece_provider = PieceProvider::new(node, NoPieceValidator, Arc::new(Semaphore::new(10)))
+        .with_peer_backoff_config(PeerBackoffConfig {
+            initial_backoff,
+            max_backoff: Duration::from_secs(60),
+        });
+
+    // A peer without any known addresses can't be reached
+    let down_peer_id = PeerId::random();
+    let mut backoffs = Vec::new();
+    for _ in 0..3 {
+        assert!(
+            piece_provider
+                .get_piece_from_peer(down_peer_id, PieceIndex::ZERO)
+                .await
+                .is_none()
+        );
+        let retry_at = piece_provider
+            .peer_backoff()
+            .retry_at(&down_peer_id)
+            .unwrap();
+        backoffs.push(retry_at.saturating_duration_since(Instant::now()));
+
+        // The healthy peer is still used while the down peer is backed off
+        assert_eq!(
+            piece_provider
+                .get_piece_from_peer(healthy_peer_id, PieceIndex::ZERO)
+                .await,
+            Some(piece.clone())
+        );
+        assert!(
+            piece_provider
+                .peer_backoff()
+                .retry_at(&healthy_peer_id)
+                .is_none()
+        );
+
+        tokio::time::sleep_until(retry_at.into()).await;
+    }
+
+    // Retry intervals double after each consecutive failure
+    for (failure, backoff) in backoffs.iter().enumerate() {
+        let expected_backoff = initial_backoff * 2_u32.pow(failure as u32);
+        assert!(
+            *backoff <= expected_backoff && *backoff > expected_backoff / 2,
+            "{backoffs:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn pieces_from_the_same_peer_are_batched() {
+    init_logger();
+
+    let (address, single_requests, batched_requests) = batching_piece_server().await;
+    let node = connected_client(vec![address]).await;
+    let piece_provider =
+        PieceProvider::new(node, DefaultPieceValidator, Arc::new(Semaphore::new(10)));
+    let piece_indices = (0..CachedPiecesByIndexRequest::RECOMMENDED_LIMIT as u64)
+        .map(PieceIndex::from)
+        .collect::<Vec<_>>();
+
+    let pieces = piece_provider
+        .get_from_cache(piece_indices.clone())
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), piece_indices.len());
+    for (_piece_index, maybe_piece) in pieces {
+        assert_eq!(maybe_piece, Some(Piece::default()));
+    }
+
+    // The first request finds out which pieces the peer has cached, then the rest are downloaded
+    // in a single batched request
+    assert_eq!(single_requests.load(Ordering::SeqCst), 1);
+    assert_eq!(batched_requests.load(Ordering::SeqCst), 1);
+}
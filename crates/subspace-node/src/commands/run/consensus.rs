@@ -26,7 +26,7 @@ use subspace_service::config::{
     ChainSyncMode, SubspaceConfiguration, SubspaceNetworking, SubstrateConfiguration,
     SubstrateNetworkConfiguration, SubstrateRpcConfiguration,
 };
-use subspace_service::dsn::DsnConfig;
+use subspace_service::dsn::{DsnConfig, PieceRetrievalMode};
 use tempfile::TempDir;
 use tracing::{error, warn};
 
@@ -158,6 +158,15 @@ struct DsnOptions {
     /// Known external addresses.
     #[arg(long = "dsn-external-address")]
     dsn_external_addresses: Vec<Multiaddr>,
+
+    /// Where pieces are retrieved from when syncing from DSN.
+    ///
+    /// `cache-then-archival` also gets pieces which are missing from DSN caches from archival
+    /// storage, which is much slower than reconstructing them from the rest of the segment.
+    ///
+    /// Examples: `cache-only`, `cache-then-archival`
+    #[arg(long, default_value_t = PieceRetrievalMode::CacheOnly)]
+    dsn_piece_retrieval_mode: PieceRetrievalMode,
 }
 
 /// This mode specifies when the block's state (ie, storage) should be pruned (ie, removed) from
@@ -768,6 +777,7 @@ pub(super) fn create_consensus_chain_configuration(
             max_pending_in_connections: dsn_options.dsn_pending_in_connections,
             max_pending_out_connections: dsn_options.dsn_pending_out_connections,
             external_addresses: dsn_options.dsn_external_addresses,
+            piece_retrieval_mode: dsn_options.dsn_piece_retrieval_mode,
         }
     };
 
@@ -132,6 +132,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 	/// Proof: `Domains::PendingSlashes` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::AccumulatedTreasuryFunds` (r:1 w:1)
 	/// Proof: `Domains::AccumulatedTreasuryFunds` (`max_values`: Some(1), `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::OperatorRewardsBySource` (r:100 w:100)
+	/// Proof: `Domains::OperatorRewardsBySource` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// The range of component `n` is `[1, 100]`.
 	/// The range of component `s` is `[0, 100]`.
 	fn confirm_domain_block(n: u32, s: u32, ) -> Weight {
@@ -146,10 +148,10 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 			// Standard Error: 262_262
 			.saturating_add(Weight::from_parts(9_319_593, 0).saturating_mul(s.into()))
 			.saturating_add(T::DbWeight::get().reads(3))
-			.saturating_add(T::DbWeight::get().reads((2_u64).saturating_mul(n.into())))
+			.saturating_add(T::DbWeight::get().reads((3_u64).saturating_mul(n.into())))
 			.saturating_add(T::DbWeight::get().reads((1_u64).saturating_mul(s.into())))
 			.saturating_add(T::DbWeight::get().writes(3))
-			.saturating_add(T::DbWeight::get().writes((1_u64).saturating_mul(n.into())))
+			.saturating_add(T::DbWeight::get().writes((2_u64).saturating_mul(n.into())))
 			.saturating_add(T::DbWeight::get().writes((1_u64).saturating_mul(s.into())))
 			.saturating_add(Weight::from_parts(0, 2588).saturating_mul(n.into()))
 			.saturating_add(Weight::from_parts(0, 976).saturating_mul(s.into()))
@@ -200,6 +202,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 	/// Proof: `Domains::OperatorHighestSlot` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorIdOwner` (r:0 w:1)
 	/// Proof: `Domains::OperatorIdOwner` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:0 w:9)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// The range of component `n` is `[0, 9]`.
 	fn slash_operator(n: u32, ) -> Weight {
 		// Proof Size summary in bytes:
@@ -213,7 +217,7 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 			.saturating_add(T::DbWeight::get().reads(14))
 			.saturating_add(T::DbWeight::get().reads((5_u64).saturating_mul(n.into())))
 			.saturating_add(T::DbWeight::get().writes(12))
-			.saturating_add(T::DbWeight::get().writes((4_u64).saturating_mul(n.into())))
+			.saturating_add(T::DbWeight::get().writes((5_u64).saturating_mul(n.into())))
 			.saturating_add(Weight::from_parts(0, 2816).saturating_mul(n.into()))
 	}
 	/// Storage: `Domains::DomainStakingSummary` (r:1 w:1)
@@ -344,6 +348,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 	/// Proof: `Domains::OperatorIdOwner` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorEpochSharePrice` (r:0 w:1)
 	/// Proof: `Domains::OperatorEpochSharePrice` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:0 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn register_operator() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `684`
@@ -352,7 +358,7 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 		Weight::from_parts(158_767_000, 0)
 			.saturating_add(Weight::from_parts(0, 6196))
 			.saturating_add(T::DbWeight::get().reads(13))
-			.saturating_add(T::DbWeight::get().writes(12))
+			.saturating_add(T::DbWeight::get().writes(13))
 	}
 	/// Storage: `Domains::Operators` (r:1 w:1)
 	/// Proof: `Domains::Operators` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -372,6 +378,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 	/// Proof: `Domains::Deposits` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorEpochSharePrice` (r:1 w:0)
 	/// Proof: `Domains::OperatorEpochSharePrice` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:1 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn nominate_operator() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `1248`
@@ -379,8 +387,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 		// Minimum execution time: 133_467_000 picoseconds.
 		Weight::from_parts(136_907_000, 0)
 			.saturating_add(Weight::from_parts(0, 6196))
-			.saturating_add(T::DbWeight::get().reads(10))
-			.saturating_add(T::DbWeight::get().writes(7))
+			.saturating_add(T::DbWeight::get().reads(11))
+			.saturating_add(T::DbWeight::get().writes(8))
 	}
 	/// Storage: `Domains::OperatorIdOwner` (r:1 w:0)
 	/// Proof: `Domains::OperatorIdOwner` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -430,6 +438,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 	/// Proof: `Domains::HeadDomainNumber` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `RuntimeConfigs::StakingWithdrawalPeriod` (r:1 w:0)
 	/// Proof: `RuntimeConfigs::StakingWithdrawalPeriod` (`max_values`: Some(1), `max_size`: Some(4), added: 499, mode: `MaxEncodedLen`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:1 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn withdraw_stake() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `1442`
@@ -437,8 +447,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 		// Minimum execution time: 119_878_000 picoseconds.
 		Weight::from_parts(123_117_000, 0)
 			.saturating_add(Weight::from_parts(0, 6196))
-			.saturating_add(T::DbWeight::get().reads(13))
-			.saturating_add(T::DbWeight::get().writes(7))
+			.saturating_add(T::DbWeight::get().reads(14))
+			.saturating_add(T::DbWeight::get().writes(8))
 	}
 	/// Storage: `Domains::Operators` (r:1 w:0)
 	/// Proof: `Domains::Operators` (`max_values`: None, `max_size`: None, mode: `Measured`)
@@ -458,6 +468,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 	/// Proof: `Balances::Holds` (`max_values`: None, `max_size`: Some(134), added: 2609, mode: `MaxEncodedLen`)
 	/// Storage: `Domains::Deposits` (r:1 w:1)
 	/// Proof: `Domains::Deposits` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:1 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// The range of component `w` is `[1, 32]`.
 	fn unlock_funds(w: u32, ) -> Weight {
 		// Proof Size summary in bytes:
@@ -466,8 +478,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 		// Minimum execution time: 105_138_000 picoseconds.
 		Weight::from_parts(113_788_558, 0)
 			.saturating_add(Weight::from_parts(0, 4643))
-			.saturating_add(T::DbWeight::get().reads(9))
-			.saturating_add(T::DbWeight::get().writes(5))
+			.saturating_add(T::DbWeight::get().reads(10))
+			.saturating_add(T::DbWeight::get().writes(6))
 			.saturating_add(Weight::from_parts(0, 36).saturating_mul(w.into()))
 	}
 	/// Storage: `Domains::Operators` (r:1 w:1)
@@ -494,6 +506,8 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 	/// Proof: `Domains::OperatorHighestSlot` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	/// Storage: `Domains::OperatorIdOwner` (r:0 w:1)
 	/// Proof: `Domains::OperatorIdOwner` (`max_values`: None, `max_size`: None, mode: `Measured`)
+	/// Storage: `Domains::NominatorStakeHistory` (r:0 w:1)
+	/// Proof: `Domains::NominatorStakeHistory` (`max_values`: None, `max_size`: None, mode: `Measured`)
 	fn unlock_nominator() -> Weight {
 		// Proof Size summary in bytes:
 		//  Measured:  `1349`
@@ -502,7 +516,7 @@ impl<T: frame_system::Config> pallet_domains::WeightInfo for WeightInfo<T> {
 		Weight::from_parts(193_086_000, 0)
 			.saturating_add(Weight::from_parts(0, 8799))
 			.saturating_add(T::DbWeight::get().reads(14))
-			.saturating_add(T::DbWeight::get().writes(9))
+			.saturating_add(T::DbWeight::get().writes(10))
 	}
 	/// Storage: `Domains::DomainRegistry` (r:1 w:1)
 	/// Proof: `Domains::DomainRegistry` (`max_values`: None, `max_size`: None, mode: `Measured`)
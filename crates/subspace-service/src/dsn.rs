@@ -1,10 +1,12 @@
 use prometheus_client::registry::Registry;
 use std::collections::HashSet;
-use std::fs;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::{fmt, fs};
 use subspace_networking::libp2p::kad::Mode;
 use subspace_networking::libp2p::{Multiaddr, identity};
 use subspace_networking::protocols::request_response::handlers::cached_piece_by_index::CachedPieceByIndexRequestHandler;
+use subspace_networking::protocols::request_response::handlers::cached_pieces_by_index::CachedPiecesByIndexRequestHandler;
 use subspace_networking::protocols::request_response::handlers::piece_by_index::PieceByIndexRequestHandler;
 use subspace_networking::protocols::request_response::handlers::segment_header::SegmentHeaderBySegmentIndexesRequestHandler;
 use subspace_networking::utils::strip_peer_id;
@@ -29,6 +31,44 @@ pub enum DsnConfigurationError {
     NetworkParameterManagerError(#[from] KnownPeersManagerPersistenceError),
 }
 
+/// Where pieces are retrieved from when syncing from DSN.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PieceRetrievalMode {
+    /// Only get pieces from DSN caches.
+    ///
+    /// Segment downloading reconstructs pieces which are missing from the DSN caches, which is
+    /// much faster than archival storage lookups.
+    #[default]
+    CacheOnly,
+    /// Get pieces from DSN caches, then get missing pieces from archival storage (L1), which is
+    /// much slower.
+    CacheThenArchival,
+}
+
+impl FromStr for PieceRetrievalMode {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "cache-only" => Ok(Self::CacheOnly),
+            "cache-then-archival" => Ok(Self::CacheThenArchival),
+            _ => Err(
+                "Unsupported piece retrieval mode: use cache-only or cache-then-archival"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for PieceRetrievalMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CacheOnly => f.write_str("cache-only"),
+            Self::CacheThenArchival => f.write_str("cache-then-archival"),
+        }
+    }
+}
+
 /// DSN configuration parameters.
 #[derive(Clone, Debug)]
 pub struct DsnConfig {
@@ -64,6 +104,9 @@ pub struct DsnConfig {
 
     /// Known external addresses
     pub external_addresses: Vec<Multiaddr>,
+
+    /// Where pieces are retrieved from when syncing from DSN.
+    pub piece_retrieval_mode: PieceRetrievalMode,
 }
 
 pub(crate) fn create_dsn_instance(
@@ -107,6 +150,8 @@ pub(crate) fn create_dsn_instance(
             // We need to enable protocol to request pieces
             CachedPieceByIndexRequestHandler::create(|_, _| async { None }),
             // We need to enable protocol to request pieces
+            CachedPiecesByIndexRequestHandler::create(|_, _| async { None }),
+            // We need to enable protocol to request pieces
             PieceByIndexRequestHandler::create(|_, _| async { None }),
             SegmentHeaderBySegmentIndexesRequestHandler::create(move |_, _| async move { None }),
         ],
@@ -839,7 +839,10 @@ where
             (
                 node,
                 dsn_config.bootstrap_nodes,
-                Arc::new(DsnPieceGetter::new(piece_provider)) as _,
+                Arc::new(DsnPieceGetter::new(
+                    piece_provider,
+                    dsn_config.piece_retrieval_mode,
+                )) as _,
             )
         }
     };
@@ -2,7 +2,10 @@ pub(crate) mod import_blocks;
 pub(crate) mod piece_validator;
 pub(crate) mod segment_header_downloader;
 pub(crate) mod snap_sync;
+#[cfg(test)]
+mod tests;
 
+use crate::dsn::PieceRetrievalMode;
 use crate::sync_from_dsn::import_blocks::import_blocks_from_dsn;
 use crate::sync_from_dsn::segment_header_downloader::SegmentHeaderDownloader;
 use async_trait::async_trait;
@@ -40,16 +43,23 @@ const CHECK_ALMOST_SYNCED_INTERVAL: Duration = Duration::from_secs(1);
 /// Period of time during which node should be offline for DSN sync to kick-in
 const MIN_OFFLINE_PERIOD: Duration = Duration::from_secs(60);
 
+/// The maximum number of peer-to-peer walking rounds for L1 archival storage.
+const MAX_RANDOM_WALK_ROUNDS: usize = 15;
+
 /// Wrapper type for [`PieceProvider`], so it can implement [`PieceGetter`]
-pub struct DsnPieceGetter<PV: PieceValidator>(PieceProvider<PV>);
+pub struct DsnPieceGetter<PV: PieceValidator> {
+    piece_provider: PieceProvider<PV>,
+    retrieval_mode: PieceRetrievalMode,
+}
 
 impl<PV> fmt::Debug for DsnPieceGetter<PV>
 where
     PV: PieceValidator,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_tuple("DsnPieceGetter")
-            .field(&format!("{:?}", self.0))
+        f.debug_struct("DsnPieceGetter")
+            .field("piece_provider", &format!("{:?}", self.piece_provider))
+            .field("retrieval_mode", &self.retrieval_mode)
             .finish()
     }
 }
@@ -61,7 +71,11 @@ where
 {
     #[inline]
     async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
-        Ok(self.0.get_piece_from_cache(piece_index).await)
+        if let Some(piece) = self.piece_provider.get_piece_from_cache(piece_index).await {
+            return Ok(Some(piece));
+        }
+
+        self.get_piece_from_archival_storage(piece_index).await
     }
 
     #[inline]
@@ -72,10 +86,21 @@ where
         Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
     > {
         let stream = self
-            .0
+            .piece_provider
             .get_from_cache(piece_indices)
             .await
-            .map(|(piece_index, maybe_piece)| (piece_index, Ok(maybe_piece)));
+            .then(move |(piece_index, maybe_piece)| {
+                Box::pin(async move {
+                    if let Some(piece) = maybe_piece {
+                        return (piece_index, Ok(Some(piece)));
+                    }
+
+                    (
+                        piece_index,
+                        self.get_piece_from_archival_storage(piece_index).await,
+                    )
+                })
+            });
         Ok(Box::new(stream))
     }
 }
@@ -84,9 +109,28 @@ impl<PV> DsnPieceGetter<PV>
 where
     PV: PieceValidator,
 {
-    /// Creates new DSN piece getter
-    pub fn new(piece_provider: PieceProvider<PV>) -> Self {
-        Self(piece_provider)
+    /// Creates new DSN piece getter, which looks for pieces according to `retrieval_mode`.
+    pub fn new(piece_provider: PieceProvider<PV>, retrieval_mode: PieceRetrievalMode) -> Self {
+        Self {
+            piece_provider,
+            retrieval_mode,
+        }
+    }
+
+    /// Gets a piece that wasn't found in the DSN caches from archival storage, if the retrieval
+    /// mode allows it.
+    async fn get_piece_from_archival_storage(
+        &self,
+        piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<Piece>> {
+        if self.retrieval_mode == PieceRetrievalMode::CacheOnly {
+            return Ok(None);
+        }
+
+        Ok(self
+            .piece_provider
+            .try_get_piece_from_archival_storage(piece_index, MAX_RANDOM_WALK_ROUNDS)
+            .await?)
     }
 }
 
@@ -0,0 +1,97 @@
+use crate::dsn::PieceRetrievalMode;
+use crate::sync_from_dsn::DsnPieceGetter;
+use async_lock::Semaphore;
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use subspace_core_primitives::pieces::{Piece, PieceIndex};
+use subspace_data_retrieval::piece_getter::PieceGetter;
+use subspace_networking::libp2p::multiaddr::Protocol;
+use subspace_networking::protocols::request_response::handlers::piece_by_index::{
+    PieceByIndexRequestHandler, PieceByIndexResponse,
+};
+use subspace_networking::utils::piece_provider::{NoPieceValidator, PieceProvider};
+use subspace_networking::{Config, Node, construct};
+
+/// Starts a node which only has `Piece::default()` at piece index zero in its archival storage,
+/// and nothing in its cache. Returns another node which is connected to it.
+async fn connected_to_archival_node() -> Node {
+    let config_1 = Config {
+        listen_on: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
+        allow_non_global_addresses_in_dht: true,
+        request_response_protocols: vec![PieceByIndexRequestHandler::create(
+            |_, request| async move {
+                Some(PieceByIndexResponse {
+                    piece: (request.piece_index == PieceIndex::ZERO).then(Piece::default),
+                    cached_pieces: Vec::new(),
+                })
+            },
+        )],
+        ..Config::default()
+    };
+    let (node_1, mut node_runner_1) = construct(config_1).unwrap();
+
+    let (node_1_address_sender, node_1_address_receiver) = oneshot::channel();
+    let on_new_listener_handler = node_1.on_new_listener(Arc::new({
+        let node_1_address_sender = Mutex::new(Some(node_1_address_sender));
+
+        move |address| {
+            if matches!(address.iter().next(), Some(Protocol::Ip4(_)))
+                && let Some(node_1_address_sender) = node_1_address_sender.lock().take()
+            {
+                node_1_address_sender.send(address.clone()).unwrap();
+            }
+        }
+    }));
+
+    tokio::spawn(async move {
+        node_runner_1.run().await;
+    });
+
+    // Wait for first node to know its address
+    let node_1_addr = node_1_address_receiver.await.unwrap();
+    drop(on_new_listener_handler);
+
+    let config_2 = Config {
+        listen_on: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
+        allow_non_global_addresses_in_dht: true,
+        bootstrap_addresses: vec![node_1_addr.with(Protocol::P2p(node_1.id()))],
+        ..Config::default()
+    };
+    let (node_2, mut node_runner_2) = construct(config_2).unwrap();
+
+    tokio::spawn(async move {
+        node_runner_2.run().await;
+    });
+
+    // Wait until the first node is available for archival storage requests
+    node_2.bootstrap().await.unwrap();
+    while node_2.connected_servers().await.unwrap().is_empty() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    node_2
+}
+
+#[tokio::test]
+async fn dsn_piece_getter_archival_fallback() {
+    let node = connected_to_archival_node().await;
+    let piece_provider =
+        || PieceProvider::new(node.clone(), NoPieceValidator, Arc::new(Semaphore::new(10)));
+
+    // The piece isn't in any cache
+    let piece_getter = DsnPieceGetter::new(piece_provider(), PieceRetrievalMode::CacheOnly);
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        None
+    );
+
+    // But it is in archival storage
+    let piece_getter = DsnPieceGetter::new(piece_provider(), PieceRetrievalMode::CacheThenArchival);
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(Piece::default())
+    );
+    assert_eq!(piece_getter.get_piece(PieceIndex::ONE).await.unwrap(), None);
+}
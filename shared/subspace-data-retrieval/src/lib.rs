@@ -3,6 +3,7 @@
 #![feature(exact_size_is_empty, trusted_len)]
 
 pub mod object_fetcher;
+pub mod object_reconstruction;
 pub mod piece_fetcher;
 pub mod piece_getter;
 pub mod segment_downloading;
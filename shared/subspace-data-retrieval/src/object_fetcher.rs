@@ -3,16 +3,24 @@
 use crate::object_fetcher::partial_object::{PartialObject, RawPieceData};
 use crate::object_fetcher::segment_header::{
     MAX_SEGMENT_PADDING, max_segment_header_encoded_size, min_segment_header_encoded_size,
+    strip_segment_header,
 };
 use crate::piece_fetcher::download_pieces;
 use crate::piece_getter::PieceGetter;
+use futures::{Stream, StreamExt, TryStreamExt, future, stream};
 use parity_scale_codec::{Compact, CompactLen, Decode};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Formatter;
+use std::num::NonZeroUsize;
+use std::ops::Range;
 use std::sync::Arc;
 use subspace_archiving::archiver::SegmentItem;
 use subspace_core_primitives::hashes::Blake3Hash;
 use subspace_core_primitives::objects::{GlobalObject, GlobalObjectMapping};
 use subspace_core_primitives::pieces::{Piece, PieceIndex, RawRecord};
 use subspace_core_primitives::segments::{RecordedHistorySegment, SegmentIndex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tracing::{debug, trace, warn};
 
 mod partial_object;
@@ -57,6 +65,20 @@ const MAX_ENCODED_LENGTH_SIZE: usize = 4;
 /// Used to store the last piece downloaded in an object fetcher batch.
 pub type LastPieceCache = (PieceIndex, Piece);
 
+/// The number of pieces an object stream downloads ahead of the data it has returned.
+const OBJECT_STREAM_PIECE_BUFFER: usize = 2;
+
+/// The size and piece layout of an object, found without fetching the whole object.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ObjectMetadata {
+    /// The length of the object data, in bytes.
+    pub size: usize,
+    /// The source pieces which contain the object, in archived history order.
+    pub piece_indices: Vec<PieceIndex>,
+    /// The number of segments containing the object's pieces.
+    pub segment_count: usize,
+}
+
 /// Object fetching errors.
 #[derive(Debug, PartialEq, Eq, thiserror::Error)]
 pub enum Error {
@@ -209,6 +231,23 @@ where
 
     /// The maximum number of data bytes we'll read for a single object.
     max_object_len: usize,
+
+    /// Limits the number of objects being fetched at the same time, by this fetcher and all its
+    /// clones. If `None`, the number of objects is unlimited.
+    object_fetch_semaphore: Option<Arc<Semaphore>>,
+}
+
+impl<PG> Clone for ObjectFetcher<PG>
+where
+    PG: PieceGetter + Send + Sync,
+{
+    fn clone(&self) -> Self {
+        Self {
+            piece_getter: Arc::clone(&self.piece_getter),
+            max_object_len: self.max_object_len,
+            object_fetch_semaphore: self.object_fetch_semaphore.clone(),
+        }
+    }
 }
 
 impl<PG> ObjectFetcher<PG>
@@ -236,9 +275,19 @@ where
         Self {
             piece_getter,
             max_object_len,
+            object_fetch_semaphore: None,
         }
     }
 
+    /// Limits the number of objects being fetched at the same time to `max_concurrent_objects`.
+    ///
+    /// The limit is shared with all clones of the returned fetcher. Once the limit is reached,
+    /// fetches wait until another object has been fetched.
+    pub fn with_max_concurrent_objects(mut self, max_concurrent_objects: NonZeroUsize) -> Self {
+        self.object_fetch_semaphore = Some(Arc::new(Semaphore::new(max_concurrent_objects.get())));
+        self
+    }
+
     /// Assemble the objects in `mapping` by fetching necessary pieces using the piece getter, and
     /// putting the objects' bytes together.
     ///
@@ -254,63 +303,342 @@ where
         &self,
         mappings: GlobalObjectMapping,
     ) -> Result<Vec<Vec<u8>>, Error> {
-        let mut objects = Vec::with_capacity(mappings.objects().len());
-        let mut piece_cache = None;
+        self.fetch_objects_stream(mappings).try_collect().await
+    }
+
+    /// Returns a stream of the objects in `mapping`, which are fetched one at a time, in mapping
+    /// order. Each object is returned as soon as it is assembled and its hash is checked, so
+    /// callers don't need to hold the whole batch in memory.
+    ///
+    /// The stream ends after the first error. Dropping the stream cancels the remaining fetches.
+    ///
+    /// If the fetcher has a concurrency limit, each object waits for a free slot before it is
+    /// fetched.
+    ///
+    /// The same sorting and batching recommendations as [`Self::fetch_objects`] apply.
+    pub fn fetch_objects_stream(
+        &self,
+        mappings: GlobalObjectMapping,
+    ) -> impl Stream<Item = Result<Vec<u8>, Error>> + use<PG> {
+        let state = (
+            self.clone(),
+            mappings.objects().to_vec().into_iter(),
+            None,
+            false,
+        );
 
         // TODO:
         // - keep the last downloaded piece until it's no longer needed
         // - document sorting mappings in piece index order
-        for &mapping in mappings.objects() {
-            let GlobalObject {
-                piece_index,
-                offset,
-                ..
-            } = mapping;
-
-            // Validate parameters
-            if !piece_index.is_source() {
-                debug!(
-                    ?mapping,
-                    "Invalid piece index for object: must be a source piece",
-                );
+        stream::unfold(
+            state,
+            |(object_fetcher, mut mappings, mut piece_cache, failed)| async move {
+                if failed {
+                    return None;
+                }
 
-                // Parity pieces contain effectively random data, and can't be used to fetch
-                // objects
-                return Err(Error::NotSourcePiece { mapping });
-            }
+                let mapping = mappings.next()?;
+                let result = {
+                    // The semaphore is never closed, so acquiring always succeeds
+                    let _permit = match &object_fetcher.object_fetch_semaphore {
+                        Some(semaphore) => semaphore.acquire().await.ok(),
+                        None => None,
+                    };
+                    object_fetcher
+                        .fetch_mapped_object(mapping, &mut piece_cache)
+                        .await
+                };
+                let failed = result.is_err();
+
+                Some((result, (object_fetcher, mappings, piece_cache, failed)))
+            },
+        )
+    }
 
-            // We could parse each segment header to do this check perfectly, but it's an edge
-            // case, so we just do a best-effort check
-            if piece_index.source_position() == 0
-                && offset < min_segment_header_encoded_size() as u32
-            {
+    /// Returns the size and piece layout of the object in `mapping`, without fetching the whole
+    /// object. Usually only the object's first piece is downloaded.
+    ///
+    /// The object's hash can't be checked without its data, so an invalid mapping can return
+    /// incorrect metadata. If an object is at the end of a segment, the amount of segment padding
+    /// isn't known, so `piece_indices` can include a piece from the next segment which doesn't
+    /// contain any object data.
+    ///
+    /// If the fetcher has a concurrency limit, this waits for a free slot, because it can fall
+    /// back to fetching the whole object.
+    pub async fn fetch_object_metadata(
+        &self,
+        mapping: GlobalObject,
+    ) -> Result<ObjectMetadata, Error> {
+        Self::validate_mapping(mapping)?;
+
+        // The semaphore is never closed, so acquiring always succeeds
+        let _permit = match &self.object_fetch_semaphore {
+            Some(semaphore) => semaphore.acquire().await.ok(),
+            None => None,
+        };
+
+        let mut piece_cache = None;
+        let (partial_object, _next_source_piece_index) =
+            self.fetch_partial_object(mapping, &mut piece_cache).await?;
+
+        let size = match partial_object.known_data_length() {
+            Some(size) => size,
+            None => {
+                // The object's length prefix overlaps with segment padding, so its length depends
+                // on the padding. This is a rare edge case, which needs the object data to check
+                // each possible length against the object hash.
                 debug!(
                     ?mapping,
-                    min_segment_header_encoded_size = ?min_segment_header_encoded_size(),
-                    "Invalid offset for object: must not be inside the segment header",
+                    "Object length depends on segment padding, fetching object to find its length",
                 );
+                self.fetch_object(mapping, &mut piece_cache).await?.len()
+            }
+        };
+
+        let object_length = Compact::<u32>::compact_len(&(size as u32)) + size;
+        let piece_indices = self
+            .object_piece_indices(mapping, object_length, &mut piece_cache)
+            .await?;
+        let segment_count = piece_indices
+            .iter()
+            .map(PieceIndex::segment_index)
+            .collect::<BTreeSet<_>>()
+            .len();
+
+        trace!(
+            ?mapping,
+            %size,
+            ?piece_indices,
+            %segment_count,
+            "Found object metadata",
+        );
+
+        Ok(ObjectMetadata {
+            size,
+            piece_indices,
+            segment_count,
+        })
+    }
+
+    /// Finds the object in `mapping`, so it can be read without holding the whole object in
+    /// memory. Usually only the object's first piece is downloaded.
+    ///
+    /// Objects in a single segment are read directly from their pieces. Objects which could
+    /// contain segment padding or a segment header are fetched and checked against their hash
+    /// before this method returns.
+    ///
+    /// If the fetcher has a concurrency limit, this waits for a free slot, and the returned reader
+    /// keeps that slot until it is dropped.
+    pub async fn open_object(&self, mapping: GlobalObject) -> Result<ObjectReader<PG>, Error> {
+        Self::validate_mapping(mapping)?;
+
+        // The semaphore is never closed, so acquiring always succeeds
+        let permit = match &self.object_fetch_semaphore {
+            Some(semaphore) => Arc::clone(semaphore).acquire_owned().await.ok(),
+            None => None,
+        };
+
+        let mut piece_cache = None;
+        let (partial_object, _next_source_piece_index) =
+            self.fetch_partial_object(mapping, &mut piece_cache).await?;
+
+        let contiguous_object = match partial_object.known_data_length() {
+            Some(size) => {
+                let object_length = Compact::<u32>::compact_len(&(size as u32)) + size;
+                let piece_indices = self
+                    .object_piece_indices(mapping, object_length, &mut piece_cache)
+                    .await?;
+                let single_segment = piece_indices.iter().all(|piece_index| {
+                    piece_index.segment_index() == mapping.piece_index.segment_index()
+                });
 
-                return Err(Error::PieceOffsetInSegmentHeader { mapping });
+                single_segment.then(|| {
+                    (
+                        size,
+                        ObjectData::Contiguous {
+                            piece_indices,
+                            // Skip the object's encoded length
+                            data_offset: mapping.offset as usize + object_length - size,
+                        },
+                    )
+                })
             }
+            None => None,
+        };
 
-            if offset >= RawRecord::SIZE as u32 {
+        let (size, data) = match contiguous_object {
+            Some(contiguous_object) => contiguous_object,
+            None => {
+                // The object's data or length could depend on segment padding, which can only be
+                // resolved by checking the object hash. This is a rare edge case.
                 debug!(
                     ?mapping,
-                    RawRecord_SIZE = RawRecord::SIZE,
-                    "Invalid piece offset for object: must be less than the size of a raw record",
+                    "Object might span segments, fetching object before reading it",
                 );
+                let data = self.fetch_object(mapping, &mut piece_cache).await?;
+                (data.len(), ObjectData::Fetched(data))
+            }
+        };
+
+        trace!(?mapping, %size, ?data, "Opened object");
+
+        Ok(ObjectReader {
+            object_fetcher: self.clone(),
+            mapping,
+            size,
+            data,
+            piece_cache,
+            _permit: permit,
+        })
+    }
+
+    /// Returns the source pieces which contain the object in `mapping`, which is
+    /// `object_length` bytes long, including its encoded length.
+    ///
+    /// If the object might continue in the next segment, downloads the first piece of that
+    /// segment to find the length of its segment header.
+    async fn object_piece_indices(
+        &self,
+        mapping: GlobalObject,
+        object_length: usize,
+        piece_cache: &mut Option<LastPieceCache>,
+    ) -> Result<Vec<PieceIndex>, Error> {
+        let GlobalObject {
+            piece_index,
+            offset,
+            ..
+        } = mapping;
+
+        let pieces_left_in_segment =
+            RecordedHistorySegment::NUM_RAW_RECORDS - piece_index.source_position() as usize;
+        let bytes_left_in_segment = pieces_left_in_segment * RawRecord::SIZE - offset as usize;
+        let single_segment_piece_indices = || {
+            let piece_count = (offset as usize + object_length).div_ceil(RawRecord::SIZE);
+            (piece_index..)
+                .filter(|i| i.is_source())
+                .take(piece_count)
+                .collect::<Vec<_>>()
+        };
+
+        // Objects which end before any possible segment padding are in a single segment
+        if object_length + MAX_SEGMENT_PADDING <= bytes_left_in_segment {
+            return Ok(single_segment_piece_indices());
+        }
+
+        // Otherwise, the rest of the object is after the segment header in the next segment
+        let mut piece_indices = (piece_index..)
+            .filter(|i| i.is_source())
+            .take(pieces_left_in_segment)
+            .collect::<Vec<_>>();
+        let next_segment_piece_index = piece_indices
+            .last()
+            .expect("there is always at least one piece left in a segment; qed")
+            .next_source_index();
+
+        let piece = self
+            .read_piece(next_segment_piece_index, mapping, piece_cache)
+            .await?;
+        let piece_data = piece
+            .record()
+            .to_raw_record_chunks()
+            .flatten()
+            .copied()
+            .collect::<Vec<u8>>();
+        let piece_data_length = piece_data.len();
 
-                return Err(Error::PieceOffsetTooLarge { mapping });
+        let object_data = match strip_segment_header(
+            piece_data,
+            next_segment_piece_index.segment_index(),
+            mapping,
+        ) {
+            Ok((object_data, _max_remaining_object_bytes)) => object_data,
+            // The next segment doesn't continue the object's block, so the object fits in this
+            // segment
+            Err(Error::UnexpectedSegmentItemVariant { .. })
+                if object_length <= bytes_left_in_segment =>
+            {
+                return Ok(single_segment_piece_indices());
             }
+            Err(error) => return Err(error),
+        };
+
+        // Assume the maximum amount of segment padding, which puts the most object data in the
+        // next segment
+        let next_segment_object_length =
+            (object_length + MAX_SEGMENT_PADDING).saturating_sub(bytes_left_in_segment);
+        let next_segment_piece_count = if next_segment_object_length <= object_data.len() {
+            1
+        } else {
+            // The block continuation fills the rest of the piece, so the data before it is the
+            // segment header
+            let segment_header_length = piece_data_length - object_data.len();
+            (segment_header_length + next_segment_object_length).div_ceil(RawRecord::SIZE)
+        };
+
+        piece_indices.extend(
+            (next_segment_piece_index..)
+                .filter(|i| i.is_source())
+                .take(next_segment_piece_count),
+        );
+
+        Ok(piece_indices)
+    }
+
+    /// Validates `mapping`, then fetches and assembles its object.
+    async fn fetch_mapped_object(
+        &self,
+        mapping: GlobalObject,
+        piece_cache: &mut Option<LastPieceCache>,
+    ) -> Result<Vec<u8>, Error> {
+        Self::validate_mapping(mapping)?;
+
+        // All objects can be assembled from individual pieces, we handle segments by checking all
+        // possible padding, and parsing and discarding segment headers.
+        self.fetch_object(mapping, piece_cache).await
+    }
+
+    /// Returns an error if `mapping` can't point to an object.
+    fn validate_mapping(mapping: GlobalObject) -> Result<(), Error> {
+        let GlobalObject {
+            piece_index,
+            offset,
+            ..
+        } = mapping;
+
+        // Validate parameters
+        if !piece_index.is_source() {
+            debug!(
+                ?mapping,
+                "Invalid piece index for object: must be a source piece",
+            );
+
+            // Parity pieces contain effectively random data, and can't be used to fetch objects
+            return Err(Error::NotSourcePiece { mapping });
+        }
+
+        // We could parse each segment header to do this check perfectly, but it's an edge case,
+        // so we just do a best-effort check
+        if piece_index.source_position() == 0 && offset < min_segment_header_encoded_size() as u32 {
+            debug!(
+                ?mapping,
+                min_segment_header_encoded_size = ?min_segment_header_encoded_size(),
+                "Invalid offset for object: must not be inside the segment header",
+            );
 
-            // All objects can be assembled from individual pieces, we handle segments by checking
-            // all possible padding, and parsing and discarding segment headers.
-            let data = self.fetch_object(mapping, &mut piece_cache).await?;
+            return Err(Error::PieceOffsetInSegmentHeader { mapping });
+        }
+
+        if offset >= RawRecord::SIZE as u32 {
+            debug!(
+                ?mapping,
+                RawRecord_SIZE = RawRecord::SIZE,
+                "Invalid piece offset for object: must be less than the size of a raw record",
+            );
 
-            objects.push(data);
+            return Err(Error::PieceOffsetTooLarge { mapping });
         }
 
-        Ok(objects)
+        Ok(())
     }
 
     /// Single object fetching and assembling.
@@ -330,6 +658,82 @@ where
         mapping: GlobalObject,
         piece_cache: &mut Option<LastPieceCache>,
     ) -> Result<Vec<u8>, Error> {
+        let (mut partial_object, next_source_piece_index) =
+            self.fetch_partial_object(mapping, piece_cache).await?;
+
+        // We might already have the whole object, let's check before downloading more pieces
+        if let Some(data) = partial_object.try_reconstruct_object(mapping)? {
+            return Ok(data);
+        }
+
+        // Read more pieces until we have enough data for all possible object lengths.
+        //
+        // Adding padding can change the size of the object up to 256x. But the maximum object size
+        // is 6 pieces, so we get better latency by downloading any pieces that could be needed at
+        // the same time. (Larger objects have already been rejected during length decoding.)
+        let remaining_piece_count = partial_object
+            .max_remaining_download_length()
+            .div_ceil(RawRecord::SIZE);
+
+        if remaining_piece_count > 0 {
+            let remaining_piece_indexes = (next_source_piece_index..)
+                .filter(|i| i.is_source())
+                .take(remaining_piece_count)
+                .collect::<Arc<[PieceIndex]>>();
+            // TODO: turn this into a concurrent stream, which cancels piece downloads if they aren't
+            // needed
+            let pieces = self
+                .read_pieces(remaining_piece_indexes.clone(), mapping, piece_cache)
+                .await?
+                .into_iter()
+                .zip(remaining_piece_indexes.iter().copied())
+                .map(|(piece, piece_index)| {
+                    (
+                        piece_index,
+                        piece
+                            .record()
+                            .to_raw_record_chunks()
+                            .flatten()
+                            .copied()
+                            .collect::<Vec<u8>>(),
+                    )
+                });
+
+            for (piece_index, piece_data) in pieces {
+                let mut new_data = RawPieceData::new_for_next_piece(
+                    partial_object.max_remaining_download_length(),
+                    piece_index,
+                );
+                new_data.add_piece_data(piece_index, piece_data, mapping)?;
+                partial_object.add_piece_data_with_padding(new_data);
+
+                // We might already have the whole object, let's check before decoding more pieces
+                if let Some(data) = partial_object.try_reconstruct_object(mapping)? {
+                    return Ok(data);
+                }
+            }
+        }
+
+        // If the mapping is invalid, we can try to read beyond the downloaded pieces.
+        // Specifically, if a cross-segment object's offset is wrong, we can try to read beyond the
+        // block continuation at the start of the second segment.
+        Err(Error::InvalidMapping {
+            next_source_piece_index,
+            remaining_piece_count,
+            object_data_length: partial_object.fetched_data_length(),
+            mapping,
+        })
+    }
+
+    /// Fetches the first piece (or two pieces) of the object in `mapping`, until the object's
+    /// possible lengths are known.
+    ///
+    /// Returns the partial object, and the next source piece index to fetch.
+    async fn fetch_partial_object(
+        &self,
+        mapping: GlobalObject,
+        piece_cache: &mut Option<LastPieceCache>,
+    ) -> Result<(PartialObject, PieceIndex), Error> {
         let GlobalObject {
             piece_index,
             offset,
@@ -362,7 +766,7 @@ where
         next_source_piece_index = next_source_piece_index.next_source_index();
 
         // Try to create a new partial object, this only works if we have enough data to find its length
-        let mut partial_object = if let Some(partial_object) =
+        let partial_object = if let Some(partial_object) =
             PartialObject::new_with_padding(&raw_data, self.max_object_len, mapping)?
         {
             // We've used up this data, so just drop it
@@ -426,68 +830,7 @@ where
             }
         };
 
-        // We might already have the whole object, let's check before downloading more pieces
-        if let Some(data) = partial_object.try_reconstruct_object(mapping)? {
-            return Ok(data);
-        }
-
-        // Read more pieces until we have enough data for all possible object lengths.
-        //
-        // Adding padding can change the size of the object up to 256x. But the maximum object size
-        // is 6 pieces, so we get better latency by downloading any pieces that could be needed at
-        // the same time. (Larger objects have already been rejected during length decoding.)
-        let remaining_piece_count = partial_object
-            .max_remaining_download_length()
-            .div_ceil(RawRecord::SIZE);
-
-        if remaining_piece_count > 0 {
-            let remaining_piece_indexes = (next_source_piece_index..)
-                .filter(|i| i.is_source())
-                .take(remaining_piece_count)
-                .collect::<Arc<[PieceIndex]>>();
-            // TODO: turn this into a concurrent stream, which cancels piece downloads if they aren't
-            // needed
-            let pieces = self
-                .read_pieces(remaining_piece_indexes.clone(), mapping, piece_cache)
-                .await?
-                .into_iter()
-                .zip(remaining_piece_indexes.iter().copied())
-                .map(|(piece, piece_index)| {
-                    (
-                        piece_index,
-                        piece
-                            .record()
-                            .to_raw_record_chunks()
-                            .flatten()
-                            .copied()
-                            .collect::<Vec<u8>>(),
-                    )
-                });
-
-            for (piece_index, piece_data) in pieces {
-                let mut new_data = RawPieceData::new_for_next_piece(
-                    partial_object.max_remaining_download_length(),
-                    piece_index,
-                );
-                new_data.add_piece_data(piece_index, piece_data, mapping)?;
-                partial_object.add_piece_data_with_padding(new_data);
-
-                // We might already have the whole object, let's check before decoding more pieces
-                if let Some(data) = partial_object.try_reconstruct_object(mapping)? {
-                    return Ok(data);
-                }
-            }
-        }
-
-        // If the mapping is invalid, we can try to read beyond the downloaded pieces.
-        // Specifically, if a cross-segment object's offset is wrong, we can try to read beyond the
-        // block continuation at the start of the second segment.
-        Err(Error::InvalidMapping {
-            next_source_piece_index,
-            remaining_piece_count,
-            object_data_length: partial_object.fetched_data_length(),
-            mapping,
-        })
+        Ok((partial_object, next_source_piece_index))
     }
 
     /// Concurrently read multiple pieces, and return them in the supplied order.
@@ -562,6 +905,220 @@ where
     }
 }
 
+/// The location of an opened object's data.
+enum ObjectData {
+    /// The object is in a single segment, so its data is contiguous in the raw data of its pieces,
+    /// starting at `data_offset` in the first piece.
+    Contiguous {
+        piece_indices: Vec<PieceIndex>,
+        data_offset: usize,
+    },
+
+    /// The object might contain segment padding or a segment header, so it has already been
+    /// fetched and checked against its hash.
+    Fetched(Vec<u8>),
+}
+
+impl fmt::Debug for ObjectData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Contiguous {
+                piece_indices,
+                data_offset,
+            } => f
+                .debug_struct("Contiguous")
+                .field("piece_indices", piece_indices)
+                .field("data_offset", data_offset)
+                .finish(),
+            Self::Fetched(data) => f.debug_tuple("Fetched").field(&data.len()).finish(),
+        }
+    }
+}
+
+/// An object which has been found by [`ObjectFetcher::open_object`], but not read yet.
+pub struct ObjectReader<PG>
+where
+    PG: PieceGetter + Send + Sync,
+{
+    object_fetcher: ObjectFetcher<PG>,
+    mapping: GlobalObject,
+    size: usize,
+    data: ObjectData,
+    piece_cache: Option<LastPieceCache>,
+    /// The object's slot in the fetcher's concurrency limit, if it has one.
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<PG> ObjectReader<PG>
+where
+    PG: PieceGetter + Send + Sync,
+{
+    /// Returns the length of the object data, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the object's data in `range`, downloading only the pieces which contain that data.
+    ///
+    /// The object hash covers the whole object, so the data of an object in a single segment
+    /// can't be checked, and an invalid mapping can return incorrect data. Other objects were
+    /// fetched and checked when they were opened.
+    ///
+    /// Panics if `range` is outside the object.
+    pub async fn read_range(self, range: Range<usize>) -> Result<Vec<u8>, Error> {
+        assert!(
+            range.start <= range.end && range.end <= self.size,
+            "range {range:?} must be inside the {} byte object",
+            self.size,
+        );
+
+        let Self {
+            object_fetcher,
+            mapping,
+            data,
+            mut piece_cache,
+            ..
+        } = self;
+
+        let (piece_indices, data_offset) = match data {
+            ObjectData::Contiguous {
+                piece_indices,
+                data_offset,
+            } => (piece_indices, data_offset),
+            ObjectData::Fetched(mut data) => {
+                data.truncate(range.end);
+                data.drain(..range.start);
+                return Ok(data);
+            }
+        };
+
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // The range's position in the raw data of the object's pieces
+        let start = data_offset + range.start;
+        let end = data_offset + range.end;
+        let range_piece_indices = piece_indices
+            [start / RawRecord::SIZE..end.div_ceil(RawRecord::SIZE)]
+            .iter()
+            .copied()
+            .collect::<Arc<[PieceIndex]>>();
+
+        trace!(
+            ?mapping,
+            ?range,
+            ?range_piece_indices,
+            "Reading object range"
+        );
+
+        let pieces = object_fetcher
+            .read_pieces(range_piece_indices, mapping, &mut piece_cache)
+            .await?;
+
+        Ok(pieces
+            .iter()
+            .flat_map(|piece| piece.record().to_raw_record_chunks().flatten())
+            .skip(start % RawRecord::SIZE)
+            .take(range.len())
+            .copied()
+            .collect())
+    }
+
+    /// Returns a stream of the object's data, which returns the data in each piece as soon as
+    /// that piece is downloaded. A few pieces are downloaded ahead of the returned data.
+    ///
+    /// The object hash covers the whole object, so it is checked before the last chunk of data is
+    /// returned. If the hash doesn't match, the stream ends with an error instead of that chunk.
+    /// Dropping the stream cancels the remaining downloads.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Vec<u8>, Error>> + use<PG> {
+        let Self {
+            object_fetcher,
+            mapping,
+            size,
+            data,
+            piece_cache,
+            _permit: permit,
+        } = self;
+
+        let (piece_indices, data_offset) = match data {
+            ObjectData::Contiguous {
+                piece_indices,
+                data_offset,
+            } => (piece_indices, data_offset),
+            ObjectData::Fetched(data) => {
+                return stream::once(future::ready(Ok(data))).left_stream();
+            }
+        };
+
+        let pieces = stream::iter(piece_indices)
+            .map(move |piece_index| {
+                let object_fetcher = object_fetcher.clone();
+                let mut piece_cache = piece_cache.clone();
+                async move {
+                    object_fetcher
+                        .read_piece(piece_index, mapping, &mut piece_cache)
+                        .await
+                }
+            })
+            .buffered(OBJECT_STREAM_PIECE_BUFFER);
+        let state = (
+            Box::pin(pieces),
+            data_offset,
+            size,
+            blake3::Hasher::new(),
+            permit,
+        );
+
+        stream::unfold(Some(state), move |state| async move {
+            let (mut pieces, skip, remaining, mut hasher, permit) = state?;
+
+            let piece = match pieces
+                .next()
+                .await
+                .expect("object pieces contain all the object data; qed")
+            {
+                Ok(piece) => piece,
+                Err(error) => return Some((Err(error), None)),
+            };
+            let data = piece
+                .record()
+                .to_raw_record_chunks()
+                .flatten()
+                .skip(skip)
+                .take(remaining)
+                .copied()
+                .collect::<Vec<u8>>();
+            let remaining = remaining - data.len();
+            hasher.update(&data);
+
+            if remaining > 0 {
+                return Some((Ok(data), Some((pieces, 0, remaining, hasher, permit))));
+            }
+
+            let data_hash: Blake3Hash = hasher.finalize().as_bytes().into();
+            if data_hash != mapping.hash {
+                debug!(?data_hash, %size, ?mapping, "Invalid data hash for streamed object");
+
+                return Some((
+                    Err(Error::InvalidDataHash {
+                        data_hash,
+                        data_length: size,
+                        mapping,
+                        // Most of the data has already been returned
+                        #[cfg(test)]
+                        data: hex::encode(&data),
+                    }),
+                    None,
+                ));
+            }
+
+            Some((Ok(data), None))
+        })
+        .right_stream()
+    }
+}
+
 /// Validate and decode the encoded length of `data`, including the encoded length bytes.
 /// `data` may be incomplete.
 ///
@@ -17,7 +17,7 @@
 
 use crate::object_fetcher::segment_header::{MAX_SEGMENT_PADDING, strip_segment_header};
 use crate::object_fetcher::{Error, MAX_ENCODED_LENGTH_SIZE, decode_data_length};
-use parity_scale_codec::{Decode, Input};
+use parity_scale_codec::{Compact, Decode, Input};
 use std::cmp::min;
 use std::collections::BTreeSet;
 use std::fmt;
@@ -619,6 +619,28 @@ impl PartialObject {
             .data_length
     }
 
+    /// Returns the length of the object data, excluding its encoded length, if the length doesn't
+    /// depend on segment padding.
+    ///
+    /// Objects which start near the end of a segment can have several possible lengths, because
+    /// segment padding can overlap with their encoded length.
+    ///
+    /// Panics if the object has no valid lengths left.
+    pub fn known_data_length(&self) -> Option<usize> {
+        let longest_object_length = self
+            .lengths
+            .last()
+            .expect("other methods return an error if lengths becomes empty; qed")
+            .data_length;
+        if self.shortest_object_length() != longest_object_length {
+            return None;
+        }
+
+        let Compact(data_length) = Compact::<u32>::decode(&mut self.shortest_object_data()).ok()?;
+
+        Some(data_length as usize)
+    }
+
     /// Returns the longest possible amount of data that has already been fetched for the object,
     /// based on potential segment padding.
     pub fn fetched_data_length(&self) -> usize {
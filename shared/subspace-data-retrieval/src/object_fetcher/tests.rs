@@ -451,6 +451,44 @@ fn create_object_fetcher(
     ObjectFetcher::new(Arc::new(piece_getter), max_supported_object_length())
 }
 
+/// Objects which are larger than the maximum object size are rejected as soon as their length is
+/// known, before the rest of the object is downloaded.
+#[tokio::test]
+async fn object_too_large_rejected_before_download() {
+    init_logger();
+
+    let max_object_len = 10_000;
+    // This object would span dozens of pieces
+    let object_len = 50 * RawRecord::SIZE;
+    let piece_index = 60;
+
+    let mut piece = random_piece();
+    write_object_length(vec![&mut piece], 0, object_len, None);
+    let mapping = GlobalObject {
+        hash: Blake3Hash::default(),
+        piece_index: idx(piece_index),
+        offset: 0,
+    };
+
+    // Only the first piece is available, any other piece requests are counted
+    let later_pieces = CountingPieceGetter::default();
+    let piece_getter = vec![(idx(piece_index), piece)].with_fallback(later_pieces.clone());
+    let object_fetcher = ObjectFetcher::new(Arc::new(piece_getter), max_object_len);
+
+    let result = object_fetcher
+        .fetch_objects(GlobalObjectMapping::from_object(mapping))
+        .await;
+    assert_eq!(
+        result,
+        Err(Error::ObjectTooLarge {
+            data_length: object_len,
+            max_object_len,
+            mapping,
+        })
+    );
+    assert_eq!(later_pieces.piece_index_counts().await, HashMap::new());
+}
+
 #[test]
 fn max_object_length_constant() {
     assert_eq!(
@@ -1362,3 +1400,107 @@ async fn last_piece_cache_works() {
         [(idx(start_piece_index), 1), (idx(start_piece_index + 2), 1)].into(),
     );
 }
+
+/// Objects in a single segment are streamed one piece at a time, and their hash is checked
+/// before the last piece of data is returned.
+#[tokio::test(flavor = "multi_thread")]
+async fn open_object_streams_pieces() {
+    init_logger();
+
+    // Set up the test case
+    // - middle of segment, in 3 pieces
+    let object_len = RawRecord::SIZE + 1000;
+    let offset = RawRecord::SIZE - 500;
+    let start_piece_index = 60;
+
+    let mut piece1 = random_piece();
+    let piece2 = random_piece();
+    let piece3 = random_piece();
+
+    write_object_length(vec![&mut piece1], offset, object_len, None);
+    let (mapping, object_data) = create_mapping(
+        vec![&piece1, &piece2, &piece3],
+        start_piece_index,
+        offset,
+        object_len,
+        None,
+        None,
+    );
+    let object_fetcher =
+        create_object_fetcher(vec![piece1, piece2, piece3], start_piece_index, None, None);
+
+    // Now stream the object back
+    let object = object_fetcher.open_object(mapping).await.unwrap();
+    assert_eq!(object.size(), object_len);
+    let chunks = object.into_stream().try_collect::<Vec<_>>().await.unwrap();
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(hex::encode(chunks.concat()), hex::encode(&object_data));
+
+    // - the same object with the wrong hash
+    let invalid_mapping = GlobalObject {
+        hash: blake3_hash(b"invalid"),
+        ..mapping
+    };
+    let chunks = object_fetcher
+        .open_object(invalid_mapping)
+        .await
+        .unwrap()
+        .into_stream()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(chunks.len(), 3);
+    assert!(chunks[..2].iter().all(Result::is_ok));
+    assert!(
+        matches!(
+            chunks[2],
+            Err(Error::InvalidDataHash { data_length, .. }) if data_length == object_len
+        ),
+        "{:?}",
+        chunks[2],
+    );
+}
+
+/// Object ranges are read from the pieces which contain the range.
+#[tokio::test(flavor = "multi_thread")]
+async fn open_object_reads_ranges() {
+    init_logger();
+
+    // Set up the test case
+    // - middle of segment, in 3 pieces
+    let object_len = RawRecord::SIZE + 1000;
+    let offset = RawRecord::SIZE - 500;
+    let start_piece_index = 60;
+
+    let mut piece1 = random_piece();
+    let piece2 = random_piece();
+    let piece3 = random_piece();
+
+    write_object_length(vec![&mut piece1], offset, object_len, None);
+    let (mapping, object_data) = create_mapping(
+        vec![&piece1, &piece2, &piece3],
+        start_piece_index,
+        offset,
+        object_len,
+        None,
+        None,
+    );
+    let object_fetcher =
+        create_object_fetcher(vec![piece1, piece2, piece3], start_piece_index, None, None);
+
+    // Ranges in one piece, spanning pieces, at the end of the object, and empty ranges
+    for range in [
+        0..100,
+        200..2000,
+        0..object_len,
+        object_len - 10..object_len,
+        100..100,
+    ] {
+        let object = object_fetcher.open_object(mapping).await.unwrap();
+        let range_data = object.read_range(range.clone()).await.unwrap();
+        assert_eq!(
+            hex::encode(range_data),
+            hex::encode(&object_data[range.clone()]),
+            "{range:?}",
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Reconstructing object data from the pieces of the archived history of Subspace Network.
+
+#[cfg(test)]
+mod tests;
+
+use crate::piece_getter::PieceGetter;
+use crate::segment_downloading::{SegmentDownloadingError, download_missing_segment_pieces};
+use futures::StreamExt;
+use parity_scale_codec::Encode;
+use std::ops::Range;
+use subspace_archiving::reconstructor::{Reconstructor, ReconstructorError};
+use subspace_core_primitives::pieces::{PieceIndex, RawRecord};
+use subspace_core_primitives::segments::{ArchivedHistorySegment, RecordedHistorySegment};
+use subspace_erasure_coding::ErasureCoding;
+use tokio::task::spawn_blocking;
+use tracing::{debug, trace};
+
+/// Object reconstruction errors.
+#[derive(Debug, thiserror::Error)]
+pub enum ObjectReconstructionError {
+    /// Piece range is empty or spans multiple segments
+    #[error("Piece range {piece_indexes:?} must be non-empty and within a single segment")]
+    InvalidPieceRange {
+        /// The supplied piece range
+        piece_indexes: Range<PieceIndex>,
+    },
+
+    /// Object data extends beyond the source pieces in the piece range
+    #[error(
+        "Object data at offset {offset} with length {length} extends beyond the \
+         {source_piece_count} source pieces in {piece_indexes:?}"
+    )]
+    ObjectOutsidePieces {
+        /// The supplied piece range
+        piece_indexes: Range<PieceIndex>,
+        /// The number of source pieces in the piece range
+        source_piece_count: usize,
+        /// The object offset in the first source piece
+        offset: usize,
+        /// The object length
+        length: usize,
+    },
+
+    /// Piece getter error
+    #[error("Piece getter error: {source}")]
+    PieceGetterError {
+        #[from]
+        source: anyhow::Error,
+    },
+
+    /// Segment downloading error
+    #[error("Segment downloading error: {source}")]
+    SegmentDownloading {
+        #[from]
+        source: SegmentDownloadingError,
+    },
+
+    /// Segment reconstruction error
+    #[error("Segment reconstruction error: {source}")]
+    SegmentReconstruction {
+        #[from]
+        source: ReconstructorError,
+    },
+}
+
+/// Reconstructs `length` bytes of object data, starting at `offset` in the raw record data of the
+/// first source piece in `piece_indexes`.
+///
+/// Only the source pieces which contain object data are downloaded. If any of those pieces can't
+/// be downloaded, just enough other pieces from the segment are downloaded to reconstruct the
+/// missing data using `erasure_coding`.
+///
+/// The piece range must be within a single segment. Objects which span segments also contain
+/// segment padding and headers, use `ObjectFetcher` to fetch them.
+pub async fn reconstruct_object<PG>(
+    piece_getter: &PG,
+    piece_indexes: Range<PieceIndex>,
+    offset: usize,
+    length: usize,
+    erasure_coding: ErasureCoding,
+) -> Result<Vec<u8>, ObjectReconstructionError>
+where
+    PG: PieceGetter,
+{
+    let segment_index = piece_indexes.start.segment_index();
+    if piece_indexes.is_empty()
+        || (piece_indexes.end - PieceIndex::ONE).segment_index() != segment_index
+    {
+        return Err(ObjectReconstructionError::InvalidPieceRange { piece_indexes });
+    }
+
+    let source_piece_indexes = (u64::from(piece_indexes.start)..u64::from(piece_indexes.end))
+        .map(PieceIndex::from)
+        .filter(PieceIndex::is_source)
+        .collect::<Vec<_>>();
+
+    // Only download the pieces which actually contain object data
+    let required_piece_count = (offset + length).div_ceil(RawRecord::SIZE);
+    if required_piece_count > source_piece_indexes.len() {
+        return Err(ObjectReconstructionError::ObjectOutsidePieces {
+            source_piece_count: source_piece_indexes.len(),
+            piece_indexes,
+            offset,
+            length,
+        });
+    }
+    let source_piece_indexes = &source_piece_indexes[..required_piece_count];
+    let Some(first_piece_index) = source_piece_indexes.first().copied() else {
+        return Ok(Vec::new());
+    };
+
+    debug!(
+        %segment_index,
+        ?source_piece_indexes,
+        offset,
+        length,
+        "Reconstructing object"
+    );
+
+    let mut segment_pieces = [const { None }; ArchivedHistorySegment::NUM_PIECES];
+    let mut received_pieces = piece_getter
+        .get_pieces(source_piece_indexes.to_vec())
+        .await?;
+
+    while let Some((piece_index, result)) = received_pieces.next().await {
+        match result {
+            Ok(Some(piece)) => {
+                segment_pieces[piece_index.position() as usize] = Some(piece);
+            }
+            Ok(None) => {
+                debug!(%piece_index, "Piece was not found");
+            }
+            Err(error) => {
+                debug!(%error, %piece_index, "Failed to get piece");
+            }
+        }
+    }
+    drop(received_pieces);
+
+    let missing_pieces = source_piece_indexes
+        .iter()
+        .filter(|piece_index| segment_pieces[piece_index.position() as usize].is_none())
+        .count();
+
+    let mut data = if missing_pieces == 0 {
+        source_piece_indexes
+            .iter()
+            .filter_map(|piece_index| segment_pieces[piece_index.position() as usize].as_ref())
+            .flat_map(|piece| piece.record().to_raw_record_chunks().flatten().copied())
+            .collect::<Vec<u8>>()
+    } else {
+        debug!(
+            %segment_index,
+            missing_pieces,
+            "Missing object pieces, reconstructing segment"
+        );
+
+        // Reconstruct the missing source pieces from the rest of the segment
+        let segment_pieces =
+            download_missing_segment_pieces(segment_index, piece_getter, segment_pieces)
+                .await
+                .map_err(|(error, _incomplete_segment_pieces)| error)?;
+
+        let reconstructor = Reconstructor::new(erasure_coding);
+        let segment = spawn_blocking(move || reconstructor.reconstruct_segment(&segment_pieces))
+            .await
+            .expect("Panic if blocking task panicked")?;
+
+        let mut segment_data = segment.encode();
+        segment_data.resize(RecordedHistorySegment::SIZE, 0);
+
+        let start = first_piece_index.source_position() as usize * RawRecord::SIZE;
+        segment_data.drain(..start);
+        segment_data.truncate(required_piece_count * RawRecord::SIZE);
+
+        segment_data
+    };
+
+    data.drain(..offset);
+    data.truncate(length);
+
+    trace!(%segment_index, offset, length, "Successfully reconstructed object");
+
+    Ok(data)
+}
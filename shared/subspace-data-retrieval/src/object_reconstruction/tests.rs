@@ -0,0 +1,186 @@
+//! Tests for object reconstruction.
+
+use super::*;
+use crate::piece_getter::{NullPieceGetter, get_pieces_individually};
+use async_trait::async_trait;
+use futures::Stream;
+use parking_lot::Mutex;
+use rand::{Rng, thread_rng};
+use std::num::NonZeroUsize;
+use subspace_archiving::archiver::{Archiver, NewArchivedSegment};
+use subspace_core_primitives::objects::BlockObjectMapping;
+use subspace_core_primitives::pieces::{Piece, Record};
+use subspace_kzg::Kzg;
+
+/// A piece getter that returns the pieces from an archived segment, except for `missing_pieces`,
+/// and records the requested piece indexes.
+#[derive(Debug)]
+struct SegmentPieceGetter {
+    archived_segment: NewArchivedSegment,
+    missing_pieces: Vec<PieceIndex>,
+    requested: Mutex<Vec<PieceIndex>>,
+}
+
+#[async_trait]
+impl PieceGetter for SegmentPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        self.requested.lock().push(piece_index);
+
+        if self.missing_pieces.contains(&piece_index) {
+            return Ok(None);
+        }
+
+        self.archived_segment.get_piece(piece_index).await
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+fn erasure_coding() -> ErasureCoding {
+    ErasureCoding::new(
+        NonZeroUsize::new(Record::NUM_S_BUCKETS.next_power_of_two().ilog2() as usize)
+            .expect("Not zero; qed"),
+    )
+    .unwrap()
+}
+
+/// Archives a block of random data which fills the first segment, and returns that segment.
+fn archived_segment(erasure_coding: ErasureCoding) -> NewArchivedSegment {
+    let mut block = vec![0u8; RecordedHistorySegment::SIZE];
+    thread_rng().fill(block.as_mut_slice());
+
+    Archiver::new(Kzg::new(), erasure_coding)
+        .add_block(block, BlockObjectMapping::default(), true)
+        .archived_segments
+        .into_iter()
+        .next()
+        .expect("block fills the first segment; qed")
+}
+
+/// Returns the raw record data of the source pieces in `archived_segment`, in order.
+fn source_data(archived_segment: &NewArchivedSegment) -> Vec<u8> {
+    archived_segment
+        .pieces
+        .source_pieces()
+        .flat_map(|piece| {
+            piece
+                .record()
+                .to_raw_record_chunks()
+                .flatten()
+                .copied()
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn reconstruct_object_from_source_pieces() {
+    let erasure_coding = erasure_coding();
+    let archived_segment = archived_segment(erasure_coding.clone());
+    let expected_data = source_data(&archived_segment);
+    let piece_getter = SegmentPieceGetter {
+        archived_segment,
+        missing_pieces: Vec::new(),
+        requested: Mutex::default(),
+    };
+
+    // An object which spans the end of the first source piece and the start of the second
+    let offset = RawRecord::SIZE - 10;
+    let length = 20;
+    let data = reconstruct_object(
+        &piece_getter,
+        PieceIndex::ZERO..PieceIndex::from(8),
+        offset,
+        length,
+        erasure_coding,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(data, expected_data[offset..][..length]);
+    // Only the source pieces containing object data are downloaded
+    assert_eq!(
+        *piece_getter.requested.lock(),
+        vec![PieceIndex::ZERO, PieceIndex::from(2)]
+    );
+}
+
+#[tokio::test]
+async fn reconstruct_object_with_missing_pieces() {
+    let erasure_coding = erasure_coding();
+    let archived_segment = archived_segment(erasure_coding.clone());
+    let expected_data = source_data(&archived_segment);
+    let piece_getter = SegmentPieceGetter {
+        archived_segment,
+        missing_pieces: vec![PieceIndex::from(2)],
+        requested: Mutex::default(),
+    };
+
+    let offset = RawRecord::SIZE - 10;
+    let length = 20;
+    let data = reconstruct_object(
+        &piece_getter,
+        PieceIndex::ZERO..PieceIndex::from(8),
+        offset,
+        length,
+        erasure_coding,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(data, expected_data[offset..][..length]);
+    // Just enough pieces are downloaded to reconstruct the segment
+    let requested = piece_getter.requested.lock();
+    assert!(
+        requested.len() <= RecordedHistorySegment::NUM_RAW_RECORDS + 2,
+        "{}",
+        requested.len()
+    );
+}
+
+#[tokio::test]
+async fn reconstruct_object_invalid_ranges() {
+    let piece_getter = NullPieceGetter;
+
+    // Ranges must be within a single segment
+    let segment_end = PieceIndex::from(ArchivedHistorySegment::NUM_PIECES as u64);
+    let result = reconstruct_object(
+        &piece_getter,
+        (segment_end - PieceIndex::ONE)..(segment_end + PieceIndex::ONE),
+        0,
+        1,
+        erasure_coding(),
+    )
+    .await;
+    assert!(
+        matches!(
+            result,
+            Err(ObjectReconstructionError::InvalidPieceRange { .. })
+        ),
+        "{result:?}"
+    );
+
+    // Objects must fit in the source pieces in the range
+    let result = reconstruct_object(
+        &piece_getter,
+        PieceIndex::ZERO..PieceIndex::from(2),
+        RawRecord::SIZE - 10,
+        20,
+        erasure_coding(),
+    )
+    .await;
+    assert!(
+        matches!(
+            result,
+            Err(ObjectReconstructionError::ObjectOutsidePieces { .. })
+        ),
+        "{result:?}"
+    );
+}
@@ -1,12 +1,45 @@
 //! Getting object pieces from the Subspace Distributed Storage Network, or various caches.
 
+mod logging;
+mod metrics;
+#[cfg(feature = "tower")]
+mod service;
+#[cfg(test)]
+mod tests;
+
+pub use logging::{LoggingPieceGetter, PieceLogLevels};
+#[cfg(feature = "prometheus")]
+pub use metrics::PrometheusPieceGetterMetrics;
+pub use metrics::{
+    InstrumentedPieceGetter, NoopPieceGetterMetrics, PieceGetterMetrics, PieceGetterStats,
+};
+#[cfg(feature = "tower")]
+pub use service::PieceGetterService;
+
 use async_trait::async_trait;
-use futures::{Stream, StreamExt, stream};
+use backoff::ExponentialBackoff;
+use futures::channel::oneshot;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, Stream, StreamExt, stream};
+use parking_lot::Mutex;
+use schnellru::{ByLength, LruMap};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::future::Future;
+use std::io::ErrorKind;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
 use subspace_archiving::archiver::NewArchivedSegment;
 use subspace_core_primitives::pieces::{Piece, PieceIndex};
+use subspace_core_primitives::segments::SegmentIndex;
+use tracing::debug;
 
 /// Trait representing a way to get pieces
 #[async_trait]
@@ -17,55 +50,1594 @@ pub trait PieceGetter: fmt::Debug {
     /// Returns `Err(_)` if trying to get the piece caused an error.
     async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>>;
 
-    /// Get pieces with provided indices.
-    ///
-    /// The number of elements in the returned stream is the same as the number of unique
-    /// `piece_indices`.
+    /// Get pieces with provided indices.
+    ///
+    /// The number of elements in the returned stream is the same as the number of unique
+    /// `piece_indices`.
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    >;
+
+    /// Get pieces with provided indices, and return them in the order of `piece_indices`.
+    ///
+    /// The returned vector has one element for each unique index in `piece_indices`. Returns the
+    /// first error in `piece_indices` order if getting any of the pieces caused an error.
+    async fn get_pieces_vec(
+        &self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<Vec<(PieceIndex, Option<Piece>)>> {
+        let mut pieces = self
+            .get_pieces(piece_indices.clone())
+            .await?
+            .collect::<HashMap<_, _>>()
+            .await;
+
+        unique_piece_indices(piece_indices)
+            .map(|piece_index| -> anyhow::Result<_> {
+                let piece = pieces.remove(&piece_index).unwrap_or(Ok(None))?;
+                Ok((piece_index, piece))
+            })
+            .collect()
+    }
+
+    /// Get piece by index, retrying errors until `max_attempts` requests have been made, and
+    /// waiting between attempts according to `backoff`.
+    ///
+    /// Pieces that are not found are not retried. Returns the last error if all the attempts fail.
+    ///
+    /// To retry all the requests to a piece getter, use `RetryPieceGetter`.
+    async fn get_piece_with_retry(
+        &self,
+        piece_index: PieceIndex,
+        max_attempts: NonZeroU32,
+        backoff: ExponentialBackoff,
+    ) -> anyhow::Result<Option<Piece>> {
+        retry_get_piece(self, piece_index, max_attempts, backoff).await
+    }
+
+    /// Get all the pieces in the segment at `segment_index`.
+    ///
+    /// The number of elements in the returned stream is the number of pieces in a segment.
+    async fn get_segment<'a>(
+        &'a self,
+        segment_index: SegmentIndex,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        self.get_pieces(segment_index.segment_piece_indexes().to_vec())
+            .await
+    }
+
+    /// Fetches pieces with provided indices into the piece getter's cache, so they can be
+    /// returned quickly by future requests.
+    ///
+    /// Piece getters without a cache do nothing, and return immediately. To avoid waiting for
+    /// piece getters with a cache, spawn this method in a separate task.
+    async fn prefetch(&self, _piece_indices: Vec<PieceIndex>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Returns a piece getter that falls back to `other` if `self` does not return the piece.
+    /// Piece getters may need to be wrapped in `Arc` to be used with this method.
+    fn with_fallback<U>(self, other: U) -> FallbackPieceGetter<Self, U>
+    where
+        Self: Send + Sync + Sized,
+        U: PieceGetter + Send + Sync,
+    {
+        FallbackPieceGetter {
+            first: self,
+            second: other,
+        }
+    }
+}
+
+/// A piece getter that falls back to another piece getter if the first one does not return the piece.
+/// If both piece getters don't return the piece, returns the result of the second piece getter.
+#[derive(Debug)]
+pub struct FallbackPieceGetter<T, U>
+where
+    T: PieceGetter + Send + Sync,
+    U: PieceGetter + Send + Sync,
+{
+    first: T,
+    second: U,
+}
+
+#[async_trait]
+impl<T, U> PieceGetter for FallbackPieceGetter<T, U>
+where
+    T: PieceGetter + Send + Sync,
+    U: PieceGetter + Send + Sync,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        if let Ok(Some(piece)) = self.first.get_piece(piece_index).await {
+            Ok(Some(piece))
+        } else {
+            self.second.get_piece(piece_index).await
+        }
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let first_stream = self.first.get_pieces(piece_indices.clone()).await;
+
+        let fallback_stream = if let Ok(first_stream) = first_stream {
+            // For each missing piece, try the second piece getter
+            Box::new(Box::pin(first_stream.then(
+                |(piece_index, piece_result)| fallback_to(piece_index, piece_result, &self.second),
+            )))
+                as Box<
+                    dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin,
+                >
+        } else {
+            // If no pieces are available, just use the second piece getter
+            self.second.get_pieces(piece_indices).await?
+        };
+
+        Ok(fallback_stream)
+    }
+}
+
+/// A heler function which falls back to another piece getter if the first one does not return the
+/// piece.
+///
+/// We can't use an async closure, because async closures eagerly capture lifetimes.
+async fn fallback_to<U>(
+    piece_index: PieceIndex,
+    piece_result: anyhow::Result<Option<Piece>>,
+    second: &U,
+) -> (PieceIndex, anyhow::Result<Option<Piece>>)
+where
+    U: PieceGetter,
+{
+    if let Ok(Some(piece)) = piece_result {
+        return (piece_index, Ok(Some(piece)));
+    }
+
+    (piece_index, second.get_piece(piece_index).await)
+}
+
+/// Identifies the source which served a piece, in a piece getter with multiple sources.
+///
+/// This is the position of the source in the piece getter's list of sources.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct SourceId(pub usize);
+
+impl fmt::Display for SourceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// A stream of pieces, where each found piece is tagged with the source which served it.
+pub type PiecesWithSource<'a> = Box<
+    dyn Stream<Item = (PieceIndex, anyhow::Result<Option<(Piece, SourceId)>>)> + Send + Unpin + 'a,
+>;
+
+/// The order a `FallbackListPieceGetter` tries its sources in.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SourceOrder {
+    /// Always try the sources in the order they were supplied.
+    #[default]
+    Strict,
+    /// Try the sources with the lowest average latency first.
+    ///
+    /// Latency is measured for found pieces. Sources without any found pieces are tried first, so
+    /// every source gets measured. Sources with the same latency are tried in the order they were
+    /// supplied.
+    Latency,
+}
+
+/// A piece getter that tries an ordered list of piece getters, until one of them returns the
+/// piece.
+///
+/// This is a more flexible version of `FallbackPieceGetter`, for more than two sources. Missing
+/// and failed pieces fall through to the next source. If none of the sources return the piece,
+/// returns the result of the last source.
+///
+/// By default, sources are tried in order, see `SourceOrder` for other options.
+#[derive(Debug, Default)]
+pub struct FallbackListPieceGetter {
+    sources: Vec<Arc<dyn PieceGetter + Send + Sync>>,
+    source_order: SourceOrder,
+    /// The rolling average latency of each source, if it has found any pieces
+    source_latencies: Mutex<Vec<Option<Duration>>>,
+}
+
+impl FallbackListPieceGetter {
+    /// The weight of each new latency sample in the rolling average latency.
+    const LATENCY_WEIGHT: f64 = 0.2;
+
+    /// Creates a new piece getter, which tries `sources` in order.
+    pub fn new(sources: Vec<Arc<dyn PieceGetter + Send + Sync>>) -> Self {
+        Self::with_source_order(sources, SourceOrder::Strict)
+    }
+
+    /// Creates a new piece getter, which tries `sources` in `source_order`.
+    pub fn with_source_order(
+        sources: Vec<Arc<dyn PieceGetter + Send + Sync>>,
+        source_order: SourceOrder,
+    ) -> Self {
+        let source_latencies = Mutex::new(vec![None; sources.len()]);
+
+        Self {
+            sources,
+            source_order,
+            source_latencies,
+        }
+    }
+
+    /// Returns the sources in the order they should be tried.
+    fn source_order(&self) -> Vec<SourceId> {
+        let mut source_order = (0..self.sources.len()).map(SourceId).collect::<Vec<_>>();
+
+        if self.source_order == SourceOrder::Latency {
+            let source_latencies = self.source_latencies.lock();
+            // Stable sort, unmeasured sources first
+            source_order.sort_by_key(|source_id| source_latencies[source_id.0]);
+        }
+
+        source_order
+    }
+
+    /// Updates the rolling average latency of a source after it found a piece.
+    fn record_latency(&self, source_id: SourceId, latency: Duration) {
+        if self.source_order != SourceOrder::Latency {
+            return;
+        }
+
+        let mut source_latencies = self.source_latencies.lock();
+        let average_latency = &mut source_latencies[source_id.0];
+        *average_latency = Some(match *average_latency {
+            Some(average_latency) => {
+                average_latency.mul_f64(1.0 - Self::LATENCY_WEIGHT)
+                    + latency.mul_f64(Self::LATENCY_WEIGHT)
+            }
+            None => latency,
+        });
+    }
+
+    /// Get piece by index, and the source which served it.
+    pub async fn get_piece_with_source(
+        &self,
+        piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<(Piece, SourceId)>> {
+        let mut piece_result = Ok(None);
+
+        for source_id in self.source_order() {
+            let start = tokio::time::Instant::now();
+            match self.sources[source_id.0].get_piece(piece_index).await {
+                Ok(Some(piece)) => {
+                    self.record_latency(source_id, start.elapsed());
+                    return Ok(Some((piece, source_id)));
+                }
+                Ok(None) => piece_result = Ok(None),
+                Err(error) => piece_result = Err(error),
+            }
+        }
+
+        piece_result
+    }
+
+    /// Get pieces with provided indices, and the source which served each piece.
+    ///
+    /// This is the same as `get_pieces`, but each found piece is tagged with its source.
+    pub async fn get_pieces_with_source<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<PiecesWithSource<'a>> {
+        let state = FallbackListState {
+            piece_getter: self,
+            source_order: self.source_order().into(),
+            current_stream: None,
+            missing_pieces: unique_piece_indices(piece_indices)
+                .map(|piece_index| (piece_index, Ok(None)))
+                .collect(),
+        };
+
+        Ok(Box::new(Box::pin(stream::unfold(
+            state,
+            |mut state| async move {
+                loop {
+                    // Yield found pieces as soon as they arrive, and keep missing pieces for the
+                    // next source
+                    if let Some((source_id, start, current_stream)) = &mut state.current_stream {
+                        match current_stream.next().await {
+                            Some((piece_index, Ok(Some(piece)))) => {
+                                let source_id = *source_id;
+                                state
+                                    .piece_getter
+                                    .record_latency(source_id, start.elapsed());
+                                return Some(((piece_index, Ok(Some((piece, source_id)))), state));
+                            }
+                            Some((piece_index, piece_result)) => {
+                                state.missing_pieces.push_back((piece_index, piece_result));
+                                continue;
+                            }
+                            None => state.current_stream = None,
+                        }
+                    }
+
+                    if state.missing_pieces.is_empty() {
+                        return None;
+                    }
+
+                    // Only request the missing pieces from the next source
+                    if let Some(source_id) = state.source_order.pop_front() {
+                        let source = &state.piece_getter.sources[source_id.0];
+                        let piece_indices = state
+                            .missing_pieces
+                            .iter()
+                            .map(|(piece_index, _)| *piece_index)
+                            .collect();
+
+                        let start = tokio::time::Instant::now();
+                        match source.get_pieces(piece_indices).await {
+                            Ok(stream) => {
+                                state.missing_pieces.clear();
+                                state.current_stream = Some((source_id, start, stream));
+                            }
+                            Err(error) => {
+                                debug!(%error, %source_id, "Piece getter failed, trying next source");
+
+                                // Keep the error, so it is returned if the later sources don't
+                                // find the pieces either
+                                let error = error.to_string();
+                                state.missing_pieces.iter_mut().for_each(
+                                    |(piece_index, piece_result)| {
+                                        *piece_result = Err(anyhow::anyhow!(
+                                            "Getting piece {piece_index} from source {source_id} \
+                                             failed: {error}"
+                                        ));
+                                    },
+                                );
+                            }
+                        }
+                        continue;
+                    }
+
+                    // There are no sources left, so return the latest results for the missing
+                    // pieces
+                    let (piece_index, piece_result) = state.missing_pieces.pop_front()?;
+                    let piece_result = piece_result.map(|_missing| None);
+                    return Some(((piece_index, piece_result), state));
+                }
+            },
+        ))))
+    }
+}
+
+/// The state of a `FallbackListPieceGetter::get_pieces` stream.
+struct FallbackListState<'a> {
+    /// The piece getter which owns the sources
+    piece_getter: &'a FallbackListPieceGetter,
+    /// The sources which haven't been tried yet, in the order they should be tried
+    source_order: VecDeque<SourceId>,
+    /// The stream of pieces from the current source, and when it was requested
+    current_stream: Option<(
+        SourceId,
+        tokio::time::Instant,
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    )>,
+    /// The pieces which haven't been found yet, with their latest results
+    missing_pieces: VecDeque<(PieceIndex, anyhow::Result<Option<Piece>>)>,
+}
+
+#[async_trait]
+impl PieceGetter for FallbackListPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        Ok(self
+            .get_piece_with_source(piece_index)
+            .await?
+            .map(|(piece, _source_id)| piece))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let pieces = self.get_pieces_with_source(piece_indices).await?;
+
+        Ok(untag_pieces(pieces))
+    }
+}
+
+/// A piece getter that requests each piece from all its sources at the same time, and returns
+/// the first piece that is found.
+///
+/// Once a piece is found, the requests to the other sources are cancelled. If none of the sources
+/// return the piece, returns `Ok(None)`, unless all the sources failed, in which case the last
+/// error is returned.
+///
+/// `get_pieces` races up to `max_concurrency` pieces at the same time.
+#[derive(Debug)]
+pub struct RacingPieceGetter {
+    sources: Vec<Arc<dyn PieceGetter + Send + Sync>>,
+    max_concurrency: NonZeroUsize,
+}
+
+impl RacingPieceGetter {
+    /// The default maximum number of pieces raced at the same time by each `get_pieces` call.
+    pub const DEFAULT_MAX_CONCURRENCY: NonZeroUsize =
+        NonZeroUsize::new(100).expect("Not zero; qed");
+
+    /// Creates a new piece getter, which races `sources` against each other.
+    pub fn new(sources: Vec<Arc<dyn PieceGetter + Send + Sync>>) -> Self {
+        Self::with_max_concurrency(sources, Self::DEFAULT_MAX_CONCURRENCY)
+    }
+
+    /// Creates a new piece getter, which races `sources` against each other, and races up to
+    /// `max_concurrency` pieces at the same time in each `get_pieces` call.
+    pub fn with_max_concurrency(
+        sources: Vec<Arc<dyn PieceGetter + Send + Sync>>,
+        max_concurrency: NonZeroUsize,
+    ) -> Self {
+        Self {
+            sources,
+            max_concurrency,
+        }
+    }
+
+    /// Get piece by index, and the source which served it.
+    pub async fn get_piece_with_source(
+        &self,
+        piece_index: PieceIndex,
+    ) -> anyhow::Result<Option<(Piece, SourceId)>> {
+        let mut piece_results = self
+            .sources
+            .iter()
+            .enumerate()
+            .map(|(source_id, source)| async move {
+                (SourceId(source_id), source.get_piece(piece_index).await)
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut last_error = None;
+        let mut any_not_found = false;
+        while let Some((source_id, piece_result)) = piece_results.next().await {
+            match piece_result {
+                // Dropping the other futures cancels them
+                Ok(Some(piece)) => return Ok(Some((piece, source_id))),
+                Ok(None) => any_not_found = true,
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        match last_error {
+            Some(error) if !any_not_found => Err(error),
+            _ => Ok(None),
+        }
+    }
+
+    /// Get pieces with provided indices, and the source which served each piece.
+    ///
+    /// This is the same as `get_pieces`, but each found piece is tagged with its source. Pieces
+    /// are yielded in the order their races complete.
+    pub async fn get_pieces_with_source<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<PiecesWithSource<'a>> {
+        Ok(Box::new(Box::pin(
+            stream::iter(unique_piece_indices(piece_indices))
+                .map(move |piece_index| async move {
+                    (piece_index, self.get_piece_with_source(piece_index).await)
+                })
+                .buffer_unordered(self.max_concurrency.get()),
+        )))
+    }
+}
+
+#[async_trait]
+impl PieceGetter for RacingPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        Ok(self
+            .get_piece_with_source(piece_index)
+            .await?
+            .map(|(piece, _source_id)| piece))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let pieces = self.get_pieces_with_source(piece_indices).await?;
+
+        Ok(untag_pieces(pieces))
+    }
+}
+
+/// A piece getter that requests each piece from multiple sources at the same time, and only
+/// returns pieces which at least `quorum` sources agree on.
+///
+/// Pieces must be byte-identical to agree. Once enough sources agree, the requests to the other
+/// sources are cancelled.
+///
+/// If pieces are found, but none of them reach the quorum, returns an error. If none of the
+/// sources return the piece, returns `Ok(None)`, unless all the sources failed, in which case the
+/// last error is returned.
+#[derive(Debug)]
+pub struct QuorumPieceGetter {
+    sources: Vec<Arc<dyn PieceGetter + Send + Sync>>,
+    quorum: NonZeroUsize,
+}
+
+impl QuorumPieceGetter {
+    /// Creates a new piece getter, which requires `quorum` of `sources` to return the same piece.
+    ///
+    /// If `quorum` is greater than the number of sources, no pieces will be returned.
+    pub fn new(sources: Vec<Arc<dyn PieceGetter + Send + Sync>>, quorum: NonZeroUsize) -> Self {
+        Self { sources, quorum }
+    }
+}
+
+#[async_trait]
+impl PieceGetter for QuorumPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        let mut piece_results = self
+            .sources
+            .iter()
+            .map(|source| source.get_piece(piece_index))
+            .collect::<FuturesUnordered<_>>();
+
+        // The distinct pieces returned by the sources, and the number of sources which returned
+        // each piece
+        let mut found_pieces = Vec::<(Piece, usize)>::new();
+        let mut last_error = None;
+        let mut any_not_found = false;
+        while let Some(piece_result) = piece_results.next().await {
+            let piece = match piece_result {
+                Ok(Some(piece)) => piece,
+                Ok(None) => {
+                    any_not_found = true;
+                    continue;
+                }
+                Err(error) => {
+                    last_error = Some(error);
+                    continue;
+                }
+            };
+
+            let agreeing_sources = match found_pieces
+                .iter_mut()
+                .find(|(found_piece, _)| *found_piece == piece)
+            {
+                Some((_, agreeing_sources)) => {
+                    *agreeing_sources += 1;
+                    *agreeing_sources
+                }
+                None => {
+                    found_pieces.push((piece.clone(), 1));
+                    1
+                }
+            };
+
+            if agreeing_sources >= self.quorum.get() {
+                // Dropping the other futures cancels them
+                return Ok(Some(piece));
+            }
+        }
+
+        if let Some(max_agreeing_sources) = found_pieces
+            .iter()
+            .map(|(_, agreeing_sources)| *agreeing_sources)
+            .max()
+        {
+            return Err(anyhow::anyhow!(
+                "Piece {piece_index} was only returned by {max_agreeing_sources} agreeing sources, \
+                 {} sources must agree, {} different pieces were returned",
+                self.quorum,
+                found_pieces.len(),
+            ));
+        }
+
+        match last_error {
+            Some(error) if !any_not_found => Err(error),
+            _ => Ok(None),
+        }
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+/// Removes the source tags from a stream of pieces.
+fn untag_pieces(
+    pieces: PiecesWithSource<'_>,
+) -> Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + '_> {
+    Box::new(pieces.map(|(piece_index, piece_result)| {
+        (
+            piece_index,
+            piece_result.map(|maybe_piece| maybe_piece.map(|(piece, _source_id)| piece)),
+        )
+    }))
+}
+
+/// A piece getter with a bounded least-recently-used cache in front of another piece getter.
+///
+/// Pieces that are not found are not cached.
+pub struct CachingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    inner: G,
+    cache: Mutex<LruMap<PieceIndex, Piece, ByLength>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<G> fmt::Debug for CachingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachingPieceGetter")
+            .field("inner", &self.inner)
+            .field("cached_pieces", &self.cache.lock().len())
+            .field("hits", &self.hits)
+            .field("misses", &self.misses)
+            .finish()
+    }
+}
+
+impl<G> CachingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    /// Creates a new piece getter, which caches up to `capacity` pieces from `inner`.
+    pub fn new(inner: G, capacity: NonZeroU32) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(LruMap::new(ByLength::new(capacity.get()))),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of pieces which were found in the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of pieces which were not found in the cache, and were requested from
+    /// the inner piece getter.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Returns the piece from the cache, and updates the hit or miss count.
+    fn get_cached_piece(&self, piece_index: PieceIndex) -> Option<Piece> {
+        let maybe_piece = self.cache.lock().get(&piece_index).cloned();
+
+        if maybe_piece.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        maybe_piece
+    }
+
+    /// Stores a found piece in the cache.
+    fn cache_piece(&self, piece_index: PieceIndex, piece_result: &anyhow::Result<Option<Piece>>) {
+        if let Ok(Some(piece)) = piece_result {
+            self.cache.lock().insert(piece_index, piece.clone());
+        }
+    }
+}
+
+#[async_trait]
+impl<G> PieceGetter for CachingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        if let Some(piece) = self.get_cached_piece(piece_index) {
+            return Ok(Some(piece));
+        }
+
+        let piece_result = self.inner.get_piece(piece_index).await;
+        self.cache_piece(piece_index, &piece_result);
+
+        piece_result
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let mut cached_pieces = Vec::new();
+        let mut missing_piece_indices = Vec::new();
+        for piece_index in unique_piece_indices(piece_indices) {
+            if let Some(piece) = self.get_cached_piece(piece_index) {
+                cached_pieces.push((piece_index, Ok(Some(piece))));
+            } else {
+                missing_piece_indices.push(piece_index);
+            }
+        }
+
+        // Only request the missing pieces from the inner piece getter
+        let missing_pieces = if missing_piece_indices.is_empty() {
+            Box::new(stream::empty())
+                as Box<
+                    dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin,
+                >
+        } else {
+            self.inner.get_pieces(missing_piece_indices).await?
+        };
+
+        Ok(Box::new(stream::iter(cached_pieces).chain(
+            missing_pieces.map(|(piece_index, piece_result)| {
+                self.cache_piece(piece_index, &piece_result);
+                (piece_index, piece_result)
+            }),
+        )))
+    }
+
+    async fn prefetch(&self, piece_indices: Vec<PieceIndex>) -> anyhow::Result<()> {
+        // Fetching missing pieces stores them in the cache
+        let mut pieces = self.get_pieces(piece_indices).await?;
+        while pieces.next().await.is_some() {}
+
+        Ok(())
+    }
+}
+
+/// The result of a coalesced piece request, shared with the other callers waiting for that piece.
+type CoalescedPieceResult = Result<Option<Piece>, String>;
+
+/// The callers waiting for each in-flight piece request in a `CoalescingPieceGetter`.
+type CoalescedWaiters = HashMap<PieceIndex, Vec<oneshot::Sender<CoalescedPieceResult>>>;
+
+/// A piece getter that combines concurrent requests for the same piece into a single request to
+/// another piece getter.
+///
+/// The first caller requests the piece from the inner piece getter, and other callers wait for its
+/// result. Errors are shared as error messages. If the first caller is cancelled, a waiting caller
+/// makes a new request.
+///
+/// `get_pieces` requests up to `max_concurrency` pieces at the same time, and coalesces each piece
+/// separately.
+#[derive(Debug)]
+pub struct CoalescingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    inner: G,
+    in_flight: Mutex<CoalescedWaiters>,
+    max_concurrency: NonZeroUsize,
+}
+
+impl<G> CoalescingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    /// The default maximum number of pieces requested at the same time by each `get_pieces` call.
+    pub const DEFAULT_MAX_CONCURRENCY: NonZeroUsize =
+        NonZeroUsize::new(100).expect("Not zero; qed");
+
+    /// Creates a new piece getter, which coalesces concurrent requests to `inner`.
+    pub fn new(inner: G) -> Self {
+        Self::with_max_concurrency(inner, Self::DEFAULT_MAX_CONCURRENCY)
+    }
+
+    /// Creates a new piece getter, which coalesces concurrent requests to `inner`, and requests up
+    /// to `max_concurrency` pieces at the same time in each `get_pieces` call.
+    pub fn with_max_concurrency(inner: G, max_concurrency: NonZeroUsize) -> Self {
+        Self {
+            inner,
+            in_flight: Mutex::default(),
+            max_concurrency,
+        }
+    }
+}
+
+#[async_trait]
+impl<G> PieceGetter for CoalescingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        loop {
+            let receiver = match self.in_flight.lock().entry(piece_index) {
+                Entry::Occupied(mut waiters) => {
+                    let (sender, receiver) = oneshot::channel();
+                    waiters.get_mut().push(sender);
+                    receiver
+                }
+                Entry::Vacant(waiters) => {
+                    waiters.insert(Vec::new());
+                    break;
+                }
+            };
+
+            match receiver.await {
+                Ok(piece_result) => return piece_result.map_err(anyhow::Error::msg),
+                // The first caller was cancelled, so try again
+                Err(oneshot::Canceled) => continue,
+            }
+        }
+
+        let in_flight_guard = InFlightGuard {
+            in_flight: &self.in_flight,
+            piece_index: Some(piece_index),
+        };
+        let piece_result = self.inner.get_piece(piece_index).await;
+
+        let waiters = in_flight_guard.finish();
+        if !waiters.is_empty() {
+            let shared_result = match &piece_result {
+                Ok(maybe_piece) => Ok(maybe_piece.clone()),
+                Err(error) => Err(format!("{error:#}")),
+            };
+            for waiter in waiters {
+                // The waiting caller might have been cancelled
+                let _ = waiter.send(shared_result.clone());
+            }
+        }
+
+        piece_result
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_with_concurrency(
+            |piece_index| self.get_piece(piece_index),
+            piece_indices,
+            self.max_concurrency,
+        )
+    }
+}
+
+/// Removes an in-flight piece request from a `CoalescingPieceGetter` when it finishes or is
+/// cancelled, which wakes up any callers waiting for it.
+struct InFlightGuard<'a> {
+    in_flight: &'a Mutex<CoalescedWaiters>,
+    /// The piece index of the request, or `None` if it has already been removed
+    piece_index: Option<PieceIndex>,
+}
+
+impl InFlightGuard<'_> {
+    /// Removes the request, and returns the callers waiting for it.
+    fn finish(mut self) -> Vec<oneshot::Sender<CoalescedPieceResult>> {
+        self.piece_index
+            .take()
+            .and_then(|piece_index| self.in_flight.lock().remove(&piece_index))
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(piece_index) = self.piece_index.take() {
+            // Dropping the senders tells the waiting callers to try again
+            self.in_flight.lock().remove(&piece_index);
+        }
+    }
+}
+
+/// A piece getter that reads pieces from files in a local directory.
+///
+/// Each file is named by its piece index, and contains the raw piece bytes.
+#[derive(Debug, Clone)]
+pub struct FilesystemPieceGetter {
+    directory: PathBuf,
+}
+
+impl FilesystemPieceGetter {
+    /// Creates a new piece getter, which reads pieces from `directory`.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// Returns the path of the file containing the piece at `piece_index`.
+    pub fn piece_path(&self, piece_index: PieceIndex) -> PathBuf {
+        self.directory.join(piece_index.to_string())
+    }
+}
+
+#[async_trait]
+impl PieceGetter for FilesystemPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        let piece_path = self.piece_path(piece_index);
+
+        let piece_bytes = match tokio::fs::read(&piece_path).await {
+            Ok(piece_bytes) => piece_bytes,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to read piece {piece_index} from {}: {error}",
+                    piece_path.display()
+                ));
+            }
+        };
+
+        let piece_len = piece_bytes.len();
+        let piece = Piece::try_from(piece_bytes).map_err(|_| {
+            anyhow::anyhow!(
+                "Piece {piece_index} in {} has invalid length {piece_len}, expected {}",
+                piece_path.display(),
+                Piece::SIZE,
+            )
+        })?;
+
+        Ok(Some(piece))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+/// A piece getter that fails each piece request that takes longer than a timeout.
+///
+/// The timeout applies independently to each piece, including pieces requested using
+/// `get_pieces`.
+#[derive(Debug)]
+pub struct TimeoutPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    inner: G,
+    timeout: Duration,
+}
+
+impl<G> TimeoutPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    /// Creates a new piece getter, which applies `timeout` to each piece request to `inner`.
+    pub fn new(inner: G, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+}
+
+#[async_trait]
+impl<G> PieceGetter for TimeoutPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        tokio::time::timeout(self.timeout, self.inner.get_piece(piece_index))
+            .await
+            .map_err(|_elapsed| {
+                anyhow::anyhow!(
+                    "Timed out after {:?} getting piece {piece_index}",
+                    self.timeout
+                )
+            })?
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        // Each piece needs its own timeout, so we can't use the inner getter's stream
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+/// A piece getter that retries failed piece requests, with exponential backoff and jitter.
+///
+/// Pieces that are not found are not retried. Each piece requested using `get_pieces` is retried
+/// independently.
+#[derive(Debug)]
+pub struct RetryPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    inner: G,
+    max_attempts: NonZeroU32,
+    backoff: ExponentialBackoff,
+}
+
+impl<G> RetryPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    /// Creates a new piece getter, which makes up to `max_attempts` requests to `inner` for each
+    /// piece, waiting between attempts according to `backoff`.
+    pub fn new(inner: G, max_attempts: NonZeroU32, backoff: ExponentialBackoff) -> Self {
+        Self {
+            inner,
+            max_attempts,
+            backoff,
+        }
+    }
+
+    /// Retries a piece which failed on the first attempt, if there are any attempts remaining.
+    async fn retry_failed(
+        &self,
+        piece_index: PieceIndex,
+        piece_result: anyhow::Result<Option<Piece>>,
+    ) -> (PieceIndex, anyhow::Result<Option<Piece>>) {
+        let Err(error) = piece_result else {
+            return (piece_index, piece_result);
+        };
+        let Some(remaining_attempts) = NonZeroU32::new(self.max_attempts.get() - 1) else {
+            return (piece_index, Err(error));
+        };
+
+        debug!(%piece_index, %error, "Getting piece failed, will retry");
+
+        (
+            piece_index,
+            retry_get_piece(
+                &self.inner,
+                piece_index,
+                remaining_attempts,
+                self.backoff.clone(),
+            )
+            .await,
+        )
+    }
+}
+
+#[async_trait]
+impl<G> PieceGetter for RetryPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        retry_get_piece(
+            &self.inner,
+            piece_index,
+            self.max_attempts,
+            self.backoff.clone(),
+        )
+        .await
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let Ok(first_stream) = self.inner.get_pieces(piece_indices.clone()).await else {
+            // If the inner piece getter can't create a stream, retry each piece individually
+            return get_pieces_individually(
+                |piece_index| self.get_piece(piece_index),
+                piece_indices,
+            );
+        };
+
+        Ok(Box::new(Box::pin(first_stream.then(
+            |(piece_index, piece_result)| self.retry_failed(piece_index, piece_result),
+        ))))
+    }
+}
+
+/// Gets a piece from `piece_getter`, retrying errors until `max_attempts` requests have been made.
+///
+/// Returns the last error if all the attempts fail.
+async fn retry_get_piece<G>(
+    piece_getter: &G,
+    piece_index: PieceIndex,
+    max_attempts: NonZeroU32,
+    backoff: ExponentialBackoff,
+) -> anyhow::Result<Option<Piece>>
+where
+    G: PieceGetter + ?Sized,
+{
+    let mut attempts = 0;
+
+    backoff::future::retry(backoff, || {
+        attempts += 1;
+        let attempt = attempts;
+
+        async move {
+            piece_getter.get_piece(piece_index).await.map_err(|error| {
+                if attempt >= max_attempts.get() {
+                    backoff::Error::permanent(error)
+                } else {
+                    debug!(%piece_index, %error, %attempt, "Getting piece failed, will retry");
+                    backoff::Error::transient(error)
+                }
+            })
+        }
+    })
+    .await
+}
+
+/// A token bucket, which limits the rate of requests.
+#[derive(Debug)]
+struct TokenBucket {
+    /// The number of requests which can be made immediately
+    tokens: f64,
+    /// The last time tokens were added to the bucket
+    last_refill: tokio::time::Instant,
+}
+
+/// A piece getter that limits the rate of piece requests to another piece getter.
+///
+/// Requests are limited using a token bucket, which allows short bursts of requests, and then
+/// limits requests to the configured rate.
+#[derive(Debug)]
+pub struct RateLimitedPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    inner: G,
+    requests_per_second: NonZeroU32,
+    burst: NonZeroU32,
+    bucket: Mutex<TokenBucket>,
+}
+
+impl<G> RateLimitedPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    /// Creates a new piece getter, which makes up to `requests_per_second` piece requests to
+    /// `inner`, after an initial burst of up to `burst` requests.
+    pub fn new(inner: G, requests_per_second: NonZeroU32, burst: NonZeroU32) -> Self {
+        Self {
+            inner,
+            requests_per_second,
+            burst,
+            bucket: Mutex::new(TokenBucket {
+                tokens: f64::from(burst.get()),
+                last_refill: tokio::time::Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a request is allowed by the rate limit.
+    async fn acquire(&self) {
+        let rate = f64::from(self.requests_per_second.get());
+
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock();
+                let now = tokio::time::Instant::now();
+                let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+                bucket.tokens = (bucket.tokens + refill).min(f64::from(self.burst.get()));
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[async_trait]
+impl<G> PieceGetter for RateLimitedPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        self.acquire().await;
+        self.inner.get_piece(piece_index).await
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        // Each piece is rate limited separately, but slow pieces don't block other pieces
+        let concurrency = NonZeroUsize::try_from(self.burst)?;
+        get_pieces_with_concurrency(
+            |piece_index| self.get_piece(piece_index),
+            piece_indices,
+            concurrency,
+        )
+    }
+}
+
+/// The failure state of a `CircuitBreakerPieceGetter`.
+#[derive(Debug, Default)]
+struct CircuitBreakerState {
+    /// The number of failed requests since the last successful request
+    consecutive_failures: u32,
+    /// If the circuit breaker is open, the time its cooldown ends
+    open_until: Option<tokio::time::Instant>,
+    /// True if the cooldown has ended, and the trial request is in flight
+    trial_in_flight: bool,
+}
+
+impl CircuitBreakerState {
+    /// Returns true if the circuit breaker is open at the current time, or its trial request is
+    /// still in flight.
+    fn is_open(&self) -> bool {
+        self.open_until.is_some_and(|open_until| {
+            self.trial_in_flight || tokio::time::Instant::now() < open_until
+        })
+    }
+}
+
+/// Allows a request to the inner piece getter of a `CircuitBreakerPieceGetter`.
+///
+/// If the request is the trial request after the cooldown, dropping the permit allows another
+/// trial request, so a cancelled trial doesn't keep the circuit breaker open.
+struct CircuitBreakerPermit<'a> {
+    /// The circuit breaker state, if this permit is for the trial request
+    trial_state: Option<&'a Mutex<CircuitBreakerState>>,
+}
+
+impl Drop for CircuitBreakerPermit<'_> {
+    fn drop(&mut self) {
+        if let Some(state) = self.trial_state {
+            state.lock().trial_in_flight = false;
+        }
+    }
+}
+
+/// A piece getter that stops requesting pieces from another piece getter after repeated failures.
+///
+/// After `failure_threshold` consecutive failed requests, the circuit breaker opens, and requests
+/// fail immediately without contacting the inner piece getter. After `cooldown`, a single trial
+/// request is sent to the inner piece getter, and other requests keep failing until it finishes.
+/// A successful trial closes the circuit breaker, but a failed trial opens it for another
+/// `cooldown`.
+///
+/// Missing pieces are not failures.
+#[derive(Debug)]
+pub struct CircuitBreakerPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    inner: G,
+    failure_threshold: NonZeroU32,
+    cooldown: Duration,
+    state: Mutex<CircuitBreakerState>,
+}
+
+impl<G> CircuitBreakerPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    /// Creates a new piece getter, which stops requesting pieces from `inner` for `cooldown`
+    /// after `failure_threshold` consecutive failures.
+    pub fn new(inner: G, failure_threshold: NonZeroU32, cooldown: Duration) -> Self {
+        Self {
+            inner,
+            failure_threshold,
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns true if requests are currently failing without contacting the inner piece getter.
+    pub fn is_open(&self) -> bool {
+        self.state.lock().is_open()
+    }
+
+    /// Returns a permit to send a request to the inner piece getter, or `None` if the request
+    /// should fail immediately.
+    fn try_permit(&self) -> Option<CircuitBreakerPermit<'_>> {
+        let mut state = self.state.lock();
+        if state.is_open() {
+            return None;
+        }
+
+        // After the cooldown, only the first request is sent until it finishes
+        let trial = state.open_until.is_some();
+        state.trial_in_flight = trial;
+
+        Some(CircuitBreakerPermit {
+            trial_state: trial.then_some(&self.state),
+        })
+    }
+
+    /// Returns the error for a request made while the circuit breaker is open.
+    fn open_error(piece_index: PieceIndex) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Circuit breaker is open after repeated failures, not requesting piece {piece_index}"
+        )
+    }
+
+    /// Updates the failure state after a request.
+    fn record(&self, failed: bool) {
+        let mut state = self.state.lock();
+
+        if !failed {
+            *state = CircuitBreakerState::default();
+            return;
+        }
+
+        state.trial_in_flight = false;
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if state.consecutive_failures >= self.failure_threshold.get() {
+            if !state.is_open() {
+                debug!(
+                    consecutive_failures = state.consecutive_failures,
+                    cooldown = ?self.cooldown,
+                    "Piece getter failed repeatedly, opening circuit breaker"
+                );
+            }
+            state.open_until = Some(tokio::time::Instant::now() + self.cooldown);
+        }
+    }
+}
+
+#[async_trait]
+impl<G> PieceGetter for CircuitBreakerPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        let Some(_permit) = self.try_permit() else {
+            return Err(Self::open_error(piece_index));
+        };
+
+        let piece_result = self.inner.get_piece(piece_index).await;
+        self.record(piece_result.is_err());
+
+        piece_result
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let Some(permit) = self.try_permit() else {
+            return Ok(Box::new(stream::iter(
+                unique_piece_indices(piece_indices)
+                    .map(|piece_index| (piece_index, Err(Self::open_error(piece_index)))),
+            )));
+        };
+
+        let pieces = match self.inner.get_pieces(piece_indices).await {
+            Ok(pieces) => pieces,
+            Err(error) => {
+                self.record(true);
+                return Err(error);
+            }
+        };
+
+        // The first piece result decides the trial
+        let mut permit = Some(permit);
+        Ok(Box::new(pieces.map(move |(piece_index, piece_result)| {
+            self.record(piece_result.is_err());
+            permit.take();
+            (piece_index, piece_result)
+        })))
+    }
+}
+
+/// The concurrency state of an `AdaptivePieceGetter`.
+#[derive(Debug)]
+struct AdaptiveConcurrency {
+    /// The current concurrency, which can be fractional during additive increases
+    concurrency: f64,
+    /// The rolling average piece latency
+    average_latency: Option<Duration>,
+    /// The last time the concurrency was decreased
+    last_decrease: Option<tokio::time::Instant>,
+}
+
+/// A piece getter that adapts the number of concurrent piece requests in `get_pieces`, based on
+/// observed piece latency and errors.
+///
+/// Concurrency is adjusted using additive increase, multiplicative decrease (AIMD). While pieces
+/// succeed within the target latency, concurrency slowly increases, up to the maximum. When a piece
+/// fails, or the rolling average latency exceeds the target, concurrency is halved, down to one
+/// request at a time.
+#[derive(Debug)]
+pub struct AdaptivePieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    inner: G,
+    max_concurrency: NonZeroUsize,
+    target_latency: Duration,
+    state: Mutex<AdaptiveConcurrency>,
+}
+
+impl<G> AdaptivePieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    /// The weight of each new latency sample in the rolling average latency.
+    const LATENCY_WEIGHT: f64 = 0.2;
+
+    /// Creates a new piece getter, which starts with `initial_concurrency` requests to `inner`,
+    /// and adapts it to keep the average piece latency below `target_latency`.
+    pub fn new(
+        inner: G,
+        initial_concurrency: NonZeroUsize,
+        max_concurrency: NonZeroUsize,
+        target_latency: Duration,
+    ) -> Self {
+        let initial_concurrency = initial_concurrency.min(max_concurrency);
+
+        Self {
+            inner,
+            max_concurrency,
+            target_latency,
+            state: Mutex::new(AdaptiveConcurrency {
+                concurrency: initial_concurrency.get() as f64,
+                average_latency: None,
+                last_decrease: None,
+            }),
+        }
+    }
+
+    /// Returns the current number of concurrent piece requests.
+    pub fn concurrency(&self) -> NonZeroUsize {
+        let concurrency = self.state.lock().concurrency as usize;
+
+        NonZeroUsize::new(concurrency).unwrap_or(NonZeroUsize::MIN)
+    }
+
+    /// Returns the rolling average piece latency, if any pieces have been requested.
+    pub fn average_latency(&self) -> Option<Duration> {
+        self.state.lock().average_latency
+    }
+
+    /// Gets a piece from the inner piece getter, and adjusts the concurrency based on the result.
+    async fn get_piece_adaptive(
+        &self,
+        piece_index: PieceIndex,
+    ) -> (PieceIndex, anyhow::Result<Option<Piece>>) {
+        let start = tokio::time::Instant::now();
+        let piece_result = self.inner.get_piece(piece_index).await;
+        self.update_concurrency(start.elapsed(), piece_result.is_err());
+
+        (piece_index, piece_result)
+    }
+
+    /// Updates the rolling latency and the concurrency after a piece request.
+    fn update_concurrency(&self, latency: Duration, failed: bool) {
+        let mut state = self.state.lock();
+
+        let average_latency = match state.average_latency {
+            Some(average_latency) => {
+                average_latency.mul_f64(1.0 - Self::LATENCY_WEIGHT)
+                    + latency.mul_f64(Self::LATENCY_WEIGHT)
+            }
+            None => latency,
+        };
+        state.average_latency = Some(average_latency);
+
+        if failed || average_latency > self.target_latency {
+            // Only decrease once per average latency, so a batch of slow requests which were
+            // already in flight doesn't collapse the concurrency
+            let now = tokio::time::Instant::now();
+            if state
+                .last_decrease
+                .is_none_or(|last_decrease| now.duration_since(last_decrease) >= average_latency)
+            {
+                state.concurrency = (state.concurrency / 2.0).max(1.0);
+                state.last_decrease = Some(now);
+
+                debug!(
+                    concurrency = state.concurrency,
+                    ?average_latency,
+                    failed,
+                    "Decreased piece request concurrency"
+                );
+            }
+        } else {
+            // Increase by one for each round of concurrent requests
+            state.concurrency = (state.concurrency + 1.0 / state.concurrency)
+                .min(self.max_concurrency.get() as f64);
+        }
+    }
+}
+
+/// The state of an `AdaptivePieceGetter::get_pieces` stream.
+struct AdaptiveState<'a, I> {
+    /// The pieces which haven't been requested yet
+    remaining_indices: I,
+    /// The piece requests which are in flight
+    in_flight: FuturesUnordered<BoxFuture<'a, (PieceIndex, anyhow::Result<Option<Piece>>)>>,
+}
+
+#[async_trait]
+impl<G> PieceGetter for AdaptivePieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        self.get_piece_adaptive(piece_index).await.1
+    }
+
     async fn get_pieces<'a>(
         &'a self,
         piece_indices: Vec<PieceIndex>,
     ) -> anyhow::Result<
         Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
-    >;
+    > {
+        let state = AdaptiveState {
+            remaining_indices: unique_piece_indices(piece_indices),
+            in_flight: FuturesUnordered::new(),
+        };
 
-    /// Returns a piece getter that falls back to `other` if `self` does not return the piece.
-    /// Piece getters may need to be wrapped in `Arc` to be used with this method.
-    fn with_fallback<U>(self, other: U) -> FallbackPieceGetter<Self, U>
-    where
-        Self: Send + Sync + Sized,
-        U: PieceGetter + Send + Sync,
-    {
-        FallbackPieceGetter {
-            first: self,
-            second: other,
-        }
+        Ok(Box::new(Box::pin(stream::unfold(
+            state,
+            move |mut state| async move {
+                // Start new requests up to the current concurrency
+                while state.in_flight.len() < self.concurrency().get()
+                    && let Some(piece_index) = state.remaining_indices.next()
+                {
+                    state
+                        .in_flight
+                        .push(self.get_piece_adaptive(piece_index).boxed());
+                }
+
+                let piece = state.in_flight.next().await?;
+                Some((piece, state))
+            },
+        ))))
     }
 }
 
-/// A piece getter that falls back to another piece getter if the first one does not return the piece.
-/// If both piece getters don't return the piece, returns the result of the second piece getter.
+/// A piece getter that yields pieces from `get_pieces` in the order they were requested.
+///
+/// See `ordered_pieces` for details.
 #[derive(Debug)]
-pub struct FallbackPieceGetter<T, U>
+pub struct OrderedPieceGetter<G>
 where
-    T: PieceGetter + Send + Sync,
-    U: PieceGetter + Send + Sync,
+    G: PieceGetter + Send + Sync,
 {
-    first: T,
-    second: U,
+    inner: G,
+    max_buffered: NonZeroUsize,
+}
+
+impl<G> OrderedPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    /// Creates a new piece getter, which buffers up to `max_buffered` out of order pieces from
+    /// `inner`.
+    pub fn new(inner: G, max_buffered: NonZeroUsize) -> Self {
+        Self {
+            inner,
+            max_buffered,
+        }
+    }
 }
 
 #[async_trait]
-impl<T, U> PieceGetter for FallbackPieceGetter<T, U>
+impl<G> PieceGetter for OrderedPieceGetter<G>
 where
-    T: PieceGetter + Send + Sync,
-    U: PieceGetter + Send + Sync,
+    G: PieceGetter + Send + Sync,
 {
     async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
-        if let Ok(Some(piece)) = self.first.get_piece(piece_index).await {
-            Ok(Some(piece))
-        } else {
-            self.second.get_piece(piece_index).await
-        }
+        self.inner.get_piece(piece_index).await
     }
 
     async fn get_pieces<'a>(
@@ -74,42 +1646,168 @@ where
     ) -> anyhow::Result<
         Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
     > {
-        let first_stream = self.first.get_pieces(piece_indices.clone()).await;
+        let pieces = self.inner.get_pieces(piece_indices.clone()).await?;
 
-        let fallback_stream = if let Ok(first_stream) = first_stream {
-            // For each missing piece, try the second piece getter
-            Box::new(Box::pin(first_stream.then(
-                |(piece_index, piece_result)| fallback_to(piece_index, piece_result, &self.second),
-            )))
-                as Box<
-                    dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin,
-                >
-        } else {
-            // If no pieces are available, just use the second piece getter
-            self.second.get_pieces(piece_indices).await?
-        };
+        Ok(ordered_pieces(pieces, piece_indices, self.max_buffered))
+    }
+}
 
-        Ok(fallback_stream)
+/// The state of an `ordered_pieces` stream.
+struct OrderedState<'a> {
+    /// The unordered pieces, or `None` if that stream has finished
+    pieces: Option<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    >,
+    /// The piece indexes which haven't been yielded yet, in request order
+    remaining_indices: VecDeque<PieceIndex>,
+    /// Pieces which arrived before an earlier piece
+    buffered: HashMap<PieceIndex, anyhow::Result<Option<Piece>>>,
+    max_buffered: usize,
+}
+
+impl OrderedState<'_> {
+    /// Removes and returns the earliest buffered piece, even if earlier pieces haven't arrived yet.
+    fn take_earliest_buffered(&mut self) -> Option<(PieceIndex, anyhow::Result<Option<Piece>>)> {
+        let position = self
+            .remaining_indices
+            .iter()
+            .position(|piece_index| self.buffered.contains_key(piece_index))?;
+        let piece_index = self.remaining_indices.remove(position)?;
+        let piece_result = self.buffered.remove(&piece_index)?;
+
+        Some((piece_index, piece_result))
     }
 }
 
-/// A heler function which falls back to another piece getter if the first one does not return the
-/// piece.
+/// Re-orders `pieces` so they are yielded in the order of `piece_indices`.
 ///
-/// We can't use an async closure, because async closures eagerly capture lifetimes.
-async fn fallback_to<U>(
-    piece_index: PieceIndex,
-    piece_result: anyhow::Result<Option<Piece>>,
-    second: &U,
-) -> (PieceIndex, anyhow::Result<Option<Piece>>)
+/// Pieces which arrive before an earlier piece are buffered. To limit memory usage, at most
+/// `max_buffered` pieces are buffered. When the buffer is full, the earliest buffered piece is
+/// yielded out of order before any more pieces are read. Pieces which are not in `piece_indices`
+/// are yielded as soon as they arrive.
+#[expect(clippy::type_complexity, reason = "type matches trait signature")]
+pub fn ordered_pieces<'a>(
+    pieces: Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    piece_indices: Vec<PieceIndex>,
+    max_buffered: NonZeroUsize,
+) -> Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a> {
+    // Duplicate pieces are only yielded once
+    let remaining_indices = unique_piece_indices(piece_indices).collect();
+
+    let state = OrderedState {
+        pieces: Some(pieces),
+        remaining_indices,
+        buffered: HashMap::new(),
+        max_buffered: max_buffered.get(),
+    };
+
+    Box::new(Box::pin(stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(next_index) = state.remaining_indices.front().copied()
+                && let Some(piece_result) = state.buffered.remove(&next_index)
+            {
+                state.remaining_indices.pop_front();
+                return Some(((next_index, piece_result), state));
+            }
+
+            if state.buffered.len() >= state.max_buffered {
+                let piece = state.take_earliest_buffered()?;
+                return Some((piece, state));
+            }
+
+            let Some(pieces) = &mut state.pieces else {
+                // The unordered stream has finished, so any remaining pieces are missing
+                let piece = state.take_earliest_buffered()?;
+                return Some((piece, state));
+            };
+
+            match pieces.next().await {
+                Some((piece_index, piece_result)) => {
+                    if state.remaining_indices.contains(&piece_index) {
+                        state.buffered.insert(piece_index, piece_result);
+                    } else {
+                        return Some(((piece_index, piece_result), state));
+                    }
+                }
+                None => state.pieces = None,
+            }
+        }
+    })))
+}
+
+/// Validates pieces fetched by a piece getter.
+#[async_trait]
+pub trait PieceValidator: fmt::Debug + Send + Sync {
+    /// Validates `piece` against `piece_index`, returning the piece if it is valid.
+    async fn validate_piece(&self, piece_index: PieceIndex, piece: Piece) -> Option<Piece>;
+}
+
+/// A piece getter that validates each piece fetched from another piece getter.
+///
+/// Invalid pieces are returned as errors, so they can be retried or fetched from another source.
+#[derive(Debug)]
+pub struct ValidatingPieceGetter<G, V>
 where
-    U: PieceGetter,
+    G: PieceGetter + Send + Sync,
+    V: PieceValidator,
 {
-    if let Ok(Some(piece)) = piece_result {
-        return (piece_index, Ok(Some(piece)));
+    inner: G,
+    validator: V,
+}
+
+impl<G, V> ValidatingPieceGetter<G, V>
+where
+    G: PieceGetter + Send + Sync,
+    V: PieceValidator,
+{
+    /// Creates a new piece getter, which validates pieces from `inner` using `validator`.
+    pub fn new(inner: G, validator: V) -> Self {
+        Self { inner, validator }
     }
 
-    (piece_index, second.get_piece(piece_index).await)
+    /// Validates a piece result, turning invalid pieces into errors.
+    async fn validate(
+        &self,
+        piece_index: PieceIndex,
+        piece_result: anyhow::Result<Option<Piece>>,
+    ) -> anyhow::Result<Option<Piece>> {
+        let Some(piece) = piece_result? else {
+            return Ok(None);
+        };
+
+        match self.validator.validate_piece(piece_index, piece).await {
+            Some(piece) => Ok(Some(piece)),
+            None => Err(anyhow::anyhow!("Piece {piece_index} failed validation")),
+        }
+    }
+}
+
+#[async_trait]
+impl<G, V> PieceGetter for ValidatingPieceGetter<G, V>
+where
+    G: PieceGetter + Send + Sync,
+    V: PieceValidator,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        let piece_result = self.inner.get_piece(piece_index).await;
+
+        self.validate(piece_index, piece_result).await
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let pieces = self.inner.get_pieces(piece_indices).await?;
+
+        Ok(Box::new(Box::pin(pieces.then(
+            |(piece_index, piece_result)| async move {
+                (piece_index, self.validate(piece_index, piece_result).await)
+            },
+        ))))
+    }
 }
 
 // Generic wrapper methods
@@ -132,6 +1830,21 @@ where
     > {
         self.as_ref().get_pieces(piece_indices).await
     }
+
+    #[inline]
+    async fn get_segment<'a>(
+        &'a self,
+        segment_index: SegmentIndex,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        self.as_ref().get_segment(segment_index).await
+    }
+
+    #[inline]
+    async fn prefetch(&self, piece_indices: Vec<PieceIndex>) -> anyhow::Result<()> {
+        self.as_ref().prefetch(piece_indices).await
+    }
 }
 
 #[async_trait]
@@ -153,6 +1866,21 @@ where
     > {
         self.as_ref().get_pieces(piece_indices).await
     }
+
+    #[inline]
+    async fn get_segment<'a>(
+        &'a self,
+        segment_index: SegmentIndex,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        self.as_ref().get_segment(segment_index).await
+    }
+
+    #[inline]
+    async fn prefetch(&self, piece_indices: Vec<PieceIndex>) -> anyhow::Result<()> {
+        self.as_ref().prefetch(piece_indices).await
+    }
 }
 
 #[async_trait]
@@ -183,6 +1911,15 @@ where
             get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
         }
     }
+
+    #[inline]
+    async fn prefetch(&self, piece_indices: Vec<PieceIndex>) -> anyhow::Result<()> {
+        if let Some(piece_getter) = self.as_ref() {
+            piece_getter.prefetch(piece_indices).await
+        } else {
+            Ok(())
+        }
+    }
 }
 
 // Convenience methods, mainly used in testing
@@ -209,6 +1946,29 @@ impl PieceGetter for NewArchivedSegment {
     > {
         get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
     }
+
+    async fn get_segment<'a>(
+        &'a self,
+        segment_index: SegmentIndex,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        if segment_index != self.segment_header.segment_index() {
+            return self
+                .get_pieces(segment_index.segment_piece_indexes().to_vec())
+                .await;
+        }
+
+        // Return all the pieces in this segment without looking up each piece
+        let pieces = segment_index
+            .segment_piece_indexes()
+            .into_iter()
+            .zip(self.pieces.pieces())
+            .map(|(piece_index, piece)| (piece_index, Ok(Some(piece))))
+            .collect::<Vec<_>>();
+
+        Ok(Box::new(stream::iter(pieces)))
+    }
 }
 
 // Used for piece caches
@@ -254,8 +2014,46 @@ impl PieceGetter for Vec<(PieceIndex, Piece)> {
     }
 }
 
-/// A default implementation which gets each piece individually, using the `get_piece` async
-/// function.
+/// A piece getter that never has any pieces.
+///
+/// Useful for tests, and for disabled piece sources.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NullPieceGetter;
+
+#[async_trait]
+impl PieceGetter for NullPieceGetter {
+    async fn get_piece(&self, _piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        Ok(None)
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        Ok(Box::new(stream::iter(
+            unique_piece_indices(piece_indices).map(|piece_index| (piece_index, Ok(None))),
+        )))
+    }
+}
+
+/// Returns the unique piece indexes in `piece_indices`, in the order they first appear.
+pub fn unique_piece_indices<PieceIndices>(
+    piece_indices: PieceIndices,
+) -> impl Iterator<Item = PieceIndex> + Send
+where
+    PieceIndices: IntoIterator<Item = PieceIndex, IntoIter: Send>,
+{
+    let mut seen_indices = HashSet::new();
+
+    piece_indices
+        .into_iter()
+        .filter(move |piece_index| seen_indices.insert(*piece_index))
+}
+
+/// A default implementation which gets each unique piece individually, using the `get_piece`
+/// async function.
 ///
 /// This is mainly used for testing and caches. Most production implementations can fetch multiple
 /// pieces more efficiently.
@@ -273,10 +2071,241 @@ where
     Func: Fn(PieceIndex) -> Fut + Clone + Send + 'a,
     Fut: Future<Output = anyhow::Result<Option<Piece>>> + Send + Unpin + 'a,
 {
-    Ok(Box::new(Box::pin(stream::iter(piece_indices).then(
-        move |piece_index| {
+    Ok(Box::new(Box::pin(
+        stream::iter(unique_piece_indices(piece_indices)).then(move |piece_index| {
             let get_piece = get_piece.clone();
             async move { (piece_index, get_piece(piece_index).await) }
-        },
-    ))))
+        }),
+    )))
+}
+
+/// A default implementation which gets up to `concurrency` unique pieces at the same time, using
+/// the `get_piece` async function.
+///
+/// Pieces are yielded in the order their fetches complete, which can be different to the order of
+/// `piece_indices`.
+///
+/// Fetches only make progress while the stream is polled, so a slow consumer applies backpressure:
+/// at most `concurrency` pieces are fetched or buffered ahead of the consumer.
+#[expect(clippy::type_complexity, reason = "type matches trait signature")]
+pub fn get_pieces_with_concurrency<'a, PieceIndices, Func, Fut>(
+    // TODO: replace with AsyncFn(PieceIndex) -> anyhow::Result<Option<Piece>> once it stabilises
+    // https://github.com/rust-lang/rust/issues/62290
+    get_piece: Func,
+    piece_indices: PieceIndices,
+    concurrency: NonZeroUsize,
+) -> anyhow::Result<
+    Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+>
+where
+    PieceIndices: IntoIterator<Item = PieceIndex, IntoIter: Send> + Send + 'a,
+    Func: Fn(PieceIndex) -> Fut + Clone + Send + 'a,
+    Fut: Future<Output = anyhow::Result<Option<Piece>>> + Send + Unpin + 'a,
+{
+    Ok(Box::new(Box::pin(
+        stream::iter(unique_piece_indices(piece_indices))
+            .map(move |piece_index| {
+                let get_piece = get_piece.clone();
+                async move { (piece_index, get_piece(piece_index).await) }
+            })
+            .buffer_unordered(concurrency.get()),
+    )))
+}
+
+/// Gets unique pieces from `piece_getter` in batches of up to `batch_size` pieces, one batch at a
+/// time.
+///
+/// Each batch is fetched using `get_pieces`, which is more efficient than individual requests for
+/// sources with per-request overhead. If a batch request fails, each piece in that batch is yielded
+/// as an error.
+#[expect(clippy::type_complexity, reason = "type matches trait signature")]
+pub fn get_pieces_in_batches<'a, G, PieceIndices>(
+    piece_getter: &'a G,
+    piece_indices: PieceIndices,
+    batch_size: NonZeroUsize,
+) -> anyhow::Result<
+    Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+>
+where
+    G: PieceGetter + Send + Sync + ?Sized,
+    PieceIndices: IntoIterator<Item = PieceIndex, IntoIter: Send> + Send + 'a,
+{
+    let piece_indices = unique_piece_indices(piece_indices).collect::<Vec<_>>();
+    let batches = piece_indices
+        .chunks(batch_size.get())
+        .map(<[PieceIndex]>::to_vec)
+        .collect::<Vec<_>>();
+
+    Ok(Box::new(Box::pin(
+        stream::iter(batches)
+            .then(move |batch| async move {
+                match piece_getter.get_pieces(batch.clone()).await {
+                    Ok(pieces) => pieces.boxed(),
+                    Err(error) => {
+                        debug!(%error, batch_len = batch.len(), "Getting piece batch failed");
+
+                        let error = error.to_string();
+                        stream::iter(batch.into_iter().map(move |piece_index| {
+                            (
+                                piece_index,
+                                Err(anyhow::anyhow!(
+                                    "Getting batch containing piece {piece_index} failed: {error}"
+                                )),
+                            )
+                        }))
+                        .boxed()
+                    }
+                }
+            })
+            .flatten(),
+    )))
+}
+
+/// Stops `pieces` when `cancelled` completes.
+///
+/// After `cancelled` completes, no more pieces are yielded, and the stream ends. Piece fetches
+/// which haven't started yet are never started. The underlying piece getter might still complete
+/// fetches which were already in flight, but their pieces are dropped.
+///
+/// For example, `cancelled` can be a channel receiver, which completes when the client
+/// disconnects and the sender is dropped.
+#[expect(clippy::type_complexity, reason = "type matches trait signature")]
+pub fn cancellable_pieces<'a, Fut>(
+    pieces: Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    cancelled: Fut,
+) -> Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>
+where
+    Fut: Future<Output = ()> + Send + 'a,
+{
+    Box::new(Box::pin(pieces.take_until(cancelled)))
+}
+
+/// Stops `pieces` at `deadline`, yielding whatever pieces completed before then.
+///
+/// This is a deadline for the whole stream, rather than each piece. After the deadline, no more
+/// piece fetches are started, pieces still in flight are dropped, and the stream ends.
+#[expect(clippy::type_complexity, reason = "type matches trait signature")]
+pub fn pieces_until_deadline<'a>(
+    pieces: Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    deadline: tokio::time::Instant,
+) -> Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a> {
+    cancellable_pieces(pieces, tokio::time::sleep_until(deadline))
+}
+
+/// Calls `progress(fetched, total)` each time `pieces` yields a piece.
+///
+/// `fetched` is the number of pieces yielded so far, including missing and failed pieces. `total`
+/// is usually the number of unique piece indexes that were requested. This can be used to show
+/// download progress, like "47/128 pieces".
+#[expect(clippy::type_complexity, reason = "type matches trait signature")]
+pub fn pieces_with_progress<'a, F>(
+    pieces: Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    total: usize,
+    progress: F,
+) -> Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>
+where
+    F: Fn(usize, usize) + Send + 'a,
+{
+    let mut fetched = 0;
+
+    Box::new(Box::pin(pieces.inspect(move |_piece| {
+        fetched += 1;
+        progress(fetched, total);
+    })))
+}
+
+/// A piece stream which tolerates up to `max_errors` failed pieces.
+///
+/// Successful pieces, missing pieces, and failed pieces are yielded as they arrive. If more than
+/// `max_errors` pieces fail, the stream ends early, without yielding the failure which exceeded the
+/// threshold. After the stream ends, the caller can check how many pieces succeeded or failed, and
+/// whether the threshold was exceeded.
+///
+/// This is useful for object reconstruction, which can often succeed with erasure coding even if
+/// some pieces fail.
+pub struct ErrorThresholdPieces<'a> {
+    pieces: Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    max_errors: usize,
+    successes: usize,
+    errors: usize,
+}
+
+impl fmt::Debug for ErrorThresholdPieces<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErrorThresholdPieces")
+            .field("max_errors", &self.max_errors)
+            .field("successes", &self.successes)
+            .field("errors", &self.errors)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> ErrorThresholdPieces<'a> {
+    /// Wraps `pieces`, ending the stream early if more than `max_errors` pieces fail.
+    #[expect(clippy::type_complexity, reason = "type matches trait signature")]
+    pub fn new(
+        pieces: Box<
+            dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a,
+        >,
+        max_errors: usize,
+    ) -> Self {
+        Self {
+            pieces,
+            max_errors,
+            successes: 0,
+            errors: 0,
+        }
+    }
+
+    /// Returns the number of pieces which were found so far.
+    pub fn successes(&self) -> usize {
+        self.successes
+    }
+
+    /// Returns the number of pieces which failed so far, including the failure which exceeded the
+    /// threshold.
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Returns `true` if more than `max_errors` pieces failed, and the stream ended early.
+    pub fn threshold_exceeded(&self) -> bool {
+        self.errors > self.max_errors
+    }
+}
+
+impl Stream for ErrorThresholdPieces<'_> {
+    type Item = (PieceIndex, anyhow::Result<Option<Piece>>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.threshold_exceeded() {
+            return Poll::Ready(None);
+        }
+
+        let Some((piece_index, piece_result)) = ready!(self.pieces.poll_next_unpin(cx)) else {
+            return Poll::Ready(None);
+        };
+
+        match &piece_result {
+            Ok(Some(_piece)) => self.successes += 1,
+            Ok(None) => {}
+            Err(error) => {
+                self.errors += 1;
+
+                if self.threshold_exceeded() {
+                    debug!(
+                        %piece_index,
+                        %error,
+                        errors = self.errors,
+                        successes = self.successes,
+                        "Too many piece errors, stopping piece stream"
+                    );
+
+                    return Poll::Ready(None);
+                }
+            }
+        }
+
+        Poll::Ready(Some((piece_index, piece_result)))
+    }
 }
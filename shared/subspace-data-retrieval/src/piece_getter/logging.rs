@@ -0,0 +1,150 @@
+//! Logging for piece getters.
+
+use crate::piece_getter::PieceGetter;
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use std::time::{Duration, Instant};
+use subspace_core_primitives::pieces::{Piece, PieceIndex};
+use tracing::Level;
+use tracing::level_filters::{LevelFilter, STATIC_MAX_LEVEL};
+
+/// Logs an event at a `Level` which is only known at runtime.
+///
+/// `tracing` macros need a constant level, so this expands to one macro call for each level.
+macro_rules! event_at_level {
+    ($level:expr, $($args:tt)+) => {
+        match $level {
+            Level::ERROR => tracing::error!($($args)+),
+            Level::WARN => tracing::warn!($($args)+),
+            Level::INFO => tracing::info!($($args)+),
+            Level::DEBUG => tracing::debug!($($args)+),
+            // Level::TRACE is the only remaining level
+            _ => tracing::trace!($($args)+),
+        }
+    };
+}
+
+/// The levels used to log the outcomes of piece requests.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PieceLogLevels {
+    /// The level used when a piece is found
+    pub hit: Level,
+    /// The level used when a piece is not found
+    pub miss: Level,
+    /// The level used when trying to get a piece caused an error
+    pub error: Level,
+}
+
+impl Default for PieceLogLevels {
+    fn default() -> Self {
+        Self {
+            hit: Level::TRACE,
+            miss: Level::DEBUG,
+            error: Level::WARN,
+        }
+    }
+}
+
+impl PieceLogLevels {
+    /// Returns true if any of the levels are enabled by the current subscriber.
+    fn any_enabled(&self) -> bool {
+        [self.hit, self.miss, self.error]
+            .into_iter()
+            .any(|level| level <= STATIC_MAX_LEVEL && level <= LevelFilter::current())
+    }
+}
+
+/// A piece getter that logs the piece index, outcome, and latency of each piece request.
+///
+/// Each outcome is logged at the level configured in `PieceLogLevels`. If none of those levels
+/// are enabled, requests are passed directly to the inner piece getter, without measuring latency.
+///
+/// For `get_pieces`, the latency of each piece is measured from the start of the request.
+#[derive(Debug)]
+pub struct LoggingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    inner: G,
+    levels: PieceLogLevels,
+}
+
+impl<G> LoggingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    /// Creates a new piece getter, which logs requests to `inner` at `levels`.
+    pub fn new(inner: G, levels: PieceLogLevels) -> Self {
+        Self { inner, levels }
+    }
+
+    /// Logs the outcome and latency of a piece request.
+    fn log(
+        &self,
+        piece_index: PieceIndex,
+        piece_result: &anyhow::Result<Option<Piece>>,
+        latency: Duration,
+    ) {
+        match piece_result {
+            Ok(Some(_)) => event_at_level!(
+                self.levels.hit,
+                %piece_index,
+                ?latency,
+                result = "hit",
+                "Piece found"
+            ),
+            Ok(None) => event_at_level!(
+                self.levels.miss,
+                %piece_index,
+                ?latency,
+                result = "miss",
+                "Piece not found"
+            ),
+            Err(error) => event_at_level!(
+                self.levels.error,
+                %piece_index,
+                ?latency,
+                result = "error",
+                %error,
+                "Failed to get piece"
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl<G> PieceGetter for LoggingPieceGetter<G>
+where
+    G: PieceGetter + Send + Sync,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        if !self.levels.any_enabled() {
+            return self.inner.get_piece(piece_index).await;
+        }
+
+        let start = Instant::now();
+        let piece_result = self.inner.get_piece(piece_index).await;
+        self.log(piece_index, &piece_result, start.elapsed());
+
+        piece_result
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        if !self.levels.any_enabled() {
+            return self.inner.get_pieces(piece_indices).await;
+        }
+
+        let start = Instant::now();
+        let pieces = self.inner.get_pieces(piece_indices).await?;
+
+        Ok(Box::new(pieces.map(move |(piece_index, piece_result)| {
+            self.log(piece_index, &piece_result, start.elapsed());
+            (piece_index, piece_result)
+        })))
+    }
+}
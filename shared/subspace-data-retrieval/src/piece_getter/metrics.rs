@@ -0,0 +1,235 @@
+//! Metrics for piece getters.
+
+use crate::piece_getter::PieceGetter;
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+#[cfg(feature = "prometheus")]
+use prometheus_client::metrics::counter::Counter;
+#[cfg(feature = "prometheus")]
+use prometheus_client::metrics::histogram::{Histogram, exponential_buckets};
+#[cfg(feature = "prometheus")]
+use prometheus_client::registry::{Registry, Unit};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use subspace_core_primitives::pieces::{Piece, PieceIndex};
+
+/// Callbacks for the outcomes of piece requests.
+///
+/// All the methods do nothing by default.
+pub trait PieceGetterMetrics: fmt::Debug + Send + Sync {
+    /// Called when a piece is found.
+    fn on_success(&self) {}
+
+    /// Called when a piece is not found.
+    fn on_miss(&self) {}
+
+    /// Called when trying to get a piece caused an error.
+    fn on_error(&self) {}
+
+    /// Called with the time taken to get a piece, regardless of the outcome.
+    fn on_latency(&self, _latency: Duration) {}
+}
+
+/// Piece getter metrics which are ignored.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoopPieceGetterMetrics;
+
+impl PieceGetterMetrics for NoopPieceGetterMetrics {}
+
+/// Piece getter metrics which are reported to Prometheus.
+#[cfg(feature = "prometheus")]
+#[derive(Debug)]
+pub struct PrometheusPieceGetterMetrics {
+    piece_get_success: Counter<u64, AtomicU64>,
+    piece_get_miss: Counter<u64, AtomicU64>,
+    piece_get_error: Counter<u64, AtomicU64>,
+    piece_get_time: Histogram,
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusPieceGetterMetrics {
+    /// Create new instance, with metrics names starting with `prefix`
+    pub fn new(registry: &mut Registry, prefix: &str) -> Self {
+        let registry = registry.sub_registry_with_prefix(prefix);
+
+        let piece_get_success = Counter::default();
+        registry.register_with_unit(
+            "piece_get_success",
+            "Piece get success",
+            Unit::Other("Requests".to_string()),
+            piece_get_success.clone(),
+        );
+
+        let piece_get_miss = Counter::default();
+        registry.register_with_unit(
+            "piece_get_miss",
+            "Piece get miss",
+            Unit::Other("Requests".to_string()),
+            piece_get_miss.clone(),
+        );
+
+        let piece_get_error = Counter::default();
+        registry.register_with_unit(
+            "piece_get_error",
+            "Piece get error",
+            Unit::Other("Requests".to_string()),
+            piece_get_error.clone(),
+        );
+
+        let piece_get_time = Histogram::new(exponential_buckets(0.001, 2.0, 15));
+        registry.register_with_unit(
+            "piece_get_time",
+            "Piece get time",
+            Unit::Seconds,
+            piece_get_time.clone(),
+        );
+
+        Self {
+            piece_get_success,
+            piece_get_miss,
+            piece_get_error,
+            piece_get_time,
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl PieceGetterMetrics for PrometheusPieceGetterMetrics {
+    fn on_success(&self) {
+        self.piece_get_success.inc();
+    }
+
+    fn on_miss(&self) {
+        self.piece_get_miss.inc();
+    }
+
+    fn on_error(&self) {
+        self.piece_get_error.inc();
+    }
+
+    fn on_latency(&self, latency: Duration) {
+        self.piece_get_time.observe(latency.as_secs_f64());
+    }
+}
+
+/// A snapshot of the outcomes and latency of the piece requests to an `InstrumentedPieceGetter`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PieceGetterStats {
+    /// The number of pieces which were found
+    pub hits: u64,
+    /// The number of pieces which were not found
+    pub misses: u64,
+    /// The number of piece requests which caused an error
+    pub errors: u64,
+    /// The total time taken by all piece requests, regardless of the outcome
+    pub total_latency: Duration,
+}
+
+/// A piece getter that reports the outcome and latency of each piece request to `metrics`.
+///
+/// For `get_pieces`, the latency of each piece is measured from the start of the request.
+/// Request totals are also available without any metrics backend, using `stats()`.
+#[derive(Debug)]
+pub struct InstrumentedPieceGetter<G, M = NoopPieceGetterMetrics>
+where
+    G: PieceGetter + Send + Sync,
+    M: PieceGetterMetrics,
+{
+    inner: G,
+    metrics: M,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+    total_latency_nanos: AtomicU64,
+}
+
+impl<G, M> InstrumentedPieceGetter<G, M>
+where
+    G: PieceGetter + Send + Sync,
+    M: PieceGetterMetrics,
+{
+    /// Creates a new piece getter, which reports requests to `inner` using `metrics`.
+    pub fn new(inner: G, metrics: M) -> Self {
+        Self {
+            inner,
+            metrics,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            total_latency_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the metrics for this piece getter.
+    pub fn metrics(&self) -> &M {
+        &self.metrics
+    }
+
+    /// Returns a snapshot of the outcomes and latency of all the piece requests so far.
+    ///
+    /// Each counter is read separately, so requests which finish during the snapshot might only
+    /// be partly included.
+    pub fn stats(&self) -> PieceGetterStats {
+        PieceGetterStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            total_latency: Duration::from_nanos(self.total_latency_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Reports the outcome and latency of a piece request.
+    fn record(&self, piece_result: &anyhow::Result<Option<Piece>>, start: Instant) {
+        match piece_result {
+            Ok(Some(_)) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                self.metrics.on_success();
+            }
+            Ok(None) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                self.metrics.on_miss();
+            }
+            Err(_) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                self.metrics.on_error();
+            }
+        }
+
+        let latency = start.elapsed();
+        let latency_nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.total_latency_nanos
+            .fetch_add(latency_nanos, Ordering::Relaxed);
+        self.metrics.on_latency(latency);
+    }
+}
+
+#[async_trait]
+impl<G, M> PieceGetter for InstrumentedPieceGetter<G, M>
+where
+    G: PieceGetter + Send + Sync,
+    M: PieceGetterMetrics,
+{
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        let start = Instant::now();
+        let piece_result = self.inner.get_piece(piece_index).await;
+        self.record(&piece_result, start);
+
+        piece_result
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let start = Instant::now();
+        let pieces = self.inner.get_pieces(piece_indices).await?;
+
+        Ok(Box::new(pieces.map(move |(piece_index, piece_result)| {
+            self.record(&piece_result, start);
+            (piece_index, piece_result)
+        })))
+    }
+}
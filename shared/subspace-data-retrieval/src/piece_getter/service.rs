@@ -0,0 +1,63 @@
+//! A `tower::Service` adapter for piece getters.
+
+use crate::piece_getter::PieceGetter;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use subspace_core_primitives::pieces::{Piece, PieceIndex};
+use tower::Service;
+
+/// A `tower::Service` which gets pieces from a piece getter.
+///
+/// Each call gets a single piece using `PieceGetter::get_piece`. The service is always ready, use
+/// `tower` layers to limit concurrency, or add timeouts or retries.
+#[derive(Debug)]
+pub struct PieceGetterService<G>
+where
+    G: PieceGetter + Send + Sync + 'static,
+{
+    piece_getter: Arc<G>,
+}
+
+impl<G> Clone for PieceGetterService<G>
+where
+    G: PieceGetter + Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            piece_getter: Arc::clone(&self.piece_getter),
+        }
+    }
+}
+
+impl<G> PieceGetterService<G>
+where
+    G: PieceGetter + Send + Sync + 'static,
+{
+    /// Creates a new service, which gets pieces from `piece_getter`.
+    pub fn new(piece_getter: G) -> Self {
+        Self {
+            piece_getter: Arc::new(piece_getter),
+        }
+    }
+}
+
+impl<G> Service<PieceIndex> for PieceGetterService<G>
+where
+    G: PieceGetter + Send + Sync + 'static,
+{
+    type Response = Option<Piece>;
+    type Error = anyhow::Error;
+    type Future = BoxFuture<'static, anyhow::Result<Option<Piece>>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, piece_index: PieceIndex) -> Self::Future {
+        let piece_getter = Arc::clone(&self.piece_getter);
+
+        async move { piece_getter.get_piece(piece_index).await }.boxed()
+    }
+}
@@ -0,0 +1,1835 @@
+//! Tests for piece getters.
+
+use super::*;
+use futures::FutureExt;
+use futures::channel::oneshot;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::time::Duration;
+use subspace_core_primitives::hashes::Blake3Hash;
+use subspace_core_primitives::segments::{
+    ArchivedBlockProgress, ArchivedHistorySegment, LastArchivedBlock, SegmentCommitment,
+    SegmentHeader,
+};
+use tokio::time::Instant;
+use tracing::Level;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Returns a piece with every byte set to the low byte of the piece index, so pieces for
+/// different indexes are usually different.
+fn test_piece(piece_index: PieceIndex) -> Piece {
+    Piece::try_from(vec![u64::from(piece_index) as u8; Piece::SIZE])
+        .expect("piece is the correct size; qed")
+}
+
+/// Converts the supplied numbers to a list of `PieceIndex`.
+fn indexes(piece_indexes: impl IntoIterator<Item = u64>) -> Vec<PieceIndex> {
+    piece_indexes.into_iter().map(PieceIndex::from).collect()
+}
+
+/// A piece getter that returns a test piece for every index, after waiting for `delay`.
+#[derive(Copy, Clone, Debug)]
+struct DelayPieceGetter {
+    delay: Duration,
+}
+
+#[async_trait]
+impl PieceGetter for DelayPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        tokio::time::sleep(self.delay).await;
+
+        Ok(Some(test_piece(piece_index)))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+/// A piece getter that returns a test piece for every index, after waiting for a delay that is
+/// shorter for larger indexes. `get_pieces` requests all pieces at the same time, so pieces
+/// complete in reverse index order.
+#[derive(Copy, Clone, Debug)]
+struct ReversePieceGetter;
+
+#[async_trait]
+impl PieceGetter for ReversePieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        let delay = 1000_u64.saturating_sub(u64::from(piece_index) * 10);
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+
+        Ok(Some(test_piece(piece_index)))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        let concurrency = NonZeroUsize::new(piece_indices.len().max(1)).unwrap();
+        get_pieces_with_concurrency(
+            |piece_index| self.get_piece(piece_index),
+            piece_indices,
+            concurrency,
+        )
+    }
+}
+
+/// Sets a flag when it is dropped.
+#[derive(Debug)]
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A piece getter that returns a default piece after a long delay, and records if the request was
+/// dropped or completed.
+#[derive(Debug, Default)]
+struct SlowPieceGetter {
+    dropped: Arc<AtomicBool>,
+    completed: AtomicBool,
+}
+
+#[async_trait]
+impl PieceGetter for SlowPieceGetter {
+    async fn get_piece(&self, _piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        let _drop_flag = DropFlag(self.dropped.clone());
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        self.completed.store(true, Ordering::SeqCst);
+
+        Ok(Some(Piece::default()))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+/// A piece getter that never returns a piece.
+#[derive(Copy, Clone, Debug)]
+struct PendingPieceGetter;
+
+#[async_trait]
+impl PieceGetter for PendingPieceGetter {
+    async fn get_piece(&self, _piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        std::future::pending().await
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+/// A piece getter that fails the first `failures` requests for each piece, then succeeds.
+#[derive(Debug, Default)]
+struct FlakyPieceGetter {
+    failures: u32,
+    piece_found: bool,
+    attempts: Mutex<HashMap<PieceIndex, u32>>,
+}
+
+impl FlakyPieceGetter {
+    fn new(failures: u32, piece_found: bool) -> Self {
+        Self {
+            failures,
+            piece_found,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the number of requests for `piece_index`.
+    fn attempts(&self, piece_index: PieceIndex) -> u32 {
+        self.attempts
+            .lock()
+            .unwrap()
+            .get(&piece_index)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl PieceGetter for FlakyPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        let attempts = {
+            let mut attempts = self.attempts.lock().unwrap();
+            let attempts = attempts.entry(piece_index).or_default();
+            *attempts += 1;
+            *attempts
+        };
+
+        if attempts <= self.failures {
+            return Err(anyhow::anyhow!(
+                "piece {piece_index} failed on attempt {attempts}"
+            ));
+        }
+
+        Ok(self.piece_found.then(|| test_piece(piece_index)))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+/// A piece getter that returns a test piece for every index, and records the requested indexes
+/// and request times.
+#[derive(Debug, Default)]
+struct RecordingPieceGetter {
+    requested: Mutex<Vec<(PieceIndex, Instant)>>,
+}
+
+impl RecordingPieceGetter {
+    /// Returns the requested piece indexes, in request order.
+    fn requested(&self) -> Vec<PieceIndex> {
+        self.requested
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(piece_index, _)| *piece_index)
+            .collect()
+    }
+
+    /// Returns the request times, in request order.
+    fn request_times(&self) -> Vec<Instant> {
+        self.requested
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, request_time)| *request_time)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl PieceGetter for RecordingPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        self.requested
+            .lock()
+            .unwrap()
+            .push((piece_index, Instant::now()));
+
+        Ok(Some(test_piece(piece_index)))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+/// Piece getter metrics which count each callback.
+#[derive(Debug, Default)]
+struct CountingMetrics {
+    successes: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+    latencies: AtomicU64,
+}
+
+impl CountingMetrics {
+    /// Returns the number of successes, misses, errors, and latencies.
+    fn counts(&self) -> (u64, u64, u64, u64) {
+        (
+            self.successes.load(Ordering::SeqCst),
+            self.misses.load(Ordering::SeqCst),
+            self.errors.load(Ordering::SeqCst),
+            self.latencies.load(Ordering::SeqCst),
+        )
+    }
+}
+
+impl PieceGetterMetrics for CountingMetrics {
+    fn on_success(&self) {
+        self.successes.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_miss(&self) {
+        self.misses.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_error(&self) {
+        self.errors.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_latency(&self, _latency: Duration) {
+        self.latencies.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// A fast backoff for tests.
+fn test_backoff() -> ExponentialBackoff {
+    ExponentialBackoff {
+        initial_interval: Duration::from_millis(10),
+        max_interval: Duration::from_millis(100),
+        max_elapsed_time: None,
+        ..ExponentialBackoff::default()
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn get_pieces_with_concurrency_is_faster() {
+    let piece_getter = DelayPieceGetter {
+        delay: Duration::from_millis(100),
+    };
+    let piece_indices = indexes(0..8);
+
+    let mut elapsed = Vec::new();
+    for concurrency in [1, 4, 8] {
+        let start = Instant::now();
+        let pieces = get_pieces_with_concurrency(
+            |piece_index| piece_getter.get_piece(piece_index),
+            piece_indices.clone(),
+            NonZeroUsize::new(concurrency).unwrap(),
+        )
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+        elapsed.push(start.elapsed());
+
+        // Every piece is returned exactly once, even if the order is different
+        assert_eq!(pieces.len(), piece_indices.len());
+        let returned_indices = pieces
+            .iter()
+            .map(|(piece_index, _)| *piece_index)
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            returned_indices,
+            piece_indices.iter().copied().collect::<HashSet<_>>()
+        );
+        for (piece_index, piece) in pieces {
+            assert_eq!(piece.unwrap(), Some(test_piece(piece_index)));
+        }
+    }
+
+    // Sequential fetches wait for every delay, concurrent fetches overlap their delays
+    assert!(elapsed[0] >= Duration::from_millis(800));
+    assert!(elapsed[1] >= Duration::from_millis(200));
+    assert!(elapsed[1] < Duration::from_millis(400));
+    assert!(elapsed[2] >= Duration::from_millis(100));
+    assert!(elapsed[2] < Duration::from_millis(200));
+}
+
+#[tokio::test(start_paused = true)]
+async fn get_pieces_with_concurrency_applies_backpressure() {
+    let piece_getter = RecordingPieceGetter::default();
+    let concurrency = 4;
+
+    let mut pieces = get_pieces_with_concurrency(
+        |piece_index| piece_getter.get_piece(piece_index),
+        indexes(0..100),
+        NonZeroUsize::new(concurrency).unwrap(),
+    )
+    .unwrap();
+
+    // A stalled consumer stops new fetches, so only `concurrency` pieces are fetched ahead
+    for consumed in 1..=10 {
+        pieces.next().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let requested = piece_getter.requested().len();
+        assert!(
+            requested <= consumed + concurrency,
+            "{requested} fetches after consuming {consumed} pieces"
+        );
+    }
+
+    drop(pieces);
+    assert!(piece_getter.requested().len() < 100);
+}
+
+#[tokio::test(start_paused = true)]
+async fn timeout_piece_getter() {
+    let timeout = Duration::from_millis(100);
+    let piece_getter = TimeoutPieceGetter::new(PendingPieceGetter, timeout);
+
+    let start = Instant::now();
+    let result = piece_getter.get_piece(PieceIndex::ZERO).await;
+    assert!(result.is_err(), "{result:?}");
+    assert!(start.elapsed() >= timeout);
+
+    // Each piece gets its own timeout
+    let pieces = piece_getter
+        .get_pieces(indexes(0..3))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 3);
+    for (_piece_index, result) in pieces {
+        assert!(result.is_err(), "{result:?}");
+    }
+
+    // Fast pieces are returned before the timeout
+    let piece_getter = TimeoutPieceGetter::new(
+        DelayPieceGetter {
+            delay: Duration::from_millis(10),
+        },
+        timeout,
+    );
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ONE).await.unwrap(),
+        Some(test_piece(PieceIndex::ONE))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn retry_piece_getter() {
+    // Enough attempts to succeed
+    let piece_getter = RetryPieceGetter::new(
+        FlakyPieceGetter::new(2, true),
+        NonZeroU32::new(3).unwrap(),
+        test_backoff(),
+    );
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(test_piece(PieceIndex::ZERO))
+    );
+    assert_eq!(piece_getter.inner.attempts(PieceIndex::ZERO), 3);
+
+    // Each piece in get_pieces is retried independently
+    let pieces = piece_getter
+        .get_pieces(indexes(1..4))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 3);
+    for (piece_index, piece) in pieces {
+        assert_eq!(piece.unwrap(), Some(test_piece(piece_index)));
+        assert_eq!(piece_getter.inner.attempts(piece_index), 3);
+    }
+
+    // Not enough attempts to succeed
+    let piece_getter = RetryPieceGetter::new(
+        FlakyPieceGetter::new(2, true),
+        NonZeroU32::new(2).unwrap(),
+        test_backoff(),
+    );
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+    assert_eq!(piece_getter.inner.attempts(PieceIndex::ZERO), 2);
+
+    let pieces = piece_getter
+        .get_pieces(indexes(1..4))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 3);
+    for (piece_index, piece) in pieces {
+        assert!(piece.is_err(), "{piece:?}");
+        assert_eq!(piece_getter.inner.attempts(piece_index), 2);
+    }
+
+    // Missing pieces are not retried
+    let piece_getter = RetryPieceGetter::new(
+        FlakyPieceGetter::new(0, false),
+        NonZeroU32::new(3).unwrap(),
+        test_backoff(),
+    );
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        None
+    );
+    assert_eq!(piece_getter.inner.attempts(PieceIndex::ZERO), 1);
+}
+
+#[tokio::test]
+async fn fallback_list_piece_getter() {
+    let first_source = Arc::new(vec![(PieceIndex::ONE, test_piece(PieceIndex::ONE))]);
+    let second_source = Arc::new(RecordingPieceGetter::default());
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> =
+        vec![first_source, second_source.clone()];
+    let piece_getter = FallbackListPieceGetter::new(sources);
+
+    let pieces = piece_getter
+        .get_pieces(indexes(0..2))
+        .await
+        .unwrap()
+        .collect::<HashMap<_, _>>()
+        .await;
+    assert_eq!(pieces.len(), 2);
+    for (piece_index, piece) in pieces {
+        assert_eq!(piece.unwrap(), Some(test_piece(piece_index)));
+    }
+
+    // The second source only fills the gap
+    assert_eq!(second_source.requested(), vec![PieceIndex::ZERO]);
+
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ONE).await.unwrap(),
+        Some(test_piece(PieceIndex::ONE))
+    );
+    assert_eq!(second_source.requested(), vec![PieceIndex::ZERO]);
+
+    // Pieces missing from every source return the result of the last source
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> = vec![
+        Arc::new(FlakyPieceGetter::new(0, false)),
+        Arc::new(FlakyPieceGetter::new(1, false)),
+    ];
+    let piece_getter = FallbackListPieceGetter::new(sources);
+    let pieces = piece_getter
+        .get_pieces(indexes(0..2))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 2);
+    for (_piece_index, piece) in pieces {
+        assert!(piece.is_err(), "{piece:?}");
+    }
+
+    // A source which fails the whole request is an error, not a missing piece
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> = vec![Arc::new(BatchPieceGetter {
+        failing_index: Some(PieceIndex::ZERO),
+        ..BatchPieceGetter::default()
+    })];
+    let piece_getter = FallbackListPieceGetter::new(sources);
+    let pieces = piece_getter
+        .get_pieces(indexes(0..2))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 2);
+    for (_piece_index, piece) in pieces {
+        assert!(piece.is_err(), "{piece:?}");
+    }
+}
+
+#[tokio::test]
+async fn caching_piece_getter_hits_and_misses() {
+    let piece_getter =
+        CachingPieceGetter::new(RecordingPieceGetter::default(), NonZeroU32::new(4).unwrap());
+
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(test_piece(PieceIndex::ZERO))
+    );
+    assert_eq!((piece_getter.hits(), piece_getter.misses()), (0, 1));
+
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(test_piece(PieceIndex::ZERO))
+    );
+    assert_eq!((piece_getter.hits(), piece_getter.misses()), (1, 1));
+    assert_eq!(piece_getter.inner.requested(), indexes([0]));
+
+    // Hits are served from the cache, and only misses are forwarded to the inner piece getter
+    let pieces = piece_getter
+        .get_pieces(indexes(0..3))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 3);
+    for (piece_index, piece) in pieces {
+        assert_eq!(piece.unwrap(), Some(test_piece(piece_index)));
+    }
+    assert_eq!((piece_getter.hits(), piece_getter.misses()), (2, 3));
+    assert_eq!(piece_getter.inner.requested(), indexes([0, 1, 2]));
+
+    // Missing pieces are not cached
+    let piece_getter =
+        CachingPieceGetter::new(FlakyPieceGetter::new(0, false), NonZeroU32::new(4).unwrap());
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        None
+    );
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        None
+    );
+    assert_eq!((piece_getter.hits(), piece_getter.misses()), (0, 2));
+    assert_eq!(piece_getter.inner.attempts(PieceIndex::ZERO), 2);
+}
+
+#[tokio::test]
+async fn caching_piece_getter_eviction() {
+    let piece_getter =
+        CachingPieceGetter::new(RecordingPieceGetter::default(), NonZeroU32::new(2).unwrap());
+
+    let pieces = piece_getter
+        .get_pieces(indexes(0..3))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 3);
+    assert_eq!((piece_getter.hits(), piece_getter.misses()), (0, 3));
+
+    // The least recently used piece was evicted
+    piece_getter.get_piece(PieceIndex::from(2)).await.unwrap();
+    piece_getter.get_piece(PieceIndex::ONE).await.unwrap();
+    assert_eq!((piece_getter.hits(), piece_getter.misses()), (2, 3));
+
+    piece_getter.get_piece(PieceIndex::ZERO).await.unwrap();
+    assert_eq!((piece_getter.hits(), piece_getter.misses()), (2, 4));
+    assert_eq!(piece_getter.inner.requested(), indexes([0, 1, 2, 0]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn coalescing_piece_getter() {
+    let piece_getter = CoalescingPieceGetter::new(InstrumentedPieceGetter::new(
+        DelayPieceGetter {
+            delay: Duration::from_millis(100),
+        },
+        CountingMetrics::default(),
+    ));
+
+    // Concurrent requests for the same piece share a single inner request
+    let (first, second) = futures::join!(
+        piece_getter.get_piece(PieceIndex::ZERO),
+        piece_getter.get_piece(PieceIndex::ZERO),
+    );
+    assert_eq!(first.unwrap(), Some(test_piece(PieceIndex::ZERO)));
+    assert_eq!(second.unwrap(), Some(test_piece(PieceIndex::ZERO)));
+    assert_eq!(piece_getter.inner.metrics().counts().0, 1);
+
+    // Later requests aren't cached
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_ok());
+    assert_eq!(piece_getter.inner.metrics().counts().0, 2);
+
+    // Cancelling the first request doesn't block other requests
+    assert!(
+        tokio::time::timeout(
+            Duration::from_millis(10),
+            piece_getter.get_piece(PieceIndex::ONE)
+        )
+        .await
+        .is_err()
+    );
+    let (piece, pieces) = futures::join!(piece_getter.get_piece(PieceIndex::ONE), async {
+        piece_getter
+            .get_pieces(indexes(0..2))
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await
+    },);
+    assert_eq!(piece.unwrap(), Some(test_piece(PieceIndex::ONE)));
+    assert_eq!(pieces.len(), 2);
+    assert_eq!(piece_getter.inner.metrics().counts().0, 4);
+
+    // Large requests are limited to the maximum concurrency
+    let piece_getter = CoalescingPieceGetter::with_max_concurrency(
+        SlowdownPieceGetter::default(),
+        NonZeroUsize::new(3).unwrap(),
+    );
+    let pieces = piece_getter
+        .get_pieces(indexes(0..10))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 10);
+    assert_eq!(piece_getter.inner.max_in_flight.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn filesystem_piece_getter() {
+    let directory = tempfile::tempdir().unwrap();
+    let piece_getter = FilesystemPieceGetter::new(directory.path());
+
+    for piece_index in indexes(0..3) {
+        std::fs::write(
+            piece_getter.piece_path(piece_index),
+            test_piece(piece_index),
+        )
+        .unwrap();
+    }
+    // A corrupt piece file
+    std::fs::write(piece_getter.piece_path(PieceIndex::from(3)), [0; 10]).unwrap();
+
+    let pieces = piece_getter
+        .get_pieces(indexes(0..3))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 3);
+    for (piece_index, piece) in pieces {
+        assert_eq!(piece.unwrap(), Some(test_piece(piece_index)));
+    }
+
+    let result = piece_getter.get_piece(PieceIndex::from(3)).await;
+    assert!(result.is_err(), "{result:?}");
+
+    // A missing piece file
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::from(4)).await.unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn get_segment() {
+    let segment_index = SegmentIndex::ONE;
+    let archived_segment = NewArchivedSegment {
+        segment_header: SegmentHeader::V0 {
+            segment_index,
+            segment_commitment: SegmentCommitment::default(),
+            prev_segment_header_hash: Blake3Hash::default(),
+            last_archived_block: LastArchivedBlock {
+                number: 1,
+                archived_progress: ArchivedBlockProgress::Complete,
+            },
+        },
+        pieces: ArchivedHistorySegment::default(),
+    };
+
+    let pieces = archived_segment
+        .get_segment(segment_index)
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), ArchivedHistorySegment::NUM_PIECES);
+    for ((piece_index, piece), expected_piece_index) in pieces
+        .into_iter()
+        .zip(segment_index.segment_piece_indexes())
+    {
+        assert_eq!(piece_index, expected_piece_index);
+        assert_eq!(piece.unwrap(), Some(Piece::default()));
+    }
+
+    // Other segments aren't available
+    let pieces = archived_segment
+        .get_segment(SegmentIndex::ZERO)
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), ArchivedHistorySegment::NUM_PIECES);
+    for (_piece_index, piece) in pieces {
+        assert_eq!(piece.unwrap(), None);
+    }
+
+    // The default implementation requests every piece in the segment
+    let piece_getter = RecordingPieceGetter::default();
+    let pieces = piece_getter
+        .get_segment(segment_index)
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), ArchivedHistorySegment::NUM_PIECES);
+    assert_eq!(
+        piece_getter.requested(),
+        segment_index.segment_piece_indexes().to_vec()
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn ordered_piece_getter() {
+    let piece_indices = indexes(0..8);
+
+    // The inner piece getter completes pieces in reverse order
+    let pieces = ReversePieceGetter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .map(|(piece_index, _)| piece_index)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces, indexes((0..8).rev()));
+
+    let piece_getter = OrderedPieceGetter::new(ReversePieceGetter, NonZeroUsize::new(8).unwrap());
+    let pieces = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(
+        pieces
+            .iter()
+            .map(|(piece_index, _)| *piece_index)
+            .collect::<Vec<_>>(),
+        piece_indices
+    );
+    for (piece_index, piece) in pieces {
+        assert_eq!(piece.unwrap(), Some(test_piece(piece_index)));
+    }
+
+    // When the buffer is full, the earliest buffered piece is yielded out of order
+    let piece_getter = OrderedPieceGetter::new(ReversePieceGetter, NonZeroUsize::new(2).unwrap());
+    let pieces = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .map(|(piece_index, _)| piece_index)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces, indexes([6, 5, 4, 3, 2, 1, 0, 7]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn get_pieces_vec_is_in_request_order() {
+    // The piece getter completes pieces in reverse order, and duplicates are removed
+    let pieces = ReversePieceGetter
+        .get_pieces_vec(indexes([3, 1, 2, 1, 0]))
+        .await
+        .unwrap();
+    assert_eq!(
+        pieces,
+        indexes([3, 1, 2, 0])
+            .into_iter()
+            .map(|piece_index| (piece_index, Some(test_piece(piece_index))))
+            .collect::<Vec<_>>()
+    );
+
+    // Any failed piece fails the whole request
+    let piece_getter = FailingPieceGetter {
+        failing_indexes: HashSet::from([PieceIndex::ONE]),
+    };
+    assert!(piece_getter.get_pieces_vec(indexes(0..3)).await.is_err());
+}
+
+#[tokio::test]
+async fn quorum_piece_getter() {
+    let honest_source = Arc::new(vec![(PieceIndex::ZERO, test_piece(PieceIndex::ZERO))]);
+    let lying_source = Arc::new(vec![(PieceIndex::ZERO, test_piece(PieceIndex::ONE))]);
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> =
+        vec![honest_source.clone(), lying_source, honest_source];
+
+    // Two sources agree on the piece
+    let piece_getter = QuorumPieceGetter::new(sources.clone(), NonZeroUsize::new(2).unwrap());
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(test_piece(PieceIndex::ZERO))
+    );
+    assert_eq!(piece_getter.get_piece(PieceIndex::ONE).await.unwrap(), None);
+
+    // But not three
+    let piece_getter = QuorumPieceGetter::new(sources, NonZeroUsize::new(3).unwrap());
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+    assert_eq!(piece_getter.get_piece(PieceIndex::ONE).await.unwrap(), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn racing_piece_getter() {
+    let slow_source = Arc::new(SlowPieceGetter::default());
+    let fast_source = Arc::new(DelayPieceGetter {
+        delay: Duration::from_millis(10),
+    });
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> = vec![slow_source.clone(), fast_source];
+    let piece_getter = RacingPieceGetter::new(sources);
+
+    // The fast result wins, and the slow request is dropped before it completes
+    let start = Instant::now();
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ONE).await.unwrap(),
+        Some(test_piece(PieceIndex::ONE))
+    );
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(slow_source.dropped.load(Ordering::SeqCst));
+    assert!(!slow_source.completed.load(Ordering::SeqCst));
+
+    // Pieces are raced at the same time, so a batch takes as long as its slowest race
+    let start = Instant::now();
+    let pieces = piece_getter
+        .get_pieces(indexes(0..8))
+        .await
+        .unwrap()
+        .collect::<HashMap<_, _>>()
+        .await;
+    assert!(start.elapsed() < Duration::from_millis(20));
+    assert_eq!(pieces.len(), 8);
+    for (piece_index, piece) in pieces {
+        assert_eq!(piece.unwrap(), Some(test_piece(piece_index)));
+    }
+
+    // Missing pieces are not found, unless every source fails
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> = vec![
+        Arc::new(FlakyPieceGetter::new(0, false)),
+        Arc::new(FlakyPieceGetter::new(1, false)),
+    ];
+    let piece_getter = RacingPieceGetter::new(sources);
+    assert_eq!(piece_getter.get_piece(PieceIndex::ONE).await.unwrap(), None);
+
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> = vec![
+        Arc::new(FlakyPieceGetter::new(1, true)),
+        Arc::new(FlakyPieceGetter::new(1, true)),
+    ];
+    let piece_getter = RacingPieceGetter::new(sources);
+    assert!(piece_getter.get_piece(PieceIndex::ONE).await.is_err());
+}
+
+#[tokio::test]
+async fn instrumented_piece_getter() {
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> = vec![
+        Arc::new(vec![(PieceIndex::ONE, test_piece(PieceIndex::ONE))]),
+        Arc::new(FlakyPieceGetter::new(1, false)),
+    ];
+    let piece_getter = InstrumentedPieceGetter::new(
+        FallbackListPieceGetter::new(sources),
+        CountingMetrics::default(),
+    );
+
+    // Success
+    piece_getter.get_piece(PieceIndex::ONE).await.unwrap();
+    assert_eq!(piece_getter.metrics().counts(), (1, 0, 0, 1));
+
+    // Error, then miss
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+    assert_eq!(piece_getter.metrics().counts(), (1, 0, 1, 2));
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        None
+    );
+    assert_eq!(piece_getter.metrics().counts(), (1, 1, 1, 3));
+
+    // Each piece in get_pieces is recorded
+    let pieces = piece_getter
+        .get_pieces(indexes(0..3))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 3);
+    // Piece 0 is a miss, piece 1 is a success, and piece 2 is an error
+    assert_eq!(piece_getter.metrics().counts(), (2, 2, 2, 6));
+}
+
+#[tokio::test]
+async fn instrumented_piece_getter_stats() {
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> = vec![
+        Arc::new(DelayPieceGetter {
+            delay: Duration::from_millis(10),
+        }),
+        Arc::new(FailingPieceGetter {
+            failing_indexes: HashSet::from([PieceIndex::ONE]),
+        }),
+    ];
+    let piece_getters = sources
+        .into_iter()
+        .map(|source| InstrumentedPieceGetter::new(source, NoopPieceGetterMetrics))
+        .collect::<Vec<_>>();
+    assert_eq!(piece_getters[0].stats(), PieceGetterStats::default());
+
+    // Successes record their latency
+    piece_getters[0]
+        .get_pieces_vec(indexes(0..2))
+        .await
+        .unwrap();
+    let stats = piece_getters[0].stats();
+    assert_eq!((stats.hits, stats.misses, stats.errors), (2, 0, 0));
+    assert!(stats.total_latency >= Duration::from_millis(10));
+
+    // Errors are counted separately
+    assert!(piece_getters[1].get_piece(PieceIndex::ONE).await.is_err());
+    assert!(piece_getters[1].get_piece(PieceIndex::ZERO).await.is_ok());
+    let stats = piece_getters[1].stats();
+    assert_eq!((stats.hits, stats.misses, stats.errors), (1, 0, 1));
+
+    // Misses are counted too
+    let piece_getter = InstrumentedPieceGetter::new(NullPieceGetter, NoopPieceGetterMetrics);
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        None
+    );
+    let stats = piece_getter.stats();
+    assert_eq!((stats.hits, stats.misses, stats.errors), (0, 1, 0));
+}
+
+#[tokio::test(start_paused = true)]
+async fn get_piece_with_retry() {
+    let piece_getter = FlakyPieceGetter::new(2, true);
+
+    assert_eq!(
+        piece_getter
+            .get_piece_with_retry(
+                PieceIndex::ZERO,
+                NonZeroU32::new(3).unwrap(),
+                test_backoff()
+            )
+            .await
+            .unwrap(),
+        Some(test_piece(PieceIndex::ZERO))
+    );
+    assert_eq!(piece_getter.attempts(PieceIndex::ZERO), 3);
+
+    // The last error is returned when all the attempts fail
+    let error = piece_getter
+        .get_piece_with_retry(PieceIndex::ONE, NonZeroU32::new(2).unwrap(), test_backoff())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("attempt 2"), "{error}");
+    assert_eq!(piece_getter.attempts(PieceIndex::ONE), 2);
+
+    // Missing pieces are not retried
+    let piece_getter = FlakyPieceGetter::new(0, false);
+    assert_eq!(
+        piece_getter
+            .get_piece_with_retry(
+                PieceIndex::ZERO,
+                NonZeroU32::new(3).unwrap(),
+                test_backoff()
+            )
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(piece_getter.attempts(PieceIndex::ZERO), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn cancellable_pieces_stops_stream() {
+    let piece_getter = DelayPieceGetter {
+        delay: Duration::from_millis(100),
+    };
+    let (cancel_sender, cancel_receiver) = oneshot::channel::<()>();
+    let mut cancel_sender = Some(cancel_sender);
+
+    let start = Instant::now();
+    let mut pieces = cancellable_pieces(
+        piece_getter.get_pieces(indexes(0..10)).await.unwrap(),
+        cancel_receiver.map(|_| ()),
+    );
+
+    let mut yielded_indices = Vec::new();
+    while let Some((piece_index, _piece)) = pieces.next().await {
+        yielded_indices.push(piece_index);
+
+        // Simulate a client disconnecting
+        if yielded_indices.len() == 3 {
+            drop(cancel_sender.take());
+        }
+    }
+
+    assert_eq!(yielded_indices, indexes(0..3));
+    assert!(start.elapsed() < Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn get_pieces_deduplicates_indices() {
+    let piece_getter = RecordingPieceGetter::default();
+    let piece_indices = indexes([5, 5, 7]);
+
+    let pieces = piece_getter
+        .get_pieces(piece_indices.clone())
+        .await
+        .unwrap()
+        .map(|(piece_index, _)| piece_index)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces, indexes([5, 7]));
+    assert_eq!(piece_getter.requested(), indexes([5, 7]));
+
+    let pieces = get_pieces_with_concurrency(
+        |piece_index| piece_getter.get_piece(piece_index),
+        piece_indices,
+        NonZeroUsize::new(2).unwrap(),
+    )
+    .unwrap()
+    .collect::<Vec<_>>()
+    .await;
+    assert_eq!(pieces.len(), 2);
+    assert_eq!(piece_getter.requested(), indexes([5, 7, 5, 7]));
+
+    // Wrapping piece getters also yield each unique index once, including cached indexes
+    let caching_piece_getter =
+        CachingPieceGetter::new(RecordingPieceGetter::default(), NonZeroU32::new(4).unwrap());
+    caching_piece_getter
+        .get_piece(PieceIndex::from(5))
+        .await
+        .unwrap();
+    let pieces = caching_piece_getter
+        .get_pieces(indexes([5, 5, 7, 7]))
+        .await
+        .unwrap()
+        .map(|(piece_index, _)| piece_index)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces, indexes([5, 7]));
+    assert_eq!(caching_piece_getter.inner.requested(), indexes([5, 7]));
+
+    let fallback_piece_getter = FallbackListPieceGetter::new(vec![
+        Arc::new(NullPieceGetter),
+        Arc::new(RecordingPieceGetter::default()),
+    ]);
+    let pieces = fallback_piece_getter
+        .get_pieces(indexes([5, 5, 7]))
+        .await
+        .unwrap()
+        .map(|(piece_index, _)| piece_index)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces, indexes([5, 7]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limited_piece_getter() {
+    let piece_getter = RateLimitedPieceGetter::new(
+        RecordingPieceGetter::default(),
+        NonZeroU32::new(10).unwrap(),
+        NonZeroU32::new(2).unwrap(),
+    );
+
+    let pieces = piece_getter
+        .get_pieces(indexes(0..6))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 6);
+
+    // The burst is allowed immediately, then requests are limited to 10 per second
+    let request_times = piece_getter.inner.request_times();
+    assert_eq!(request_times.len(), 6);
+    assert!(request_times[1] - request_times[0] < Duration::from_millis(1));
+    // Allow for timer rounding
+    let min_interval = Duration::from_millis(99);
+    for window in request_times[1..].windows(2) {
+        assert!(window[1] - window[0] >= min_interval, "{request_times:?}");
+    }
+    assert!(
+        *request_times.last().unwrap() - request_times[0] >= Duration::from_millis(399),
+        "{request_times:?}"
+    );
+}
+
+#[tokio::test]
+async fn caching_piece_getter_prefetch() {
+    let piece_getter =
+        CachingPieceGetter::new(RecordingPieceGetter::default(), NonZeroU32::new(4).unwrap());
+
+    piece_getter.prefetch(indexes(0..2)).await.unwrap();
+    assert_eq!(piece_getter.inner.requested(), indexes(0..2));
+    assert_eq!(piece_getter.hits(), 0);
+
+    // Prefetched pieces are cache hits
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ONE).await.unwrap(),
+        Some(test_piece(PieceIndex::ONE))
+    );
+    assert_eq!(piece_getter.hits(), 1);
+    assert_eq!(piece_getter.inner.requested(), indexes(0..2));
+
+    // Piece getters without a cache don't fetch anything
+    let piece_getter = RecordingPieceGetter::default();
+    piece_getter.prefetch(indexes(0..2)).await.unwrap();
+    assert!(piece_getter.requested().is_empty());
+}
+
+/// A piece validator that only accepts pieces which match `test_piece`.
+#[derive(Copy, Clone, Debug)]
+struct TestPieceValidator;
+
+#[async_trait]
+impl PieceValidator for TestPieceValidator {
+    async fn validate_piece(&self, piece_index: PieceIndex, piece: Piece) -> Option<Piece> {
+        (piece == test_piece(piece_index)).then_some(piece)
+    }
+}
+
+#[tokio::test]
+async fn validating_piece_getter_rejects_corrupt_pieces() {
+    let mut corrupt_piece = test_piece(PieceIndex::ONE);
+    corrupt_piece.as_mut()[0] ^= 0xff;
+    let pieces = vec![
+        (PieceIndex::ZERO, test_piece(PieceIndex::ZERO)),
+        (PieceIndex::ONE, corrupt_piece),
+    ];
+    let piece_getter = ValidatingPieceGetter::new(pieces, TestPieceValidator);
+
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(test_piece(PieceIndex::ZERO))
+    );
+    assert!(piece_getter.get_piece(PieceIndex::ONE).await.is_err());
+    // Missing pieces are not validation failures
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::from(2)).await.unwrap(),
+        None
+    );
+
+    let results = piece_getter
+        .get_pieces(indexes(0..3))
+        .await
+        .unwrap()
+        .collect::<HashMap<_, _>>()
+        .await;
+    assert_eq!(results.len(), 3);
+    assert_eq!(
+        results[&PieceIndex::ZERO].as_ref().unwrap(),
+        &Some(test_piece(PieceIndex::ZERO))
+    );
+    assert!(results[&PieceIndex::ONE].is_err());
+    assert!(results[&PieceIndex::from(2)].as_ref().unwrap().is_none());
+}
+
+/// A piece getter that records each `get_pieces` batch, and fails batches containing
+/// `failing_index`.
+#[derive(Debug, Default)]
+struct BatchPieceGetter {
+    batches: Mutex<Vec<Vec<PieceIndex>>>,
+    failing_index: Option<PieceIndex>,
+}
+
+#[async_trait]
+impl PieceGetter for BatchPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        Ok(Some(test_piece(piece_index)))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        self.batches.lock().unwrap().push(piece_indices.clone());
+
+        if let Some(failing_index) = self.failing_index
+            && piece_indices.contains(&failing_index)
+        {
+            return Err(anyhow::anyhow!("Batch failed"));
+        }
+
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+#[tokio::test]
+async fn get_pieces_in_batches_flattens_batches() {
+    let piece_getter = BatchPieceGetter::default();
+    let mut piece_indices = indexes(0..7);
+    // Duplicates are only fetched once
+    piece_indices.extend(indexes([2, 6]));
+
+    let pieces = get_pieces_in_batches(&piece_getter, piece_indices, NonZeroUsize::new(3).unwrap())
+        .unwrap()
+        .map(|(piece_index, piece_result)| (piece_index, piece_result.unwrap()))
+        .collect::<Vec<_>>()
+        .await;
+
+    assert_eq!(
+        *piece_getter.batches.lock().unwrap(),
+        vec![indexes(0..3), indexes(3..6), indexes([6])]
+    );
+    assert_eq!(
+        pieces,
+        indexes(0..7)
+            .into_iter()
+            .map(|piece_index| (piece_index, Some(test_piece(piece_index))))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn get_pieces_in_batches_failed_batch() {
+    let piece_getter = BatchPieceGetter {
+        failing_index: Some(PieceIndex::from(4)),
+        ..BatchPieceGetter::default()
+    };
+
+    let results =
+        get_pieces_in_batches(&piece_getter, indexes(0..6), NonZeroUsize::new(2).unwrap())
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+    // Only the pieces in the failed batch are errors
+    assert_eq!(
+        results
+            .iter()
+            .map(|(piece_index, _)| *piece_index)
+            .collect::<Vec<_>>(),
+        indexes(0..6)
+    );
+    for (piece_index, piece_result) in results {
+        if [PieceIndex::from(4), PieceIndex::from(5)].contains(&piece_index) {
+            assert!(piece_result.is_err());
+        } else {
+            assert_eq!(piece_result.unwrap(), Some(test_piece(piece_index)));
+        }
+    }
+}
+
+/// A piece getter that fails the pieces in `failing_indexes`, and returns a test piece for all
+/// other indexes.
+#[derive(Debug, Default)]
+struct FailingPieceGetter {
+    failing_indexes: HashSet<PieceIndex>,
+}
+
+#[async_trait]
+impl PieceGetter for FailingPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        if self.failing_indexes.contains(&piece_index) {
+            return Err(anyhow::anyhow!("Piece {piece_index} failed"));
+        }
+
+        Ok(Some(test_piece(piece_index)))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+#[tokio::test]
+async fn error_threshold_pieces_tolerates_errors() {
+    let piece_getter = FailingPieceGetter {
+        failing_indexes: indexes([1, 3]).into_iter().collect(),
+    };
+
+    let mut pieces =
+        ErrorThresholdPieces::new(piece_getter.get_pieces(indexes(0..6)).await.unwrap(), 2);
+    let results = pieces.by_ref().collect::<Vec<_>>().await;
+
+    // All pieces are yielded, including the failures
+    assert_eq!(results.len(), 6);
+    assert_eq!(
+        results
+            .iter()
+            .filter(|(_, piece_result)| piece_result.is_err())
+            .count(),
+        2
+    );
+    assert_eq!(pieces.successes(), 4);
+    assert_eq!(pieces.errors(), 2);
+    assert!(!pieces.threshold_exceeded());
+}
+
+#[tokio::test]
+async fn error_threshold_pieces_stops_after_threshold() {
+    let piece_getter = FailingPieceGetter {
+        failing_indexes: indexes([1, 3, 4]).into_iter().collect(),
+    };
+
+    let mut pieces =
+        ErrorThresholdPieces::new(piece_getter.get_pieces(indexes(0..6)).await.unwrap(), 1);
+    let results = pieces.by_ref().collect::<Vec<_>>().await;
+
+    // The stream ends at the second failure, which isn't yielded
+    assert_eq!(
+        results
+            .iter()
+            .map(|(piece_index, _)| *piece_index)
+            .collect::<Vec<_>>(),
+        indexes(0..3)
+    );
+    assert_eq!(pieces.successes(), 2);
+    assert_eq!(pieces.errors(), 2);
+    assert!(pieces.threshold_exceeded());
+    assert!(pieces.next().await.is_none());
+}
+
+#[tokio::test]
+async fn null_piece_getter_has_no_pieces() {
+    assert_eq!(
+        NullPieceGetter.get_piece(PieceIndex::ONE).await.unwrap(),
+        None
+    );
+
+    let results = NullPieceGetter
+        .get_pieces(indexes([0, 1, 2, 1]))
+        .await
+        .unwrap()
+        .map(|(piece_index, piece_result)| (piece_index, piece_result.unwrap()))
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(
+        results,
+        indexes(0..3)
+            .into_iter()
+            .map(|piece_index| (piece_index, None))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn pieces_with_progress_reports_each_piece() {
+    let piece_getter = FailingPieceGetter {
+        failing_indexes: indexes([1]).into_iter().collect(),
+    };
+    let piece_indices = indexes([0, 1, 2, 1, 3]);
+    let total = unique_piece_indices(piece_indices.clone()).count();
+    let progress = Arc::new(Mutex::new(Vec::new()));
+
+    let pieces = pieces_with_progress(
+        piece_getter.get_pieces(piece_indices).await.unwrap(),
+        total,
+        {
+            let progress = progress.clone();
+            move |fetched, total| progress.lock().unwrap().push((fetched, total))
+        },
+    )
+    .collect::<Vec<_>>()
+    .await;
+
+    // Progress is reported for successful and failed pieces
+    assert_eq!(pieces.len(), 4);
+    assert_eq!(
+        *progress.lock().unwrap(),
+        vec![(1, 4), (2, 4), (3, 4), (4, 4)]
+    );
+}
+
+#[tokio::test]
+async fn multi_source_piece_getters_tag_sources() {
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> = vec![
+        Arc::new(vec![(PieceIndex::ONE, test_piece(PieceIndex::ONE))]),
+        Arc::new(RecordingPieceGetter::default()),
+    ];
+    let fallback_getter = FallbackListPieceGetter::new(sources.clone());
+    let racing_getter = RacingPieceGetter::new(sources);
+
+    // Only the second source has piece zero
+    assert_eq!(
+        fallback_getter
+            .get_piece_with_source(PieceIndex::ZERO)
+            .await
+            .unwrap(),
+        Some((test_piece(PieceIndex::ZERO), SourceId(1)))
+    );
+    assert_eq!(
+        racing_getter
+            .get_piece_with_source(PieceIndex::ZERO)
+            .await
+            .unwrap(),
+        Some((test_piece(PieceIndex::ZERO), SourceId(1)))
+    );
+
+    let pieces = fallback_getter
+        .get_pieces_with_source(indexes(0..2))
+        .await
+        .unwrap()
+        .map(|(piece_index, piece_result)| (piece_index, piece_result.unwrap()))
+        .collect::<HashMap<_, _>>()
+        .await;
+    assert_eq!(
+        pieces,
+        HashMap::from([
+            (
+                PieceIndex::ZERO,
+                Some((test_piece(PieceIndex::ZERO), SourceId(1)))
+            ),
+            (
+                PieceIndex::ONE,
+                Some((test_piece(PieceIndex::ONE), SourceId(0)))
+            ),
+        ])
+    );
+
+    let pieces = racing_getter
+        .get_pieces_with_source(indexes([0]))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 1);
+    assert_eq!(
+        pieces[0].1.as_ref().unwrap(),
+        &Some((test_piece(PieceIndex::ZERO), SourceId(1)))
+    );
+}
+
+/// A piece getter that returns a test piece for every index, after a short delay for indexes below
+/// `slow_from`, and a long delay for other indexes. Records the maximum number of concurrent
+/// requests.
+#[derive(Debug, Default)]
+struct SlowdownPieceGetter {
+    slow_from: u64,
+    in_flight: AtomicU64,
+    max_in_flight: AtomicU64,
+}
+
+#[async_trait]
+impl PieceGetter for SlowdownPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+        let delay = if u64::from(piece_index) < self.slow_from {
+            Duration::from_millis(10)
+        } else {
+            Duration::from_millis(500)
+        };
+        tokio::time::sleep(delay).await;
+
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        Ok(Some(test_piece(piece_index)))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn circuit_breaker_piece_getter() {
+    let piece_getter = CircuitBreakerPieceGetter::new(
+        FlakyPieceGetter::new(5, true),
+        NonZeroU32::new(3).unwrap(),
+        Duration::from_secs(1),
+    );
+
+    // Repeated errors open the circuit breaker
+    for _ in 0..3 {
+        assert!(!piece_getter.is_open());
+        assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+    }
+    assert!(piece_getter.is_open());
+
+    // Requests fail without contacting the inner piece getter until the cooldown elapses
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+    let pieces = piece_getter
+        .get_pieces(indexes([0, 1]))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 2);
+    assert!(pieces.iter().all(|(_, piece_result)| piece_result.is_err()));
+    assert_eq!(piece_getter.inner.attempts(PieceIndex::ZERO), 3);
+    assert_eq!(piece_getter.inner.attempts(PieceIndex::ONE), 0);
+
+    // After the cooldown, another failure opens it again straight away
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(!piece_getter.is_open());
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+    assert!(piece_getter.is_open());
+    assert_eq!(piece_getter.inner.attempts(PieceIndex::ZERO), 4);
+
+    // A success closes it
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(
+        piece_getter.get_piece(PieceIndex::ZERO).await.unwrap(),
+        Some(test_piece(PieceIndex::ZERO))
+    );
+    assert!(!piece_getter.is_open());
+    assert_eq!(piece_getter.inner.attempts(PieceIndex::ZERO), 6);
+}
+
+/// A piece getter that fails every request after a delay, and counts the requests.
+#[derive(Debug, Default)]
+struct SlowFailingPieceGetter {
+    requests: AtomicU64,
+}
+
+#[async_trait]
+impl PieceGetter for SlowFailingPieceGetter {
+    async fn get_piece(&self, piece_index: PieceIndex) -> anyhow::Result<Option<Piece>> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        Err(anyhow::anyhow!("piece {piece_index} failed"))
+    }
+
+    async fn get_pieces<'a>(
+        &'a self,
+        piece_indices: Vec<PieceIndex>,
+    ) -> anyhow::Result<
+        Box<dyn Stream<Item = (PieceIndex, anyhow::Result<Option<Piece>>)> + Send + Unpin + 'a>,
+    > {
+        get_pieces_individually(|piece_index| self.get_piece(piece_index), piece_indices)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn circuit_breaker_sends_one_trial_request() {
+    let piece_getter = CircuitBreakerPieceGetter::new(
+        SlowFailingPieceGetter::default(),
+        NonZeroU32::MIN,
+        Duration::from_secs(1),
+    );
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+    assert!(piece_getter.is_open());
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(!piece_getter.is_open());
+
+    // After the cooldown, other requests fail immediately while the trial request is in flight
+    let mut trial = piece_getter.get_piece(PieceIndex::ZERO);
+    assert!((&mut trial).now_or_never().is_none());
+    assert!(piece_getter.is_open());
+    assert!(piece_getter.get_piece(PieceIndex::ONE).await.is_err());
+    let pieces = piece_getter
+        .get_pieces(indexes([1]))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert!(pieces[0].1.is_err());
+    assert_eq!(piece_getter.inner.requests.load(Ordering::SeqCst), 2);
+
+    // A failed trial opens the circuit breaker for another cooldown
+    assert!(trial.await.is_err());
+    assert!(piece_getter.is_open());
+
+    // A cancelled trial allows another trial
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(
+        piece_getter
+            .get_piece(PieceIndex::ZERO)
+            .now_or_never()
+            .is_none()
+    );
+    assert!(!piece_getter.is_open());
+    assert_eq!(piece_getter.inner.requests.load(Ordering::SeqCst), 3);
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test(start_paused = true)]
+async fn piece_getter_service_with_concurrency_limit() {
+    use tower::{ServiceBuilder, ServiceExt};
+
+    let piece_getter = Arc::new(SlowdownPieceGetter {
+        slow_from: 100,
+        ..SlowdownPieceGetter::default()
+    });
+    let service = ServiceBuilder::new()
+        .concurrency_limit(2)
+        .service(PieceGetterService::new(Arc::clone(&piece_getter)));
+
+    let pieces = futures::future::join_all(
+        indexes(0..5)
+            .into_iter()
+            .map(|piece_index| service.clone().oneshot(piece_index)),
+    )
+    .await;
+
+    for (piece_index, piece) in indexes(0..5).into_iter().zip(pieces) {
+        assert_eq!(piece.unwrap(), Some(test_piece(piece_index)));
+    }
+    assert_eq!(piece_getter.max_in_flight.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn adaptive_piece_getter_adapts_concurrency() {
+    let piece_getter = AdaptivePieceGetter::new(
+        SlowdownPieceGetter {
+            slow_from: 100,
+            ..SlowdownPieceGetter::default()
+        },
+        NonZeroUsize::new(2).unwrap(),
+        NonZeroUsize::new(16).unwrap(),
+        Duration::from_millis(100),
+    );
+    assert_eq!(piece_getter.concurrency().get(), 2);
+
+    // Fast pieces increase the concurrency
+    let pieces = piece_getter
+        .get_pieces(indexes(0..100))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 100);
+    let fast_concurrency = piece_getter.concurrency().get();
+    assert!(fast_concurrency > 2, "{fast_concurrency}");
+    assert!(piece_getter.inner.max_in_flight.load(Ordering::SeqCst) > 2);
+
+    // Then slow pieces decrease it
+    let pieces = piece_getter
+        .get_pieces(indexes(100..150))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 50);
+    let slow_concurrency = piece_getter.concurrency().get();
+    assert!(
+        slow_concurrency < fast_concurrency,
+        "{slow_concurrency} {fast_concurrency}"
+    );
+    assert!(piece_getter.average_latency().unwrap() > Duration::from_millis(100));
+
+    // Errors also decrease the concurrency
+    let piece_getter = AdaptivePieceGetter::new(
+        FlakyPieceGetter::new(1, true),
+        NonZeroUsize::new(8).unwrap(),
+        NonZeroUsize::new(16).unwrap(),
+        Duration::from_millis(100),
+    );
+    assert!(piece_getter.get_piece(PieceIndex::ZERO).await.is_err());
+    assert_eq!(piece_getter.concurrency().get(), 4);
+}
+
+#[tokio::test(start_paused = true)]
+async fn pieces_until_deadline_yields_partial_results() {
+    let piece_getter = SlowdownPieceGetter {
+        slow_from: 2,
+        ..SlowdownPieceGetter::default()
+    };
+    let start = Instant::now();
+
+    let pieces = pieces_until_deadline(
+        piece_getter.get_pieces(indexes(0..5)).await.unwrap(),
+        start + Duration::from_millis(100),
+    )
+    .collect::<Vec<_>>()
+    .await;
+
+    // Only the fast pieces complete before the deadline, and the slow piece in flight is dropped
+    assert_eq!(
+        pieces
+            .into_iter()
+            .map(|(piece_index, _)| piece_index)
+            .collect::<Vec<_>>(),
+        indexes(0..2)
+    );
+    assert!(start.elapsed() <= Duration::from_millis(101));
+    assert_eq!(piece_getter.in_flight.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn fallback_list_piece_getter_latency_order() {
+    let sources: Vec<Arc<dyn PieceGetter + Send + Sync>> = vec![
+        Arc::new(DelayPieceGetter {
+            delay: Duration::from_millis(100),
+        }),
+        Arc::new(DelayPieceGetter {
+            delay: Duration::from_millis(10),
+        }),
+    ];
+
+    // Strict order always uses the first source, even though it is slower
+    let piece_getter = FallbackListPieceGetter::new(sources.clone());
+    for piece_index in indexes(0..10) {
+        let (_piece, source_id) = piece_getter
+            .get_piece_with_source(piece_index)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(source_id, SourceId(0));
+    }
+
+    // Latency order measures each source once, then prefers the faster source
+    let piece_getter = FallbackListPieceGetter::with_source_order(sources, SourceOrder::Latency);
+    let mut source_ids = Vec::new();
+    for piece_index in indexes(0..10) {
+        let (_piece, source_id) = piece_getter
+            .get_piece_with_source(piece_index)
+            .await
+            .unwrap()
+            .unwrap();
+        source_ids.push(source_id);
+    }
+    assert_eq!(source_ids[0], SourceId(0));
+    assert!(
+        source_ids[1..]
+            .iter()
+            .all(|source_id| *source_id == SourceId(1))
+    );
+
+    // Streams use the faster source too
+    let pieces = piece_getter
+        .get_pieces_with_source(indexes(10..12))
+        .await
+        .unwrap()
+        .map(|(_piece_index, piece_result)| piece_result.unwrap().unwrap().1)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces, vec![SourceId(1); 2]);
+}
+
+/// A tracing layer that records the level and fields of each event.
+#[derive(Clone, Debug, Default)]
+struct EventRecorder {
+    events: Arc<Mutex<Vec<(Level, String)>>>,
+}
+
+impl<S> tracing_subscriber::Layer<S> for EventRecorder
+where
+    S: tracing::Subscriber,
+{
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut fields = String::new();
+        event.record(
+            &mut |field: &tracing::field::Field, value: &dyn fmt::Debug| {
+                fields.push_str(&format!("{}={value:?} ", field.name()));
+            },
+        );
+
+        self.events
+            .lock()
+            .unwrap()
+            .push((*event.metadata().level(), fields));
+    }
+}
+
+#[tokio::test]
+async fn logging_piece_getter_logs_misses() {
+    let recorder = EventRecorder::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+
+    let piece_getter = LoggingPieceGetter::new(
+        NullPieceGetter,
+        PieceLogLevels {
+            miss: Level::INFO,
+            ..PieceLogLevels::default()
+        },
+    );
+    assert_eq!(piece_getter.get_piece(PieceIndex::ONE).await.unwrap(), None);
+    let pieces = piece_getter
+        .get_pieces(indexes([2]))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(pieces.len(), 1);
+
+    let events = recorder.events.lock().unwrap();
+    let misses = events
+        .iter()
+        .filter(|(_level, fields)| fields.contains("result=\"miss\""))
+        .collect::<Vec<_>>();
+    assert_eq!(misses.len(), 2, "{events:?}");
+    for (piece_index, (level, fields)) in [1, 2].into_iter().zip(misses) {
+        assert_eq!(*level, Level::INFO);
+        assert!(
+            fields.contains(&format!("piece_index={piece_index} ")),
+            "{fields}"
+        );
+    }
+}
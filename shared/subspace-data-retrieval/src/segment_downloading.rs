@@ -141,7 +141,7 @@ where
 ///
 /// Prefers source pieces if available, on error returns the incomplete piece download (including
 /// existing pieces).
-async fn download_missing_segment_pieces<PG>(
+pub(crate) async fn download_missing_segment_pieces<PG>(
     segment_index: SegmentIndex,
     piece_getter: &PG,
     existing_pieces: [Option<Piece>; ArchivedHistorySegment::NUM_PIECES],